@@ -0,0 +1,224 @@
+use lazy_static::lazy_static;
+
+lazy_static! {
+    static ref SGR: regex::bytes::Regex = regex::bytes::Regex::new(r"\x1b\[[0-9;]*m").unwrap();
+    static ref ESCAPES: regex::bytes::Regex = regex::bytes::Regex::new(
+        r"\x1b(\[[0-?]*[ -/]*[@-~]|\][^\x07\x1b]*(\x07|\x1b\\)|[@-Z\\-_])"
+    )
+    .unwrap();
+}
+
+const PALETTE: [&str; 16] = [
+    "#000000", "#cd3131", "#0dbc79", "#e5e510", "#2472c8", "#bc3fbc", "#11a8cd", "#e5e5e5",
+    "#666666", "#f14c4c", "#23d18b", "#f5f543", "#3b8eea", "#d670d6", "#29b8db", "#ffffff",
+];
+
+/// Returns true if the content contains SGR (Select Graphic Rendition) escape sequences, i.e.
+/// it's terminal output carrying colors or text styling.
+pub fn has_sgr(content: &[u8]) -> bool {
+    SGR.is_match(content)
+}
+
+/// Removes all terminal escape sequences, leaving the plain text.
+pub fn strip(content: &[u8]) -> Vec<u8> {
+    ESCAPES.replace_all(content, &b""[..]).into_owned()
+}
+
+#[derive(Default, Clone, PartialEq, Debug)]
+struct Style {
+    fg: Option<String>,
+    bg: Option<String>,
+    bold: bool,
+    dim: bool,
+    italic: bool,
+    underline: bool,
+    inverse: bool,
+}
+
+impl Style {
+    fn css(&self) -> String {
+        let (fg, bg) = if self.inverse {
+            (
+                Some(self.bg.clone().unwrap_or_else(|| "#ffffff".to_string())),
+                Some(self.fg.clone().unwrap_or_else(|| "#000000".to_string())),
+            )
+        } else {
+            (self.fg.clone(), self.bg.clone())
+        };
+        let mut css = Vec::new();
+        if let Some(fg) = fg {
+            css.push(format!("color: {fg}"));
+        }
+        if let Some(bg) = bg {
+            css.push(format!("background-color: {bg}"));
+        }
+        if self.bold {
+            css.push("font-weight: bold".to_string());
+        }
+        if self.dim {
+            css.push("opacity: 0.7".to_string());
+        }
+        if self.italic {
+            css.push("font-style: italic".to_string());
+        }
+        if self.underline {
+            css.push("text-decoration: underline".to_string());
+        }
+        css.join("; ")
+    }
+
+    fn apply(&mut self, params: &[u16]) {
+        let mut params = params.iter().copied();
+        while let Some(param) = params.next() {
+            match param {
+                0 => *self = Style::default(),
+                1 => self.bold = true,
+                2 => self.dim = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                7 => self.inverse = true,
+                22 => {
+                    self.bold = false;
+                    self.dim = false;
+                }
+                23 => self.italic = false,
+                24 => self.underline = false,
+                27 => self.inverse = false,
+                30..=37 => self.fg = Some(PALETTE[(param - 30) as usize].to_string()),
+                90..=97 => self.fg = Some(PALETTE[(param - 90 + 8) as usize].to_string()),
+                40..=47 => self.bg = Some(PALETTE[(param - 40) as usize].to_string()),
+                100..=107 => self.bg = Some(PALETTE[(param - 100 + 8) as usize].to_string()),
+                39 => self.fg = None,
+                49 => self.bg = None,
+                38 | 48 => {
+                    let color = match params.next() {
+                        Some(5) => params.next().map(color_256),
+                        Some(2) => match (params.next(), params.next(), params.next()) {
+                            (Some(r), Some(g), Some(b)) => Some(format!(
+                                "#{:02x}{:02x}{:02x}",
+                                r.min(255),
+                                g.min(255),
+                                b.min(255)
+                            )),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    if param == 38 {
+                        self.fg = color;
+                    } else {
+                        self.bg = color;
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+fn color_256(n: u16) -> String {
+    match n {
+        0..=15 => PALETTE[n as usize].to_string(),
+        16..=231 => {
+            let n = n - 16;
+            let level = |v: u16| if v == 0 { 0 } else { v * 40 + 55 };
+            format!(
+                "#{:02x}{:02x}{:02x}",
+                level(n / 36),
+                level((n / 6) % 6),
+                level(n % 6)
+            )
+        }
+        _ => {
+            let gray = (n.min(255) - 232) * 10 + 8;
+            format!("#{gray:02x}{gray:02x}{gray:02x}")
+        }
+    }
+}
+
+fn escape_html(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            _ => out.push(c),
+        }
+    }
+}
+
+fn push_text(html: &mut String, style: &Style, text: &[u8]) {
+    if text.is_empty() {
+        return;
+    }
+    let text = String::from_utf8_lossy(text);
+    let css = style.css();
+    if css.is_empty() {
+        escape_html(&text, html);
+    } else {
+        html.push_str(&format!("<span style=\"{css}\">"));
+        escape_html(&text, html);
+        html.push_str("</span>");
+    }
+}
+
+/// Renders terminal output to HTML, mapping SGR sequences to styled spans. All other escape
+/// sequences (cursor movement, OSC titles, ...) are dropped.
+pub fn to_html(content: &[u8]) -> String {
+    let mut html = String::new();
+    let mut style = Style::default();
+    let mut last = 0;
+
+    for m in ESCAPES.find_iter(content) {
+        push_text(&mut html, &style, &content[last..m.start()]);
+        last = m.end();
+
+        let seq = m.as_bytes();
+        if seq.len() >= 3 && seq[1] == b'[' && seq[seq.len() - 1] == b'm' {
+            let params: Vec<u16> = std::str::from_utf8(&seq[2..seq.len() - 1])
+                .unwrap_or("")
+                .split(';')
+                .map(|p| p.parse::<u16>().unwrap_or(0))
+                .collect();
+            style.apply(&params);
+        }
+    }
+    push_text(&mut html, &style, &content[last..]);
+
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_has_sgr() {
+        assert!(has_sgr(b"\x1b[31mred\x1b[0m"));
+        assert!(has_sgr(b"\x1b[mreset"));
+        assert!(!has_sgr(b"plain text"));
+        assert!(!has_sgr(b"\x1b[2Kcleared line"));
+    }
+
+    #[test]
+    fn test_strip() {
+        assert_eq!(
+            strip(b"\x1b[1;32mok\x1b[0m \x1b]0;title\x07done\x1b[K"),
+            b"ok done".to_vec()
+        );
+    }
+
+    #[test]
+    fn test_to_html() {
+        assert_eq!(
+            to_html(b"\x1b[31mred\x1b[0m <plain>"),
+            "<span style=\"color: #cd3131\">red</span> &lt;plain&gt;"
+        );
+        assert_eq!(
+            to_html(b"\x1b[1;38;5;196mhot\x1b[22m\x1b[48;2;0;0;255mblue"),
+            "<span style=\"color: #ff0000; font-weight: bold\">hot</span>\
+             <span style=\"color: #ff0000; background-color: #0000ff\">blue</span>"
+        );
+    }
+}
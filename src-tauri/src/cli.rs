@@ -1,11 +1,13 @@
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use http_body_util::BodyExt;
 use hyper_util::rt::TokioIo;
 
 use clap::{Parser, Subcommand};
 
+use crate::storage::{self, Backend};
+
 #[derive(Parser, Debug, Clone)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
@@ -45,6 +47,9 @@ enum Commands {
         /// Rebuild the search index from current CAS content
         #[clap(long)]
         rebuild: bool,
+        /// Show how far indexing has got with new content (JSON)
+        #[clap(long)]
+        status: bool,
     },
     /// View complete structure (JSON)
     View {
@@ -56,6 +61,88 @@ enum Commands {
         #[clap(subcommand)]
         command: CasCommand,
     },
+    /// Add a file's content as a clip to the current stack; use - to read stdin
+    Add {
+        path: String,
+        /// content type for the clip, e.g. Markdown (defaults to one based on the extension)
+        #[clap(long)]
+        content_type: Option<String>,
+        /// add a reference to the file, with a snapshot of its content, instead
+        #[clap(long = "ref", conflicts_with = "content_type")]
+        reference: bool,
+    },
+    /// Bind a stack to a directory, which its shell commands run from
+    Directory {
+        /// id of the stack
+        stack_id: String,
+        /// directory to bind to; omit to unbind the stack
+        path: Option<PathBuf>,
+        /// capture files which change under the directory as clips
+        #[clap(long)]
+        watch: bool,
+    },
+    /// Rename a stack, and set its description, color or icon
+    Rename {
+        /// id of the stack
+        stack_id: String,
+        /// new name for the stack; omit to only update its details
+        name: Option<String>,
+        /// pass an empty string to clear a detail
+        #[clap(long)]
+        description: Option<String>,
+        #[clap(long)]
+        color: Option<String>,
+        #[clap(long)]
+        icon: Option<String>,
+    },
+    /// Add and remove tags on an item, e.g. `stacks tag <id> +todo -done`
+    Tag {
+        /// id of the item
+        id: String,
+        /// tags to add, prefixed with +, and to remove, prefixed with -
+        #[clap(required = true, allow_hyphen_values = true)]
+        tags: Vec<String>,
+    },
+    /// Show a stack, or every stack, with everything nested inside it (JSON)
+    Tree {
+        /// id of the stack; omit for every top level stack
+        stack_id: Option<String>,
+    },
+    /// Check the store for damage: corrupt or orphaned content, and dangling packets (JSON)
+    Fsck {
+        /// purge what can't be recovered, relink content and rebuild the search index
+        #[clap(long)]
+        repair: bool,
+    },
+    /// Rewrite the packet log as the fewest packets which reproduce the current view (JSON)
+    Compact {
+        /// report what compacting would do, without doing it
+        #[clap(long)]
+        dry_run: bool,
+        /// keep the full history of the last N days
+        #[clap(long)]
+        keep_days: Option<u64>,
+    },
+    /// Sync with the peers in the settings now, rather than waiting for the next sync (JSON)
+    Sync,
+    /// Copy the store to another backend, while Stacks isn't running
+    Migrate {
+        /// directory to copy the store to, which must be empty
+        to: PathBuf,
+        /// backend to keep the copy in: sled, sqlite, or sqlite-cas to keep blobs on disk
+        #[clap(long, default_value = "sled")]
+        backend: Backend,
+    },
+    /// Run a read-only SQL query against a store kept in SQLite (JSONL format)
+    Sql {
+        /// the query, e.g. "select id, stack_id from packets where packet_type = 'Add'"
+        query: String,
+    },
+    /// Manage commands which periodically capture their output to a stack
+    Schedule {
+        #[clap(subcommand)]
+        command: ScheduleCommand,
+    },
 }
 
 #[derive(Subcommand, Debug, Clone)]
@@ -68,6 +155,27 @@ enum CasCommand {
     Purge { hash: String },
 }
 
+#[derive(Subcommand, Debug, Clone)]
+enum ScheduleCommand {
+    /// Schedule a command, adding its output to a stack whenever it changes
+    Add {
+        /// id of the stack to capture output to
+        stack_id: String,
+        /// interval between runs, e.g. 30s, 5m, 1h
+        #[clap(long, conflicts_with = "cron", required_unless_present = "cron")]
+        every: Option<String>,
+        /// 5 field cron expression, e.g. "0 * * * *"
+        #[clap(long)]
+        cron: Option<String>,
+        /// shell command to run, as a single argument, e.g. "git log -5"
+        command: String,
+    },
+    /// List schedules (JSONL format)
+    List,
+    /// Remove a schedule
+    Rm { id: String },
+}
+
 #[derive(Subcommand, Debug, Clone)]
 enum ViewCommand {
     /// View current navigation state (JSON)
@@ -77,22 +185,36 @@ enum ViewCommand {
 pub async fn cli(db_path: &str) {
     let args = Args::parse();
 
-    let socket_path = Path::new(db_path).join("sock");
-    let stream = tokio::net::UnixStream::connect(socket_path)
-        .await
-        .expect("Failed to connect to server");
-    let io = TokioIo::new(stream);
+    // commands run from Stacks are pointed at the instance which launched them
+    let socket_path = std::env::var_os("STACKS_SOCKET")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| crate::http::socket_path(db_path));
 
-    use hyper::client::conn;
+    // migrating copies the store directly, rather than going through a running instance
+    if let Some(Commands::Migrate { to, backend }) = args.command.clone() {
+        handle_migrate_command(db_path, &to, backend);
+        return;
+    }
 
-    let (mut request_sender, connection) = conn::http1::handshake(io).await.unwrap();
+    // SQLite lets readers in alongside Stacks, so queries don't go through it
+    if let Some(Commands::Sql { query }) = args.command.clone() {
+        handle_sql_command(db_path, &query);
+        return;
+    }
 
-    // spawn a task to poll the connection and drive the HTTP state
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("Error in connection: {e}");
-        }
-    });
+    // adding content is the only request which sends a body
+    if let Some(Commands::Add {
+        path,
+        content_type,
+        reference,
+    }) = args.command.clone()
+    {
+        let mut request_sender = connect(&socket_path).await;
+        handle_add_command(path, content_type, reference, &mut request_sender).await;
+        return;
+    }
+
+    let mut request_sender = connect(&socket_path).await;
 
     match args.command {
         Some(Commands::List) => {
@@ -105,8 +227,9 @@ pub async fn cli(db_path: &str) {
             query,
             limit,
             rebuild,
+            status,
         }) => {
-            handle_search_command(query, limit, rebuild, &mut request_sender).await;
+            handle_search_command(query, limit, rebuild, status, &mut request_sender).await;
         }
         Some(Commands::View { command }) => {
             handle_view_command(command, &mut request_sender).await;
@@ -114,6 +237,51 @@ pub async fn cli(db_path: &str) {
         Some(Commands::Cas { command }) => {
             handle_cas_command(command, &mut request_sender).await;
         }
+        Some(Commands::Directory {
+            stack_id,
+            path,
+            watch,
+        }) => {
+            handle_directory_command(stack_id, path, watch, &mut request_sender).await;
+        }
+        Some(Commands::Rename {
+            stack_id,
+            name,
+            description,
+            color,
+            icon,
+        }) => {
+            let params = [
+                ("name", name),
+                ("description", description),
+                ("color", color),
+                ("icon", icon),
+            ];
+            handle_rename_command(stack_id, &params, &mut request_sender).await;
+        }
+        Some(Commands::Tag { id, tags }) => {
+            handle_tag_command(id, tags, &mut request_sender).await;
+        }
+        Some(Commands::Tree { stack_id }) => {
+            handle_tree_command(stack_id, &mut request_sender).await;
+        }
+        Some(Commands::Fsck { repair }) => {
+            handle_fsck_command(repair, &mut request_sender).await;
+        }
+        Some(Commands::Compact { dry_run, keep_days }) => {
+            handle_compact_command(dry_run, keep_days, &mut request_sender).await;
+        }
+        Some(Commands::Sync) => {
+            handle_sync_command(&mut request_sender).await;
+        }
+        Some(Commands::Schedule { command }) => {
+            handle_schedule_command(command, &mut request_sender).await;
+        }
+        Some(Commands::Add { .. })
+        | Some(Commands::Migrate { .. })
+        | Some(Commands::Sql { .. }) => {
+            unreachable!("handled above")
+        }
         None => {
             // Legacy behavior for backward compatibility
             handle_legacy_request(args, &mut request_sender).await;
@@ -121,6 +289,159 @@ pub async fn cli(db_path: &str) {
     }
 }
 
+fn handle_migrate_command(db_path: &str, to: &Path, backend: Backend) {
+    if to
+        .read_dir()
+        .is_ok_and(|mut entries| entries.next().is_some())
+    {
+        eprintln!("{} is not empty", to.display());
+        std::process::exit(1);
+    }
+    // the store is locked while Stacks is running
+    let from = storage::open(Backend::Sled, Path::new(db_path));
+    let to_storage = storage::open(backend, to);
+    let (from, to_storage) = match (from, to_storage) {
+        (Ok(from), Ok(to_storage)) => (from, to_storage),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+
+    let (entries, blobs) = storage::copy(from.as_ref(), to_storage.as_ref());
+    println!(
+        "Copied {entries} entries and {blobs} blobs to {}",
+        to.display()
+    );
+}
+
+fn handle_sql_command(db_path: &str, query: &str) {
+    match crate::sqlite::query(Path::new(db_path), query) {
+        Ok(result) => {
+            for row in &result.rows {
+                println!("{}", serde_json::to_string(row).unwrap());
+            }
+            if result.truncated {
+                eprintln!(
+                    "Stopped after {} rows: add a LIMIT, or select fewer columns",
+                    result.rows.len()
+                );
+            }
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn connect<B>(socket_path: &Path) -> hyper::client::conn::http1::SendRequest<B>
+where
+    B: hyper::body::Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let stream = tokio::net::UnixStream::connect(socket_path)
+        .await
+        .expect("Failed to connect to server");
+    let io = TokioIo::new(stream);
+
+    let (request_sender, connection) = hyper::client::conn::http1::handshake(io).await.unwrap();
+
+    // spawn a task to poll the connection and drive the HTTP state
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            eprintln!("Error in connection: {e}");
+        }
+    });
+
+    request_sender
+}
+
+async fn handle_add_command(
+    path: String,
+    content_type: Option<String>,
+    reference: bool,
+    request_sender: &mut hyper::client::conn::http1::SendRequest<
+        http_body_util::Full<bytes::Bytes>,
+    >,
+) {
+    use bytes::Bytes;
+    use http_body_util::Full;
+    use hyper::{Method, Request, StatusCode};
+    use std::io::Read;
+
+    let mut query = url::form_urlencoded::Serializer::new(String::new());
+
+    let content = if reference {
+        let path = match std::fs::canonicalize(&path) {
+            Ok(path) => path,
+            Err(e) => {
+                eprintln!("{path}: {e}");
+                return;
+            }
+        };
+        query.append_pair("file_ref", &path.to_string_lossy());
+        Vec::new()
+    } else {
+        let content = if path == "-" {
+            let mut content = Vec::new();
+            std::io::stdin().read_to_end(&mut content).map(|_| content)
+        } else {
+            std::fs::read(&path)
+        };
+        let content = match content {
+            Ok(content) => content,
+            Err(e) => {
+                eprintln!("{path}: {e}");
+                return;
+            }
+        };
+
+        let file_path = (path != "-").then(|| Path::new(&path));
+        let Some((mime_type, detected)) = crate::content_type::detect_file(file_path, &content)
+        else {
+            eprintln!("{path}: unsupported binary content");
+            return;
+        };
+        query.append_pair(
+            "mime_type",
+            match mime_type {
+                crate::store::MimeType::TextPlain => "text/plain",
+                crate::store::MimeType::ImagePng => "image/png",
+            },
+        );
+        if let Some(content_type) = content_type.as_deref().or(detected) {
+            query.append_pair("content_type", content_type);
+        }
+        content
+    };
+
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(format!("/add?{}", query.finish()))
+        .body(Full::new(Bytes::from(content)))
+        .unwrap();
+
+    let mut res = request_sender.send_request(request).await.unwrap();
+    let status = res.status();
+
+    let mut body_bytes = Vec::new();
+    while let Some(next) = res.frame().await {
+        let frame = next.expect("Error reading frame");
+        if let Some(chunk) = frame.data_ref() {
+            body_bytes.extend_from_slice(chunk);
+        }
+    }
+    let body_str = String::from_utf8_lossy(&body_bytes);
+
+    if status != StatusCode::OK {
+        eprintln!("Request failed with status: {status} {body_str}");
+        return;
+    }
+    println!("{body_str}");
+}
+
 async fn handle_list_command(
     request_sender: &mut hyper::client::conn::http1::SendRequest<
         http_body_util::Empty<bytes::Bytes>,
@@ -223,6 +544,7 @@ async fn handle_search_command(
     query: Option<String>,
     limit: Option<usize>,
     rebuild: bool,
+    status: bool,
     request_sender: &mut hyper::client::conn::http1::SendRequest<
         http_body_util::Empty<bytes::Bytes>,
     >,
@@ -232,11 +554,16 @@ async fn handle_search_command(
     use hyper::{Method, Request, StatusCode};
     use std::io::Write;
 
-    // Handle rebuild request separately
-    if rebuild {
+    // Handle rebuild and status requests separately
+    if rebuild || status {
+        let (method, uri) = if rebuild {
+            (Method::POST, "/search/rebuild")
+        } else {
+            (Method::GET, "/search/status")
+        };
         let request = Request::builder()
-            .method(Method::POST)
-            .uri("/search/rebuild")
+            .method(method)
+            .uri(uri)
             .body(Empty::<Bytes>::new())
             .unwrap();
 
@@ -247,7 +574,7 @@ async fn handle_search_command(
             return;
         }
 
-        // Output the rebuild response
+        // Output the response
         while let Some(next) = res.frame().await {
             let frame = next.expect("Error reading frame");
             if let Some(chunk) = frame.data_ref() {
@@ -442,6 +769,386 @@ async fn handle_cas_command(
     }
 }
 
+async fn handle_directory_command(
+    stack_id: String,
+    path: Option<PathBuf>,
+    watch: bool,
+    request_sender: &mut hyper::client::conn::http1::SendRequest<
+        http_body_util::Empty<bytes::Bytes>,
+    >,
+) {
+    use bytes::Bytes;
+    use http_body_util::Empty;
+    use hyper::{Method, Request, StatusCode};
+
+    let mut query = url::form_urlencoded::Serializer::new(String::new());
+    if let Some(path) = path {
+        // resolve relative paths against the caller's working directory
+        let path = match std::fs::canonicalize(&path) {
+            Ok(path) => path,
+            Err(e) => {
+                eprintln!("{}: {e}", path.display());
+                return;
+            }
+        };
+        query.append_pair("path", &path.to_string_lossy());
+    }
+    if watch {
+        query.append_pair("watch", "");
+    }
+
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(format!("/stacks/{stack_id}/directory?{}", query.finish()))
+        .body(Empty::<Bytes>::new())
+        .unwrap();
+
+    let mut res = request_sender.send_request(request).await.unwrap();
+    let status = res.status();
+
+    let mut body_bytes = Vec::new();
+    while let Some(next) = res.frame().await {
+        let frame = next.expect("Error reading frame");
+        if let Some(chunk) = frame.data_ref() {
+            body_bytes.extend_from_slice(chunk);
+        }
+    }
+    let body_str = String::from_utf8_lossy(&body_bytes);
+
+    if status != StatusCode::OK {
+        eprintln!("Request failed with status: {status} {body_str}");
+        return;
+    }
+    println!("{body_str}");
+}
+
+async fn handle_rename_command(
+    stack_id: String,
+    params: &[(&str, Option<String>)],
+    request_sender: &mut hyper::client::conn::http1::SendRequest<
+        http_body_util::Empty<bytes::Bytes>,
+    >,
+) {
+    use bytes::Bytes;
+    use http_body_util::Empty;
+    use hyper::{Method, Request, StatusCode};
+
+    let mut query = url::form_urlencoded::Serializer::new(String::new());
+    for (key, value) in params {
+        if let Some(value) = value {
+            query.append_pair(key, value);
+        }
+    }
+
+    let request = Request::builder()
+        .method(Method::PATCH)
+        .uri(format!("/stacks/{stack_id}?{}", query.finish()))
+        .body(Empty::<Bytes>::new())
+        .unwrap();
+
+    let mut res = request_sender.send_request(request).await.unwrap();
+    let status = res.status();
+
+    let mut body_bytes = Vec::new();
+    while let Some(next) = res.frame().await {
+        let frame = next.expect("Error reading frame");
+        if let Some(chunk) = frame.data_ref() {
+            body_bytes.extend_from_slice(chunk);
+        }
+    }
+    let body_str = String::from_utf8_lossy(&body_bytes);
+
+    if status != StatusCode::OK {
+        eprintln!("Request failed with status: {status} {body_str}");
+        return;
+    }
+    println!("{body_str}");
+}
+
+async fn handle_tag_command(
+    id: String,
+    tags: Vec<String>,
+    request_sender: &mut hyper::client::conn::http1::SendRequest<
+        http_body_util::Empty<bytes::Bytes>,
+    >,
+) {
+    use bytes::Bytes;
+    use http_body_util::Empty;
+    use hyper::{Method, Request, StatusCode};
+
+    let mut add = Vec::new();
+    let mut remove = Vec::new();
+    for tag in &tags {
+        if let Some(tag) = tag.strip_prefix('-') {
+            remove.push(tag);
+        } else {
+            add.push(tag.strip_prefix('+').unwrap_or(tag));
+        }
+    }
+
+    let mut query = url::form_urlencoded::Serializer::new(String::new());
+    if !add.is_empty() {
+        query.append_pair("add", &add.join(","));
+    }
+    if !remove.is_empty() {
+        query.append_pair("remove", &remove.join(","));
+    }
+
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(format!("/{id}/tags?{}", query.finish()))
+        .body(Empty::<Bytes>::new())
+        .unwrap();
+
+    let mut res = request_sender.send_request(request).await.unwrap();
+    let status = res.status();
+
+    let mut body_bytes = Vec::new();
+    while let Some(next) = res.frame().await {
+        let frame = next.expect("Error reading frame");
+        if let Some(chunk) = frame.data_ref() {
+            body_bytes.extend_from_slice(chunk);
+        }
+    }
+    let body_str = String::from_utf8_lossy(&body_bytes);
+
+    if status != StatusCode::OK {
+        eprintln!("Request failed with status: {status} {body_str}");
+        return;
+    }
+    println!("{body_str}");
+}
+
+async fn handle_tree_command(
+    stack_id: Option<String>,
+    request_sender: &mut hyper::client::conn::http1::SendRequest<
+        http_body_util::Empty<bytes::Bytes>,
+    >,
+) {
+    use bytes::Bytes;
+    use http_body_util::Empty;
+    use hyper::{Method, Request, StatusCode};
+
+    let uri = match stack_id {
+        Some(stack_id) => format!("/stacks/{stack_id}/tree"),
+        None => "/stacks/tree".to_string(),
+    };
+
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(uri)
+        .body(Empty::<Bytes>::new())
+        .unwrap();
+
+    let mut res = request_sender.send_request(request).await.unwrap();
+    let status = res.status();
+
+    let mut body_bytes = Vec::new();
+    while let Some(next) = res.frame().await {
+        let frame = next.expect("Error reading frame");
+        if let Some(chunk) = frame.data_ref() {
+            body_bytes.extend_from_slice(chunk);
+        }
+    }
+    let body_str = String::from_utf8_lossy(&body_bytes);
+
+    if status != StatusCode::OK {
+        eprintln!("Request failed with status: {status} {body_str}");
+        return;
+    }
+    println!("{body_str}");
+}
+
+async fn handle_fsck_command(
+    repair: bool,
+    request_sender: &mut hyper::client::conn::http1::SendRequest<
+        http_body_util::Empty<bytes::Bytes>,
+    >,
+) {
+    use bytes::Bytes;
+    use http_body_util::Empty;
+    use hyper::{Method, Request, StatusCode};
+
+    let uri = if repair {
+        "/admin/fsck?repair"
+    } else {
+        "/admin/fsck"
+    };
+
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(uri)
+        .body(Empty::<Bytes>::new())
+        .unwrap();
+
+    let mut res = request_sender.send_request(request).await.unwrap();
+    let status = res.status();
+
+    let mut body_bytes = Vec::new();
+    while let Some(next) = res.frame().await {
+        let frame = next.expect("Error reading frame");
+        if let Some(chunk) = frame.data_ref() {
+            body_bytes.extend_from_slice(chunk);
+        }
+    }
+    let body_str = String::from_utf8_lossy(&body_bytes);
+
+    if status != StatusCode::OK {
+        eprintln!("Request failed with status: {status} {body_str}");
+        return;
+    }
+    println!("{body_str}");
+}
+
+async fn handle_compact_command(
+    dry_run: bool,
+    keep_days: Option<u64>,
+    request_sender: &mut hyper::client::conn::http1::SendRequest<
+        http_body_util::Empty<bytes::Bytes>,
+    >,
+) {
+    use bytes::Bytes;
+    use http_body_util::Empty;
+    use hyper::{Method, Request, StatusCode};
+
+    let mut query = url::form_urlencoded::Serializer::new(String::new());
+    if dry_run {
+        query.append_pair("dry-run", "");
+    }
+    if let Some(days) = keep_days {
+        query.append_pair("keep-days", &days.to_string());
+    }
+
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(format!("/admin/compact?{}", query.finish()))
+        .body(Empty::<Bytes>::new())
+        .unwrap();
+
+    let mut res = request_sender.send_request(request).await.unwrap();
+    let status = res.status();
+
+    let mut body_bytes = Vec::new();
+    while let Some(next) = res.frame().await {
+        let frame = next.expect("Error reading frame");
+        if let Some(chunk) = frame.data_ref() {
+            body_bytes.extend_from_slice(chunk);
+        }
+    }
+    let body_str = String::from_utf8_lossy(&body_bytes);
+
+    if status != StatusCode::OK {
+        eprintln!("Request failed with status: {status} {body_str}");
+        return;
+    }
+    println!("{body_str}");
+}
+
+async fn handle_sync_command(
+    request_sender: &mut hyper::client::conn::http1::SendRequest<
+        http_body_util::Empty<bytes::Bytes>,
+    >,
+) {
+    use bytes::Bytes;
+    use http_body_util::Empty;
+    use hyper::{Method, Request, StatusCode};
+
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/sync")
+        .body(Empty::<Bytes>::new())
+        .unwrap();
+
+    let mut res = request_sender.send_request(request).await.unwrap();
+    let status = res.status();
+
+    let mut body_bytes = Vec::new();
+    while let Some(next) = res.frame().await {
+        let frame = next.expect("Error reading frame");
+        if let Some(chunk) = frame.data_ref() {
+            body_bytes.extend_from_slice(chunk);
+        }
+    }
+    let body_str = String::from_utf8_lossy(&body_bytes);
+
+    if status != StatusCode::OK {
+        eprintln!("Request failed with status: {status} {body_str}");
+        return;
+    }
+    println!("{body_str}");
+}
+
+async fn handle_schedule_command(
+    command: ScheduleCommand,
+    request_sender: &mut hyper::client::conn::http1::SendRequest<
+        http_body_util::Empty<bytes::Bytes>,
+    >,
+) {
+    use bytes::Bytes;
+    use http_body_util::Empty;
+    use hyper::{Method, Request, StatusCode};
+
+    let (method, uri) = match &command {
+        ScheduleCommand::Add {
+            stack_id,
+            every,
+            cron,
+            command,
+        } => {
+            let mut query = url::form_urlencoded::Serializer::new(String::new());
+            query.append_pair("stack_id", stack_id);
+            query.append_pair("command", command);
+            if let Some(every) = every {
+                query.append_pair("every", every);
+            }
+            if let Some(cron) = cron {
+                query.append_pair("cron", cron);
+            }
+            (Method::POST, format!("/schedules?{}", query.finish()))
+        }
+        ScheduleCommand::List => (Method::GET, "/schedules".to_string()),
+        ScheduleCommand::Rm { id } => (Method::DELETE, format!("/schedules/{id}")),
+    };
+
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .body(Empty::<Bytes>::new())
+        .unwrap();
+
+    let mut res = request_sender.send_request(request).await.unwrap();
+    let status = res.status();
+
+    let mut body_bytes = Vec::new();
+    while let Some(next) = res.frame().await {
+        let frame = next.expect("Error reading frame");
+        if let Some(chunk) = frame.data_ref() {
+            body_bytes.extend_from_slice(chunk);
+        }
+    }
+    let body_str = String::from_utf8_lossy(&body_bytes);
+
+    if status != StatusCode::OK {
+        eprintln!("Request failed with status: {status} {body_str}");
+        return;
+    }
+
+    match command {
+        ScheduleCommand::List => match serde_json::from_str::<Vec<serde_json::Value>>(&body_str) {
+            Ok(schedules) => {
+                for schedule in schedules {
+                    println!("{}", serde_json::to_string(&schedule).unwrap());
+                }
+            }
+            Err(e) => {
+                eprintln!("Failed to parse JSON response: {e}");
+                eprintln!("Raw response: {body_str}");
+            }
+        },
+        _ => println!("{body_str}"),
+    }
+}
+
 async fn handle_legacy_request(
     args: Args,
     request_sender: &mut hyper::client::conn::http1::SendRequest<
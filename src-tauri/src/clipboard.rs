@@ -7,61 +7,139 @@ use tracing::info;
 
 use crate::state;
 use crate::state::SharedState;
-use crate::store::MimeType;
+use crate::store::{CaptureRule, MimeType, Packet};
 use crate::util;
 
+// flavors stored as alternates of a plain text clip
+const RICH_TEXT_TYPES: [&str; 2] = ["public.html", "public.rtf"];
+
 #[tracing::instrument(skip_all)]
 fn handle_clipboard_update(state: &mut state::State, line: &str, app: &tauri::AppHandle) {
+    if let Some(packet) = capture(state, line) {
+        // if Stacks isn't active, focus the new clip
+        if !state.ui.is_visible {
+            let focus = state.view.get_focus_for_id(&packet.id);
+            state.ui.select(focus);
+        }
+
+        app.emit_all("refresh-items", true).unwrap();
+    }
+}
+
+/// The first of the capture rules from settings which applies to clips copied from `source`.
+fn capture_rule(state: &state::State, source: Option<&str>) -> Option<CaptureRule> {
+    let source = source?;
+    let rules = state.store.settings_get()?.capture_rules?;
+    rules.into_iter().find(|rule| rule.matches(source))
+}
+
+/// Adds the clip from a pasteboard update, as reported by `x-macos-pasteboard`, to the store
+/// according to the capture rules. Returns the new clip's packet, or None when nothing was
+/// captured.
+fn capture(state: &mut state::State, line: &str) -> Option<Packet> {
     let clipped: Value = serde_json::from_str(line).unwrap();
 
     let change_num = clipped["change"].as_i64().unwrap();
     if let Some(skip_change_num) = state.skip_change_num {
         if change_num == skip_change_num {
             info!("CLIPBOARD UPDATE: {} SKIP", &change_num);
-            return;
+            return None;
         }
     }
 
     let types = clipped["types"].as_object().unwrap();
-    let source = clipped["source"].as_str();
-    let _source = source.map(|s| s.to_string());
+    let source = clipped["source"].as_str().map(|s| s.to_string());
+
+    let rule = capture_rule(state, source.as_deref()).unwrap_or_default();
+    if rule.ignore {
+        info!("CLIPBOARD UPDATE: {} IGNORE {:?}", &change_num, &source);
+        return None;
+    }
+
+    // skip blank text, unless there's a file or an image on the pasteboard too
+    if !["public.file-url", "public.png", "public.tiff"]
+        .iter()
+        .any(|pasteboard_type| types.contains_key(*pasteboard_type))
+    {
+        if let Some(content) = types
+            .get("public.utf8-plain-text")
+            .and_then(|content| content.as_str())
+        {
+            if let Ok(str_ref) = std::str::from_utf8(&util::b64decode(content)) {
+                if str_ref.trim().is_empty() {
+                    return None;
+                }
+            }
+        }
+    }
+
+    let file = types
+        .get("public.file-url")
+        .and_then(|url| url.as_str())
+        .and_then(file_url_to_path);
+    if file.is_none()
+        && !["public.png", "public.tiff", "public.utf8-plain-text"]
+            .iter()
+            .any(|pasteboard_type| types.contains_key(*pasteboard_type))
+    {
+        return None;
+    }
 
-    let curr_stack = state.get_curr_stack();
+    // the stack is only looked up once there's a clip for it, as looking it up can create it
+    let stack_id = match &rule.stack {
+        Some(name) => state.get_stack_by_name(name),
+        None => state.get_curr_stack(),
+    };
 
-    let packet = if types.contains_key("public.png") {
+    // Finder also puts the file's icon and name on the pasteboard, so check for files first
+    let packet = if let Some(path) = file {
+        state.store.add_file_ref(&path, stack_id)
+    } else if types.contains_key("public.png") {
         let content = util::b64decode(types["public.png"].as_str().unwrap());
-        Some(state.store.add(&content, MimeType::ImagePng, curr_stack))
+        state
+            .store
+            .add_clip(&content, MimeType::ImagePng, stack_id, source, &[])
     } else if types.contains_key("public.tiff") {
         let content = util::b64decode(types["public.tiff"].as_str().unwrap());
         let png_content = tiff_to_png(&content).unwrap();
-        Some(
+        state
+            .store
+            .add_clip(&png_content, MimeType::ImagePng, stack_id, source, &[])
+    } else {
+        let content = util::b64decode(types["public.utf8-plain-text"].as_str().unwrap());
+        // keep the rich text flavors so copying the clip back preserves its formatting
+        let alternates: Vec<(String, Vec<u8>)> = RICH_TEXT_TYPES
+            .iter()
+            .filter_map(|pasteboard_type| {
+                let content = types.get(*pasteboard_type)?.as_str()?;
+                Some((pasteboard_type.to_string(), util::b64decode(content)))
+            })
+            .collect();
+        let packet =
             state
                 .store
-                .add(&png_content, MimeType::ImagePng, curr_stack),
-        )
-    } else if types.contains_key("public.utf8-plain-text") {
-        let content = util::b64decode(types["public.utf8-plain-text"].as_str().unwrap());
-        if let Ok(str_ref) = std::str::from_utf8(&content) {
-            if str_ref.trim().is_empty() {
-                return;
-            }
+                .add_clip(&content, MimeType::TextPlain, stack_id, source, &alternates);
+        if let Some(content_type) = rule.content_type {
+            state.merge(&packet);
+            let update = state
+                .store
+                .update_content_type(packet.hash.clone().unwrap(), content_type);
+            state.merge(&update);
+            return Some(packet);
         }
-        Some(state.store.add(&content, MimeType::TextPlain, curr_stack))
-    } else {
-        None
+        packet
     };
 
-    if let Some(packet) = packet {
-        state.merge(&packet);
-
-        // if Stacks isn't active, focus the new clip
-        if !state.ui.is_visible {
-            let focus = state.view.get_focus_for_id(&packet.id);
-            state.ui.select(focus);
-        }
+    state.merge(&packet);
+    Some(packet)
+}
 
-        app.emit_all("refresh-items", true).unwrap();
-    }
+fn file_url_to_path(b64: &str) -> Option<std::path::PathBuf> {
+    let url = String::from_utf8(util::b64decode(b64)).ok()?;
+    url::Url::parse(url.trim_end_matches('\0'))
+        .ok()?
+        .to_file_path()
+        .ok()
 }
 
 pub fn start(app: tauri::AppHandle, state: &SharedState) {
@@ -101,3 +179,118 @@ pub fn tiff_to_png(tiff_data: &[u8]) -> Result<Vec<u8>, ImageError> {
 
     Ok(png_data)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::store::{Settings, StackLockStatus};
+
+    fn event(change: i64, source: &str, text: &str) -> String {
+        serde_json::json!({
+            "change": change,
+            "source": source,
+            "types": {
+                "public.utf8-plain-text": util::b64encode(&text.as_bytes().to_vec()),
+            },
+        })
+        .to_string()
+    }
+
+    fn stack_name(state: &state::State, id: &scru128::Scru128Id) -> String {
+        let stack = state.view.items.get(id).unwrap();
+        String::from_utf8(state.store.get_content(&stack.hash).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_capture_rules() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let (sender, _receiver) = std::sync::mpsc::channel();
+        let mut state = state::State::new(path, sender);
+
+        let research = state
+            .store
+            .add_stack(b"Research", StackLockStatus::Unlocked);
+        state.merge(&research);
+
+        state.store.settings_save(Settings {
+            capture_rules: Some(vec![
+                CaptureRule {
+                    source: "1password".to_string(),
+                    ignore: true,
+                    ..Default::default()
+                },
+                CaptureRule {
+                    source: "Terminal".to_string(),
+                    stack: Some("Terminal".to_string()),
+                    content_type: Some("Shell".to_string()),
+                    ..Default::default()
+                },
+                CaptureRule {
+                    source: "safari".to_string(),
+                    stack: Some("Research".to_string()),
+                    ..Default::default()
+                },
+                CaptureRule {
+                    source: "Preview".to_string(),
+                    stack: Some("Images".to_string()),
+                    ..Default::default()
+                },
+            ]),
+            ..Default::default()
+        });
+
+        assert_eq!(
+            capture(&mut state, &event(1, "1Password 7", "hunter2")),
+            None
+        );
+
+        let packet = capture(&mut state, &event(2, "Terminal", "ls -la")).unwrap();
+        let item = state.view.items.get(&packet.id).unwrap();
+        assert_eq!(stack_name(&state, &item.stack_id.unwrap()), "Terminal");
+        let meta = state.store.get_content_meta(&item.hash).unwrap();
+        assert_eq!(meta.content_type, "Shell");
+        assert_eq!(meta.source, Some("Terminal".to_string()));
+
+        // routed clips reuse the existing stack
+        let packet = capture(&mut state, &event(3, "Safari", "https://example.com")).unwrap();
+        let item = state.view.items.get(&packet.id).unwrap();
+        assert_eq!(item.stack_id, Some(research.id));
+
+        let packet = capture(&mut state, &event(4, "Terminal", "pwd")).unwrap();
+        let item = state.view.items.get(&packet.id).unwrap();
+        assert_eq!(stack_name(&state, &item.stack_id.unwrap()), "Terminal");
+        let stacks = state
+            .view
+            .root()
+            .iter()
+            .filter(|stack| stack_name(&state, &stack.id) == "Terminal")
+            .count();
+        assert_eq!(stacks, 1);
+
+        // everything else goes to the current stack
+        let packet = capture(&mut state, &event(5, "TextEdit", "notes")).unwrap();
+        let item = state.view.items.get(&packet.id).unwrap();
+        let stack_id = item.stack_id.unwrap();
+        assert_ne!(stack_id, research.id);
+        assert_eq!(stack_id, state.get_curr_stack());
+
+        // skipped changes and blank text aren't captured
+        state.skip_change_num = Some(6);
+        assert_eq!(capture(&mut state, &event(6, "TextEdit", "skipped")), None);
+        assert_eq!(capture(&mut state, &event(7, "TextEdit", "  ")), None);
+
+        // nor are types which aren't kept, and the stack they'd be routed to isn't created
+        let jpeg = serde_json::json!({
+            "change": 8,
+            "source": "Preview",
+            "types": {"public.jpeg": util::b64encode(&b"JFIF".to_vec())},
+        });
+        assert_eq!(capture(&mut state, &jpeg.to_string()), None);
+        let root = state.view.root();
+        assert!(!root
+            .iter()
+            .any(|stack| stack_name(&state, &stack.id) == "Images"));
+    }
+}
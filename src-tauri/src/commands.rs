@@ -5,12 +5,16 @@ use tauri::Manager;
 
 use scru128::Scru128Id;
 
+use crate::ansi;
 use crate::content_type::process_command;
+use crate::exec;
+use crate::http;
 use crate::spotlight;
 use crate::spotlight::Shortcut;
 use crate::state::SharedState;
 use crate::store::{
-    InProgressStream, MimeType, Movement, Settings, StackLockStatus, StackSortOrder,
+    InProgressStream, MimeType, Movement, PinStatus, Settings, StackDirectory, StackLockStatus,
+    StackMeta, StackSortOrder,
 };
 use crate::ui::{with_meta, Item as UIItem, Nav};
 
@@ -40,7 +44,8 @@ pub async fn store_pipe_stack_to_shell(
     exec_id: u32,
     stack_id: scru128::Scru128Id,
     command: String,
-) -> Result<(), ()> {
+    pty: Option<bool>,
+) -> Result<(), String> {
     let item_hashes = state.with_lock(|state| {
         state
             .view
@@ -72,33 +77,21 @@ pub async fn store_pipe_stack_to_shell(
 
     let (cooked_command, content_type) = process_command(&command);
 
-    let home_dir = dirs::home_dir().expect("Could not fetch home directory");
-    let shell = match std::env::var("SHELL") {
-        Ok(val) => val,
-        Err(_) => String::from("/bin/sh"), // default to sh if no SHELL variable is set
-    };
-
-    let rc_file = match shell.as_str() {
-        "/bin/bash" => ".bashrc",
-        "/bin/zsh" => ".zshrc",
-        _ => "", // if the shell is neither bash nor zsh, don't source an rc file
+    let (settings, socket) = state.with_lock(|state| {
+        (
+            state.shell_settings(Some(stack_id)),
+            http::socket_path(&state.db_path),
+        )
+    });
+    let env = exec::Env {
+        item_id: None,
+        stack_id: Some(stack_id),
+        hash: None,
+        socket,
     };
-
-    let rc_path = home_dir.join(rc_file);
-    let rc_command = format!(
-        "source {}\n{}",
-        rc_path.to_str().unwrap_or(""),
-        cooked_command
-    );
-
-    let mut cmd = tokio::process::Command::new(shell)
-        .arg("-c")
-        .arg(rc_command)
-        .stdin(std::process::Stdio::piped())
-        .stdout(std::process::Stdio::piped())
-        .stderr(std::process::Stdio::piped())
-        .spawn()
-        .unwrap();
+    let shell_cmd = exec::shell_command(&settings, &cooked_command, &env);
+    let (mut cmd, mut stdout) =
+        exec::spawn(shell_cmd, pty.unwrap_or(false)).map_err(|e| e.to_string())?;
 
     let mut stdin = cmd.stdin.take().ok_or("Failed to open stdin").unwrap();
     let json_list_string = serde_json::to_string(&json_list).unwrap();
@@ -106,7 +99,6 @@ pub async fn store_pipe_stack_to_shell(
         stdin.write_all(json_list_string.as_bytes()).await.unwrap();
     });
 
-    let mut stdout = cmd.stdout.take().unwrap();
     let read_stdout = {
         let state = state.inner().clone();
         let app = app.clone();
@@ -124,7 +116,13 @@ pub async fn store_pipe_stack_to_shell(
             let (mime_type, content_type_2) = match m.map(|m| m.mime_type()) {
                 None => (
                     MimeType::TextPlain,
-                    content_type.clone().unwrap_or("Text".to_string()),
+                    content_type.clone().unwrap_or_else(|| {
+                        if ansi::has_sgr(&buffer[..size]) {
+                            "ANSI".to_string()
+                        } else {
+                            "Text".to_string()
+                        }
+                    }),
                 ),
                 Some("image/png") => (MimeType::ImagePng, "Image".to_string()),
                 Some("text/html") => (MimeType::TextPlain, "HTML".to_string()),
@@ -169,14 +167,14 @@ pub async fn store_pipe_stack_to_shell(
                         streamer.append(&buffer[..size]);
 
                         if mime_type == MimeType::TextPlain {
-                            let preview = state.with_lock(|state| {
-                                state.ui.generate_preview(
+                            let preview = state
+                                .with_lock(|state| state.ui.previewer())
+                                .generate_preview(
                                     &Some(streamer.content.clone()),
                                     &streamer.content_meta.mime_type,
                                     &streamer.content_meta.content_type,
                                     true,
-                                )
-                            });
+                                );
 
                             let content = String::from_utf8_lossy(&streamer.content);
                             let content = Content {
@@ -187,6 +185,7 @@ pub async fn store_pipe_stack_to_shell(
                                 words: content.split_whitespace().count(),
                                 chars: content.chars().count(),
                                 preview,
+                                source: None,
                             };
 
                             app.emit_all("streaming", (streamer.packet.id, content))
@@ -207,6 +206,12 @@ pub async fn store_pipe_stack_to_shell(
 
                 // TODO: rework this as common a pipeline
                 if mime_type == MimeType::TextPlain {
+                    // escape sequences may only show up after the first chunk
+                    let content_type = content_type.or_else(|| {
+                        (streamer.content_meta.content_type == "Text"
+                            && ansi::has_sgr(&streamer.content))
+                        .then(|| "ANSI".to_string())
+                    });
                     if let Some(content_type) = content_type {
                         let hash = packet.hash.clone().unwrap();
                         let packet = state.store.update_content_type(hash.clone(), content_type);
@@ -297,51 +302,38 @@ pub async fn store_pipe_to_command(
     exec_id: u32,
     source_id: scru128::Scru128Id,
     command: String,
-) -> Result<(), ()> {
-    let (cache_path, hash, stack_id) = state.with_lock(|state| {
-        let cache_path = state.store.cache_path.clone();
+    pty: Option<bool>,
+) -> Result<(), String> {
+    let (blobs, hash, stack_id) = state.with_lock(|state| {
+        let blobs = state.store.blobs.clone();
         let item = state.view.items.get(&source_id).unwrap();
-        (cache_path, item.hash.clone(), item.stack_id)
+        (blobs, item.hash.clone(), item.stack_id)
     });
 
     let (cooked_command, content_type) = process_command(&command);
 
-    let home_dir = dirs::home_dir().expect("Could not fetch home directory");
-    let shell = match std::env::var("SHELL") {
-        Ok(val) => val,
-        Err(_) => String::from("/bin/sh"), // default to sh if no SHELL variable is set
-    };
-
-    let rc_file = match shell.as_str() {
-        "/bin/bash" => ".bashrc",
-        "/bin/zsh" => ".zshrc",
-        _ => "", // if the shell is neither bash nor zsh, don't source an rc file
+    let (settings, socket) = state.with_lock(|state| {
+        (
+            state.shell_settings(stack_id),
+            http::socket_path(&state.db_path),
+        )
+    });
+    let env = exec::Env {
+        item_id: Some(source_id),
+        stack_id,
+        hash: Some(hash.clone()),
+        socket,
     };
-
-    let rc_path = home_dir.join(rc_file);
-    let rc_command = format!(
-        "source {}\n{}",
-        rc_path.to_str().unwrap_or(""),
-        cooked_command
-    );
-
-    let mut cmd = tokio::process::Command::new(shell)
-        .arg("-c")
-        .arg(rc_command)
-        .stdin(std::process::Stdio::piped())
-        .stdout(std::process::Stdio::piped())
-        .stderr(std::process::Stdio::piped())
-        .spawn()
-        .unwrap();
+    let shell_cmd = exec::shell_command(&settings, &cooked_command, &env);
+    let (mut cmd, mut stdout) =
+        exec::spawn(shell_cmd, pty.unwrap_or(false)).map_err(|e| e.to_string())?;
 
     let mut stdin = cmd.stdin.take().ok_or("Failed to open stdin").unwrap();
-    let mut reader = cacache::Reader::open_hash(cache_path, hash).await.unwrap();
+    let content = blobs.read(&hash).unwrap();
     tokio::spawn(async move {
-        tokio::io::copy(&mut reader, &mut stdin).await.unwrap();
+        stdin.write_all(&content).await.unwrap();
     });
 
-    let mut stdout = cmd.stdout.take().unwrap();
-
     let read_stdout = {
         let state = state.inner().clone();
         let app = app.clone();
@@ -359,7 +351,13 @@ pub async fn store_pipe_to_command(
             let (mime_type, content_type_2) = match m.map(|m| m.mime_type()) {
                 None => (
                     MimeType::TextPlain,
-                    content_type.clone().unwrap_or("Text".to_string()),
+                    content_type.clone().unwrap_or_else(|| {
+                        if ansi::has_sgr(&buffer[..size]) {
+                            "ANSI".to_string()
+                        } else {
+                            "Text".to_string()
+                        }
+                    }),
                 ),
                 Some("image/png") => (MimeType::ImagePng, "Image".to_string()),
                 Some("text/html") => (MimeType::TextPlain, "HTML".to_string()),
@@ -404,14 +402,14 @@ pub async fn store_pipe_to_command(
                         streamer.append(&buffer[..size]);
 
                         if mime_type == MimeType::TextPlain {
-                            let preview = state.with_lock(|state| {
-                                state.ui.generate_preview(
+                            let preview = state
+                                .with_lock(|state| state.ui.previewer())
+                                .generate_preview(
                                     &Some(streamer.content.clone()),
                                     &streamer.content_meta.mime_type,
                                     &streamer.content_meta.content_type,
                                     true,
-                                )
-                            });
+                                );
 
                             let content = String::from_utf8_lossy(&streamer.content);
                             let content = Content {
@@ -422,6 +420,7 @@ pub async fn store_pipe_to_command(
                                 words: content.split_whitespace().count(),
                                 chars: content.chars().count(),
                                 preview,
+                                source: None,
                             };
 
                             app.emit_all("streaming", (streamer.packet.id, content))
@@ -442,6 +441,12 @@ pub async fn store_pipe_to_command(
 
                 // TODO: rework this as common a pipeline
                 if mime_type == MimeType::TextPlain {
+                    // escape sequences may only show up after the first chunk
+                    let content_type = content_type.or_else(|| {
+                        (streamer.content_meta.content_type == "Text"
+                            && ansi::has_sgr(&streamer.content))
+                        .then(|| "ANSI".to_string())
+                    });
                     if let Some(content_type) = content_type {
                         let hash = packet.hash.clone().unwrap();
                         let packet = state.store.update_content_type(hash.clone(), content_type);
@@ -557,41 +562,65 @@ pub struct Content {
     pub words: usize,
     pub chars: usize,
     pub preview: String,
+    // the application the content was copied from
+    pub source: Option<String>,
 }
 
 #[tauri::command]
 #[tracing::instrument(skip(state), fields(%hash = truncate_hash(&hash, 8)))]
-pub fn store_get_content(state: tauri::State<SharedState>, hash: ssri::Integrity) -> Content {
-    state.with_lock(|state| {
-        let content = state.store.get_content(&hash);
+pub fn store_get_content(
+    state: tauri::State<SharedState>,
+    hash: ssri::Integrity,
+    snapshot: Option<ssri::Integrity>,
+) -> Content {
+    // content is read and rendered after letting go of the state, as large content can be slow
+    let (meta, snapshot_meta, blobs, previewer) = state.with_lock(|state| {
         let meta = state.store.get_content_meta(&hash).unwrap();
+        // file references preview the snapshot of the file's content
+        let snapshot_meta = snapshot
+            .as_ref()
+            .or(meta.snapshot.as_ref())
+            .and_then(|snapshot| state.store.get_content_meta(snapshot));
+        (
+            meta,
+            snapshot_meta,
+            state.store.blobs.clone(),
+            state.ui.previewer(),
+        )
+    });
+    let content = blobs.read(&hash);
+
+    let (words, chars) = match (&meta.mime_type, &content) {
+        (MimeType::TextPlain, Some(bytes)) => {
+            let str_slice = std::str::from_utf8(bytes).expect("Invalid UTF-8");
+            (
+                str_slice.split_whitespace().count(),
+                str_slice.chars().count(),
+            )
+        }
+        _ => (0, 0),
+    };
 
-        let (words, chars) = match (&meta.mime_type, &content) {
-            (MimeType::TextPlain, Some(bytes)) => {
-                let str_slice = std::str::from_utf8(bytes).expect("Invalid UTF-8");
-                (
-                    str_slice.split_whitespace().count(),
-                    str_slice.chars().count(),
-                )
-            }
-            _ => (0, 0),
-        };
+    let preview = match snapshot_meta {
+        Some(snapshot_meta) => previewer.generate_preview(
+            &blobs.read(&snapshot_meta.hash),
+            &snapshot_meta.mime_type,
+            &snapshot_meta.content_type,
+            false,
+        ),
+        None => previewer.generate_preview(&content, &meta.mime_type, &meta.content_type, false),
+    };
 
-        let preview =
-            state
-                .ui
-                .generate_preview(&content, &meta.mime_type, &meta.content_type, false);
-
-        Content {
-            mime_type: meta.mime_type,
-            content_type: meta.content_type,
-            terse: meta.terse,
-            tiktokens: meta.tiktokens,
-            words,
-            chars,
-            preview,
-        }
-    })
+    Content {
+        mime_type: meta.mime_type,
+        content_type: meta.content_type,
+        terse: meta.terse,
+        tiktokens: meta.tiktokens,
+        words,
+        chars,
+        preview,
+        source: meta.source,
+    }
 }
 
 #[tauri::command]
@@ -653,6 +682,33 @@ pub fn store_nav_select(state: tauri::State<SharedState>, focused_id: Scru128Id)
     })
 }
 
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_nav_toggle_selection(state: tauri::State<SharedState>, id: Scru128Id) -> Nav {
+    state.with_lock(|state| {
+        state.ui.toggle_selection(&id);
+        state.ui.render(&state.store)
+    })
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_nav_select_range(state: tauri::State<SharedState>, id: Scru128Id) -> Nav {
+    state.with_lock(|state| {
+        state.ui.select_range(&id);
+        state.ui.render(&state.store)
+    })
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_nav_clear_selection(state: tauri::State<SharedState>) -> Nav {
+    state.with_lock(|state| {
+        state.ui.clear_selection();
+        state.ui.render(&state.store)
+    })
+}
+
 #[tauri::command]
 #[tracing::instrument(skip(state))]
 pub fn store_nav_select_up(state: tauri::State<SharedState>) -> Nav {
@@ -712,25 +768,33 @@ use cocoa::foundation::NSString;
 use objc::{msg_send, sel, sel_impl};
 
 pub fn write_to_clipboard(mime_type: &str, data: &[u8]) -> Option<i64> {
-    unsafe {
-        let nsdata: *mut objc::runtime::Object = msg_send![objc::class!(NSData), alloc];
-        let nsdata: *mut objc::runtime::Object =
-            msg_send![nsdata, initWithBytes:data.as_ptr() length:data.len()];
+    write_all_to_clipboard(&[(mime_type, data)])
+}
 
+/// Replaces the clipboard's contents with several representations of the same clip, given as
+/// `(pasteboard type, data)`.
+pub fn write_all_to_clipboard(representations: &[(&str, &[u8])]) -> Option<i64> {
+    unsafe {
         let pasteboard: *mut objc::runtime::Object =
             msg_send![objc::class!(NSPasteboard), generalPasteboard];
 
-        let png_type = NSString::alloc(nil).init_str(mime_type);
-
         let i: i64 = msg_send![pasteboard, clearContents];
-        let success: bool = msg_send![pasteboard, setData: nsdata forType: png_type];
 
-        // After the data is set, release the nsdata object to prevent a memory leak.
-        let () = msg_send![nsdata, release];
-        let () = msg_send![png_type, release];
+        for (mime_type, data) in representations {
+            let nsdata: *mut objc::runtime::Object = msg_send![objc::class!(NSData), alloc];
+            let nsdata: *mut objc::runtime::Object =
+                msg_send![nsdata, initWithBytes:data.as_ptr() length:data.len()];
+            let pasteboard_type = NSString::alloc(nil).init_str(mime_type);
+
+            let success: bool = msg_send![pasteboard, setData: nsdata forType: pasteboard_type];
 
-        if !success {
-            return None;
+            // After the data is set, release the nsdata object to prevent a memory leak.
+            let () = msg_send![nsdata, release];
+            let () = msg_send![pasteboard_type, release];
+
+            if !success {
+                return None;
+            }
         }
         Some(i)
     }
@@ -752,7 +816,25 @@ pub fn store_copy_to_clipboard(
             };
             let content = state.store.get_content(&item.hash).unwrap();
 
-            let _change_num = write_to_clipboard(mime_type, &content);
+            let mut representations = vec![(mime_type.to_string(), content.clone())];
+            for alternate in &meta.alternates {
+                if let Some(content) = state.store.get_content(&alternate.hash) {
+                    representations.push((alternate.pasteboard_type.clone(), content));
+                }
+            }
+            // file references paste as the file itself in apps which accept files
+            if meta.content_type == "File" {
+                let path = String::from_utf8_lossy(&content).into_owned();
+                if let Ok(url) = url::Url::from_file_path(path) {
+                    representations.push(("public.file-url".to_string(), url.as_str().into()));
+                }
+            }
+
+            let representations: Vec<(&str, &[u8])> = representations
+                .iter()
+                .map(|(pasteboard_type, content)| (pasteboard_type.as_str(), content.as_slice()))
+                .collect();
+            let _change_num = write_all_to_clipboard(&representations);
             Some(())
         } else {
             None
@@ -760,6 +842,23 @@ pub fn store_copy_to_clipboard(
     })
 }
 
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_copy_selected_to_clipboard(state: tauri::State<SharedState>) -> Option<()> {
+    let (items, text) = state.with_lock(|state| {
+        let items = state.ui.selected_items();
+        let text = state.selected_text();
+        state.ui.clear_selection();
+        (items, text)
+    });
+    // a single clip is copied as is, with all of its representations
+    if let [item] = items.as_slice() {
+        return store_copy_to_clipboard(state, item.id);
+    }
+    let _change_num = write_all_to_clipboard(&[("public.utf8-plain-text", text?.as_bytes())]);
+    Some(())
+}
+
 #[tauri::command]
 #[tracing::instrument(skip(app, state))]
 pub fn store_new_note(
@@ -838,7 +937,7 @@ pub fn store_edit_note(
         }
 
         // if this isn't a stack, focus the updated clip
-        if source.stack_id.is_some() {
+        if !source.is_stack {
             let focus = state.view.get_focus_for_id(&source_id);
             state.ui.select(focus);
             state.skip_change_num =
@@ -884,6 +983,51 @@ pub fn store_set_content_type(
     app.emit_all("content", hash).unwrap();
 }
 
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_set_selected_content_type(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    content_type: String,
+) {
+    let content_type = if content_type == "Plain Text" {
+        "Text".to_string()
+    } else {
+        content_type
+    };
+    let hashes = state.with_lock(|state| state.set_selected_content_type(&content_type));
+    for hash in hashes {
+        app.emit_all("content", hash).unwrap();
+    }
+    app.emit_all("refresh-items", true).unwrap();
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_strip_ansi(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
+) {
+    state.with_lock(|state| {
+        // only clips can be forked
+        let item = state.view.items.get(&source_id).cloned();
+        if let Some(item) = item.filter(|item| !item.is_stack) {
+            if let Some(content) = state.store.get_content(&item.hash) {
+                let stripped = ansi::strip(&content);
+                let packet =
+                    state
+                        .store
+                        .fork(source_id, Some(&stripped), MimeType::TextPlain, None);
+                state.merge(&packet);
+                let focus = state.view.get_focus_for_id(&packet.id);
+                state.ui.select(focus);
+            }
+        }
+    });
+    app.emit_all("refresh-items", true).unwrap();
+}
+
 #[tauri::command]
 #[tracing::instrument(skip(app, state))]
 pub fn store_set_theme_mode(app: tauri::AppHandle, state: tauri::State<SharedState>, mode: String) {
@@ -901,21 +1045,27 @@ pub fn store_delete(
     id: scru128::Scru128Id,
 ) {
     state.with_lock(|state| {
+        if let Err(e) = state.can_delete(&id) {
+            tracing::warn!("Not deleting: {}", e);
+            return;
+        }
         let packet = state.store.delete(id);
         state.merge(&packet);
     });
     app.emit_all("refresh-items", true).unwrap();
 }
 
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_delete_selected(app: tauri::AppHandle, state: tauri::State<SharedState>) {
+    state.with_lock(|state| state.delete_selected());
+    app.emit_all("refresh-items", true).unwrap();
+}
+
 #[tauri::command]
 #[tracing::instrument(skip(app, state))]
 pub fn store_undo(app: tauri::AppHandle, state: tauri::State<SharedState>) {
-    state.with_lock(|state| {
-        if let Some(item) = state.view.undo.clone() {
-            state.store.remove_packet(&item.last_touched);
-            state.rescan(Some(item.id));
-        }
-    });
+    state.with_lock(|state| state.undo());
     app.emit_all("refresh-items", true).unwrap();
 }
 
@@ -962,13 +1112,36 @@ pub fn store_add_to_stack(
 
 #[tauri::command]
 #[tracing::instrument(skip(app, state))]
-pub fn store_new_stack(app: tauri::AppHandle, state: tauri::State<SharedState>, name: String) {
+pub fn store_add_selected_to_stack(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    stack_id: scru128::Scru128Id,
+) {
+    state.with_lock(|state| state.add_selected_to_stack(stack_id));
+    app.emit_all("refresh-items", true).unwrap();
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_new_stack(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    name: String,
+    parent_id: Option<scru128::Scru128Id>,
+) {
     state.with_lock(|state| {
-        let packet = state
-            .store
-            .add_stack(name.as_bytes(), StackLockStatus::Unlocked);
+        let parent_id =
+            parent_id.filter(|id| state.view.items.get(id).is_some_and(|item| item.is_stack));
+        let packet =
+            state
+                .store
+                .add_stack_to(name.as_bytes(), StackLockStatus::Unlocked, parent_id);
         state.merge(&packet);
-        state.ui.select(None); // focus first
+        match parent_id {
+            // focus the new stack, inside its parent
+            Some(_) => state.ui.select(state.view.get_focus_for_id(&packet.id)),
+            None => state.ui.select(None), // focus first
+        }
     });
     app.emit_all("refresh-items", true).unwrap();
 }
@@ -1044,6 +1217,18 @@ pub fn store_move_up(
     app.emit_all("refresh-items", true).unwrap();
 }
 
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_move(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    source_ids: Vec<scru128::Scru128Id>,
+    movement: Movement,
+) {
+    state.with_lock(|state| state.move_items(&source_ids, movement));
+    app.emit_all("refresh-items", true).unwrap();
+}
+
 #[tauri::command]
 #[tracing::instrument(skip(app, state))]
 pub fn store_mark_as_cross_stream(
@@ -1088,6 +1273,125 @@ pub fn store_stack_lock(
     app.emit_all("refresh-items", true).unwrap();
 }
 
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_stack_set_directory(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
+    path: Option<String>,
+    watch: bool,
+) {
+    state.with_lock(|state| {
+        let packet = state.store.update_stack_directory(
+            source_id,
+            StackDirectory {
+                path: path.unwrap_or_default(),
+                watch,
+            },
+        );
+        state.merge(&packet);
+    });
+    app.emit_all("refresh-items", true).unwrap();
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_stack_fork(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
+) {
+    state.with_lock(|state| {
+        if let Some(id) = state.fork_stack(source_id) {
+            state.ui.select(state.view.get_focus_for_id(&id));
+        }
+    });
+    app.emit_all("refresh-items", true).unwrap();
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_stack_merge(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
+    target_id: scru128::Scru128Id,
+) {
+    state.with_lock(|state| {
+        if state.merge_stack(source_id, target_id).is_some() {
+            state.ui.select(state.view.get_focus_for_id(&target_id));
+        }
+    });
+    app.emit_all("refresh-items", true).unwrap();
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_stack_split(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
+    name: String,
+) {
+    state.with_lock(|state| {
+        if state.split_stack(source_id, &name).is_some() {
+            state.ui.select(state.view.get_focus_for_id(&source_id));
+        }
+    });
+    app.emit_all("refresh-items", true).unwrap();
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_stack_rename(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
+    name: String,
+) {
+    state.with_lock(|state| {
+        let packet = state.store.rename_stack(source_id, name.trim());
+        state.merge(&packet);
+    });
+    app.emit_all("refresh-items", true).unwrap();
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_stack_set_meta(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
+    stack_meta: StackMeta,
+) {
+    state.with_lock(|state| {
+        let packet = state.store.update_stack_meta(source_id, stack_meta);
+        state.merge(&packet);
+    });
+    app.emit_all("refresh-items", true).unwrap();
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_set_pinned(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
+    pinned: bool,
+) {
+    state.with_lock(|state| {
+        let pin_status = if pinned {
+            PinStatus::Pinned
+        } else {
+            PinStatus::Unpinned
+        };
+        let packet = state.store.update_pin_status(source_id, pin_status);
+        state.merge(&packet);
+    });
+    app.emit_all("refresh-items", true).unwrap();
+}
+
 #[tauri::command]
 #[tracing::instrument(skip(app, state))]
 pub fn store_stack_unlock(
@@ -0,0 +1,182 @@
+use std::collections::HashSet;
+
+use scru128::Scru128Id;
+
+use crate::store::{
+    Movement, Packet, PacketType, PinStatus, StackLockStatus, StackMeta, StackSortOrder, Tags,
+};
+use crate::view::{Item, View};
+
+fn packet(id: Scru128Id, packet_type: PacketType, source_id: Option<Scru128Id>) -> Packet {
+    Packet {
+        id,
+        packet_type,
+        source_id,
+        hash: None,
+        stack_id: None,
+        ephemeral: false,
+        content_type: None,
+        movement: None,
+        lock_status: None,
+        sort_order: None,
+        cross_stream: false,
+        directory: None,
+        stack_meta: None,
+        tags: None,
+        pin_status: None,
+        snapshot: None,
+    }
+}
+
+enum Event<'a> {
+    Create(&'a Item),
+    Touch(&'a Item),
+}
+
+/// The fewest packets which build `view` from nothing. Items keep their ids, and the packet
+/// which last touched an item keeps its id too, so items sort as they did and show when they
+/// were last touched; the rest of their history is dropped. Packets which only set an item's
+/// details don't touch it, so they take their ids from `next_id`, which must follow every id in
+/// the view.
+pub fn packets(view: &View, mut next_id: impl FnMut() -> Scru128Id) -> Vec<Packet> {
+    // touching an item touches its stack too, so a stack which was last touched along with one
+    // of its children doesn't need touching itself
+    let touched_with_children: HashSet<(Scru128Id, Scru128Id)> = view
+        .items
+        .values()
+        .filter_map(|child| child.stack_id.map(|stack_id| (stack_id, child)))
+        .flat_map(|(stack_id, child)| [(stack_id, child.id), (stack_id, child.last_touched)])
+        .collect();
+
+    let mut events = Vec::new();
+    for item in view.items.values() {
+        events.push((item.id, Event::Create(item)));
+        let moved = moved_into_later_stack(item);
+        if item.last_touched != item.id
+            && (moved || !touched_with_children.contains(&(item.id, item.last_touched)))
+        {
+            events.push((item.last_touched, Event::Touch(item)));
+        }
+    }
+    events.sort_by_key(|(id, _)| *id);
+
+    let mut built = View::new();
+    let mut packets = Vec::new();
+    for (id, event) in events {
+        let packet = match event {
+            Event::Create(item) => create(&built, item),
+            Event::Touch(item) => {
+                let mut packet = packet(id, PacketType::Update, Some(item.id));
+                if moved_into_later_stack(item) {
+                    packet.stack_id = item.stack_id;
+                }
+                packet
+            }
+        };
+        built.merge(&packet);
+        packets.push(packet);
+    }
+
+    let mut items: Vec<&Item> = view.items.values().collect();
+    items.sort_by_key(|item| item.id);
+    for item in items {
+        let update = |id| packet(id, PacketType::Update, Some(item.id));
+        if item.locked && !item.is_stack {
+            packets.push(Packet {
+                lock_status: Some(StackLockStatus::Locked),
+                ..update(next_id())
+            });
+        }
+        if item.pinned {
+            packets.push(Packet {
+                pin_status: Some(PinStatus::Pinned),
+                ..update(next_id())
+            });
+        }
+        if !item.tags.is_empty() {
+            let tags = Tags {
+                add: item.tags.iter().cloned().collect(),
+                remove: Vec::new(),
+            };
+            packets.push(Packet {
+                tags: Some(tags),
+                ..update(next_id())
+            });
+        }
+        if item.stack_meta != StackMeta::default() {
+            packets.push(Packet {
+                stack_meta: Some(item.stack_meta.clone()),
+                ..update(next_id())
+            });
+        }
+        if item.directory.is_some() {
+            packets.push(Packet {
+                directory: item.directory.clone(),
+                ..update(next_id())
+            });
+        }
+        if item.cross_stream {
+            packets.push(Packet {
+                cross_stream: true,
+                stack_id: Some(item.id),
+                ..packet(next_id(), PacketType::Update, None)
+            });
+        }
+        if item.ordered {
+            packets.push(Packet {
+                sort_order: Some(StackSortOrder::Manual),
+                ..update(next_id())
+            });
+            for (index, child) in view.children(item).into_iter().enumerate() {
+                packets.push(Packet {
+                    movement: Some(Movement::MoveTo { index }),
+                    ..packet(next_id(), PacketType::Update, Some(child))
+                });
+            }
+        }
+    }
+
+    packets
+}
+
+/// Whether `item` was moved into a stack which was created after it. It's added before its
+/// stack exists, and moved into it by the packet which last touched it.
+fn moved_into_later_stack(item: &Item) -> bool {
+    item.stack_id.is_some_and(|stack_id| stack_id > item.id)
+}
+
+fn create(built: &View, item: &Item) -> Packet {
+    let mut packet = Packet {
+        hash: Some(item.hash.clone()),
+        snapshot: item.snapshot.clone(),
+        stack_id: item.stack_id,
+        ephemeral: item.ephemeral,
+        ..packet(item.id, PacketType::Add, None)
+    };
+    if item.is_stack {
+        packet.lock_status = Some(if item.locked {
+            StackLockStatus::Locked
+        } else {
+            StackLockStatus::Unlocked
+        });
+        return packet;
+    }
+
+    // adding content which is already in the stack touches it, rather than adding it again, so
+    // a duplicate is forked from the item it duplicates
+    if !item.ephemeral {
+        let stack = item.stack_id.and_then(|id| built.items.get(&id));
+        let twin = stack.and_then(|stack| {
+            built.children(stack).into_iter().find(|id| {
+                built.items.get(id).is_some_and(|child| {
+                    !child.is_stack && !child.ephemeral && child.hash == item.hash
+                })
+            })
+        });
+        if let Some(twin) = twin {
+            packet.packet_type = PacketType::Fork;
+            packet.source_id = Some(twin);
+        }
+    }
+    packet
+}
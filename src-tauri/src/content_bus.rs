@@ -6,9 +6,9 @@ use crate::state::SharedState;
 use crate::store::{count_tiktokens, MimeType};
 
 pub fn spawn_tiktokens(app: tauri::AppHandle, state: SharedState) {
-    let (cache_path, mut rx) = state.with_lock(|state| {
+    let (blobs, mut rx) = state.with_lock(|state| {
         (
-            state.store.cache_path.clone(),
+            state.store.blobs.clone(),
             state.store.content_bus_tx.subscribe(),
         )
     });
@@ -16,14 +16,13 @@ pub fn spawn_tiktokens(app: tauri::AppHandle, state: SharedState) {
     tokio::spawn(async move {
         tracing::info!(name = "content_bus::tiktokens", "booting");
         loop {
-            let cache_path = cache_path.clone();
+            let blobs = blobs.clone();
             match rx.recv().await {
                 Ok(content_meta) => {
                     if content_meta.mime_type == MimeType::TextPlain {
                         let hash = content_meta.hash.clone();
                         let tiktokens = tokio::task::spawn_blocking(move || {
-                            let content =
-                                cacache::read_hash_sync(&cache_path, &content_meta.hash).unwrap();
+                            let content = blobs.read(&content_meta.hash).unwrap();
                             let content = String::from_utf8_lossy(&content);
                             let tiktokens = count_tiktokens(&content);
                         tracing::info!(name = "content_bus::tiktokens", hash = %content_meta.hash, tiktokens = tiktokens);
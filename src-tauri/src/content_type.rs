@@ -1,5 +1,8 @@
 use lazy_static::lazy_static;
 use std::collections::HashMap;
+use std::path::Path;
+
+use crate::store::MimeType;
 
 lazy_static! {
     static ref FILE_EXTENSIONS: HashMap<&'static str, &'static str> = [
@@ -40,6 +43,32 @@ lazy_static! {
     .collect();
 }
 
+/// Returns the content type for a file extension, e.g. "rs" is "Rust".
+pub fn from_extension(extension: &str) -> Option<&'static str> {
+    FILE_EXTENSIONS
+        .get(extension.to_lowercase().as_str())
+        .copied()
+}
+
+/// Works out how file content should be stored: PNGs are images, and valid UTF-8 is text, with
+/// a content type from the file's extension. Other binary content isn't supported.
+pub fn detect_file(
+    path: Option<&Path>,
+    content: &[u8],
+) -> Option<(MimeType, Option<&'static str>)> {
+    match infer::get(content).map(|kind| kind.mime_type()) {
+        Some("image/png") => Some((MimeType::ImagePng, None)),
+        None if std::str::from_utf8(content).is_ok() => {
+            let content_type = path
+                .and_then(|path| path.extension())
+                .and_then(|ext| ext.to_str())
+                .and_then(from_extension);
+            Some((MimeType::TextPlain, content_type))
+        }
+        _ => None,
+    }
+}
+
 pub fn process_command(command: &str) -> (String, Option<String>) {
     let parts: Vec<&str> = command.split('|').map(str::trim).collect();
     if let Some(last_part) = parts.last() {
@@ -80,4 +109,26 @@ mod tests {
         assert_eq!(new_command, "llm");
         assert_eq!(content_type, Some("Markdown".to_string()));
     }
+
+    #[test]
+    fn test_from_extension() {
+        assert_eq!(from_extension("rs"), Some("Rust"));
+        assert_eq!(from_extension("MD"), Some("Markdown"));
+        assert_eq!(from_extension("txt"), None);
+    }
+
+    #[test]
+    fn test_detect_file() {
+        let png = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0, 0, 0, 0];
+        assert_eq!(detect_file(None, &png), Some((MimeType::ImagePng, None)));
+        assert_eq!(
+            detect_file(Some(Path::new("src/main.rs")), b"fn main() {}"),
+            Some((MimeType::TextPlain, Some("Rust")))
+        );
+        assert_eq!(
+            detect_file(None, b"hello"),
+            Some((MimeType::TextPlain, None))
+        );
+        assert_eq!(detect_file(None, &[0xff, 0xfe, 0x00, 0x01]), None);
+    }
 }
@@ -0,0 +1,505 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use base64::{engine::general_purpose, Engine as _};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::{Method, Request};
+use hyper_util::rt::TokioIo;
+use scru128::Scru128Id;
+use serde::{Deserialize, Serialize};
+use ssri::Integrity;
+
+use tauri::Manager;
+
+use crate::state::SharedState;
+use crate::storage::Blobs;
+use crate::store::{MimeType, Store};
+use crate::view::View;
+
+// how long to wait before retrying a delivery, doubling with each attempt up to a cap
+const RETRY_DELAY_MS: u64 = 1000;
+const MAX_RETRY_DELAY_MS: u64 = 60 * 60 * 1000;
+// a delivery which has failed this many times is given up on
+const MAX_ATTEMPTS: u32 = 12;
+
+// how long to wait on an endpoint before giving up on an attempt
+const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+// the topic items are appended to in an xs store, unless the settings say otherwise
+const DEFAULT_TOPIC: &str = "stacks.item";
+
+/// Where the items added to the cross-stream stack are published.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Endpoint {
+    // POSTs each item as JSON, with the cross-stream access token, if there is one, as a
+    // bearer token
+    Http {
+        url: String,
+    },
+    // appends each item to the file as a line of JSON
+    File {
+        path: PathBuf,
+    },
+    // appends each item to a topic in an xs event store, through the socket in its directory
+    Xs {
+        path: PathBuf,
+        topic: Option<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+/// How publishing an item is going. A delivery which fails is retried, waiting longer each
+/// time, until it's given up on.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Delivery {
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    // when to try again, in milliseconds since the epoch, while it's pending
+    pub next_attempt: u64,
+    pub error: Option<String>,
+}
+
+impl Delivery {
+    pub fn new(now: u64) -> Self {
+        Self {
+            status: DeliveryStatus::Pending,
+            attempts: 0,
+            next_attempt: now,
+            error: None,
+        }
+    }
+
+    pub fn is_due(&self, now: u64) -> bool {
+        self.status == DeliveryStatus::Pending && self.next_attempt <= now
+    }
+
+    /// The delivery after an attempt at it, made at `now`.
+    pub fn attempted(&self, result: Result<(), String>, now: u64) -> Self {
+        let attempts = self.attempts + 1;
+        match result {
+            Ok(()) => Self {
+                status: DeliveryStatus::Delivered,
+                attempts,
+                next_attempt: now,
+                error: None,
+            },
+            Err(error) => {
+                let delay = RETRY_DELAY_MS
+                    .saturating_mul(1 << (attempts - 1).min(31))
+                    .min(MAX_RETRY_DELAY_MS);
+                let status = match attempts >= MAX_ATTEMPTS {
+                    true => DeliveryStatus::Failed,
+                    false => DeliveryStatus::Pending,
+                };
+                Self {
+                    status,
+                    attempts,
+                    next_attempt: now + delay,
+                    error: Some(error),
+                }
+            }
+        }
+    }
+}
+
+pub fn now() -> u64 {
+    chrono::Utc::now().timestamp_millis() as u64
+}
+
+/// An item, as it's published.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Event {
+    pub id: Scru128Id,
+    pub stack_id: Scru128Id,
+    // the stack's name
+    pub stack: String,
+    pub hash: Integrity,
+    pub mime_type: MimeType,
+    pub content_type: String,
+    #[serde(skip)]
+    pub content: Vec<u8>,
+}
+
+impl Event {
+    /// The item `id`, if it's still in the view, without its content: see `with_content`.
+    pub fn new(store: &Store, view: &View, id: &Scru128Id) -> Option<Self> {
+        let item = view.items.get(id)?;
+        let stack_id = item.stack_id?;
+        let stack = view.items.get(&stack_id)?;
+        let stack = store.get_content_meta(&stack.hash)?.terse;
+        let meta = store.get_content_meta(&item.hash)?;
+        Some(Self {
+            id: item.id,
+            stack_id,
+            stack,
+            hash: item.hash.clone(),
+            mime_type: meta.mime_type,
+            content_type: meta.content_type,
+            content: Vec::new(),
+        })
+    }
+
+    /// The event with its content, if it's still in the CAS. Reading it doesn't need the store,
+    /// so it can be done without holding the state lock.
+    pub fn with_content(self, blobs: &dyn Blobs) -> Option<Self> {
+        let content = blobs.read(&self.hash)?;
+        Some(Self { content, ..self })
+    }
+
+    /// The event as JSON, with its content: text as it is, and anything else as base64.
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut json = serde_json::to_value(self).unwrap();
+        let text = match self.mime_type {
+            MimeType::TextPlain => std::str::from_utf8(&self.content).ok(),
+            _ => None,
+        };
+        match text {
+            Some(text) => json["content"] = text.into(),
+            None => {
+                json["content"] = general_purpose::STANDARD.encode(&self.content).into();
+                json["encoding"] = "base64".into();
+            }
+        }
+        json
+    }
+}
+
+/// Publishes `event` to `endpoint`.
+pub async fn publish(
+    client: &reqwest::Client,
+    endpoint: &Endpoint,
+    token: Option<&str>,
+    event: &Event,
+) -> Result<(), String> {
+    match endpoint {
+        Endpoint::Http { url } => {
+            let request = client.post(url).json(&event.to_json());
+            let request = match token {
+                Some(token) => request.bearer_auth(token),
+                None => request,
+            };
+            let response = request.send().await.map_err(|e| e.to_string())?;
+            let status = response.status();
+            if !status.is_success() {
+                return Err(format!("{url} replied {status}"));
+            }
+            Ok(())
+        }
+
+        Endpoint::File { path } => {
+            let mut line = serde_json::to_vec(&event.to_json()).unwrap();
+            line.push(b'\n');
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+            }
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| e.to_string())?;
+            file.write_all(&line).map_err(|e| e.to_string())
+        }
+
+        // the content is appended as it is, with the rest of the event as its meta
+        Endpoint::Xs { path, topic } => {
+            let topic = topic.as_deref().unwrap_or(DEFAULT_TOPIC);
+            tokio::time::timeout(TIMEOUT, publish_xs(path, topic, event))
+                .await
+                .map_err(|_| format!("xs didn't reply within {}s", TIMEOUT.as_secs()))?
+        }
+    }
+}
+
+async fn publish_xs(path: &Path, topic: &str, event: &Event) -> Result<(), String> {
+    let socket = path.join("sock");
+    let stream = tokio::net::UnixStream::connect(&socket)
+        .await
+        .map_err(|e| format!("{}: {e}", socket.display()))?;
+    let (mut request_sender, connection) =
+        hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .map_err(|e| e.to_string())?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            tracing::warn!("Error in xs connection: {}", e);
+        }
+    });
+
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(format!("/{topic}"))
+        .header("Host", "localhost")
+        .header("xs-meta", serde_json::to_string(event).unwrap())
+        .body(Full::new(Bytes::from(event.content.clone())))
+        .map_err(|e| e.to_string())?;
+    let response = request_sender
+        .send_request(request)
+        .await
+        .map_err(|e| e.to_string())?;
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.into_body().collect().await;
+        let body = body.map(|body| body.to_bytes()).unwrap_or_default();
+        return Err(format!(
+            "xs replied {status}: {}",
+            String::from_utf8_lossy(&body)
+        ));
+    }
+    Ok(())
+}
+
+/// Publishes the items waiting in the deliveries table, as they come due, to the endpoint in
+/// the settings. Items are queued as they're added to the cross-stream stack, see
+/// `State::merge`.
+pub fn start(app: tauri::AppHandle, state: SharedState) {
+    tauri::async_runtime::spawn(async move {
+        tracing::info!(name = "cross_stream", "booting");
+        let client = reqwest::Client::builder().timeout(TIMEOUT).build().unwrap();
+        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(1));
+
+        loop {
+            ticker.tick().await;
+            let now = now();
+            let settings = state.with_lock(|state| state.store.settings_get().unwrap_or_default());
+            // items wait until there's somewhere to publish them
+            let Some(endpoint) = settings.cross_stream_endpoint else {
+                continue;
+            };
+            let token = settings.cross_stream_access_token;
+
+            let (blobs, due) = state.with_lock(|state| {
+                let due: Vec<_> = state
+                    .store
+                    .deliveries_due(now)
+                    .into_iter()
+                    .map(|(id, delivery)| {
+                        let event = Event::new(&state.store, &state.view, &id);
+                        (id, delivery, event)
+                    })
+                    .collect();
+                (state.store.blobs.clone(), due)
+            });
+            if due.is_empty() {
+                continue;
+            }
+
+            for (id, delivery, event) in due {
+                let event = event.and_then(|event| event.with_content(blobs.as_ref()));
+                let result = match event {
+                    Some(event) => publish(&client, &endpoint, token.as_deref(), &event).await,
+                    None => Err("The item, or its content, is gone".to_string()),
+                };
+                if let Err(e) = &result {
+                    tracing::warn!(%id, "Publishing to cross-stream failed: {}", e);
+                }
+                let delivery = delivery.attempted(result, now);
+                // unless the item was deleted, or its content purged, while it was published
+                state.with_lock(|state| {
+                    if state.store.delivery_get(&id).is_some() {
+                        state.store.delivery_save(&id, &delivery);
+                    }
+                });
+            }
+            app.emit_all("refresh-items", true).unwrap();
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    use hyper::server::conn::http1;
+    use hyper::service::service_fn;
+    use hyper::{Response, StatusCode};
+
+    fn event(content: &[u8], mime_type: MimeType) -> Event {
+        Event {
+            id: scru128::new(),
+            stack_id: scru128::new(),
+            stack: "Stack".to_string(),
+            hash: Integrity::from(content),
+            mime_type,
+            content_type: "Text".to_string(),
+            content: content.to_vec(),
+        }
+    }
+
+    // a stand-in for an endpoint, which fails the first `failures` requests, and records the
+    // authorization, path and body of the rest
+    type Received = Arc<Mutex<Vec<(Option<String>, String, Vec<u8>)>>>;
+
+    async fn stand_in<S>(listener: S, failures: usize) -> Received
+    where
+        S: Listener,
+    {
+        let received: Received = Default::default();
+        let failed = Arc::new(Mutex::new(0));
+        let recorded = received.clone();
+        tokio::spawn(async move {
+            loop {
+                let io = listener.accept().await;
+                let (received, failed) = (recorded.clone(), failed.clone());
+                tokio::spawn(async move {
+                    let service = service_fn(move |req: Request<hyper::body::Incoming>| {
+                        let (received_by_service, failed) = (received.clone(), failed.clone());
+                        async move {
+                            let authorization = req
+                                .headers()
+                                .get("Authorization")
+                                .map(|value| value.to_str().unwrap().to_string());
+                            let meta = req
+                                .headers()
+                                .get("xs-meta")
+                                .map(|value| value.to_str().unwrap().to_string());
+                            let path = req.uri().path().to_string();
+                            let body = req.into_body().collect().await?.to_bytes().to_vec();
+
+                            let mut response = Response::new(Full::new(Bytes::new()));
+                            let mut failed = failed.lock().unwrap();
+                            if *failed < failures {
+                                *failed += 1;
+                                *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                            } else {
+                                let received = (authorization.or(meta), path, body);
+                                received_by_service.lock().unwrap().push(received);
+                            }
+                            Ok::<_, hyper::Error>(response)
+                        }
+                    });
+                    let _ = http1::Builder::new().serve_connection(io, service).await;
+                });
+            }
+        });
+        received
+    }
+
+    trait Listener: Send + 'static {
+        type Io: hyper::rt::Read + hyper::rt::Write + Unpin + Send + 'static;
+        fn accept(&self) -> impl std::future::Future<Output = Self::Io> + Send;
+    }
+
+    impl Listener for tokio::net::TcpListener {
+        type Io = TokioIo<tokio::net::TcpStream>;
+        async fn accept(&self) -> Self::Io {
+            TokioIo::new(tokio::net::TcpListener::accept(self).await.unwrap().0)
+        }
+    }
+
+    impl Listener for tokio::net::UnixListener {
+        type Io = TokioIo<tokio::net::UnixStream>;
+        async fn accept(&self) -> Self::Io {
+            TokioIo::new(tokio::net::UnixListener::accept(self).await.unwrap().0)
+        }
+    }
+
+    #[test]
+    fn test_delivery_backoff() {
+        let delivery = Delivery::new(0);
+        assert!(delivery.is_due(0));
+
+        let failed = delivery.attempted(Err("down".to_string()), 0);
+        assert_eq!(failed.status, DeliveryStatus::Pending);
+        assert_eq!(failed.next_attempt, 1000);
+        assert!(!failed.is_due(999));
+        let failed = failed.attempted(Err("down".to_string()), 1000);
+        assert_eq!(failed.next_attempt, 3000);
+
+        let delivered = failed.attempted(Ok(()), 3000);
+        assert_eq!(delivered.status, DeliveryStatus::Delivered);
+        assert_eq!((delivered.attempts, delivered.error.as_deref()), (3, None));
+        assert!(!delivered.is_due(u64::MAX));
+
+        // retries are capped at an hour apart, and given up on eventually
+        let mut delivery = Delivery::new(0);
+        for _ in 0..MAX_ATTEMPTS {
+            assert_eq!(delivery.status, DeliveryStatus::Pending);
+            delivery = delivery.attempted(Err("down".to_string()), 0);
+            assert!(delivery.next_attempt <= MAX_RETRY_DELAY_MS);
+        }
+        assert_eq!(delivery.status, DeliveryStatus::Failed);
+        assert_eq!(delivery.error.as_deref(), Some("down"));
+        assert!(!delivery.is_due(u64::MAX));
+    }
+
+    #[test]
+    fn test_publish() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let client = reqwest::Client::new();
+            let text = event(b"hello", MimeType::TextPlain);
+            let image = event(b"\x89PNG", MimeType::ImagePng);
+
+            // over HTTP, with a server which is down at first
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}/items", listener.local_addr().unwrap());
+            let received = stand_in(listener, 1).await;
+            let endpoint = Endpoint::Http { url };
+
+            let error = publish(&client, &endpoint, Some("token"), &text).await;
+            assert!(error.unwrap_err().contains("503"));
+            publish(&client, &endpoint, Some("token"), &text)
+                .await
+                .unwrap();
+            publish(&client, &endpoint, None, &image).await.unwrap();
+
+            let received = received.lock().unwrap().clone();
+            assert_eq!(received.len(), 2);
+            assert_eq!(received[0].0.as_deref(), Some("Bearer token"));
+            assert_eq!(received[0].1, "/items");
+            let json: serde_json::Value = serde_json::from_slice(&received[0].2).unwrap();
+            assert_eq!(json["content"], "hello");
+            assert_eq!(json["id"], text.id.to_string());
+            assert_eq!(json["stack"], "Stack");
+            assert_eq!(received[1].0, None);
+            let json: serde_json::Value = serde_json::from_slice(&received[1].2).unwrap();
+            assert_eq!(json["content"], "iVBORw==");
+            assert_eq!(json["encoding"], "base64");
+
+            // to a file, a line at a time
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("published").join("items.jsonl");
+            let endpoint = Endpoint::File { path: path.clone() };
+            publish(&client, &endpoint, None, &text).await.unwrap();
+            publish(&client, &endpoint, None, &image).await.unwrap();
+            let lines: Vec<serde_json::Value> = std::fs::read_to_string(&path)
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect();
+            assert_eq!(lines, vec![text.to_json(), image.to_json()]);
+
+            // to an xs store, which isn't running at first
+            let endpoint = Endpoint::Xs {
+                path: dir.path().to_path_buf(),
+                topic: None,
+            };
+            assert!(publish(&client, &endpoint, None, &text).await.is_err());
+            let listener = tokio::net::UnixListener::bind(dir.path().join("sock")).unwrap();
+            let received = stand_in(listener, 0).await;
+            publish(&client, &endpoint, None, &text).await.unwrap();
+
+            let received = received.lock().unwrap().clone();
+            let (meta, path, body) = &received[0];
+            assert_eq!(path, "/stacks.item");
+            assert_eq!(body, b"hello");
+            let meta: serde_json::Value = serde_json::from_str(meta.as_ref().unwrap()).unwrap();
+            assert_eq!(meta["hash"], text.hash.to_string());
+            assert_eq!(meta.get("content"), None);
+        });
+    }
+}
@@ -0,0 +1,287 @@
+use std::os::fd::{FromRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use scru128::Scru128Id;
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::process::{Child, Command};
+
+use crate::store::ShellSettings;
+
+pub type Output = Pin<Box<dyn AsyncRead + Send>>;
+
+// Reading from a pty's master side returns EIO once the child has exited and the last handle to
+// the slave side is closed. Translate that to a regular end of stream.
+struct PtyReader {
+    inner: tokio::fs::File,
+}
+
+impl AsyncRead for PtyReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        match Pin::new(&mut self.inner).poll_read(cx, buf) {
+            Poll::Ready(Err(e)) if e.raw_os_error() == Some(libc::EIO) => Poll::Ready(Ok(())),
+            other => other,
+        }
+    }
+}
+
+fn open_pty() -> std::io::Result<(OwnedFd, OwnedFd)> {
+    let mut master: libc::c_int = -1;
+    let mut slave: libc::c_int = -1;
+    let ret = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let (master, slave) = unsafe { (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+
+    // disable output post-processing, so "\n" isn't translated to "\r\n"
+    unsafe {
+        use std::os::fd::AsRawFd;
+        let mut termios = std::mem::MaybeUninit::<libc::termios>::uninit();
+        if libc::tcgetattr(slave.as_raw_fd(), termios.as_mut_ptr()) == 0 {
+            let mut termios = termios.assume_init();
+            termios.c_oflag &= !libc::OPOST;
+            libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios);
+        }
+    }
+
+    Ok((master, slave))
+}
+
+/// Fails when the directory `cmd` is to run in doesn't exist, which spawning it would only
+/// report as a missing file.
+fn check_cwd(cmd: &Command) -> std::io::Result<()> {
+    match cmd.as_std().get_current_dir() {
+        Some(cwd) if !cwd.is_dir() => Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("The directory {} doesn't exist", cwd.display()),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Spawns `cmd` with piped stdin and stderr. When `pty` is set, stdout is attached to a
+/// pseudo-terminal, so tools which check `isatty` emit colors, otherwise it's a regular pipe.
+pub fn spawn(mut cmd: Command, pty: bool) -> std::io::Result<(Child, Output)> {
+    check_cwd(&cmd)?;
+    cmd.stdin(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped());
+
+    if !pty {
+        cmd.stdout(std::process::Stdio::piped());
+        let mut child = cmd.spawn()?;
+        let stdout = child.stdout.take().unwrap();
+        return Ok((child, Box::pin(stdout)));
+    }
+
+    let (master, slave) = open_pty()?;
+    if std::env::var_os("TERM").is_none() {
+        cmd.env("TERM", "xterm-256color");
+    }
+    cmd.stdout(std::process::Stdio::from(slave));
+    let child = cmd.spawn()?;
+    // drop our copy of the slave side, so reads see the end of stream when the child exits
+    drop(cmd);
+
+    let master = tokio::fs::File::from_std(std::fs::File::from(master));
+    Ok((child, Box::pin(PtyReader { inner: master })))
+}
+
+/// What a pipe command is being run against, exported to the command's environment so scripts
+/// can call back into the `stacks` CLI.
+#[derive(Debug, Clone, Default)]
+pub struct Env {
+    pub item_id: Option<Scru128Id>,
+    pub stack_id: Option<Scru128Id>,
+    pub hash: Option<ssri::Integrity>,
+    pub socket: PathBuf,
+}
+
+fn expand_home(path: &str, home: &Path) -> PathBuf {
+    match path.strip_prefix("~/") {
+        Some(rest) => home.join(rest),
+        None if path == "~" => home.to_path_buf(),
+        None => PathBuf::from(path),
+    }
+}
+
+fn default_rc_file(shell: &str, home: &Path) -> Option<PathBuf> {
+    let name = Path::new(shell).file_name()?.to_str()?;
+    let rc_file = match name {
+        "bash" => ".bashrc",
+        "zsh" => ".zshrc",
+        _ => return None,
+    };
+    Some(home.join(rc_file)).filter(|path| path.exists())
+}
+
+// quotes `arg` for a POSIX-style shell, when it has anything the shell would take apart
+fn quote(arg: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "/._-+,@:=%~".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+/// Builds the command used to run `command` in the user's configured shell.
+pub fn shell_command(settings: &ShellSettings, command: &str, env: &Env) -> Command {
+    let home = dirs::home_dir().unwrap_or_default();
+    let shell = settings
+        .shell
+        .clone()
+        .or_else(|| std::env::var("SHELL").ok())
+        .unwrap_or_else(|| String::from("/bin/sh"));
+
+    let rc_file = match &settings.rc_file {
+        Some(rc_file) if rc_file.is_empty() => None,
+        Some(rc_file) => Some(expand_home(rc_file, &home)),
+        None => default_rc_file(&shell, &home),
+    };
+    let command = match rc_file {
+        Some(rc_file) => format!("source {}\n{}", quote(&rc_file.to_string_lossy()), command),
+        None => command.to_string(),
+    };
+
+    let mut cmd = Command::new(&shell);
+    match &settings.args {
+        Some(args) => cmd.args(args),
+        None => cmd.arg("-c"),
+    };
+    cmd.arg(command);
+
+    if let Some(cwd) = &settings.cwd {
+        cmd.current_dir(expand_home(cwd, &home));
+    }
+    if let Some(path) = &settings.path {
+        cmd.env("PATH", path);
+    }
+    cmd.envs(&settings.env);
+
+    if let Some(item_id) = env.item_id {
+        cmd.env("STACKS_ITEM_ID", item_id.to_string());
+    }
+    if let Some(stack_id) = env.stack_id {
+        cmd.env("STACKS_STACK_ID", stack_id.to_string());
+    }
+    if let Some(hash) = &env.hash {
+        cmd.env("STACKS_HASH", hash.to_string());
+    }
+    cmd.env("STACKS_SOCKET", &env.socket);
+
+    cmd
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::ffi::OsStr;
+
+    fn envs(cmd: &Command) -> HashMap<&OsStr, Option<&OsStr>> {
+        cmd.as_std().get_envs().collect()
+    }
+
+    #[test]
+    fn test_shell_command() {
+        let settings = ShellSettings {
+            shell: Some("/opt/homebrew/bin/nu".to_string()),
+            args: Some(vec!["--login".to_string(), "-c".to_string()]),
+            rc_file: Some("/tmp/env.nu".to_string()),
+            cwd: Some("/tmp".to_string()),
+            env: HashMap::from([("FOO".to_string(), "bar".to_string())]),
+            path: Some("/opt/homebrew/bin:/usr/bin".to_string()),
+        };
+        let item_id = scru128::new();
+        let env = Env {
+            item_id: Some(item_id),
+            stack_id: None,
+            hash: Some(ssri::Integrity::from("hai")),
+            socket: PathBuf::from("/tmp/stacks/sock"),
+        };
+
+        let cmd = shell_command(&settings, "cat", &env);
+        let std = cmd.as_std();
+        assert_eq!(std.get_program(), "/opt/homebrew/bin/nu");
+        assert_eq!(
+            std.get_args().collect::<Vec<_>>(),
+            vec!["--login", "-c", "source /tmp/env.nu\ncat"]
+        );
+        assert_eq!(std.get_current_dir(), Some(Path::new("/tmp")));
+
+        let envs = envs(&cmd);
+        let get = |key: &str| envs.get(OsStr::new(key)).cloned().flatten();
+        assert_eq!(get("FOO"), Some(OsStr::new("bar")));
+        assert_eq!(get("PATH"), Some(OsStr::new("/opt/homebrew/bin:/usr/bin")));
+        assert_eq!(
+            get("STACKS_ITEM_ID"),
+            Some(OsStr::new(&item_id.to_string()))
+        );
+        assert_eq!(get("STACKS_STACK_ID"), None);
+        assert_eq!(
+            get("STACKS_HASH"),
+            Some(OsStr::new(&ssri::Integrity::from("hai").to_string()))
+        );
+        assert_eq!(get("STACKS_SOCKET"), Some(OsStr::new("/tmp/stacks/sock")));
+    }
+
+    #[test]
+    fn test_shell_command_rc_file() {
+        // an empty rc_file disables sourcing, and unknown shells don't get a default
+        let settings = ShellSettings {
+            shell: Some("/usr/local/bin/bash".to_string()),
+            rc_file: Some("".to_string()),
+            ..Default::default()
+        };
+        let cmd = shell_command(&settings, "cat", &Env::default());
+        assert_eq!(
+            cmd.as_std().get_args().collect::<Vec<_>>(),
+            vec!["-c", "cat"]
+        );
+
+        let settings = ShellSettings {
+            shell: Some("/usr/local/bin/fish".to_string()),
+            ..Default::default()
+        };
+        let cmd = shell_command(&settings, "cat", &Env::default());
+        assert_eq!(
+            cmd.as_std().get_args().collect::<Vec<_>>(),
+            vec!["-c", "cat"]
+        );
+
+        // paths the shell would split up are quoted
+        let settings = ShellSettings {
+            shell: Some("/bin/bash".to_string()),
+            rc_file: Some("/Users/me/My Config/it's.sh".to_string()),
+            ..Default::default()
+        };
+        let cmd = shell_command(&settings, "cat", &Env::default());
+        assert_eq!(
+            cmd.as_std().get_args().collect::<Vec<_>>(),
+            vec!["-c", "source '/Users/me/My Config/it'\\''s.sh'\ncat"]
+        );
+    }
+
+    #[test]
+    fn test_check_cwd() {
+        let mut cmd = Command::new("/bin/sh");
+        cmd.current_dir("/no/such/directory");
+        let error = check_cwd(&cmd).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::NotFound);
+        assert!(error.to_string().contains("/no/such/directory"));
+    }
+}
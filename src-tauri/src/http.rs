@@ -1,30 +1,45 @@
 use std::error::Error;
 use std::str::FromStr;
-
-use futures_util::TryStreamExt;
+use std::sync::Arc;
 
 use tokio::net::UnixListener;
 
 use tauri::Manager;
 
-use http_body_util::{combinators::BoxBody, BodyExt, Empty, Full, StreamBody};
+use http_body_util::{combinators::BoxBody, BodyExt, Empty, Full};
 use hyper::body::Bytes;
-use hyper::body::Frame;
 use hyper::server::conn::http1;
 use hyper::service::service_fn;
 use hyper::{Method, StatusCode};
 use hyper::{Request, Response};
 use hyper_util::rt::TokioIo;
 
-use crate::state::SharedState;
-use crate::store::{infer_mime_type, InProgressStream, MimeType};
+use crate::schedule::{Schedule, Trigger};
+use crate::state::{SharedSnapshot, SharedState, Snapshot, State};
+use crate::store::{
+    infer_mime_type, InProgressStream, Index, MimeType, Movement, StackDirectory, StackMeta,
+};
 
 type BoxError = Box<dyn std::error::Error + Send + Sync>;
 type HTTPResult = Result<Response<BoxBody<Bytes, BoxError>>, BoxError>;
 
-#[tracing::instrument(skip(state, app_handle))]
+/// What read-only routes are served from, without taking the state's lock.
+#[derive(Clone)]
+struct Readers {
+    snapshot: SharedSnapshot,
+    index: Index,
+}
+
+impl Readers {
+    fn snapshot(&self) -> Arc<Snapshot> {
+        self.snapshot.read().unwrap().clone()
+    }
+}
+
+#[tracing::instrument(skip(state, readers, app_handle))]
 async fn handle(
     state: SharedState,
+    readers: Readers,
     app_handle: tauri::AppHandle,
     req: Request<hyper::body::Incoming>,
 ) -> HTTPResult {
@@ -50,7 +65,35 @@ async fn handle(
 
     // Handle stacks routes
     if path == "/stacks" && req.method() == Method::GET {
-        return get_stacks_list(state).await;
+        return get_stacks_list(readers).await;
+    }
+
+    if path == "/stacks/tree" && req.method() == Method::GET {
+        return get_stacks_tree(None, state).await;
+    }
+
+    if let Some(id_str) = path
+        .strip_prefix("/stacks/")
+        .and_then(|rest| rest.strip_suffix("/tree"))
+    {
+        if req.method() == Method::GET {
+            return get_stacks_tree(Some(id_str), state).await;
+        }
+    }
+
+    if let Some(id_str) = path
+        .strip_prefix("/stacks/")
+        .and_then(|rest| rest.strip_suffix("/directory"))
+    {
+        if req.method() == Method::POST {
+            return set_stack_directory(id_str, &params, state, app_handle).await;
+        }
+    }
+
+    if let Some(id_str) = path.strip_prefix("/stacks/") {
+        if req.method() == Method::PATCH {
+            return update_stack(id_str, &params, state, app_handle).await;
+        }
     }
 
     // Handle stream routes
@@ -60,7 +103,11 @@ async fn handle(
 
     // Handle search routes
     if path == "/search" && req.method() == Method::GET {
-        return handle_search(req.uri().query(), state).await;
+        return handle_search(req.uri().query(), readers).await;
+    }
+
+    if path == "/search/status" && req.method() == Method::GET {
+        return get_search_status(readers).await;
     }
 
     // Handle search rebuild
@@ -68,9 +115,30 @@ async fn handle(
         return handle_search_rebuild(state, app_handle).await;
     }
 
+    if path == "/admin/fsck" && req.method() == Method::POST {
+        return handle_fsck(&params, state, app_handle).await;
+    }
+
+    if path == "/admin/compact" && req.method() == Method::POST {
+        return handle_compact(&params, state, app_handle).await;
+    }
+
+    if path == "/sync" && req.method() == Method::POST {
+        return handle_sync(state, app_handle).await;
+    }
+
+    if path == "/sql" && req.method() == Method::GET {
+        return handle_sql(&params, state).await;
+    }
+
+    // Handle schedule routes
+    if path == "/schedules" || path.starts_with("/schedules/") {
+        return handle_schedules(req.method(), path, &params, state).await;
+    }
+
     // Handle view routes
     if path == "/view" && req.method() == Method::GET {
-        return get_view(state).await;
+        return get_view(readers).await;
     }
 
     if path == "/view/nav" && req.method() == Method::GET {
@@ -82,6 +150,28 @@ async fn handle(
         return handle_delete(path, state, app_handle).await;
     }
 
+    if path == "/add" && req.method() == Method::POST {
+        return add(req, params, state, app_handle).await;
+    }
+
+    if let Some(id_str) = path
+        .strip_prefix('/')
+        .and_then(|rest| rest.strip_suffix("/move"))
+    {
+        if req.method() == Method::POST {
+            return move_items(id_str, &params, state, app_handle).await;
+        }
+    }
+
+    if let Some(id_str) = path
+        .strip_prefix('/')
+        .and_then(|rest| rest.strip_suffix("/tags"))
+    {
+        if req.method() == Method::POST {
+            return update_tags(id_str, &params, state, app_handle).await;
+        }
+    }
+
     // Handle legacy routes
     let id_option = match path.strip_prefix('/') {
         Some("") | None => None, // Path is "/" or empty
@@ -142,17 +232,8 @@ async fn get_cas_content(state: SharedState, hash: ssri::Integrity) -> HTTPResul
     });
 
     match content {
-        Some(_content_bytes) => {
-            let cache_path = state.with_lock(|state| state.store.cache_path.clone());
-            let reader = cacache::Reader::open_hash(cache_path, hash.clone())
-                .await
-                .unwrap();
-
-            let stream = tokio_util::io::ReaderStream::new(reader);
-            let stream = stream
-                .map_ok(Frame::data)
-                .map_err(|e| Box::new(e) as BoxError);
-            let body = BodyExt::boxed(StreamBody::new(stream));
+        Some(content) => {
+            let body = full(content);
 
             let content_type = match meta {
                 Some(ref meta) => match meta.mime_type {
@@ -177,14 +258,11 @@ async fn delete_cas_content(
     hash: ssri::Integrity,
     app_handle: tauri::AppHandle,
 ) -> HTTPResult {
-    let result = state.with_lock(|state| {
-        let purge_result = state.store.purge(&hash);
-        if purge_result.is_ok() {
-            // Rescan to clean up any dangling references after purge
-            state.rescan(None);
-        }
-        purge_result
-    });
+    let result = state.with_lock(|state| state.store.purge(&hash));
+    if result.is_ok() {
+        // Rescan to clean up any dangling references after purge
+        State::rescan_shared(&state, None);
+    }
 
     // Notify UI to refresh after successful purge and rescan
     if result.is_ok() {
@@ -209,19 +287,9 @@ async fn delete_cas_content(
     }
 }
 
-async fn get_stacks_list(state: SharedState) -> HTTPResult {
-    let stacks = state.with_lock(|state| {
-        // Use the same sorting logic as the GUI (most recently touched first)
-        let stack_items = state.view.root();
-
-        // Convert to UI items with full metadata
-        stack_items
-            .into_iter()
-            .map(|item| crate::ui::with_meta(&state.store, item))
-            .collect::<Vec<_>>()
-    });
-
-    let json_response = serde_json::to_string(&stacks).unwrap();
+async fn get_stacks_list(readers: Readers) -> HTTPResult {
+    // the same order as the GUI: most recently touched first
+    let json_response = serde_json::to_string(&readers.snapshot().stacks).unwrap();
 
     Ok(Response::builder()
         .status(StatusCode::OK)
@@ -229,6 +297,248 @@ async fn get_stacks_list(state: SharedState) -> HTTPResult {
         .body(full(json_response))?)
 }
 
+/// Every top level stack, or just the stack `id_str`, with everything nested inside it.
+async fn get_stacks_tree(id_str: Option<&str>, state: SharedState) -> HTTPResult {
+    let stack_id = match id_str.map(scru128::Scru128Id::from_str) {
+        Some(Ok(stack_id)) => Some(stack_id),
+        Some(Err(_)) => return response_404(),
+        None => None,
+    };
+
+    let result = state.with_lock(|state| {
+        if let Some(stack_id) = stack_id {
+            match state.view.items.get(&stack_id) {
+                Some(item) if item.is_stack => (),
+                _ => return Err(format!("Stack not found: {stack_id}")),
+            }
+        }
+        Ok(state.ui.tree(&state.store, stack_id.as_ref()))
+    });
+
+    match result {
+        Ok(tree) => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .body(full(serde_json::to_string(&tree).unwrap()))?),
+        Err(error) => Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .header("Content-Type", "text/plain")
+            .body(full(error))?),
+    }
+}
+
+async fn set_stack_directory(
+    id_str: &str,
+    params: &std::collections::HashMap<String, String>,
+    state: SharedState,
+    app_handle: tauri::AppHandle,
+) -> HTTPResult {
+    let Ok(stack_id) = scru128::Scru128Id::from_str(id_str) else {
+        return response_404();
+    };
+    // no path unbinds the stack
+    let path = params.get("path").cloned().unwrap_or_default();
+    if !path.is_empty() && !std::path::Path::new(&path).is_dir() {
+        return Ok(Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .header("Content-Type", "text/plain")
+            .body(full(format!("Not a directory: {path}")))?);
+    }
+    let directory = StackDirectory {
+        path,
+        watch: params.contains_key("watch"),
+    };
+
+    let result = state.with_lock(|state| {
+        match state.view.items.get(&stack_id) {
+            Some(item) if item.is_stack => (),
+            _ => return Err(format!("Stack not found: {stack_id}")),
+        }
+        let packet = state.store.update_stack_directory(stack_id, directory);
+        state.merge(&packet);
+        Ok(crate::ui::with_meta(
+            &state.store,
+            state.view.items.get(&stack_id).unwrap(),
+        ))
+    });
+
+    match result {
+        Ok(stack) => {
+            app_handle.emit_all("refresh-items", true).unwrap();
+            Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "application/json")
+                .body(full(serde_json::to_string(&stack).unwrap()))?)
+        }
+        Err(error) => Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .header("Content-Type", "text/plain")
+            .body(full(error))?),
+    }
+}
+
+async fn update_stack(
+    id_str: &str,
+    params: &std::collections::HashMap<String, String>,
+    state: SharedState,
+    app_handle: tauri::AppHandle,
+) -> HTTPResult {
+    let Ok(stack_id) = scru128::Scru128Id::from_str(id_str) else {
+        return response_404();
+    };
+    if params
+        .get("name")
+        .is_some_and(|name| name.trim().is_empty())
+    {
+        return Ok(Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .header("Content-Type", "text/plain")
+            .body(full("A stack's name can't be empty"))?);
+    }
+    let stack_meta = StackMeta {
+        description: params.get("description").cloned(),
+        color: params.get("color").cloned(),
+        icon: params.get("icon").cloned(),
+    };
+
+    let result = state.with_lock(|state| {
+        match state.view.items.get(&stack_id) {
+            Some(item) if item.is_stack => (),
+            _ => return Err(format!("Stack not found: {stack_id}")),
+        }
+        if let Some(name) = params.get("name") {
+            let packet = state.store.rename_stack(stack_id, name.trim());
+            state.merge(&packet);
+        }
+        if stack_meta != StackMeta::default() {
+            let packet = state.store.update_stack_meta(stack_id, stack_meta);
+            state.merge(&packet);
+        }
+        Ok(crate::ui::with_meta(
+            &state.store,
+            state.view.items.get(&stack_id).unwrap(),
+        ))
+    });
+
+    match result {
+        Ok(stack) => {
+            app_handle.emit_all("refresh-items", true).unwrap();
+            Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "application/json")
+                .body(full(serde_json::to_string(&stack).unwrap()))?)
+        }
+        Err(error) => Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .header("Content-Type", "text/plain")
+            .body(full(error))?),
+    }
+}
+
+/// Moves an item within its stack: `to` is `up`, `down`, `top`, `bottom` or an index. `with` is
+/// a comma separated list of other items in the stack to move along with it. Responds with the
+/// stack's items, in their new order.
+async fn move_items(
+    id_str: &str,
+    params: &std::collections::HashMap<String, String>,
+    state: SharedState,
+    app_handle: tauri::AppHandle,
+) -> HTTPResult {
+    let Ok(id) = scru128::Scru128Id::from_str(id_str) else {
+        return response_404();
+    };
+    let movement = match params.get("to").map(|to| to.parse::<Movement>()) {
+        Some(Ok(movement)) => movement,
+        Some(Err(error)) => {
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .header("Content-Type", "text/plain")
+                .body(full(error))?)
+        }
+        None => {
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .header("Content-Type", "text/plain")
+                .body(full("Missing parameter: to"))?)
+        }
+    };
+    let mut ids = vec![id];
+    if let Some(with) = params.get("with") {
+        ids.extend(
+            with.split(',')
+                .filter_map(|id| scru128::Scru128Id::from_str(id).ok()),
+        );
+    }
+
+    let result = state.with_lock(|state| {
+        let Some(stack_id) = state.move_items(&ids, movement) else {
+            return Err(format!("Item not found: {id}"));
+        };
+        let stack = state.view.items.get(&stack_id).unwrap();
+        Ok(state
+            .view
+            .children(stack)
+            .iter()
+            .filter_map(|id| state.view.items.get(id))
+            .map(|item| crate::ui::with_meta(&state.store, item))
+            .collect::<Vec<_>>())
+    });
+
+    match result {
+        Ok(items) => {
+            app_handle.emit_all("refresh-items", true).unwrap();
+            Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "application/json")
+                .body(full(serde_json::to_string(&items).unwrap()))?)
+        }
+        Err(error) => Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .header("Content-Type", "text/plain")
+            .body(full(error))?),
+    }
+}
+
+/// Adds and removes tags on an item: `add` and `remove` are comma separated lists of tags.
+async fn update_tags(
+    id_str: &str,
+    params: &std::collections::HashMap<String, String>,
+    state: SharedState,
+    app_handle: tauri::AppHandle,
+) -> HTTPResult {
+    let Ok(id) = scru128::Scru128Id::from_str(id_str) else {
+        return response_404();
+    };
+    let tags = |key: &str| -> Vec<String> {
+        params
+            .get(key)
+            .map(|tags| tags.split(',').map(|tag| tag.to_string()).collect())
+            .unwrap_or_default()
+    };
+    let (add, remove) = (tags("add"), tags("remove"));
+
+    let result = state.with_lock(|state| {
+        state
+            .tag(id, &add, &remove)
+            .map(|item| crate::ui::with_meta(&state.store, &item))
+            .ok_or_else(|| format!("Item not found: {id}"))
+    });
+
+    match result {
+        Ok(item) => {
+            app_handle.emit_all("refresh-items", true).unwrap();
+            Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "application/json")
+                .body(full(serde_json::to_string(&item).unwrap()))?)
+        }
+        Err(error) => Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .header("Content-Type", "text/plain")
+            .body(full(error))?),
+    }
+}
+
 async fn get_packet_stream(state: SharedState) -> HTTPResult {
     let packets: Vec<_> = state.with_lock(|state| state.store.scan().collect());
 
@@ -240,7 +550,7 @@ async fn get_packet_stream(state: SharedState) -> HTTPResult {
         .body(full(json_response))?)
 }
 
-async fn handle_search(query_str: Option<&str>, state: SharedState) -> HTTPResult {
+async fn handle_search(query_str: Option<&str>, readers: Readers) -> HTTPResult {
     let query_str = query_str.unwrap_or("");
 
     // Parse query parameters
@@ -261,8 +571,7 @@ async fn handle_search(query_str: Option<&str>, state: SharedState) -> HTTPResul
 
     let limit = params.get("limit").and_then(|l| l.parse::<usize>().ok());
 
-    let results =
-        state.with_lock(|state| state.store.index.query(query, limit).unwrap_or_default());
+    let results = readers.index.query(query, limit).unwrap_or_default();
 
     // Convert results to JSON format
     let json_results: Vec<serde_json::Value> = results
@@ -283,8 +592,26 @@ async fn handle_search(query_str: Option<&str>, state: SharedState) -> HTTPResul
         .body(full(json_response))?)
 }
 
+/// How far the indexer has got with the changes queued for it: `lag` is the changes which
+/// aren't searchable yet, and `dropped` is content which was purged before it was indexed.
+async fn get_search_status(readers: Readers) -> HTTPResult {
+    let json_response = serde_json::to_string(&readers.index.status()).unwrap();
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(full(json_response))?)
+}
+
 async fn handle_search_rebuild(state: SharedState, app_handle: tauri::AppHandle) -> HTTPResult {
-    let result = state.with_lock(|state| state.store.rebuild_index());
+    // only clearing the index needs the state, content is read and indexed without it
+    let (index, sources) = state.with_lock(|state| {
+        (
+            state.store.index.clone(),
+            state.store.clear_index(&state.view),
+        )
+    });
+    let result = tokio::task::spawn_blocking(move || index.rebuild(&sources)).await?;
 
     match result {
         Ok((total_items, indexed_count)) => {
@@ -309,10 +636,210 @@ async fn handle_search_rebuild(state: SharedState, app_handle: tauri::AppHandle)
     }
 }
 
-async fn get_view(state: SharedState) -> HTTPResult {
-    let view = state.with_lock(|state| state.view.clone());
+/// Checks the store for damage, and with `repair`, fixes what it can. Responds with the report.
+async fn handle_fsck(
+    params: &std::collections::HashMap<String, String>,
+    state: SharedState,
+    app_handle: tauri::AppHandle,
+) -> HTTPResult {
+    let repair = params.contains_key("repair");
+    let report = state.with_lock(|state| state.store.fsck(repair));
+    if report.repaired {
+        State::rescan_shared(&state, None);
+    }
+
+    if report.repaired {
+        app_handle.emit_all("refresh-items", true).unwrap();
+    }
+
+    let json_response = serde_json::to_string(&report).unwrap();
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(full(json_response))?)
+}
+
+/// Runs the read-only query `q` against the store, if it's kept in SQLite and the endpoint is
+/// turned on. Responds with the rows, as JSON, along with whether there were more rows than
+/// are returned.
+async fn handle_sql(
+    params: &std::collections::HashMap<String, String>,
+    state: SharedState,
+) -> HTTPResult {
+    let (enabled, db_path) = state.with_lock(|state| {
+        let settings = state.store.settings_get();
+        let enabled = settings.and_then(|s| s.sql_endpoint).unwrap_or(false);
+        (enabled, state.db_path.clone())
+    });
+    if !enabled {
+        return Ok(Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .header("Content-Type", "text/plain")
+            .body(full(
+                "The SQL endpoint is off: turn on sql_endpoint in the settings",
+            ))?);
+    }
+    let Some(sql) = params.get("q").cloned() else {
+        return Ok(Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .header("Content-Type", "text/plain")
+            .body(full("Missing query parameter: q"))?);
+    };
+
+    // the query runs over a connection of its own, so it doesn't need the state
+    let rows = tokio::task::spawn_blocking(move || {
+        crate::sqlite::query(std::path::Path::new(&db_path), &sql)
+    })
+    .await?;
+    match rows {
+        Ok(rows) => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .body(full(serde_json::to_string(&rows)?))?),
+        Err(e) => Ok(Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .header("Content-Type", "text/plain")
+            .body(full(e))?),
+    }
+}
+
+/// Syncs with the peers in the settings now, rather than waiting for the next sync. Responds
+/// with a report for each peer.
+async fn handle_sync(state: SharedState, app_handle: tauri::AppHandle) -> HTTPResult {
+    let peers = state.with_lock(|state| {
+        let settings = state.store.settings_get();
+        settings.and_then(|s| s.sync).unwrap_or_default().peers
+    });
+    let replica = crate::sync::App {
+        app: app_handle,
+        state,
+    };
+    let reports =
+        tokio::task::spawn_blocking(move || crate::sync::sync_all(&replica, &peers)).await?;
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(full(serde_json::to_string(&reports)?))?)
+}
+
+/// Compacts the packet log: `dry-run` reports what compacting would do, and `keep-days` keeps
+/// that many days of recent history as it is.
+async fn handle_compact(
+    params: &std::collections::HashMap<String, String>,
+    state: SharedState,
+    app_handle: tauri::AppHandle,
+) -> HTTPResult {
+    let dry_run = params.contains_key("dry-run");
+    let keep_days = match params.get("keep-days").map(|days| days.parse::<u64>()) {
+        Some(Ok(days)) => Some(days),
+        Some(Err(e)) => {
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .header("Content-Type", "text/plain")
+                .body(full(format!("Invalid keep-days: {e}")))?);
+        }
+        None => None,
+    };
+
+    let result = state.with_lock(|state| state.compact(keep_days, dry_run));
+
+    if !dry_run {
+        app_handle.emit_all("refresh-items", true).unwrap();
+    }
+
+    match result {
+        Ok(report) => {
+            let json_response = serde_json::to_string(&report).unwrap();
+            Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "application/json")
+                .body(full(json_response))?)
+        }
+        Err(e) => Ok(Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .header("Content-Type", "text/plain")
+            .body(full(e))?),
+    }
+}
+
+async fn handle_schedules(
+    method: &Method,
+    path: &str,
+    params: &std::collections::HashMap<String, String>,
+    state: SharedState,
+) -> HTTPResult {
+    match (method, path.strip_prefix("/schedules/")) {
+        (&Method::GET, None) => {
+            let schedules = state.with_lock(|state| state.store.schedule_list());
+            Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "application/json")
+                .body(full(serde_json::to_string(&schedules).unwrap()))?)
+        }
+        (&Method::POST, None) => match add_schedule(params, &state) {
+            Ok(schedule) => Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "application/json")
+                .body(full(serde_json::to_string(&schedule).unwrap()))?),
+            Err(error) => Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .header("Content-Type", "text/plain")
+                .body(full(error))?),
+        },
+        (&Method::DELETE, Some(id_str)) => {
+            let removed = scru128::Scru128Id::from_str(id_str)
+                .ok()
+                .and_then(|id| state.with_lock(|state| state.store.schedule_remove(&id)));
+            match removed {
+                Some(schedule) => Ok(Response::builder()
+                    .status(StatusCode::OK)
+                    .header("Content-Type", "text/plain")
+                    .body(full(format!("Removed schedule: {}", schedule.id)))?),
+                None => response_404(),
+            }
+        }
+        _ => response_404(),
+    }
+}
+
+fn add_schedule(
+    params: &std::collections::HashMap<String, String>,
+    state: &SharedState,
+) -> Result<Schedule, String> {
+    let stack_id = params
+        .get("stack_id")
+        .and_then(|id| scru128::Scru128Id::from_str(id).ok())
+        .ok_or("a valid stack_id is required")?;
+    let command = params
+        .get("command")
+        .filter(|command| !command.is_empty())
+        .ok_or("command is required")?;
+    let trigger = match (params.get("every"), params.get("cron")) {
+        (Some(every), None) => Trigger::every(every)?,
+        (None, Some(cron)) => Trigger::cron(cron)?,
+        _ => return Err("exactly one of every or cron is required".to_string()),
+    };
 
-    let json_response = serde_json::to_string(&view).unwrap();
+    state.with_lock(|state| {
+        match state.view.items.get(&stack_id) {
+            Some(item) if item.is_stack => (),
+            _ => return Err(format!("Stack not found: {stack_id}")),
+        }
+        let schedule = Schedule {
+            id: scru128::new(),
+            stack_id,
+            command: command.clone(),
+            trigger,
+            last_output: None,
+        };
+        state.store.schedule_save(&schedule);
+        Ok(schedule)
+    })
+}
+
+async fn get_view(readers: Readers) -> HTTPResult {
+    let json_response = serde_json::to_string(&readers.snapshot().view).unwrap();
 
     Ok(Response::builder()
         .status(StatusCode::OK)
@@ -349,8 +876,11 @@ async fn handle_delete(path: &str, state: SharedState, app_handle: tauri::AppHan
         };
 
         let Some(item) = item else {
-            return Err("Item not found".to_string());
+            return Err((StatusCode::NOT_FOUND, "Item not found".to_string()));
         };
+        if let Err(e) = state.can_delete(&item.id) {
+            return Err((StatusCode::CONFLICT, e));
+        }
 
         // Get the item's hash for CAS cleanup
         let hash = item.hash.clone();
@@ -363,12 +893,14 @@ async fn handle_delete(path: &str, state: SharedState, app_handle: tauri::AppHan
             tracing::warn!("Failed to purge CAS content for {}: {}", hash, e);
         }
 
-        // Trigger rescan to clean up dangling references
-        state.rescan(None);
-
         Ok(format!("Deleted item: {}", item.id))
     });
 
+    // Trigger rescan to clean up dangling references
+    if result.is_ok() {
+        State::rescan_shared(&state, None);
+    }
+
     // Notify UI to refresh after successful deletion
     if result.is_ok() {
         app_handle.emit_all("refresh-items", true).unwrap();
@@ -379,21 +911,21 @@ async fn handle_delete(path: &str, state: SharedState, app_handle: tauri::AppHan
             .status(StatusCode::OK)
             .header("Content-Type", "text/plain")
             .body(full(message))?),
-        Err(error) => Ok(Response::builder()
-            .status(StatusCode::NOT_FOUND)
+        Err((status, error)) => Ok(Response::builder()
+            .status(status)
             .header("Content-Type", "text/plain")
             .body(full(error))?),
     }
 }
 
 fn get_as_html(state: SharedState, hash: ssri::Integrity) -> HTTPResult {
-    let preview = state.with_lock(|state| {
+    let (content, meta, previewer) = state.with_lock(|state| {
         let content = state.store.get_content(&hash);
         let meta = state.store.get_content_meta(&hash).unwrap();
-        state
-            .ui
-            .generate_preview(&content, &meta.mime_type, &meta.content_type, false)
+        (content, meta, state.ui.previewer())
     });
+    // rendered after letting go of the state, as large content can be slow to render
+    let preview = previewer.generate_preview(&content, &meta.mime_type, &meta.content_type, false);
 
     Ok(Response::builder()
         .status(StatusCode::OK)
@@ -421,16 +953,13 @@ async fn get(id: Option<scru128::Scru128Id>, state: SharedState, as_html: bool)
                 return get_as_html(state, item.hash);
             }
 
-            let cache_path = state.with_lock(|state| state.store.cache_path.clone());
-            let reader = cacache::Reader::open_hash(cache_path, item.hash.clone())
-                .await
-                .unwrap();
-
-            let stream = tokio_util::io::ReaderStream::new(reader);
-            let stream = stream
-                .map_ok(Frame::data)
-                .map_err(|e| Box::new(e) as BoxError);
-            let body = BodyExt::boxed(StreamBody::new(stream));
+            let blobs = state.with_lock(|state| state.store.blobs.clone());
+            let hash = item.hash.clone();
+            let Some(content) = tokio::task::spawn_blocking(move || blobs.read(&hash)).await?
+            else {
+                return response_404();
+            };
+            let body = full(content);
 
             let content_type = match meta {
                 Some(ref meta) => match meta.mime_type {
@@ -484,14 +1013,14 @@ async fn post(
     while let Some(frame) = body.frame().await {
         let data = frame?.into_data().unwrap();
         streamer.append(&data);
-        let preview = state.with_lock(|state| {
-            state.ui.generate_preview(
+        let preview = state
+            .with_lock(|state| state.ui.previewer())
+            .generate_preview(
                 &Some(streamer.content.clone()),
                 &MimeType::TextPlain,
                 &"Text".to_string(),
                 true,
-            )
-        });
+            );
 
         let content = String::from_utf8_lossy(&streamer.content);
         let content = Content {
@@ -522,6 +1051,54 @@ async fn post(
         .body(full(response_body))?)
 }
 
+/// Adds a clip to the current stack in one go, as opposed to streaming it like `post`. Supports
+/// images, a content type, and references to files by path.
+async fn add(
+    req: Request<hyper::body::Incoming>,
+    params: std::collections::HashMap<String, String>,
+    state: SharedState,
+    app_handle: tauri::AppHandle,
+) -> HTTPResult {
+    let content = req.into_body().collect().await?.to_bytes();
+
+    let mime_type = match params.get("mime_type").map(|s| s.as_str()) {
+        None | Some("text/plain") => MimeType::TextPlain,
+        Some("image/png") => MimeType::ImagePng,
+        Some(mime_type) => {
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .header("Content-Type", "text/plain")
+                .body(full(format!("Unsupported mime type: {mime_type}")))?)
+        }
+    };
+
+    let packet = state.with_lock(|state| {
+        let stack = state.get_curr_stack();
+        state.ui.select(None); // focus first
+
+        let packet = match params.get("file_ref") {
+            Some(path) => state.store.add_file_ref(std::path::Path::new(path), stack),
+            None => state.store.add(&content, mime_type.clone(), stack),
+        };
+        state.merge(&packet);
+
+        if mime_type == MimeType::TextPlain {
+            if let Some(content_type) = params.get("content_type") {
+                let packet = state
+                    .store
+                    .update_content_type(packet.hash.clone().unwrap(), content_type.clone());
+                state.merge(&packet);
+            }
+        }
+        packet
+    });
+    app_handle.emit_all("refresh-items", true).unwrap();
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .body(full(packet.id.to_string()))?)
+}
+
 fn response_404() -> HTTPResult {
     Ok(Response::builder()
         .status(StatusCode::NOT_FOUND)
@@ -540,10 +1117,18 @@ fn empty() -> BoxBody<Bytes, BoxError> {
         .boxed()
 }
 
+pub fn socket_path(db_path: &str) -> std::path::PathBuf {
+    std::path::Path::new(db_path).join("sock")
+}
+
 pub fn start(app_handle: tauri::AppHandle, state: SharedState, db_path: &str) {
-    let socket_path = std::path::Path::new(db_path).join("sock");
+    let socket_path = socket_path(db_path);
     let _ = std::fs::remove_file(&socket_path);
     let listener = UnixListener::bind(socket_path).unwrap();
+    let readers = state.with_lock(|state| Readers {
+        snapshot: state.snapshot.clone(),
+        index: state.store.index.clone(),
+    });
 
     tauri::async_runtime::spawn(async move {
         loop {
@@ -551,6 +1136,7 @@ pub fn start(app_handle: tauri::AppHandle, state: SharedState, db_path: &str) {
             let io = TokioIo::new(stream);
 
             let state_cloned = state.clone();
+            let readers_cloned = readers.clone();
             let app_handle_clonded = app_handle.clone();
 
             tauri::async_runtime::spawn(async move {
@@ -558,7 +1144,12 @@ pub fn start(app_handle: tauri::AppHandle, state: SharedState, db_path: &str) {
                     .serve_connection(
                         io,
                         service_fn(move |req| {
-                            handle(state_cloned.clone(), app_handle_clonded.clone(), req)
+                            handle(
+                                state_cloned.clone(),
+                                readers_cloned.clone(),
+                                app_handle_clonded.clone(),
+                                req,
+                            )
                         }),
                     )
                     .await
@@ -5,19 +5,29 @@
 
 use tracing::info;
 
+mod ansi;
 mod cli;
 mod clipboard;
 mod commands;
+mod compact;
 mod content_bus;
 mod content_type;
+mod cross_stream;
+mod exec;
 mod http;
+mod rollover;
+mod schedule;
 mod serve;
 mod spotlight;
+mod sqlite;
 mod state;
+mod storage;
 mod store;
+mod sync;
 mod ui;
 mod util;
 mod view;
+mod watcher;
 
 #[cfg(test)]
 mod store_tests;
@@ -0,0 +1,264 @@
+use std::fmt::Write;
+use std::path::Path;
+
+use chrono::{DateTime, TimeZone};
+use serde::{Deserialize, Serialize};
+
+use crate::view::Item;
+
+/// When clips should stop being captured to the current stack, and go to a new stack instead.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "policy", rename_all = "snake_case")]
+pub enum Rollover {
+    /// once the current stack hasn't been touched for this many minutes
+    Idle { minutes: u64 },
+    /// once per calendar day, in local time
+    Daily,
+    /// whenever the git branch checked out in the current context's directory changes, or per
+    /// directory when it isn't a git repository. Switching back to a context picks its stack
+    /// back up.
+    Context,
+    /// never: everything is captured to a single inbox
+    Never,
+}
+
+impl Default for Rollover {
+    fn default() -> Self {
+        Rollover::Idle { minutes: 60 }
+    }
+}
+
+const DEFAULT_NAME_TEMPLATE: &str = "%a, %b %d %Y, %I:%M %p";
+
+impl Rollover {
+    pub fn default_name_template(&self) -> &'static str {
+        match self {
+            Rollover::Idle { .. } | Rollover::Never => DEFAULT_NAME_TEMPLATE,
+            Rollover::Daily => "%a, %b %d %Y",
+            Rollover::Context => "{context}",
+        }
+    }
+
+    /// Whether the current stack, `stack`, is due to roll over at `now`. Context stacks are
+    /// matched by name instead, so are never due.
+    pub fn is_due<Tz: TimeZone>(&self, stack: &Item, now: &DateTime<Tz>) -> bool {
+        match self {
+            Rollover::Idle { minutes } => {
+                let idle = now.timestamp_millis() - stack.last_touched.timestamp() as i64;
+                idle >= *minutes as i64 * 60_000
+            }
+            Rollover::Daily => {
+                let created = now
+                    .timezone()
+                    .timestamp_millis_opt(stack.id.timestamp() as i64)
+                    .single();
+                created.is_none_or(|created| created.date_naive() != now.date_naive())
+            }
+            Rollover::Context | Rollover::Never => false,
+        }
+    }
+}
+
+/// The directory, and git branch, which context stacks are named for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Context {
+    pub dir: String,
+    pub branch: Option<String>,
+}
+
+impl Context {
+    pub fn new(path: &Path) -> Self {
+        Context {
+            dir: path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| path.to_string_lossy().into_owned()),
+            branch: git_branch(path),
+        }
+    }
+}
+
+/// The branch checked out in the git repository at `path`, or the abbreviated commit when the
+/// HEAD is detached.
+pub fn git_branch(path: &Path) -> Option<String> {
+    let git = path.join(".git");
+    // worktrees and submodules have a .git file pointing to the actual git directory
+    let git = if git.is_file() {
+        let gitdir = std::fs::read_to_string(&git).ok()?;
+        path.join(gitdir.strip_prefix("gitdir:")?.trim())
+    } else {
+        git
+    };
+    let head = std::fs::read_to_string(git.join("HEAD")).ok()?;
+    let head = head.trim();
+    match head.strip_prefix("ref: ") {
+        Some(reference) => Some(
+            reference
+                .strip_prefix("refs/heads/")
+                .unwrap_or(reference)
+                .to_string(),
+        ),
+        None => Some(head.chars().take(7).collect()),
+    }
+}
+
+/// Renders the name for a new stack: `template` is strftime formatted, then `{dir}`, `{branch}`
+/// and `{context}` are replaced with the stack's context, if it has one. A name which renders
+/// empty, such as `{branch}` outside a git repository, falls back to the date and time.
+pub fn stack_name<Tz: TimeZone>(
+    template: &str,
+    now: &DateTime<Tz>,
+    context: Option<&Context>,
+) -> String
+where
+    Tz::Offset: std::fmt::Display,
+{
+    let mut name = String::new();
+    if write!(name, "{}", now.format(template)).is_err() {
+        // an invalid format specifier: use the template as is
+        name = template.to_string();
+    }
+
+    let dir = context.map(|context| context.dir.as_str()).unwrap_or("");
+    let branch = context
+        .and_then(|context| context.branch.as_deref())
+        .unwrap_or("");
+    let context = match context {
+        Some(Context {
+            dir,
+            branch: Some(branch),
+        }) => format!("{} ({})", dir, branch),
+        Some(Context { dir, branch: None }) => dir.clone(),
+        None => "".to_string(),
+    };
+
+    let name = name
+        .replace("{dir}", dir)
+        .replace("{branch}", branch)
+        .replace("{context}", &context)
+        .trim()
+        .to_string();
+    if name.is_empty() {
+        return now.format(DEFAULT_NAME_TEMPLATE).to_string();
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::{Duration, Utc};
+
+    use crate::store::{Packet, PacketType, StackLockStatus};
+    use crate::view::View;
+
+    fn stack_at(now: &DateTime<Utc>) -> Item {
+        let id = scru128::Scru128Id::from_fields(now.timestamp_millis() as u64, 0, 0, 0);
+        let mut view = View::new();
+        view.merge(&Packet {
+            id,
+            packet_type: PacketType::Add,
+            source_id: None,
+            hash: Some(ssri::Integrity::from("Stack")),
+            stack_id: None,
+            ephemeral: false,
+            content_type: None,
+            movement: None,
+            lock_status: Some(StackLockStatus::Unlocked),
+            sort_order: None,
+            cross_stream: false,
+            directory: None,
+            stack_meta: None,
+            tags: None,
+            pin_status: None,
+            snapshot: None,
+        });
+        view.items.get(&id).unwrap().clone()
+    }
+
+    #[test]
+    fn test_is_due() {
+        let created = Utc.with_ymd_and_hms(2024, 3, 1, 22, 30, 0).unwrap();
+        let stack = stack_at(&created);
+
+        let idle = Rollover::Idle { minutes: 90 };
+        assert!(!idle.is_due(&stack, &(created + Duration::minutes(89))));
+        assert!(idle.is_due(&stack, &(created + Duration::minutes(90))));
+
+        let daily = Rollover::Daily;
+        assert!(!daily.is_due(&stack, &(created + Duration::minutes(89))));
+        assert!(daily.is_due(&stack, &(created + Duration::minutes(90))));
+
+        let never = Rollover::Never;
+        assert!(!never.is_due(&stack, &(created + Duration::days(365))));
+    }
+
+    #[test]
+    fn test_stack_name() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 1, 22, 30, 0).unwrap();
+        let template = Rollover::default().default_name_template();
+        assert_eq!(
+            stack_name(template, &now, None),
+            "Fri, Mar 01 2024, 10:30 PM"
+        );
+        assert_eq!(stack_name("Inbox", &now, None), "Inbox");
+        assert_eq!(stack_name("%Y-%m-%d %Q", &now, None), "%Y-%m-%d %Q");
+
+        let context = Context {
+            dir: "stacks".to_string(),
+            branch: Some("main".to_string()),
+        };
+        assert_eq!(
+            stack_name("{context}", &now, Some(&context)),
+            "stacks (main)"
+        );
+        assert_eq!(
+            stack_name("{branch} %b %d", &now, Some(&context)),
+            "main Mar 01"
+        );
+
+        let context = Context {
+            dir: "notes".to_string(),
+            branch: None,
+        };
+        assert_eq!(stack_name("{context}", &now, Some(&context)), "notes");
+
+        // names which render empty fall back to the date and time
+        assert_eq!(
+            stack_name("{branch}", &now, Some(&context)),
+            "Fri, Mar 01 2024, 10:30 PM"
+        );
+        assert_eq!(
+            stack_name("{context}", &now, None),
+            "Fri, Mar 01 2024, 10:30 PM"
+        );
+    }
+
+    #[test]
+    fn test_settings() {
+        let context: Rollover = serde_json::from_str(r#"{"policy": "context"}"#).unwrap();
+        assert_eq!(context, Rollover::Context);
+        // the context used to be a fixed path in settings: it's now the current directory
+        let legacy: Rollover =
+            serde_json::from_str(r#"{"policy": "context", "path": "/src/stacks"}"#).unwrap();
+        assert_eq!(legacy, Rollover::Context);
+    }
+
+    #[test]
+    fn test_git_branch() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(git_branch(dir.path()), None);
+
+        std::fs::create_dir(dir.path().join(".git")).unwrap();
+        std::fs::write(dir.path().join(".git/HEAD"), "ref: refs/heads/feature/x\n").unwrap();
+        assert_eq!(git_branch(dir.path()), Some("feature/x".to_string()));
+
+        std::fs::write(
+            dir.path().join(".git/HEAD"),
+            "4b825dc642cb6eb9a060e54bf8d69288fbee4904\n",
+        )
+        .unwrap();
+        assert_eq!(git_branch(dir.path()), Some("4b825dc".to_string()));
+    }
+}
@@ -0,0 +1,490 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use chrono::{DateTime, Datelike, Duration, Local, LocalResult, NaiveDateTime, TimeZone, Timelike};
+use scru128::Scru128Id;
+use serde::{Deserialize, Serialize};
+
+use tauri::Manager;
+
+use crate::ansi;
+use crate::content_type::process_command;
+use crate::exec;
+use crate::http;
+use crate::state::SharedState;
+use crate::store::MimeType;
+use crate::view::View;
+
+/// A command which is run periodically, with its output captured to a stack.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Schedule {
+    pub id: Scru128Id,
+    pub stack_id: Scru128Id,
+    pub command: String,
+    pub trigger: Trigger,
+    // the hash of the last output captured, which later runs are compared against
+    #[serde(default)]
+    pub last_output: Option<ssri::Integrity>,
+}
+
+// scheduled commands which run for longer than this are killed, so they don't hold up the runs
+// which follow
+const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Trigger {
+    /// run every n seconds
+    Every(u64),
+    /// a standard 5 field cron expression, evaluated in local time
+    Cron(String),
+}
+
+impl Trigger {
+    pub fn every(interval: &str) -> Result<Self, String> {
+        parse_interval(interval).map(Trigger::Every)
+    }
+
+    pub fn cron(expr: &str) -> Result<Self, String> {
+        Cron::from_str(expr)?;
+        Ok(Trigger::Cron(expr.to_string()))
+    }
+
+    /// When a newly seen schedule should first run: intervals run straight away, cron
+    /// expressions wait for their next match.
+    pub fn first_run<Tz: TimeZone>(&self, now: DateTime<Tz>) -> Option<DateTime<Tz>> {
+        match self {
+            Trigger::Every(_) => Some(now),
+            Trigger::Cron(_) => self.next_after(now),
+        }
+    }
+
+    pub fn next_after<Tz: TimeZone>(&self, now: DateTime<Tz>) -> Option<DateTime<Tz>> {
+        match self {
+            Trigger::Every(secs) => Some(now + Duration::seconds(*secs as i64)),
+            Trigger::Cron(expr) => Cron::from_str(expr).ok()?.next_after(now),
+        }
+    }
+}
+
+/// Parses intervals like "90s", "5m", "1h" or "2d". A bare number is seconds.
+pub fn parse_interval(interval: &str) -> Result<u64, String> {
+    let interval = interval.trim();
+    let split = interval
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(interval.len());
+    let (n, unit) = interval.split_at(split);
+    let n: u64 = n
+        .parse()
+        .map_err(|_| format!("invalid interval: {interval}"))?;
+    let multiplier = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("invalid interval unit: {unit}")),
+    };
+    if n == 0 {
+        return Err("interval must be greater than zero".to_string());
+    }
+    Ok(n * multiplier)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // cron matches either the day of month or the day of week when both are restricted
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<u32>()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .ok_or_else(|| format!("invalid step: {part}"))?,
+            ),
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (
+                start
+                    .parse()
+                    .map_err(|_| format!("invalid range: {part}"))?,
+                end.parse().map_err(|_| format!("invalid range: {part}"))?,
+            )
+        } else {
+            let n = range
+                .parse()
+                .map_err(|_| format!("invalid value: {part}"))?;
+            // "5/15" means starting at 5, every 15
+            (n, if part.contains('/') { max } else { n })
+        };
+        if start < min || end > max || start > end {
+            return Err(format!("out of range: {part}"));
+        }
+        for n in (start..=end).step_by(step as usize) {
+            bits |= 1 << n;
+        }
+    }
+    Ok(bits)
+}
+
+impl FromStr for Cron {
+    type Err = String;
+
+    fn from_str(expr: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!("expected 5 fields: {expr}"));
+        };
+        let mut weekday_bits = parse_field(weekdays, 0, 7)?;
+        // both 0 and 7 are Sunday
+        if weekday_bits & (1 << 7) != 0 {
+            weekday_bits |= 1;
+        }
+        Ok(Cron {
+            minutes: parse_field(minutes, 0, 59)?,
+            hours: parse_field(hours, 0, 23)?,
+            days: parse_field(days, 1, 31)?,
+            months: parse_field(months, 1, 12)?,
+            weekdays: weekday_bits,
+            days_restricted: days != "*",
+            weekdays_restricted: weekdays != "*",
+        })
+    }
+}
+
+impl Cron {
+    fn matches_day(&self, t: &NaiveDateTime) -> bool {
+        let day = self.days & (1 << t.day()) != 0;
+        let weekday = self.weekdays & (1 << t.weekday().num_days_from_sunday()) != 0;
+        match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+
+    // steps through wall clock times, which are only resolved to instants once they match: as
+    // clocks change, local times can be skipped or happen twice
+    fn next_after<Tz: TimeZone>(&self, now: DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let start = now.naive_local().with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let mut t = start;
+        // skip a whole day or hour at a time when they can't match
+        while t < start + Duration::days(366 * 4) {
+            if self.months & (1 << t.month()) == 0 || !self.matches_day(&t) {
+                t = t.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if self.hours & (1 << t.hour()) == 0 {
+                t = (t + Duration::hours(1)).with_minute(0)?;
+            } else if self.minutes & (1 << t.minute()) == 0 {
+                t += Duration::minutes(1);
+            } else if let Some(next) = resolve(&now, t) {
+                return Some(next);
+            } else {
+                t += Duration::minutes(1);
+            }
+        }
+        None
+    }
+}
+
+// the first instant after `now` at the wall clock time `t`: a time which happens twice, as
+// clocks go back, is taken the first time it's still to come, and a time which is skipped, as
+// clocks go forward, is taken as the first instant after the gap
+fn resolve<Tz: TimeZone>(now: &DateTime<Tz>, t: NaiveDateTime) -> Option<DateTime<Tz>> {
+    let tz = now.timezone();
+    let next = match tz.from_local_datetime(&t) {
+        LocalResult::Single(next) => Some(next),
+        LocalResult::Ambiguous(earliest, latest) => {
+            [earliest, latest].into_iter().find(|next| next > now)
+        }
+        // gaps are never longer than a day
+        LocalResult::None => (1..=24 * 60)
+            .map(|minutes| t + Duration::minutes(minutes))
+            .find_map(|t| tz.from_local_datetime(&t).earliest()),
+    };
+    next.filter(|next| next > now)
+}
+
+/// Returns true if output with `hash` should be added to the schedule's stack: it's only new
+/// output if it differs from the last output the schedule captured. Schedules from before that
+/// was kept are compared against the stack's most recent item.
+pub fn is_new_output(view: &View, schedule: &Schedule, hash: &ssri::Integrity) -> bool {
+    let Some(stack) = view.items.get(&schedule.stack_id) else {
+        return false;
+    };
+    let last = schedule.last_output.clone().or_else(|| {
+        let latest = view
+            .children(stack)
+            .first()
+            .and_then(|id| view.items.get(id));
+        latest.map(|latest| latest.hash.clone())
+    });
+    last.as_ref() != Some(hash)
+}
+
+#[tracing::instrument(skip(app, state))]
+async fn run(app: tauri::AppHandle, state: SharedState, schedule: Schedule) {
+    let (cooked_command, content_type) = process_command(&schedule.command);
+
+    let (settings, socket) = state.with_lock(|state| {
+        (
+            state.shell_settings(Some(schedule.stack_id)),
+            http::socket_path(&state.db_path),
+        )
+    });
+    let env = exec::Env {
+        item_id: None,
+        stack_id: Some(schedule.stack_id),
+        hash: None,
+        socket,
+    };
+    let mut cmd = exec::shell_command(&settings, &cooked_command, &env);
+    cmd.stdin(std::process::Stdio::null()).kill_on_drop(true);
+
+    // the command is killed when its output is dropped, on timing out
+    let output = match tokio::time::timeout(TIMEOUT, cmd.output()).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(e)) => {
+            tracing::error!("Failed to run scheduled command: {}", e);
+            return;
+        }
+        Err(_) => {
+            tracing::error!("Scheduled command timed out after {:?}", TIMEOUT);
+            return;
+        }
+    };
+    if !output.status.success() {
+        tracing::warn!(code = output.status.code(), "scheduled command failed");
+    }
+    if output.stdout.is_empty() {
+        return;
+    }
+
+    let hash = ssri::Integrity::from(&output.stdout);
+    let added = state.with_lock(|state| {
+        // the schedule may have been removed while its command ran
+        let schedules = state.store.schedule_list();
+        let Some(mut schedule) = schedules.into_iter().find(|s| s.id == schedule.id) else {
+            return false;
+        };
+        if !is_new_output(&state.view, &schedule, &hash) {
+            return false;
+        }
+        schedule.last_output = Some(hash.clone());
+        state.store.schedule_save(&schedule);
+
+        let packet = state
+            .store
+            .add(&output.stdout, MimeType::TextPlain, schedule.stack_id);
+        state.merge(&packet);
+
+        let content_type =
+            content_type.or_else(|| ansi::has_sgr(&output.stdout).then(|| "ANSI".to_string()));
+        if let Some(content_type) = content_type {
+            let packet = state
+                .store
+                .update_content_type(packet.hash.unwrap(), content_type);
+            state.merge(&packet);
+        }
+        true
+    });
+
+    if added {
+        app.emit_all("refresh-items", true).unwrap();
+    }
+}
+
+pub fn start(app: tauri::AppHandle, state: SharedState) {
+    tauri::async_runtime::spawn(async move {
+        tracing::info!(name = "schedule", "booting");
+        let mut next_runs: HashMap<Scru128Id, DateTime<Local>> = HashMap::new();
+        let mut running: HashMap<Scru128Id, tokio::task::JoinHandle<()>> = HashMap::new();
+        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(1));
+
+        loop {
+            ticker.tick().await;
+            let schedules = state.with_lock(|state| state.store.schedule_list());
+            let now = Local::now();
+
+            next_runs.retain(|id, _| schedules.iter().any(|s| &s.id == id));
+            running.retain(|_, handle| !handle.is_finished());
+
+            for schedule in schedules {
+                let Some(next) = next_runs
+                    .get(&schedule.id)
+                    .cloned()
+                    .or_else(|| schedule.trigger.first_run(now))
+                else {
+                    continue;
+                };
+                if next > now {
+                    next_runs.insert(schedule.id, next);
+                    continue;
+                }
+                // a schedule with no next run is looked at afresh on the next tick, rather than
+                // left due
+                match schedule.trigger.next_after(now) {
+                    Some(next) => next_runs.insert(schedule.id, next),
+                    None => next_runs.remove(&schedule.id),
+                };
+                // don't pile up runs of a slow command
+                if running.contains_key(&schedule.id) {
+                    continue;
+                }
+                let id = schedule.id;
+                let handle = tokio::spawn(run(app.clone(), state.clone(), schedule));
+                running.insert(id, handle);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::Utc;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval("90"), Ok(90));
+        assert_eq!(parse_interval("5m"), Ok(300));
+        assert_eq!(parse_interval("1h"), Ok(3600));
+        assert_eq!(parse_interval("2d"), Ok(172800));
+        assert!(parse_interval("0s").is_err());
+        assert!(parse_interval("5w").is_err());
+        assert!(parse_interval("m").is_err());
+    }
+
+    #[test]
+    fn test_cron_next_after() {
+        let next = |expr: &str, now: &str| Trigger::cron(expr).unwrap().next_after(at(now));
+
+        assert_eq!(
+            next("*/5 * * * *", "2024-03-01T10:02:30Z"),
+            Some(at("2024-03-01T10:05:00Z"))
+        );
+        assert_eq!(
+            next("0 * * * *", "2024-03-01T10:00:00Z"),
+            Some(at("2024-03-01T11:00:00Z"))
+        );
+        // 2024-03-01 is a Friday, next Monday is the 4th
+        assert_eq!(
+            next("30 9 * * 1-5", "2024-03-01T10:00:00Z"),
+            Some(at("2024-03-04T09:30:00Z"))
+        );
+        assert_eq!(
+            next("0 0 1 1 *", "2024-03-01T10:00:00Z"),
+            Some(at("2025-01-01T00:00:00Z"))
+        );
+        // day of month or day of week, when both are given
+        assert_eq!(
+            next("0 12 15 * 0", "2024-03-01T13:00:00Z"),
+            Some(at("2024-03-03T12:00:00Z"))
+        );
+        assert_eq!(
+            next("0 0 30 2 *", "2024-03-01T10:00:00Z"),
+            None,
+            "February 30th never happens"
+        );
+
+        assert!(Trigger::cron("* * * *").is_err());
+        assert!(Trigger::cron("60 * * * *").is_err());
+        assert!(Trigger::cron("*/0 * * * *").is_err());
+    }
+
+    #[test]
+    fn test_cron_next_after_dst() {
+        use chrono_tz::America::New_York;
+
+        let at = |s: &str| at(s).with_timezone(&New_York);
+        let next = |expr: &str, now| Trigger::cron(expr).unwrap().next_after(now);
+
+        // clocks go back at 2am on 2026-11-01, so 1:xx happens twice
+        assert_eq!(
+            next("0 9 * * *", at("2026-10-31T13:00:00Z")),
+            Some(at("2026-11-01T14:00:00Z"))
+        );
+        assert_eq!(
+            next("30 1 * * *", at("2026-10-31T06:00:00Z")),
+            Some(at("2026-11-01T05:30:00Z")),
+            "the first 1:30"
+        );
+        assert_eq!(
+            next("*/30 * * * *", at("2026-11-01T06:10:00Z")),
+            Some(at("2026-11-01T06:30:00Z")),
+            "the second 1:30, once the first has passed"
+        );
+        assert_eq!(
+            next("0 * * * *", at("2026-11-01T05:00:00Z")),
+            Some(at("2026-11-01T07:00:00Z")),
+            "hours run once by the clock on the wall"
+        );
+
+        // clocks go forward at 2am on 2026-03-08, so 2:xx never happens
+        assert_eq!(
+            next("30 2 * * *", at("2026-03-08T06:00:00Z")),
+            Some(at("2026-03-08T07:00:00Z")),
+            "runs as the clocks go forward"
+        );
+        assert_eq!(
+            next("30 2 * * *", at("2026-03-08T07:00:00Z")),
+            Some(at("2026-03-09T06:30:00Z"))
+        );
+        assert_eq!(
+            next("0 0 * * *", at("2026-03-07T12:00:00Z")),
+            Some(at("2026-03-08T05:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn test_is_new_output() {
+        let mut store = crate::store::Store::in_memory();
+        let mut view = View::new();
+
+        let stack = store.add_stack(b"Watch", crate::store::StackLockStatus::Unlocked);
+        view.merge(&stack);
+        let mut schedule = Schedule {
+            id: scru128::new(),
+            stack_id: stack.id,
+            command: "date".to_string(),
+            trigger: Trigger::Every(60),
+            last_output: None,
+        };
+        let one = ssri::Integrity::from("one");
+        let two = ssri::Integrity::from("two");
+        assert!(is_new_output(&view, &schedule, &one));
+
+        // schedules which haven't captured anything yet compare against the latest item
+        let packet = store.add(b"one", MimeType::TextPlain, stack.id);
+        view.merge(&packet);
+        assert!(!is_new_output(&view, &schedule, &one));
+        assert!(is_new_output(&view, &schedule, &two));
+
+        // once they have, other items added to the stack don't matter
+        schedule.last_output = Some(one.clone());
+        let packet = store.add(b"two", MimeType::TextPlain, stack.id);
+        view.merge(&packet);
+        assert!(!is_new_output(&view, &schedule, &one));
+        assert!(is_new_output(&view, &schedule, &two));
+
+        schedule.stack_id = scru128::new();
+        assert!(!is_new_output(&view, &schedule, &two));
+    }
+}
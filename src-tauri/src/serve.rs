@@ -11,9 +11,13 @@ use tracing_subscriber::util::SubscriberInitExt;
 use crate::clipboard;
 use crate::commands;
 use crate::content_bus;
+use crate::cross_stream;
 use crate::http;
+use crate::schedule;
 use crate::spotlight;
-use crate::state::{SharedState, State};
+use crate::state::{self, SharedState, State};
+use crate::sync;
+use crate::watcher;
 
 pub async fn serve<A: tauri::Assets>(context: tauri::Context<A>, db_path: String) {
     init_tracing();
@@ -56,6 +60,9 @@ pub async fn serve<A: tauri::Assets>(context: tauri::Context<A>, db_path: String
             commands::store_nav_reset,
             commands::store_nav_set_filter,
             commands::store_nav_select,
+            commands::store_nav_toggle_selection,
+            commands::store_nav_select_range,
+            commands::store_nav_clear_selection,
             commands::store_nav_select_up,
             commands::store_nav_select_down,
             commands::store_nav_select_up_stack,
@@ -63,15 +70,25 @@ pub async fn serve<A: tauri::Assets>(context: tauri::Context<A>, db_path: String
             commands::store_nav_select_left,
             commands::store_nav_select_right,
             commands::store_copy_to_clipboard,
+            commands::store_copy_selected_to_clipboard,
             commands::store_delete,
+            commands::store_delete_selected,
             commands::store_undo,
             commands::store_new_note,
             commands::store_edit_note,
             commands::store_move_up,
             commands::store_touch,
             commands::store_move_down,
+            commands::store_move,
             commands::store_stack_lock,
             commands::store_stack_unlock,
+            commands::store_set_pinned,
+            commands::store_stack_set_directory,
+            commands::store_stack_rename,
+            commands::store_stack_fork,
+            commands::store_stack_merge,
+            commands::store_stack_split,
+            commands::store_stack_set_meta,
             commands::store_stack_sort_auto,
             commands::store_stack_sort_manual,
             commands::store_settings_save,
@@ -80,7 +97,10 @@ pub async fn serve<A: tauri::Assets>(context: tauri::Context<A>, db_path: String
             commands::store_pipe_to_command,
             commands::store_pipe_stack_to_shell,
             commands::store_set_content_type,
+            commands::store_set_selected_content_type,
+            commands::store_strip_ansi,
             commands::store_add_to_stack,
+            commands::store_add_selected_to_stack,
             commands::store_add_to_new_stack,
             commands::store_new_stack,
             commands::store_mark_as_cross_stream,
@@ -101,15 +121,20 @@ pub async fn serve<A: tauri::Assets>(context: tauri::Context<A>, db_path: String
                 let _ = window.move_window(Position::Center);
             }
 
-            let (packet_sender, _) = std::sync::mpsc::channel();
+            let (packet_sender, packet_receiver) = std::sync::mpsc::channel();
             let state = State::new(&db_path, packet_sender);
             let mutex = tracing_mutex_span::TracingMutexSpan::new("SharedState", state);
             let state: SharedState = Arc::new(mutex);
             app.manage(state.clone());
+            state::start_publisher(state.clone());
 
             content_bus::spawn_tiktokens(app.handle(), state.clone());
 
             http::start(app.handle().clone(), state.clone(), &db_path);
+            schedule::start(app.handle(), state.clone());
+            sync::start(app.handle(), state.clone());
+            cross_stream::start(app.handle(), state.clone());
+            watcher::start(app.handle(), state.clone(), packet_receiver);
             clipboard::start(app.handle(), &state);
 
             let shortcut = state.with_lock(|state| {
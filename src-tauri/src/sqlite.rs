@@ -0,0 +1,398 @@
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use rusqlite::{OpenFlags, OptionalExtension};
+use ssri::Integrity;
+
+use crate::storage::{Blobs, Cacache, Entry, Storage, Table};
+
+// the database, in the store's directory
+pub const FILE: &str = "stacks.sqlite";
+
+// Every table keeps its entries as key and value, as the store encodes them. The fields of
+// packets and content meta are pulled out of their values into columns too, with indexes on
+// the ones they're looked up by, so they can be queried with plain SQL.
+const FIELDS: [(&str, &[&str]); 2] = [
+    (
+        "packets",
+        &["id", "packet_type", "source_id", "stack_id", "hash"],
+    ),
+    (
+        "content_meta",
+        &["hash", "mime_type", "content_type", "terse", "source"],
+    ),
+];
+const INDEXES: [(&str, &str); 4] = [
+    ("packets", "source_id"),
+    ("packets", "stack_id"),
+    ("packets", "hash"),
+    ("content_meta", "hash"),
+];
+
+// a column for the field `name` of a value in the envelope the store encodes packets and
+// content meta in: values from before the envelope have none
+fn field(name: &str) -> String {
+    format!(
+        "{name} TEXT GENERATED ALWAYS AS (CASE WHEN substr(value, 1, 2) = X'FF01' \
+         THEN json_extract(CAST(substr(value, 3) AS TEXT), '$.{name}') END) VIRTUAL"
+    )
+}
+
+fn create_table(name: &str) -> String {
+    let mut columns = vec![
+        "key BLOB PRIMARY KEY".to_string(),
+        "value BLOB NOT NULL".to_string(),
+    ];
+    if let Some((_, fields)) = FIELDS.iter().find(|(table, _)| *table == name) {
+        columns.extend(fields.iter().map(|name| field(name)));
+    }
+    if name == "settings" {
+        columns.push("name TEXT GENERATED ALWAYS AS (CAST(key AS TEXT)) VIRTUAL".to_string());
+    }
+
+    let mut sql = format!(
+        "CREATE TABLE IF NOT EXISTS {name} ({});",
+        columns.join(", ")
+    );
+    for (_, column) in INDEXES.iter().filter(|(table, _)| *table == name) {
+        sql += &format!("CREATE INDEX IF NOT EXISTS {name}_{column} ON {name} ({column});");
+    }
+    sql
+}
+
+// the store's meta table holds its settings, along with a few markers
+fn table_name(name: &str) -> &str {
+    match name {
+        "meta" => "settings",
+        name => name,
+    }
+}
+
+/// A store kept in a single SQLite database. Blobs are kept in the database too, unless they're
+/// kept on disk, in a CAS alongside it, as stores in sled keep them.
+pub struct SqliteStorage {
+    conn: Arc<Mutex<rusqlite::Connection>>,
+    path: PathBuf,
+    inline_blobs: bool,
+}
+
+impl SqliteStorage {
+    /// Opens the store at `path`, creating it if it doesn't exist. Whether blobs are kept
+    /// inline is settled when the store is created: a store with a CAS keeps its blobs there.
+    pub fn open(path: &Path, inline_blobs: bool) -> Result<Self, Box<dyn std::error::Error>> {
+        let inline_blobs = inline_blobs && !path.join("cas").is_dir();
+        if !inline_blobs {
+            std::fs::create_dir_all(path.join("cas"))?;
+        }
+
+        let conn = rusqlite::Connection::open(path.join(FILE))?;
+        // readers, such as `stacks sql`, don't hold up the store, or get held up by it
+        conn.execute_batch("PRAGMA journal_mode = WAL;")?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS blobs (hash TEXT PRIMARY KEY, content BLOB NOT NULL);",
+        )?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+            path: path.to_path_buf(),
+            inline_blobs,
+        })
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn table(&self, name: &str) -> Arc<dyn Table> {
+        let name = table_name(name).to_string();
+        let sql = create_table(&name);
+        self.conn.lock().unwrap().execute_batch(&sql).unwrap();
+        Arc::new(SqliteTable {
+            conn: self.conn.clone(),
+            name,
+        })
+    }
+
+    fn blobs(&self) -> Arc<dyn Blobs> {
+        if self.inline_blobs {
+            Arc::new(SqliteBlobs {
+                conn: self.conn.clone(),
+            })
+        } else {
+            Arc::new(Cacache::new(self.path.join("cas")))
+        }
+    }
+
+    fn index_path(&self) -> Option<PathBuf> {
+        Some(self.path.join("index"))
+    }
+}
+
+pub struct SqliteTable {
+    conn: Arc<Mutex<rusqlite::Connection>>,
+    name: String,
+}
+
+impl SqliteTable {
+    fn conn(&self) -> std::sync::MutexGuard<'_, rusqlite::Connection> {
+        self.conn.lock().unwrap()
+    }
+}
+
+impl Table for SqliteTable {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let sql = format!("SELECT value FROM {} WHERE key = ?1", self.name);
+        self.conn()
+            .query_row(&sql, [key], |row| row.get(0))
+            .optional()
+            .unwrap()
+    }
+
+    fn insert(&self, key: &[u8], value: &[u8]) {
+        let sql = format!(
+            "INSERT OR REPLACE INTO {} (key, value) VALUES (?1, ?2)",
+            self.name
+        );
+        self.conn().execute(&sql, (key, value)).unwrap();
+    }
+
+    fn remove(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let sql = format!("DELETE FROM {} WHERE key = ?1 RETURNING value", self.name);
+        self.conn()
+            .query_row(&sql, [key], |row| row.get(0))
+            .optional()
+            .unwrap()
+    }
+
+    // reads the entries up front, so the table can change while they're iterated over
+    fn range(&self, from: Bound<Vec<u8>>) -> Box<dyn Iterator<Item = Entry> + '_> {
+        let (condition, key) = match from {
+            Bound::Included(key) => ("WHERE key >= ?1", key),
+            Bound::Excluded(key) => ("WHERE key > ?1", key),
+            // every key sorts after the empty key
+            Bound::Unbounded => ("WHERE key >= ?1", Vec::new()),
+        };
+        let sql = format!(
+            "SELECT key, value FROM {} {condition} ORDER BY key",
+            self.name
+        );
+        let conn = self.conn();
+        let mut statement = conn.prepare(&sql).unwrap();
+        let entries: Vec<Entry> = statement
+            .query_map([key], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .flatten()
+            .collect();
+        Box::new(entries.into_iter())
+    }
+
+    fn last(&self) -> Option<Entry> {
+        let sql = format!(
+            "SELECT key, value FROM {} ORDER BY key DESC LIMIT 1",
+            self.name
+        );
+        self.conn()
+            .query_row(&sql, (), |row| Ok((row.get(0)?, row.get(1)?)))
+            .optional()
+            .unwrap()
+    }
+
+    fn len(&self) -> usize {
+        let sql = format!("SELECT COUNT(*) FROM {}", self.name);
+        self.conn()
+            .query_row(&sql, (), |row| row.get::<_, i64>(0))
+            .unwrap() as usize
+    }
+
+    fn clear(&self) {
+        let sql = format!("DELETE FROM {}", self.name);
+        self.conn().execute(&sql, ()).unwrap();
+    }
+
+    fn apply(&self, remove: Vec<Vec<u8>>, insert: Vec<Entry>) {
+        let mut conn = self.conn();
+        let transaction = conn.transaction().unwrap();
+        {
+            let sql = format!("DELETE FROM {} WHERE key = ?1", self.name);
+            let mut statement = transaction.prepare(&sql).unwrap();
+            for key in remove {
+                statement.execute([key]).unwrap();
+            }
+            let sql = format!(
+                "INSERT OR REPLACE INTO {} (key, value) VALUES (?1, ?2)",
+                self.name
+            );
+            let mut statement = transaction.prepare(&sql).unwrap();
+            for (key, value) in insert {
+                statement.execute((key, value)).unwrap();
+            }
+        }
+        transaction.commit().unwrap();
+    }
+}
+
+/// Blobs kept inline, in the database's `blobs` table, keyed by their hash.
+pub struct SqliteBlobs {
+    conn: Arc<Mutex<rusqlite::Connection>>,
+}
+
+impl Blobs for SqliteBlobs {
+    fn write(&self, content: &[u8]) -> Integrity {
+        let hash = Integrity::from(content);
+        self.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT OR IGNORE INTO blobs (hash, content) VALUES (?1, ?2)",
+                (hash.to_string(), content),
+            )
+            .unwrap();
+        hash
+    }
+
+    fn read(&self, hash: &Integrity) -> Option<Vec<u8>> {
+        let content = self.read_unchecked(hash)?;
+        hash.check(&content).ok()?;
+        Some(content)
+    }
+
+    fn exists(&self, hash: &Integrity) -> bool {
+        self.conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT 1 FROM blobs WHERE hash = ?1",
+                [hash.to_string()],
+                |_| Ok(()),
+            )
+            .optional()
+            .unwrap()
+            .is_some()
+    }
+
+    fn remove(&self, hash: &Integrity) -> std::io::Result<()> {
+        let removed = self
+            .conn
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM blobs WHERE hash = ?1", [hash.to_string()])
+            .map_err(std::io::Error::other)?;
+        match removed {
+            0 => Err(std::io::ErrorKind::NotFound.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn list(&self) -> Vec<Integrity> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare("SELECT hash FROM blobs").unwrap();
+        let hashes: Vec<String> = statement
+            .query_map((), |row| row.get(0))
+            .unwrap()
+            .flatten()
+            .collect();
+        hashes.iter().filter_map(|hash| hash.parse().ok()).collect()
+    }
+
+    fn read_unchecked(&self, hash: &Integrity) -> Option<Vec<u8>> {
+        self.conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT content FROM blobs WHERE hash = ?1",
+                [hash.to_string()],
+                |row| row.get(0),
+            )
+            .optional()
+            .unwrap()
+    }
+}
+
+pub type Row = serde_json::Map<String, serde_json::Value>;
+
+// queries stop once they've returned this many rows, or this many bytes of values
+pub const MAX_ROWS: usize = 1000;
+const MAX_BYTES: usize = 16 * 1024 * 1024;
+// and are interrupted once they've run for this long
+const QUERY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// The rows a query returned, and whether it was stopped short of returning them all.
+#[derive(serde::Serialize, Debug, Clone, PartialEq)]
+pub struct Rows {
+    pub rows: Vec<Row>,
+    pub truncated: bool,
+}
+
+/// Runs the query `sql` against the store at `path`, over a read-only connection, so it can't
+/// change the store. Rows come back keyed by column name, with blobs encoded as base64. Only
+/// the first `MAX_ROWS` rows are returned, and queries which run too long are interrupted.
+pub fn query(path: &Path, sql: &str) -> Result<Rows, String> {
+    query_with_timeout(path, sql, QUERY_TIMEOUT)
+}
+
+pub fn query_with_timeout(
+    path: &Path,
+    sql: &str,
+    timeout: std::time::Duration,
+) -> Result<Rows, String> {
+    use base64::{engine::general_purpose, Engine as _};
+    use rusqlite::types::ValueRef;
+
+    let file = path.join(FILE);
+    if !file.exists() {
+        return Err(
+            "The store isn't kept in SQLite: copy it with `stacks migrate --backend sqlite`"
+                .to_string(),
+        );
+    }
+    let conn = rusqlite::Connection::open_with_flags(file, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| e.to_string())?;
+    let deadline = std::time::Instant::now() + timeout;
+    conn.progress_handler(1000, Some(move || std::time::Instant::now() > deadline));
+    let timed_out = |e: rusqlite::Error| match e.sqlite_error_code() {
+        Some(rusqlite::ErrorCode::OperationInterrupted) => format!(
+            "The query ran for longer than {:?}, so it was stopped",
+            timeout
+        ),
+        _ => e.to_string(),
+    };
+
+    let mut statement = conn.prepare(sql).map_err(timed_out)?;
+    let columns: Vec<String> = statement
+        .column_names()
+        .into_iter()
+        .map(String::from)
+        .collect();
+
+    let mut rows = statement.query(()).map_err(timed_out)?;
+    let mut results = Vec::new();
+    let mut bytes = 0;
+    while let Some(row) = rows.next().map_err(timed_out)? {
+        if results.len() == MAX_ROWS || bytes > MAX_BYTES {
+            return Ok(Rows {
+                rows: results,
+                truncated: true,
+            });
+        }
+        let mut result = Row::new();
+        for (i, column) in columns.iter().enumerate() {
+            let value = match row.get_ref(i).map_err(|e| e.to_string())? {
+                ValueRef::Null => serde_json::Value::Null,
+                ValueRef::Integer(n) => n.into(),
+                ValueRef::Real(n) => n.into(),
+                ValueRef::Text(text) => {
+                    bytes += text.len();
+                    String::from_utf8_lossy(text).into()
+                }
+                ValueRef::Blob(blob) => {
+                    bytes += blob.len();
+                    general_purpose::STANDARD.encode(blob).into()
+                }
+            };
+            result.insert(column.clone(), value);
+        }
+        results.push(result);
+    }
+    Ok(Rows {
+        rows: results,
+        truncated: false,
+    })
+}
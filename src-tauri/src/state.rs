@@ -1,15 +1,34 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
 use std::sync::mpsc::Sender;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 
 use chrono::prelude::*;
 use scru128::Scru128Id;
+use ssri::Integrity;
 
 use tracing_mutex_span::TracingMutexSpan;
 
-pub use crate::store::{Packet, StackLockStatus, Store};
+use crate::rollover::{self, Rollover};
+use crate::store::{
+    normalize_tag, CompactReport, MimeType, Movement, PacketType, StackMeta, StackSortOrder, Tags,
+};
+pub use crate::store::{Packet, ShellSettings, StackLockStatus, Store};
+use crate::sync::Change;
 pub use crate::ui::UI;
 pub use crate::view::View;
 
+// the number of packets replayed on top of the last snapshot which warrants taking a new one
+const SNAPSHOT_INTERVAL: usize = 1000;
+
+/// The packets written by a single operation on whole stacks, which one undo reverts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Operation {
+    pub packets: Vec<Scru128Id>,
+    // what to focus once the operation is undone
+    pub focus: Option<Scru128Id>,
+}
+
 pub struct State {
     pub view: View,
     pub store: Store,
@@ -20,8 +39,39 @@ pub struct State {
     // information, we use skip_change_num to ignore the change id associated with the item.
     pub skip_change_num: Option<i64>,
     pub packet_sender: Sender<View>,
+    pub db_path: String,
+    pub last_operation: Option<Operation>,
+    pub snapshot: SharedSnapshot,
+    // the view has changed since it was last published
+    stale: bool,
+    // wakes the publisher, once there is one, see `start_publisher`
+    publisher: Option<Sender<()>>,
 }
 
+/// An immutable copy of the state as of its last change, for readers which shouldn't have to
+/// wait on writers for the state's lock.
+pub struct Snapshot {
+    pub view: View,
+    // the top level stacks, most recently touched first
+    pub stacks: Vec<crate::ui::Item>,
+}
+
+impl Snapshot {
+    fn new(view: &View, store: &Store) -> Self {
+        let stacks = view
+            .root()
+            .into_iter()
+            .map(|item| crate::ui::with_meta(store, item))
+            .collect();
+        Self {
+            view: view.clone(),
+            stacks,
+        }
+    }
+}
+
+pub type SharedSnapshot = Arc<RwLock<Arc<Snapshot>>>;
+
 impl State {
     fn garbage_collect_delete_packet(store: &mut Store, packet: &Packet) {
         if let Some(source_id) = packet.source_id {
@@ -42,42 +92,58 @@ impl State {
 
     pub fn new(db_path: &str, packet_sender: Sender<View>) -> Self {
         let mut store = Store::new(db_path);
-        let mut view = View::new();
-        let mut delete_packets = Vec::new();
-
-        store.scan().for_each(|p| {
-            // Collect delete packets for garbage collection
-            if p.packet_type == crate::store::PacketType::Delete {
-                delete_packets.push(p.clone());
-            }
-
-            view.merge(&p);
-        });
 
-        // Garbage collection: process collected delete packets
-        let had_deletes = !delete_packets.is_empty();
-        for packet in delete_packets {
-            Self::garbage_collect_delete_packet(&mut store, &packet);
+        // Garbage collection: items deleted since the last start are collected, wherever they
+        // are in the log, including before the view snapshot
+        let delete_packets = store.delete_packets();
+        for packet in &delete_packets {
+            Self::garbage_collect_delete_packet(&mut store, packet);
         }
 
-        // Rebuild view after garbage collection to remove dangling references
-        if had_deletes {
-            view = View::new();
-            store.scan().for_each(|p| view.merge(&p));
-        }
+        // Collecting usually drops the view snapshot, so a fresh one is saved, rather than
+        // replaying everything again on the next start.
+        let snapshot_after = match delete_packets.is_empty() {
+            true => SNAPSHOT_INTERVAL,
+            false => 1,
+        };
+        let view = Self::replay(&store, snapshot_after);
 
+        Self::write_pinned_name(&mut store);
         let ui = UI::new(&view);
+        let snapshot = Arc::new(RwLock::new(Arc::new(Snapshot::new(&view, &store))));
         let state = Self {
             view,
             store,
             ui,
             skip_change_num: None,
             packet_sender,
+            db_path: db_path.to_string(),
+            last_operation: None,
+            snapshot,
+            stale: false,
+            publisher: None,
         };
         let _ = state.packet_sender.send(state.view.clone());
         state
     }
 
+    /// The shell settings for running a command in the context of `stack_id`: commands in a
+    /// stack bound to a directory run from that directory.
+    pub fn shell_settings(&self, stack_id: Option<Scru128Id>) -> ShellSettings {
+        let mut settings = self
+            .store
+            .settings_get()
+            .and_then(|settings| settings.shell)
+            .unwrap_or_default();
+        let directory = stack_id
+            .and_then(|id| self.view.items.get(&id))
+            .and_then(|stack| stack.directory.as_ref());
+        if let Some(directory) = directory {
+            settings.cwd = Some(directory.path.clone());
+        }
+        settings
+    }
+
     pub fn nav_set_filter(&mut self, filter: &str, content_type: &str) {
         self.ui
             .set_filter(&self.store, &self.view, filter, content_type);
@@ -89,58 +155,703 @@ impl State {
     }
 
     pub fn get_curr_stack(&mut self) -> Scru128Id {
-        let curr_stack = self
+        self.get_curr_stack_at(Local::now())
+    }
+
+    /// The stack new clips are captured to at `now`, which is rolled over to a new stack
+    /// according to the rollover policy from settings.
+    pub fn get_curr_stack_at<Tz: TimeZone>(&mut self, now: DateTime<Tz>) -> Scru128Id
+    where
+        Tz::Offset: std::fmt::Display,
+    {
+        let settings = self.store.settings_get().unwrap_or_default();
+        let rollover = settings.rollover.unwrap_or_default();
+        let template = settings
+            .stack_name_template
+            .unwrap_or_else(|| rollover.default_name_template().to_string());
+        // the context is only looked up when it's needed: for the policy, or the name
+        let context = (rollover == Rollover::Context || template.contains('{'))
+            .then(|| self.context_dir())
+            .flatten()
+            .map(|dir| rollover::Context::new(&dir));
+        let stack_name = rollover::stack_name(&template, &now, context.as_ref());
+
+        if rollover == Rollover::Context {
+            return self.get_stack_by_name(&stack_name);
+        }
+
+        let curr_stack = self.view.root().into_iter().find(|item| !item.locked);
+        if let Some(item) = curr_stack {
+            if !rollover.is_due(item, &now) {
+                return item.id;
+            }
+        }
+
+        let packet = self
+            .store
+            .add_stack(stack_name.as_bytes(), StackLockStatus::Unlocked);
+
+        self.merge(&packet);
+        packet.id
+    }
+
+    /// The directory clips are captured in the context of: the directory the focused stack is
+    /// bound to, or the nearest stack it's nested in which is bound to one, and otherwise the
+    /// working directory.
+    fn context_dir(&self) -> Option<PathBuf> {
+        let focused = self.ui.focused.as_ref().map(|focus| focus.item.id);
+        let bound = focused.and_then(|id| {
+            self.view
+                .ancestors(&id)
+                .iter()
+                .rev()
+                .find_map(|id| self.view.items.get(id)?.directory.as_ref())
+                .map(|directory| PathBuf::from(&directory.path))
+        });
+        bound.or_else(|| std::env::current_dir().ok())
+    }
+
+    /// The unlocked top-level stack named `name`, which is created if there isn't one.
+    pub fn get_stack_by_name(&mut self, name: &str) -> Scru128Id {
+        // a stack's content is its name, so names are compared by hash, without reading them
+        let hash = ssri::Integrity::from(name);
+        let existing = self
             .view
             .root()
             .iter()
-            .find(|&&item| !item.locked)
-            .map(|&item| item.id);
-
-        if let Some(id) = curr_stack {
-            if let Some(item) = self.view.items.get(&id) {
-                let now = std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_millis() as u64;
-                let last_touched = item.last_touched.timestamp();
-                if now - last_touched < 3_600_000 {
-                    return id;
+            .find(|item| !item.locked && item.hash == hash)
+            .map(|item| item.id);
+
+        if let Some(id) = existing {
+            return id;
+        }
+
+        let packet = self
+            .store
+            .add_stack(name.as_bytes(), StackLockStatus::Unlocked);
+        self.merge(&packet);
+        packet.id
+    }
+
+    // The order to copy or move a stack's items in, so they keep their order in the stack
+    // they're added to: oldest first, unless the stack has been manually ordered.
+    fn replay_order(&self, stack: &crate::view::Item, items: Vec<Scru128Id>) -> Vec<Scru128Id> {
+        if stack.ordered {
+            items
+        } else {
+            items.into_iter().rev().collect()
+        }
+    }
+
+    fn get_stack(&self, id: &Scru128Id) -> Option<crate::view::Item> {
+        self.view
+            .items
+            .get(id)
+            .filter(|item| item.is_stack)
+            .cloned()
+    }
+
+    /// Moves the items `ids` together, keeping their order, within the stack the first of them is
+    /// in. Items in other stacks are left where they are. Returns the stack's id.
+    pub fn move_items(&mut self, ids: &[Scru128Id], movement: Movement) -> Option<Scru128Id> {
+        let stack_id = self.view.items.get(ids.first()?)?.stack_id?;
+        let stack = self.view.items.get(&stack_id)?.clone();
+        let children = self.view.children(&stack);
+        let moved: HashSet<Scru128Id> = ids.iter().copied().collect();
+
+        // the order the stack ends up in
+        let (block, rest): (Vec<Scru128Id>, Vec<Scru128Id>) =
+            children.iter().partition(|id| moved.contains(id));
+        let order = match movement {
+            Movement::Up => {
+                // items swap with their neighbour when it isn't moving too, so they stay in order
+                let mut order = children.clone();
+                for i in 1..order.len() {
+                    if moved.contains(&order[i]) && !moved.contains(&order[i - 1]) {
+                        order.swap(i, i - 1);
+                    }
                 }
+                order
+            }
+            Movement::Down => {
+                let mut order = children.clone();
+                for i in (0..order.len().saturating_sub(1)).rev() {
+                    if moved.contains(&order[i]) && !moved.contains(&order[i + 1]) {
+                        order.swap(i, i + 1);
+                    }
+                }
+                order
+            }
+            Movement::Top => [block, rest].concat(),
+            Movement::Bottom => [rest, block].concat(),
+            Movement::MoveTo { index } => {
+                let index = index.min(rest.len());
+                [&rest[..index], &block, &rest[index..]].concat()
+            }
+        };
+
+        // each moved item is placed straight after the item it follows in the new order, which
+        // only takes a packet per item, however far it moves
+        let mut current = children;
+        for (index, id) in order.iter().enumerate() {
+            if !moved.contains(id) {
+                continue;
+            }
+            let from = current.iter().position(|other| other == id)?;
+            current.remove(from);
+            let to = match index {
+                0 => 0,
+                _ => {
+                    current
+                        .iter()
+                        .position(|other| *other == order[index - 1])?
+                        + 1
+                }
+            };
+            current.insert(to, *id);
+            if to != from {
+                let packet = self.store.update_move(*id, Movement::MoveTo { index: to });
+                self.merge(&packet);
             }
         }
+        Some(stack_id)
+    }
 
-        let local: DateTime<Local> = Local::now();
-        let stack_name = format!("{}", local.format("%a, %b %d %Y, %I:%M %p"));
+    /// Checks the item `id` can be deleted: pinned items, and stacks with pinned items in them,
+    /// are protected until they're unpinned.
+    pub fn can_delete(&self, id: &Scru128Id) -> Result<(), String> {
+        fn is_protected(view: &View, item: &crate::view::Item) -> bool {
+            item.pinned
+                || view
+                    .children(item)
+                    .iter()
+                    .filter_map(|id| view.items.get(id))
+                    .any(|child| is_protected(view, child))
+        }
 
-        let packet = self
-            .store
-            .add_stack(stack_name.as_bytes(), StackLockStatus::Unlocked);
+        let item = self
+            .view
+            .items
+            .get(id)
+            .ok_or_else(|| format!("Item not found: {id}"))?;
+        if is_protected(&self.view, item) {
+            return Err(format!("Item is pinned: {id}"));
+        }
+        Ok(())
+    }
+
+    /// Deletes the selected items, or the focused item, as one operation which a single undo
+    /// restores. Pinned items, and stacks with pinned items in them, are skipped.
+    pub fn delete_selected(&mut self) {
+        let items = self.ui.selected_items();
+        let mut packets = Vec::new();
+        for item in &items {
+            if let Err(e) = self.can_delete(&item.id) {
+                tracing::warn!("Not deleting: {}", e);
+                continue;
+            }
+            let packet = self.store.delete(item.id);
+            self.merge(&packet);
+            packets.push(packet.id);
+        }
+        self.ui.clear_selection();
+        // a lone delete is undone like any other
+        if packets.len() > 1 {
+            self.last_operation = Some(Operation {
+                packets,
+                focus: items.first().map(|item| item.id),
+            });
+        }
+    }
+
+    /// Adds the selected clips, or the focused clip, to the stack `stack_id`, keeping the order
+    /// they're displayed in.
+    pub fn add_selected_to_stack(&mut self, stack_id: Scru128Id) -> Option<()> {
+        let stack = self.get_stack(&stack_id)?;
+        let items: Vec<Scru128Id> = self
+            .ui
+            .selected_items()
+            .iter()
+            .filter(|item| !item.is_stack)
+            .map(|item| item.id)
+            .collect();
+        // realize current focus, so that it remains stable, to avoid jumping to the forked items
+        self.ui.select(self.view.get_best_focus(&self.ui.focused));
+        for id in self.replay_order(&stack, items) {
+            let packet = self
+                .store
+                .fork(id, None, MimeType::TextPlain, Some(stack_id));
+            self.merge(&packet);
+        }
+        self.ui.clear_selection();
+        Some(())
+    }
+
+    /// Sets the content type of the selected clips, or the focused clip. Returns the hashes of
+    /// the content which was updated.
+    pub fn set_selected_content_type(&mut self, content_type: &str) -> Vec<Integrity> {
+        let mut hashes: Vec<Integrity> = Vec::new();
+        for item in self.ui.selected_items() {
+            if item.is_stack || hashes.contains(&item.hash) {
+                continue;
+            }
+            let packet = self
+                .store
+                .update_content_type(item.hash.clone(), content_type.to_string());
+            self.merge(&packet);
+            hashes.push(item.hash);
+        }
+        self.ui.clear_selection();
+        hashes
+    }
+
+    /// The text of the selected clips, joined to be copied as one. Links and file paths are put
+    /// one per line, anything else is separated by a blank line. Images are left out.
+    pub fn selected_text(&self) -> Option<String> {
+        let mut pieces = Vec::new();
+        let mut one_per_line = true;
+        for item in self.ui.selected_items() {
+            if item.is_stack {
+                continue;
+            }
+            let Some(meta) = self.store.get_content_meta(&item.hash) else {
+                continue;
+            };
+            if meta.mime_type != MimeType::TextPlain {
+                continue;
+            }
+            let Some(content) = self.store.get_content(&item.hash) else {
+                continue;
+            };
+            one_per_line &= matches!(meta.content_type.as_str(), "Link" | "File");
+            pieces.push(String::from_utf8_lossy(&content).trim_end().to_string());
+        }
+        if pieces.is_empty() {
+            return None;
+        }
+        Some(pieces.join(if one_per_line { "\n" } else { "\n\n" }))
+    }
+
+    /// Copies a stack, along with all of its items and nested stacks. Returns the new stack's id.
+    pub fn fork_stack(&mut self, stack_id: Scru128Id) -> Option<Scru128Id> {
+        let stack = self.get_stack(&stack_id)?;
+
+        // the copy is a stack in its own right, rather than a fork of the original stack's
+        // packet, so that it outlives the original being deleted
+        let name = self.store.get_content(&stack.hash)?;
+        let name = format!("{} (fork)", String::from_utf8_lossy(&name));
+        let mut packets = Vec::new();
+        let fork_id = self.copy_stack(&stack, name.as_bytes(), stack.stack_id, &mut packets)?;
+
+        self.last_operation = Some(Operation {
+            packets,
+            focus: Some(stack_id),
+        });
+        Some(fork_id)
+    }
 
+    fn copy_stack(
+        &mut self,
+        stack: &crate::view::Item,
+        name: &[u8],
+        parent_id: Option<Scru128Id>,
+        packets: &mut Vec<Scru128Id>,
+    ) -> Option<Scru128Id> {
+        let lock_status = if stack.locked {
+            StackLockStatus::Locked
+        } else {
+            StackLockStatus::Unlocked
+        };
+        let packet = self.store.add_stack_to(name, lock_status, parent_id);
         self.merge(&packet);
-        packet.id
+        let copy_id = packet.id;
+        packets.push(packet.id);
+
+        if stack.ordered {
+            let packet = self
+                .store
+                .update_stack_sort_order(copy_id, StackSortOrder::Manual);
+            self.merge(&packet);
+            packets.push(packet.id);
+        }
+        if stack.stack_meta != StackMeta::default() {
+            let packet = self
+                .store
+                .update_stack_meta(copy_id, stack.stack_meta.clone());
+            self.merge(&packet);
+            packets.push(packet.id);
+        }
+
+        for id in self.replay_order(stack, self.view.children(stack)) {
+            let item = self.view.items.get(&id)?.clone();
+            if item.is_stack {
+                let name = self.store.get_content(&item.hash)?;
+                self.copy_stack(&item, &name, Some(copy_id), packets)?;
+                continue;
+            }
+            let packet = self
+                .store
+                .fork(id, None, MimeType::TextPlain, Some(copy_id));
+            self.merge(&packet);
+            packets.push(packet.id);
+        }
+        Some(copy_id)
+    }
+
+    /// Merges the items of the stack `source_id` into `target_id`, skipping items the target
+    /// already has, and then deletes the source stack. Nested stacks are moved into the target.
+    pub fn merge_stack(&mut self, source_id: Scru128Id, target_id: Scru128Id) -> Option<()> {
+        if self.view.ancestors(&target_id).contains(&source_id) {
+            return None;
+        }
+        let source = self.get_stack(&source_id)?;
+        let target = self.get_stack(&target_id)?;
+
+        let mut hashes: HashSet<Integrity> = self
+            .view
+            .children(&target)
+            .iter()
+            .filter_map(|id| self.view.items.get(id))
+            .map(|item| item.hash.clone())
+            .collect();
+        let items = self.replay_order(&source, self.view.children(&source));
+
+        let mut packets = Vec::new();
+        for id in items {
+            let item = self.view.items.get(&id).unwrap().clone();
+            // nested stacks are moved across whole
+            if item.is_stack {
+                let packet = self
+                    .store
+                    .update(id, None, MimeType::TextPlain, Some(target_id));
+                self.merge(&packet);
+                packets.push(packet.id);
+                continue;
+            }
+            if !hashes.insert(item.hash) {
+                continue;
+            }
+            let packet = self
+                .store
+                .fork(id, None, MimeType::TextPlain, Some(target_id));
+            self.merge(&packet);
+            packets.push(packet.id);
+        }
+
+        let packet = self.store.delete(source_id);
+        self.merge(&packet);
+        packets.push(packet.id);
+
+        self.last_operation = Some(Operation {
+            packets,
+            focus: Some(source_id),
+        });
+        Some(())
+    }
+
+    /// Moves the item `item_id`, and every item above it, out of its stack into a new stack
+    /// named `name`. Returns the new stack's id.
+    pub fn split_stack(&mut self, item_id: Scru128Id, name: &str) -> Option<Scru128Id> {
+        let stack_id = self.view.items.get(&item_id)?.stack_id?;
+        let stack = self.get_stack(&stack_id)?;
+        let children = self.view.children(&stack);
+        let index = children.iter().position(|id| id == &item_id)?;
+        let items = self.replay_order(&stack, children[..=index].to_vec());
+
+        let packet =
+            self.store
+                .add_stack_to(name.as_bytes(), StackLockStatus::Unlocked, stack.stack_id);
+        self.merge(&packet);
+        let new_stack_id = packet.id;
+        let mut packets = vec![packet.id];
+
+        if stack.ordered {
+            let packet = self
+                .store
+                .update_stack_sort_order(new_stack_id, StackSortOrder::Manual);
+            self.merge(&packet);
+            packets.push(packet.id);
+        }
+
+        for id in items {
+            let packet = self
+                .store
+                .update(id, None, MimeType::TextPlain, Some(new_stack_id));
+            self.merge(&packet);
+            packets.push(packet.id);
+        }
+
+        self.last_operation = Some(Operation {
+            packets,
+            focus: Some(item_id),
+        });
+        Some(new_stack_id)
+    }
+
+    /// Reverts the most recent stack operation or deletion, whichever came last.
+    pub fn undo(&mut self) {
+        let deleted = self.view.undo.clone();
+        match self.last_operation.take() {
+            Some(operation)
+                if deleted
+                    .as_ref()
+                    .is_none_or(|item| operation.packets.last() >= Some(&item.last_touched)) =>
+            {
+                for id in operation.packets.iter().rev() {
+                    self.store.remove_packet(id);
+                }
+                self.rescan(operation.focus);
+            }
+            operation => {
+                self.last_operation = operation;
+                if let Some(item) = deleted {
+                    self.store.remove_packet(&item.last_touched);
+                    self.rescan(Some(item.id));
+                }
+            }
+        }
+    }
+
+    /// Adds and removes tags on the item `id`. Returns the updated item.
+    pub fn tag(
+        &mut self,
+        id: Scru128Id,
+        add: &[String],
+        remove: &[String],
+    ) -> Option<crate::view::Item> {
+        self.view.items.get(&id)?;
+        let normalize = |tags: &[String]| {
+            tags.iter()
+                .filter_map(|tag| normalize_tag(tag))
+                .collect::<Vec<_>>()
+        };
+        let packet = self.store.update_tags(
+            id,
+            Tags {
+                add: normalize(add),
+                remove: normalize(remove),
+            },
+        );
+        self.merge(&packet);
+        self.view.items.get(&id).cloned()
     }
 
     pub fn merge(&mut self, packet: &Packet) {
         self.view.merge(packet);
+
+        // new items in the cross-stream stack are published, see cross_stream::start
+        if matches!(packet.packet_type, PacketType::Add | PacketType::Fork) {
+            let item = self
+                .view
+                .items
+                .get(&packet.id)
+                .filter(|item| !item.is_stack);
+            let stack = item
+                .and_then(|item| item.stack_id)
+                .and_then(|id| self.view.items.get(&id));
+            if stack.is_some_and(|stack| stack.cross_stream) {
+                self.store.delivery_queue(&packet.id);
+            }
+        }
+
+        // tags are indexed per item, so follow tagged items as they're updated and forked
+        let id = match packet.packet_type {
+            PacketType::Update => packet.source_id,
+            PacketType::Fork => Some(packet.id),
+            _ => None,
+        };
+        if let Some(item) = id.and_then(|id| self.view.items.get(&id)) {
+            if packet.tags.is_some() || !item.tags.is_empty() {
+                let tags: Vec<String> = item.tags.iter().cloned().collect();
+                self.store.index.write_tags(&item.id, &item.hash, &tags);
+            }
+        }
+
         self.ui.refresh_view(&self.view);
+        self.changed();
+    }
+
+    // with a publisher, the view is published once whatever is changing the state lets go of
+    // it, so an operation which merges many packets is published once; without one, such as
+    // from the CLI, it's published straight away
+    fn changed(&mut self) {
+        self.stale = true;
+        match &self.publisher {
+            Some(publisher) if publisher.send(()).is_ok() => (),
+            _ => self.publish(),
+        }
+    }
+
+    /// Lets the watchers, and readers of the snapshot, know the view has changed, unless it's
+    /// been published since.
+    pub fn publish(&mut self) {
+        if !std::mem::take(&mut self.stale) {
+            return;
+        }
         let _ = self.packet_sender.send(self.view.clone());
+        let snapshot = Arc::new(Snapshot::new(&self.view, &self.store));
+        *self.snapshot.write().unwrap() = snapshot;
+    }
+
+    /// The virtual stack of pinned clips is named like any other stack, by its content. Content
+    /// can be purged, so this is written again whenever the view is rebuilt.
+    fn write_pinned_name(store: &mut Store) {
+        store.cas_write(
+            crate::ui::PINNED_NAME.as_bytes(),
+            MimeType::TextPlain,
+            "Text".to_string(),
+        );
+    }
+
+    /// Builds the view from the latest snapshot, replaying only the packets written since, and
+    /// takes a new snapshot once `snapshot_after` packets have been replayed.
+    fn replay(store: &Store, snapshot_after: usize) -> View {
+        let (mut view, last_id) = match store.load_view_snapshot() {
+            Some((id, view)) => (view, Some(id)),
+            None => (View::new(), None),
+        };
+        let (last_id, replayed) = replay_packets(&mut view, last_id, store.scan_after(last_id));
+        if let Some(id) = last_id.filter(|_| replayed >= snapshot_after) {
+            store.save_view_snapshot(&id, &view);
+        }
+        view
+    }
+
+    /// Compacts the packet log, keeping the last `keep_days` days of history as it is. Deleted
+    /// items are garbage collected first, as they are on startup, so they can't be restored
+    /// with undo afterwards.
+    pub fn compact(
+        &mut self,
+        keep_days: Option<u64>,
+        dry_run: bool,
+    ) -> Result<CompactReport, String> {
+        let keep_after = keep_days.map(|days| {
+            let cutoff = Utc::now() - chrono::Duration::days(days as i64);
+            Scru128Id::from_fields(cutoff.timestamp_millis() as u64, 0, 0, 0)
+        });
+        if dry_run {
+            return self.store.compact(keep_after, true);
+        }
+
+        let delete_packets = self.store.delete_packets();
+        for packet in &delete_packets {
+            Self::garbage_collect_delete_packet(&mut self.store, packet);
+        }
+        let report = self.store.compact(keep_after, false);
+
+        self.last_operation = None;
+        let focus = self.ui.focused.as_ref().map(|focus| focus.item.id);
+        self.rescan(focus);
+        report
+    }
+
+    /// Rebuilds the view once a sync has changed the packet log. Packets from peers can land
+    /// anywhere in the log, so they can't just be merged into the view.
+    pub fn synced(state: &SharedState, added: &[Packet]) {
+        let focus = state.with_lock(|state| {
+            state.last_operation = None;
+            state.ui.focused.as_ref().map(|focus| focus.item.id)
+        });
+        Self::rescan_shared(state, focus);
+
+        state.with_lock(|state| {
+            for packet in added.iter().filter(|packet| packet.tags.is_some()) {
+                let id = match packet.packet_type {
+                    PacketType::Update => packet.source_id,
+                    _ => Some(packet.id),
+                };
+                if let Some(item) = id.and_then(|id| state.view.items.get(&id)) {
+                    let tags: Vec<String> = item.tags.iter().cloned().collect();
+                    state.store.index.write_tags(&item.id, &item.hash, &tags);
+                }
+            }
+        });
     }
 
     pub fn rescan(&mut self, focus_item_id: Option<Scru128Id>) {
-        let mut view = View::new();
-        self.store.scan().for_each(|p| view.merge(&p));
+        let view = Self::replay(&self.store, SNAPSHOT_INTERVAL);
+        self.rebuilt(view, focus_item_id);
+    }
+
+    /// Rebuilds the view, like `rescan`, but replays the packet log without holding on to
+    /// `state`, so it can be used meanwhile. Packets added while replaying are caught up on
+    /// afterwards; if the log changed any other way, it's replayed again, holding on to the
+    /// state.
+    pub fn rescan_shared(state: &SharedState, focus_item_id: Option<Scru128Id>) {
+        let (log, seq, purges) = state.with_lock(|state| {
+            let store = &state.store;
+            (store.log(), store.last_change(), store.purges())
+        });
+        let (mut view, last_id) = match log.load_view_snapshot() {
+            Some((id, view)) => (view, Some(id)),
+            None => (View::new(), None),
+        };
+        let (last_id, replayed) = replay_packets(&mut view, last_id, log.scan_after(last_id));
+
+        state.with_lock(|state| {
+            let (changes, _) = state.store.changes_after(seq, usize::MAX);
+            let only_added = state.store.purges() == purges
+                && changes.iter().all(
+                    |change| matches!(change, Change::Packet(packet) if Some(packet.id) > last_id),
+                );
+            if !only_added {
+                state.rescan(focus_item_id);
+                return;
+            }
+
+            let (last_id, caught_up) =
+                replay_packets(&mut view, last_id, state.store.scan_after(last_id));
+            if let Some(id) = last_id.filter(|_| replayed + caught_up >= SNAPSHOT_INTERVAL) {
+                state.store.save_view_snapshot(&id, &view);
+            }
+            state.rebuilt(view, focus_item_id);
+        });
+    }
+
+    fn rebuilt(&mut self, view: View, focus_item_id: Option<Scru128Id>) {
+        Self::write_pinned_name(&mut self.store);
         let mut ui = UI::new(&view);
         if let Some(id) = focus_item_id {
             ui.select(view.get_focus_for_id(&id));
         }
         self.view = view;
         self.ui = ui;
-        let _ = self.packet_sender.send(self.view.clone());
+        self.changed();
+    }
+}
+
+/// Merges `packets` into `view`, which is as of the packet `last_id`. Returns the id of the last
+/// packet merged, or `last_id` if there weren't any, and how many there were.
+fn replay_packets(
+    view: &mut View,
+    mut last_id: Option<Scru128Id>,
+    packets: impl Iterator<Item = Packet>,
+) -> (Option<Scru128Id>, usize) {
+    let mut replayed = 0;
+    for packet in packets {
+        view.merge(&packet);
+        last_id = Some(packet.id);
+        replayed += 1;
     }
+    (last_id, replayed)
 }
 
 pub type SharedState = Arc<TracingMutexSpan<State>>;
 
+/// Publishes changes to the view once each operation on the state lets go of it, rather than
+/// as each packet is merged.
+pub fn start_publisher(state: SharedState) {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    state.with_lock(|state| state.publisher = Some(sender));
+    std::thread::spawn(move || {
+        while receiver.recv().is_ok() {
+            // changes made while waiting on the state are published together
+            while receiver.try_recv().is_ok() {}
+            state.with_lock(|state| state.publish());
+        }
+    });
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -153,4 +864,245 @@ mod tests {
         let _ = state.get_curr_stack();
         let _ = state.get_curr_stack();
     }
+
+    #[test]
+    fn test_state_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let (sender, _receiver) = std::sync::mpsc::channel();
+        let mut state = State::new(path, sender);
+
+        // readers hold on to the snapshot they took, while the state moves on
+        let before = state.snapshot.read().unwrap().clone();
+        let stack_id = state.get_curr_stack();
+        let packet = state.store.add(b"Item 1", MimeType::TextPlain, stack_id);
+        state.merge(&packet);
+        assert!(before.view.items.is_empty());
+
+        let snapshot = state.snapshot.read().unwrap().clone();
+        assert_eq!(snapshot.view.items, state.view.items);
+        assert_eq!(
+            snapshot
+                .stacks
+                .iter()
+                .map(|stack| stack.id)
+                .collect::<Vec<_>>(),
+            vec![stack_id]
+        );
+
+        // searches go through a handle on the index, rather than the store
+        let index = state.store.index.clone();
+        index.flush();
+        let results = index.query("item", None).unwrap();
+        assert_eq!(results.len(), 1);
+    }
+
+    #[test]
+    fn test_state_publisher() {
+        use std::time::Duration;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let state = State::new(path, sender);
+        receiver.recv().unwrap();
+        let state: SharedState = Arc::new(TracingMutexSpan::new("SharedState", state));
+        start_publisher(state.clone());
+
+        // an operation which merges several packets is published once it lets go of the state
+        let ids = state.with_lock(|state| {
+            let stack_id = state.get_curr_stack();
+            let packet = state.store.add(b"Item 1", MimeType::TextPlain, stack_id);
+            state.merge(&packet);
+            let packet = state.store.add(b"Item 2", MimeType::TextPlain, stack_id);
+            state.merge(&packet);
+            assert!(state.snapshot.read().unwrap().view.items.is_empty());
+            vec![stack_id, packet.id]
+        });
+        let view = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(ids.iter().all(|id| view.items.contains_key(id)));
+        assert!(receiver.recv_timeout(Duration::from_millis(200)).is_err());
+        let snapshot = state.with_lock(|state| state.snapshot.read().unwrap().clone());
+        assert_eq!(snapshot.view.items, view.items);
+
+        // rebuilding the view replays the log without holding on to the state
+        state.with_lock(|state| state.view = View::new());
+        State::rescan_shared(&state, None);
+        let view = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(view.items, snapshot.view.items);
+    }
+
+    #[test]
+    fn test_state_garbage_collect() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let (sender, _receiver) = std::sync::mpsc::channel();
+        let mut state = State::new(path, sender.clone());
+
+        let stack_id = state.get_curr_stack();
+        let kept = state.store.add(b"Kept", MimeType::TextPlain, stack_id);
+        state.merge(&kept);
+        let deleted = state.store.add(b"Deleted", MimeType::TextPlain, stack_id);
+        state.merge(&deleted);
+        let packet = state.store.delete(deleted.id);
+        state.merge(&packet);
+        drop(state);
+
+        // deleted items are collected on start, and the view is snapshotted afterwards
+        let state = State::new(path, sender.clone());
+        assert!(state.view.items.contains_key(&kept.id));
+        assert!(!state.view.items.contains_key(&deleted.id));
+        assert!(!state.store.cas_exists(&deleted.hash.unwrap()));
+        let (_, snapshot) = state.store.load_view_snapshot().unwrap();
+        assert_eq!(snapshot.items, state.view.items);
+        drop(state);
+
+        // including those deleted before the view was last snapshotted
+        let mut state = State::new(path, sender.clone());
+        let deleted = state
+            .store
+            .add(b"Snapshotted", MimeType::TextPlain, stack_id);
+        state.merge(&deleted);
+        let packet = state.store.delete(deleted.id);
+        state.merge(&packet);
+        state.store.save_view_snapshot(&packet.id, &state.view);
+        drop(state);
+
+        let state = State::new(path, sender);
+        assert!(!state.store.cas_exists(&deleted.hash.unwrap()));
+        assert_eq!(state.store.get_packet(&deleted.id), None);
+        assert!(state.store.delete_packets().is_empty());
+    }
+
+    #[test]
+    fn test_state_cross_stream_delivery() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let (sender, _receiver) = std::sync::mpsc::channel();
+        let mut state = State::new(path, sender);
+
+        let stack_id = state.get_curr_stack();
+        let before = state.store.add(b"Before", MimeType::TextPlain, stack_id);
+        state.merge(&before);
+        let packet = state.store.mark_as_cross_stream(stack_id);
+        state.merge(&packet);
+
+        // only items added once the stack is marked are published
+        let after = state.store.add(b"After", MimeType::TextPlain, stack_id);
+        state.merge(&after);
+        let other = state.get_stack_by_name("Other");
+        let elsewhere = state.store.add(b"Elsewhere", MimeType::TextPlain, other);
+        state.merge(&elsewhere);
+
+        let due = state.store.deliveries_due(u64::MAX);
+        assert_eq!(
+            due.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            vec![after.id]
+        );
+        let item = crate::ui::with_meta(&state.store, &state.view.items[&after.id]);
+        let delivery = item.delivery.unwrap();
+        assert_eq!(
+            delivery.status,
+            crate::cross_stream::DeliveryStatus::Pending
+        );
+        let item = crate::ui::with_meta(&state.store, &state.view.items[&before.id]);
+        assert_eq!(item.delivery, None);
+
+        // a delivered item isn't due again
+        let delivered = delivery.attempted(Ok(()), 0);
+        state.store.delivery_save(&after.id, &delivered);
+        state.store.delivery_queue(&after.id);
+        assert!(state.store.deliveries_due(u64::MAX).is_empty());
+
+        // deliveries go with the item, when it's deleted or its content purged
+        let deleted = state.store.add(b"Deleted", MimeType::TextPlain, stack_id);
+        state.merge(&deleted);
+        let purged = state.store.add(b"Purged", MimeType::TextPlain, stack_id);
+        state.merge(&purged);
+        assert_eq!(state.store.deliveries_due(u64::MAX).len(), 2);
+        let packet = state.store.delete(deleted.id);
+        state.merge(&packet);
+        assert_eq!(state.store.delivery_get(&deleted.id), None);
+        state.store.purge(&purged.hash.unwrap()).unwrap();
+        assert_eq!(state.store.delivery_get(&purged.id), None);
+        assert!(state.store.delivery_get(&after.id).is_some());
+    }
+
+    #[test]
+    fn test_state_rollover() {
+        use crate::store::{Settings, StackDirectory};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let (sender, _receiver) = std::sync::mpsc::channel();
+        let mut state = State::new(path, sender);
+
+        let name = |state: &State, id: &Scru128Id| {
+            let stack = state.view.items.get(id).unwrap();
+            String::from_utf8(state.store.get_content(&stack.hash).unwrap()).unwrap()
+        };
+
+        let now = Local::now();
+        let later = now + chrono::Duration::days(2);
+
+        // the default: roll over after an hour idle
+        let stack = state.get_curr_stack_at(now);
+        assert_eq!(state.get_curr_stack_at(now), stack);
+        let next = state.get_curr_stack_at(later);
+        assert_ne!(next, stack);
+
+        state.store.settings_save(Settings {
+            rollover: Some(Rollover::Never),
+            stack_name_template: Some("Inbox".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(
+            state.get_curr_stack_at(later + chrono::Duration::days(365)),
+            next
+        );
+
+        let project = tempfile::tempdir().unwrap();
+        std::fs::create_dir(project.path().join(".git")).unwrap();
+        let checkout = |branch: &str| {
+            let head = format!("ref: refs/heads/{}", branch);
+            std::fs::write(project.path().join(".git/HEAD"), head).unwrap();
+        };
+        state.store.settings_save(Settings {
+            rollover: Some(Rollover::Context),
+            stack_name_template: Some("{branch}".to_string()),
+            ..Default::default()
+        });
+
+        // the context is the directory of the focused stack, at the time of the rollover
+        let bound = state.store.add_stack(b"Project", StackLockStatus::Unlocked);
+        state.merge(&bound);
+        let directory = StackDirectory {
+            path: project.path().to_string_lossy().into_owned(),
+            watch: false,
+        };
+        let packet = state.store.update_stack_directory(bound.id, directory);
+        state.merge(&packet);
+        state.nav_select(&bound.id);
+
+        checkout("main");
+        let main = state.get_curr_stack_at(now);
+        assert_eq!(name(&state, &main), "main");
+        checkout("feature");
+        let feature = state.get_curr_stack_at(now);
+        assert_eq!(name(&state, &feature), "feature");
+        checkout("main");
+        assert_eq!(state.get_curr_stack_at(now), main);
+
+        // outside a git repository, a name which renders empty still names the stack
+        let elsewhere = tempfile::tempdir().unwrap();
+        let directory = StackDirectory {
+            path: elsewhere.path().to_string_lossy().into_owned(),
+            watch: false,
+        };
+        let packet = state.store.update_stack_directory(bound.id, directory);
+        state.merge(&packet);
+        let unnamed = state.get_curr_stack_at(now);
+        assert!(![main, feature].contains(&unnamed));
+        assert!(!name(&state, &unnamed).is_empty());
+    }
 }
@@ -0,0 +1,396 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+use ssri::Integrity;
+
+use crate::sqlite::SqliteStorage;
+
+pub type Entry = (Vec<u8>, Vec<u8>);
+
+/// An ordered map of keys to values. The store keeps its packet log, content meta and settings
+/// in tables, and encodes what it keeps itself, so a table only needs to keep bytes.
+pub trait Table: Send + Sync {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>>;
+    fn insert(&self, key: &[u8], value: &[u8]);
+    fn remove(&self, key: &[u8]) -> Option<Vec<u8>>;
+    /// The entries from `from` on, in order of their keys.
+    fn range(&self, from: Bound<Vec<u8>>) -> Box<dyn Iterator<Item = Entry> + '_>;
+    fn last(&self) -> Option<Entry>;
+    fn len(&self) -> usize;
+    fn clear(&self);
+    /// Removes the keys `remove`, then inserts `insert`, as a single change.
+    fn apply(&self, remove: Vec<Vec<u8>>, insert: Vec<Entry>);
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Entry> + '_> {
+        self.range(Bound::Unbounded)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Content, addressed by its hash.
+pub trait Blobs: Send + Sync {
+    fn write(&self, content: &[u8]) -> Integrity;
+    /// The content `hash`, checked against it: None if it's missing, or corrupt.
+    fn read(&self, hash: &Integrity) -> Option<Vec<u8>>;
+    fn exists(&self, hash: &Integrity) -> bool;
+    fn remove(&self, hash: &Integrity) -> std::io::Result<()>;
+    /// Every blob which is stored, intact or not.
+    fn list(&self) -> Vec<Integrity>;
+    /// The content stored as `hash`, without checking it.
+    fn read_unchecked(&self, hash: &Integrity) -> Option<Vec<u8>>;
+}
+
+/// Where a store keeps its data: its tables, its blobs, and its search index.
+pub trait Storage {
+    fn table(&self, name: &str) -> Arc<dyn Table>;
+    fn blobs(&self) -> Arc<dyn Blobs>;
+    /// The directory for the search index, or None to keep the index in memory.
+    fn index_path(&self) -> Option<PathBuf>;
+}
+
+// every table a store keeps
+pub const TABLES: [&str; 9] = [
+    "packets",
+    "content_meta",
+    "meta",
+    "schedules",
+    "view_snapshots",
+    "changes",
+    "tombstones",
+    "deliveries",
+    "deletes",
+];
+
+/// The backends a store can be kept in on disk.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    Sled,
+    // a SQLite database, with blobs kept in it
+    Sqlite,
+    // a SQLite database, with blobs kept on disk alongside it
+    SqliteCas,
+}
+
+impl std::str::FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sled" => Ok(Backend::Sled),
+            "sqlite" => Ok(Backend::Sqlite),
+            "sqlite-cas" => Ok(Backend::SqliteCas),
+            _ => Err(format!("Unknown backend: {s}")),
+        }
+    }
+}
+
+/// The backend the store at `path` is kept in: stores are kept in sled, unless they've been
+/// migrated to SQLite.
+pub fn detect(path: &Path) -> Backend {
+    if !path.join(crate::sqlite::FILE).exists() {
+        Backend::Sled
+    } else if path.join("cas").is_dir() {
+        Backend::SqliteCas
+    } else {
+        Backend::Sqlite
+    }
+}
+
+pub fn open(backend: Backend, path: &Path) -> Result<Box<dyn Storage>, String> {
+    let storage: Result<Box<dyn Storage>, Box<dyn std::error::Error>> = match backend {
+        Backend::Sled => SledStorage::open(path)
+            .map(|storage| Box::new(storage) as _)
+            .map_err(Into::into),
+        Backend::Sqlite | Backend::SqliteCas => {
+            SqliteStorage::open(path, backend == Backend::Sqlite)
+                .map(|storage| Box::new(storage) as _)
+        }
+    };
+    storage.map_err(|e| format!("Could not open {}: {e}", path.display()))
+}
+
+/// Copies everything kept in `from` to `to`, such as to move a store to another backend.
+/// Returns how many entries, and blobs, were copied: corrupt blobs are left behind.
+pub fn copy(from: &dyn Storage, to: &dyn Storage) -> (usize, usize) {
+    let mut entries = 0;
+    for name in TABLES {
+        let (from, to) = (from.table(name), to.table(name));
+        let insert: Vec<Entry> = from.iter().collect();
+        entries += insert.len();
+        to.apply(Vec::new(), insert);
+    }
+
+    let (from, to) = (from.blobs(), to.blobs());
+    let mut blobs = 0;
+    for hash in from.list() {
+        if let Some(content) = from.read(&hash) {
+            to.write(&content);
+            blobs += 1;
+        }
+    }
+    (entries, blobs)
+}
+
+/// Tables in sled, with blobs in a cacache content store alongside: the layout stores have
+/// always had on disk.
+pub struct SledStorage {
+    db: sled::Db,
+    path: PathBuf,
+}
+
+impl SledStorage {
+    pub fn open(path: &Path) -> sled::Result<Self> {
+        Ok(Self {
+            db: sled::open(path.join("sled"))?,
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Storage for SledStorage {
+    fn table(&self, name: &str) -> Arc<dyn Table> {
+        Arc::new(self.db.open_tree(name).unwrap())
+    }
+
+    fn blobs(&self) -> Arc<dyn Blobs> {
+        Arc::new(Cacache::new(self.path.join("cas")))
+    }
+
+    fn index_path(&self) -> Option<PathBuf> {
+        Some(self.path.join("index"))
+    }
+}
+
+impl Table for sled::Tree {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        sled::Tree::get(self, key).unwrap().map(|v| v.to_vec())
+    }
+
+    fn insert(&self, key: &[u8], value: &[u8]) {
+        sled::Tree::insert(self, key, value).unwrap();
+    }
+
+    fn remove(&self, key: &[u8]) -> Option<Vec<u8>> {
+        sled::Tree::remove(self, key).unwrap().map(|v| v.to_vec())
+    }
+
+    fn range(&self, from: Bound<Vec<u8>>) -> Box<dyn Iterator<Item = Entry> + '_> {
+        Box::new(
+            sled::Tree::range(self, (from, Bound::Unbounded))
+                .flatten()
+                .map(|(k, v)| (k.to_vec(), v.to_vec())),
+        )
+    }
+
+    fn last(&self) -> Option<Entry> {
+        sled::Tree::last(self)
+            .unwrap()
+            .map(|(k, v)| (k.to_vec(), v.to_vec()))
+    }
+
+    fn len(&self) -> usize {
+        sled::Tree::len(self)
+    }
+
+    fn clear(&self) {
+        sled::Tree::clear(self).unwrap();
+    }
+
+    fn apply(&self, remove: Vec<Vec<u8>>, insert: Vec<Entry>) {
+        let mut batch = sled::Batch::default();
+        for key in remove {
+            batch.remove(key);
+        }
+        for (key, value) in insert {
+            batch.insert(key, value);
+        }
+        self.apply_batch(batch).unwrap();
+    }
+}
+
+/// A cacache content store: blobs are kept at
+/// `content-v2/<algorithm>/<hex[0..2]>/<hex[2..4]>/<hex[4..]>`.
+pub struct Cacache {
+    path: PathBuf,
+}
+
+impl Cacache {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    fn blob_path(&self, hash: &Integrity) -> PathBuf {
+        let (algorithm, hex) = hash.to_hex();
+        self.path
+            .join("content-v2")
+            .join(algorithm.to_string())
+            .join(&hex[0..2])
+            .join(&hex[2..4])
+            .join(&hex[4..])
+    }
+}
+
+impl Blobs for Cacache {
+    fn write(&self, content: &[u8]) -> Integrity {
+        cacache::write_hash_sync(&self.path, content).unwrap()
+    }
+
+    fn read(&self, hash: &Integrity) -> Option<Vec<u8>> {
+        cacache::read_hash_sync(&self.path, hash).ok()
+    }
+
+    fn exists(&self, hash: &Integrity) -> bool {
+        cacache::exists_sync(&self.path, hash)
+    }
+
+    fn remove(&self, hash: &Integrity) -> std::io::Result<()> {
+        cacache::remove_hash_sync(&self.path, hash).map_err(std::io::Error::other)
+    }
+
+    // cacache's index only knows about content written with a key, so this walks the content
+    fn list(&self) -> Vec<Integrity> {
+        fn entries(path: &Path) -> impl Iterator<Item = std::fs::DirEntry> {
+            std::fs::read_dir(path).into_iter().flatten().flatten()
+        }
+        let name = |entry: &std::fs::DirEntry| entry.file_name().to_string_lossy().into_owned();
+
+        let mut blobs = Vec::new();
+        for algorithm in entries(&self.path.join("content-v2")) {
+            let Ok(algo) = name(&algorithm).parse::<ssri::Algorithm>() else {
+                continue;
+            };
+            for first in entries(&algorithm.path()) {
+                for second in entries(&first.path()) {
+                    for blob in entries(&second.path()) {
+                        let hex = format!("{}{}{}", name(&first), name(&second), name(&blob));
+                        if let Ok(hash) = Integrity::from_hex(hex, algo) {
+                            blobs.push(hash);
+                        }
+                    }
+                }
+            }
+        }
+        blobs
+    }
+
+    fn read_unchecked(&self, hash: &Integrity) -> Option<Vec<u8>> {
+        std::fs::read(self.blob_path(hash)).ok()
+    }
+}
+
+/// Keeps everything in memory, and the search index too, for stores which don't need to
+/// outlive the process, such as in tests.
+#[derive(Default)]
+pub struct MemoryStorage {
+    tables: Mutex<HashMap<String, Arc<MemoryTable>>>,
+    blobs: Arc<MemoryBlobs>,
+}
+
+impl Storage for MemoryStorage {
+    fn table(&self, name: &str) -> Arc<dyn Table> {
+        let mut tables = self.tables.lock().unwrap();
+        tables.entry(name.to_string()).or_default().clone()
+    }
+
+    fn blobs(&self) -> Arc<dyn Blobs> {
+        self.blobs.clone()
+    }
+
+    fn index_path(&self) -> Option<PathBuf> {
+        None
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryTable(RwLock<BTreeMap<Vec<u8>, Vec<u8>>>);
+
+impl Table for MemoryTable {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.0.read().unwrap().get(key).cloned()
+    }
+
+    fn insert(&self, key: &[u8], value: &[u8]) {
+        self.0.write().unwrap().insert(key.to_vec(), value.to_vec());
+    }
+
+    fn remove(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.0.write().unwrap().remove(key)
+    }
+
+    // iterates over a copy of the entries, so the table can change while it's iterated over
+    fn range(&self, from: Bound<Vec<u8>>) -> Box<dyn Iterator<Item = Entry> + '_> {
+        let entries: Vec<Entry> = self
+            .0
+            .read()
+            .unwrap()
+            .range((from, Bound::Unbounded))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        Box::new(entries.into_iter())
+    }
+
+    fn last(&self) -> Option<Entry> {
+        let map = self.0.read().unwrap();
+        map.last_key_value().map(|(k, v)| (k.clone(), v.clone()))
+    }
+
+    fn len(&self) -> usize {
+        self.0.read().unwrap().len()
+    }
+
+    fn clear(&self) {
+        self.0.write().unwrap().clear();
+    }
+
+    fn apply(&self, remove: Vec<Vec<u8>>, insert: Vec<Entry>) {
+        let mut map = self.0.write().unwrap();
+        for key in remove {
+            map.remove(&key);
+        }
+        map.extend(insert);
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryBlobs(RwLock<HashMap<Integrity, Vec<u8>>>);
+
+impl Blobs for MemoryBlobs {
+    fn write(&self, content: &[u8]) -> Integrity {
+        let hash = Integrity::from(content);
+        self.0
+            .write()
+            .unwrap()
+            .insert(hash.clone(), content.to_vec());
+        hash
+    }
+
+    fn read(&self, hash: &Integrity) -> Option<Vec<u8>> {
+        let content = self.read_unchecked(hash)?;
+        hash.check(&content).ok()?;
+        Some(content)
+    }
+
+    fn exists(&self, hash: &Integrity) -> bool {
+        self.0.read().unwrap().contains_key(hash)
+    }
+
+    fn remove(&self, hash: &Integrity) -> std::io::Result<()> {
+        match self.0.write().unwrap().remove(hash) {
+            Some(_) => Ok(()),
+            None => Err(std::io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    fn list(&self) -> Vec<Integrity> {
+        self.0.read().unwrap().keys().cloned().collect()
+    }
+
+    fn read_unchecked(&self, hash: &Integrity) -> Option<Vec<u8>> {
+        self.0.read().unwrap().get(hash).cloned()
+    }
+}
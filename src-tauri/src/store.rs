@@ -1,10 +1,16 @@
 use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
 
 use scru128::Scru128Id;
 use serde::{Deserialize, Serialize};
 use ssri::Integrity;
 
+use crate::cross_stream::{Delivery, Endpoint};
+use crate::rollover::Rollover;
+use crate::schedule::Schedule;
 use crate::spotlight;
+use crate::storage::{self, Blobs, MemoryStorage, Storage, Table};
+use crate::sync::Change;
 
 #[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
 pub enum MimeType {
@@ -14,13 +20,118 @@ pub enum MimeType {
     ImagePng,
 }
 
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct ContentMetaV1 {
+    pub hash: Integrity,
+    pub mime_type: MimeType,
+    pub content_type: String,
+    pub terse: String,
+    pub tiktokens: usize,
+}
+
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct ContentMetaV2 {
+    pub hash: Integrity,
+    pub mime_type: MimeType,
+    pub content_type: String,
+    pub terse: String,
+    pub tiktokens: usize,
+    pub snapshot: Option<Integrity>,
+}
+
+/// Another representation of the same clip, such as the HTML or RTF a rich text copy puts on the
+/// pasteboard alongside the plain text. The content is stored in the CAS.
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct Alternate {
+    pub pasteboard_type: String,
+    pub hash: Integrity,
+}
+
+/// Stored in an envelope, like [`Packet`]: new fields need `#[serde(default)]`.
 #[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
 pub struct ContentMeta {
     pub hash: Integrity,
     pub mime_type: MimeType,
     pub content_type: String,
     pub terse: String,
+    #[serde(default)]
     pub tiktokens: usize,
+    // for file references added before snapshots were kept with their packet: the file's content
+    // at the time it was added
+    #[serde(default)]
+    pub snapshot: Option<Integrity>,
+    // the application the content was last copied from
+    #[serde(default)]
+    pub source: Option<String>,
+    #[serde(default)]
+    pub alternates: Vec<Alternate>,
+}
+
+// packets and content meta are stored in an envelope: this marker, which can't start their
+// legacy bincode encodings, as those start with the low byte of a short string's length, then
+// the version of the encoding, then the value
+const ENVELOPE: u8 = 0xFF;
+// version 1 encodes values as JSON, so fields are named, and a field added with
+// `#[serde(default)]` can be read from values written before it
+const ENCODING_VERSION: u8 = 1;
+
+// the kinds of change in the change log: each is followed by the id of the packet it's about
+const CHANGE_PACKET: u8 = 0;
+const CHANGE_REMOVED: u8 = 1;
+
+// set once the packets and content meta in the store are all in the current encoding
+const ENCODING_KEY: &str = "encoding_version";
+
+fn encode<T: Serialize>(value: &T) -> Vec<u8> {
+    let mut bytes = vec![ENVELOPE, ENCODING_VERSION];
+    serde_json::to_writer(&mut bytes, value).unwrap();
+    bytes
+}
+
+/// Decodes a value from its envelope, or returns None for a value from before the envelope.
+fn decode<T: serde::de::DeserializeOwned>(value: &[u8]) -> Option<Result<T, String>> {
+    match value {
+        [ENVELOPE, ENCODING_VERSION, rest @ ..] => {
+            Some(serde_json::from_slice(rest).map_err(|e| e.to_string()))
+        }
+        [ENVELOPE, version, ..] => Some(Err(format!("Unknown encoding version: {version}"))),
+        _ => None,
+    }
+}
+
+fn deserialize_content_meta(value: &[u8]) -> Result<ContentMeta, String> {
+    decode(value).unwrap_or_else(|| deserialize_legacy_content_meta(value))
+}
+
+/// Content meta from before the envelope, which was encoded with bincode. Bincode is
+/// positional, so each layout is tried in turn, newest first.
+fn deserialize_legacy_content_meta(value: &[u8]) -> Result<ContentMeta, String> {
+    bincode::deserialize::<ContentMeta>(value)
+        .or_else(|_| {
+            bincode::deserialize::<ContentMetaV2>(value).map(|v2| ContentMeta {
+                hash: v2.hash,
+                mime_type: v2.mime_type,
+                content_type: v2.content_type,
+                terse: v2.terse,
+                tiktokens: v2.tiktokens,
+                snapshot: v2.snapshot,
+                source: None,
+                alternates: Vec::new(),
+            })
+        })
+        .or_else(|_| {
+            bincode::deserialize::<ContentMetaV1>(value).map(|v1| ContentMeta {
+                hash: v1.hash,
+                mime_type: v1.mime_type,
+                content_type: v1.content_type,
+                terse: v1.terse,
+                tiktokens: v1.tiktokens,
+                snapshot: None,
+                source: None,
+                alternates: Vec::new(),
+            })
+        })
+        .map_err(|e| e.to_string())
 }
 
 #[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
@@ -41,6 +152,9 @@ impl InProgressStream {
             content_type: content_type.clone(),
             terse: "".to_string(),
             tiktokens: 0,
+            snapshot: None,
+            source: None,
+            alternates: Vec::new(),
         };
 
         InProgressStream {
@@ -58,6 +172,11 @@ impl InProgressStream {
                 lock_status: None,
                 sort_order: None,
                 cross_stream: false,
+                directory: None,
+                stack_meta: None,
+                tags: None,
+                pin_status: None,
+                snapshot: None,
             },
         }
     }
@@ -111,11 +230,35 @@ pub struct PacketV3 {
     pub ephemeral: bool,
 }
 
+/// Moves an item within its stack. New variants go at the end, as packets store the variant's
+/// position.
 #[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
-#[serde(rename_all = "lowercase")]
+#[serde(rename_all = "snake_case")]
 pub enum Movement {
     Up,
     Down,
+    // to the position `index`, counting from the top, or to the bottom when it's past the end
+    MoveTo { index: usize },
+    Top,
+    Bottom,
+}
+
+impl std::str::FromStr for Movement {
+    type Err = String;
+
+    /// Parses `up`, `down`, `top`, `bottom` or an index.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "up" => Ok(Movement::Up),
+            "down" => Ok(Movement::Down),
+            "top" => Ok(Movement::Top),
+            "bottom" => Ok(Movement::Bottom),
+            index => index
+                .parse()
+                .map(|index| Movement::MoveTo { index })
+                .map_err(|_| format!("Invalid movement: {s}")),
+        }
+    }
 }
 
 #[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
@@ -132,23 +275,241 @@ pub enum StackSortOrder {
     Manual,
 }
 
+/// Pinned items are kept at the top of their stack, and can't be deleted until they're unpinned.
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum PinStatus {
+    Unpinned,
+    Pinned,
+}
+
+/// The filesystem directory a stack is bound to. An empty path unbinds the stack.
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct StackDirectory {
+    pub path: String,
+    pub watch: bool,
+}
+
+/// Optional details shown alongside a stack's name. On update, fields which are None are left
+/// as they are, and an empty string clears the field.
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone, Default)]
+pub struct StackMeta {
+    pub description: Option<String>,
+    pub color: Option<String>,
+    pub icon: Option<String>,
+}
+
+/// Tags to add to, and remove from, an item. Tags are many-to-many, unlike stacks: an item can
+/// have any number of them, and a tag can be on items across stacks.
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Tags {
+    pub add: Vec<String>,
+    pub remove: Vec<String>,
+}
+
+/// Tags are matched case-insensitively, and can be written with a leading `#`. Returns None for
+/// an empty tag, or one with whitespace in it.
+pub fn normalize_tag(tag: &str) -> Option<String> {
+    let tag = tag.trim().trim_start_matches('#').to_lowercase();
+    (!tag.is_empty() && !tag.contains(char::is_whitespace)).then_some(tag)
+}
+
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct PacketV4 {
+    pub id: Scru128Id,
+    pub packet_type: PacketType,
+    pub source_id: Option<Scru128Id>,
+    pub hash: Option<Integrity>,
+    pub stack_id: Option<Scru128Id>,
+    pub ephemeral: bool,
+    pub content_type: Option<String>,
+    pub movement: Option<Movement>,
+    pub lock_status: Option<StackLockStatus>,
+    pub sort_order: Option<StackSortOrder>,
+    pub cross_stream: bool,
+}
+
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct PacketV5 {
+    pub id: Scru128Id,
+    pub packet_type: PacketType,
+    pub source_id: Option<Scru128Id>,
+    pub hash: Option<Integrity>,
+    pub stack_id: Option<Scru128Id>,
+    pub ephemeral: bool,
+    pub content_type: Option<String>,
+    pub movement: Option<Movement>,
+    pub lock_status: Option<StackLockStatus>,
+    pub sort_order: Option<StackSortOrder>,
+    pub cross_stream: bool,
+    pub directory: Option<StackDirectory>,
+}
+
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct PacketV6 {
+    pub id: Scru128Id,
+    pub packet_type: PacketType,
+    pub source_id: Option<Scru128Id>,
+    pub hash: Option<Integrity>,
+    pub stack_id: Option<Scru128Id>,
+    pub ephemeral: bool,
+    pub content_type: Option<String>,
+    pub movement: Option<Movement>,
+    pub lock_status: Option<StackLockStatus>,
+    pub sort_order: Option<StackSortOrder>,
+    pub cross_stream: bool,
+    pub directory: Option<StackDirectory>,
+    pub stack_meta: Option<StackMeta>,
+}
+
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct PacketV7 {
+    pub id: Scru128Id,
+    pub packet_type: PacketType,
+    pub source_id: Option<Scru128Id>,
+    pub hash: Option<Integrity>,
+    pub stack_id: Option<Scru128Id>,
+    pub ephemeral: bool,
+    pub content_type: Option<String>,
+    pub movement: Option<Movement>,
+    pub lock_status: Option<StackLockStatus>,
+    pub sort_order: Option<StackSortOrder>,
+    pub cross_stream: bool,
+    pub directory: Option<StackDirectory>,
+    pub stack_meta: Option<StackMeta>,
+    pub tags: Option<Tags>,
+}
+
+/// Packets are stored in an envelope, with named fields: a new field needs
+/// `#[serde(default)]`, so packets written before it can still be read, rather than a new
+/// version of the struct.
 #[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
 pub struct Packet {
     pub id: Scru128Id,
     pub packet_type: PacketType,
+    #[serde(default)]
     pub source_id: Option<Scru128Id>,
+    #[serde(default)]
     pub hash: Option<Integrity>,
+    #[serde(default)]
     pub stack_id: Option<Scru128Id>,
+    #[serde(default)]
     pub ephemeral: bool,
+    #[serde(default)]
     pub content_type: Option<String>,
+    #[serde(default)]
     pub movement: Option<Movement>,
+    #[serde(default)]
     pub lock_status: Option<StackLockStatus>,
+    #[serde(default)]
     pub sort_order: Option<StackSortOrder>,
+    #[serde(default)]
     pub cross_stream: bool,
+    #[serde(default)]
+    pub directory: Option<StackDirectory>,
+    #[serde(default)]
+    pub stack_meta: Option<StackMeta>,
+    #[serde(default)]
+    pub tags: Option<Tags>,
+    #[serde(default)]
+    pub pin_status: Option<PinStatus>,
+    // for file references: a snapshot of the file's content, kept with the clip rather than
+    // with its content, as the same path can be added again once the file has changed
+    #[serde(default)]
+    pub snapshot: Option<Integrity>,
 }
 
 fn deserialize_packet(value: &[u8]) -> Option<Packet> {
+    match decode(value) {
+        Some(packet) => packet.ok(),
+        None => deserialize_legacy_packet(value),
+    }
+}
+
+/// Packets from before the envelope, which were encoded with bincode. Bincode is positional,
+/// so each layout is tried in turn, newest first.
+fn deserialize_legacy_packet(value: &[u8]) -> Option<Packet> {
     bincode::deserialize::<Packet>(value)
+        .or_else(|_| {
+            bincode::deserialize::<PacketV7>(value).map(|v7_packet| Packet {
+                id: v7_packet.id,
+                packet_type: v7_packet.packet_type,
+                source_id: v7_packet.source_id,
+                hash: v7_packet.hash,
+                stack_id: v7_packet.stack_id,
+                ephemeral: v7_packet.ephemeral,
+                content_type: v7_packet.content_type,
+                movement: v7_packet.movement,
+                lock_status: v7_packet.lock_status,
+                sort_order: v7_packet.sort_order,
+                cross_stream: v7_packet.cross_stream,
+                directory: v7_packet.directory,
+                stack_meta: v7_packet.stack_meta,
+                tags: v7_packet.tags,
+                pin_status: None,
+                snapshot: None,
+            })
+        })
+        .or_else(|_| {
+            bincode::deserialize::<PacketV6>(value).map(|v6_packet| Packet {
+                id: v6_packet.id,
+                packet_type: v6_packet.packet_type,
+                source_id: v6_packet.source_id,
+                hash: v6_packet.hash,
+                stack_id: v6_packet.stack_id,
+                ephemeral: v6_packet.ephemeral,
+                content_type: v6_packet.content_type,
+                movement: v6_packet.movement,
+                lock_status: v6_packet.lock_status,
+                sort_order: v6_packet.sort_order,
+                cross_stream: v6_packet.cross_stream,
+                directory: v6_packet.directory,
+                stack_meta: v6_packet.stack_meta,
+                tags: None,
+                pin_status: None,
+                snapshot: None,
+            })
+        })
+        .or_else(|_| {
+            bincode::deserialize::<PacketV5>(value).map(|v5_packet| Packet {
+                id: v5_packet.id,
+                packet_type: v5_packet.packet_type,
+                source_id: v5_packet.source_id,
+                hash: v5_packet.hash,
+                stack_id: v5_packet.stack_id,
+                ephemeral: v5_packet.ephemeral,
+                content_type: v5_packet.content_type,
+                movement: v5_packet.movement,
+                lock_status: v5_packet.lock_status,
+                sort_order: v5_packet.sort_order,
+                cross_stream: v5_packet.cross_stream,
+                directory: v5_packet.directory,
+                stack_meta: None,
+                tags: None,
+                pin_status: None,
+                snapshot: None,
+            })
+        })
+        .or_else(|_| {
+            bincode::deserialize::<PacketV4>(value).map(|v4_packet| Packet {
+                id: v4_packet.id,
+                packet_type: v4_packet.packet_type,
+                source_id: v4_packet.source_id,
+                hash: v4_packet.hash,
+                stack_id: v4_packet.stack_id,
+                ephemeral: v4_packet.ephemeral,
+                content_type: v4_packet.content_type,
+                movement: v4_packet.movement,
+                lock_status: v4_packet.lock_status,
+                sort_order: v4_packet.sort_order,
+                cross_stream: v4_packet.cross_stream,
+                directory: None,
+                stack_meta: None,
+                tags: None,
+                pin_status: None,
+                snapshot: None,
+            })
+        })
         .or_else(|_| {
             bincode::deserialize::<PacketV3>(value).map(|v3_packet| Packet {
                 id: v3_packet.id,
@@ -162,98 +523,298 @@ fn deserialize_packet(value: &[u8]) -> Option<Packet> {
                 lock_status: None,
                 sort_order: None,
                 cross_stream: false,
+                directory: None,
+                stack_meta: None,
+                tags: None,
+                pin_status: None,
+                snapshot: None,
             })
         })
         .ok()
 }
 
+/// Full text search over text content, and over the tags on items. Content is indexed by hash,
+/// while tags are indexed per item, along with the hash of the item's content.
+///
+/// The index is a handle which can be cloned, so searches don't need the store: the writer has
+/// a lock of its own.
+///
+/// Changes to the index are made in the background, in the order they're queued, and committed
+/// in batches: see [`Index::spawn_indexer`]. Segment merges run on tantivy's own merge threads.
+#[derive(Clone)]
 pub struct Index {
-    content_field: tantivy::schema::Field,
-    hash_field: tantivy::schema::Field,
-    writer: tantivy::IndexWriter,
+    fields: IndexFields,
+    writer: Arc<Mutex<tantivy::IndexWriter>>,
     reader: tantivy::IndexReader,
     index: tantivy::Index,
+    jobs: std::sync::mpsc::Sender<IndexJob>,
+    progress: Arc<(Mutex<IndexStatus>, std::sync::Condvar)>,
+}
+
+#[derive(Clone, Copy)]
+struct IndexFields {
+    content: tantivy::schema::Field,
+    hash: tantivy::schema::Field,
+    tags: tantivy::schema::Field,
+    item: tantivy::schema::Field,
+}
+
+// a change to the index, queued for the indexer
+enum IndexJob {
+    Content(ssri::Integrity),
+    Tags {
+        id: Scru128Id,
+        hash: ssri::Integrity,
+        tags: Vec<String>,
+    },
+    Delete(ssri::Integrity),
+}
+
+/// How far the indexer has got with the changes queued for it.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct IndexStatus {
+    // changes queued for the indexer, and how many of them are committed to the index
+    pub queued: u64,
+    pub indexed: u64,
+    // queued content which was purged before the indexer got to it
+    pub dropped: u64,
+    // changes which are queued, but not yet searchable
+    pub lag: u64,
+    pub docs: u64,
+    // when the index was last committed to, in milliseconds since the epoch
+    pub last_commit: Option<u64>,
+}
+
+// the indexer commits once it has this many changes, or once the oldest has waited this long
+const INDEX_BATCH_SIZE: usize = 100;
+const INDEX_BATCH_INTERVAL: std::time::Duration = std::time::Duration::from_millis(200);
+
+/// What the search index is built from: the text content in the CAS, and the tags on items.
+pub struct IndexSources {
+    blobs: Arc<dyn Blobs>,
+    total: usize,
+    content: Vec<ssri::Integrity>,
+    tags: Vec<(Scru128Id, ssri::Integrity, Vec<String>)>,
 }
 
 impl Index {
-    fn new(path: std::path::PathBuf) -> (Index, bool) {
+    /// Opens the index kept at `path`, or creates one in memory if there's no `path`, along with
+    /// its indexer, which reads content from `blobs`.
+    fn new(path: Option<std::path::PathBuf>, blobs: Arc<dyn Blobs>) -> (Index, bool) {
         let mut schema_builder = tantivy::schema::Schema::builder();
-        let content_field = schema_builder.add_text_field("content", tantivy::schema::TEXT);
-        let hash_field = schema_builder
-            .add_bytes_field("hash", tantivy::schema::STORED | tantivy::schema::INDEXED);
+        let fields = IndexFields {
+            content: schema_builder.add_text_field("content", tantivy::schema::TEXT),
+            hash: schema_builder
+                .add_bytes_field("hash", tantivy::schema::STORED | tantivy::schema::INDEXED),
+            tags: schema_builder.add_text_field("tags", tantivy::schema::TEXT),
+            item: schema_builder.add_bytes_field("item", tantivy::schema::INDEXED),
+        };
         let schema = schema_builder.build();
 
-        std::fs::create_dir_all(&path).unwrap();
-        let dir = tantivy::directory::MmapDirectory::open(&path).unwrap();
+        let (index, needs_rebuild) = match path {
+            Some(path) => Index::open_in_dir(&path, schema),
+            None => (tantivy::Index::create_in_ram(schema), false),
+        };
+
+        let writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+        let reader = index.reader().unwrap();
+        let (jobs, rx) = std::sync::mpsc::channel();
 
-        let (index, needs_rebuild) = match tantivy::Index::open_or_create(dir, schema.clone()) {
+        let index = Index {
+            fields,
+            writer: Arc::new(Mutex::new(writer)),
+            reader,
+            index,
+            jobs,
+            progress: Arc::new((
+                Mutex::new(IndexStatus::default()),
+                std::sync::Condvar::new(),
+            )),
+        };
+        index.spawn_indexer(rx, blobs);
+        (index, needs_rebuild)
+    }
+
+    fn open_in_dir(
+        path: &std::path::Path,
+        schema: tantivy::schema::Schema,
+    ) -> (tantivy::Index, bool) {
+        std::fs::create_dir_all(path).unwrap();
+        let dir = tantivy::directory::MmapDirectory::open(path).unwrap();
+
+        match tantivy::Index::open_or_create(dir, schema.clone()) {
             Ok(index) => (index, false),
             Err(tantivy::TantivyError::SchemaError(_)) => {
                 // Schema mismatch - delete old index and create new one
                 tracing::info!("Schema mismatch detected, recreating index with new schema");
-                std::fs::remove_dir_all(&path).unwrap();
-                std::fs::create_dir_all(&path).unwrap();
-                let new_index = tantivy::Index::create_in_dir(&path, schema).unwrap();
+                std::fs::remove_dir_all(path).unwrap();
+                std::fs::create_dir_all(path).unwrap();
+                let new_index = tantivy::Index::create_in_dir(path, schema).unwrap();
                 (new_index, true)
             }
             Err(e) => panic!("Failed to open/create index: {e}"),
-        };
-
-        let writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
-        let reader = index.reader().unwrap();
+        }
+    }
 
-        (
-            Index {
-                content_field,
-                hash_field,
-                writer,
-                reader,
-                index,
-            },
-            needs_rebuild,
-        )
+    fn writer(&self) -> std::sync::MutexGuard<'_, tantivy::IndexWriter> {
+        self.writer.lock().unwrap()
     }
 
-    #[tracing::instrument(skip_all)]
-    fn write(&mut self, hash: &ssri::Integrity, content: &[u8]) {
-        let content = String::from_utf8_lossy(content);
-        let mut doc = tantivy::TantivyDocument::new();
-        doc.add_text(self.content_field, &content);
-        let bytes = bincode::serialize(&hash).unwrap();
-        doc.add_bytes(self.hash_field, bytes);
-        self.writer.add_document(doc).unwrap();
-        self.writer.commit().unwrap();
+    fn commit(&self, mut writer: std::sync::MutexGuard<'_, tantivy::IndexWriter>) {
+        writer.commit().unwrap();
+        drop(writer);
         self.reader.reload().unwrap();
     }
 
-    #[tracing::instrument(skip_all)]
-    fn delete(&mut self, hash: &ssri::Integrity) {
-        let bytes = bincode::serialize(&hash).unwrap();
-        let term = tantivy::Term::from_field_bytes(self.hash_field, &bytes);
-        self.writer.delete_term(term);
-        self.writer.commit().unwrap();
-        self.reader.reload().unwrap();
+    /// Makes the changes queued on `rx`, on a thread of its own, in the order they were queued,
+    /// committing in batches. The thread finishes once every handle on the index is dropped.
+    fn spawn_indexer(&self, rx: std::sync::mpsc::Receiver<IndexJob>, blobs: Arc<dyn Blobs>) {
+        // the thread only holds on to the writer while committing, so the index can be opened
+        // again as soon as the store is dropped
+        let writer = Arc::downgrade(&self.writer);
+        let fields = self.fields;
+        let reader = self.reader.clone();
+        let progress = self.progress.clone();
+
+        std::thread::spawn(move || {
+            while let Ok(job) = rx.recv() {
+                let mut batch = vec![job];
+                let deadline = std::time::Instant::now() + INDEX_BATCH_INTERVAL;
+                while batch.len() < INDEX_BATCH_SIZE {
+                    let timeout = deadline.saturating_duration_since(std::time::Instant::now());
+                    match rx.recv_timeout(timeout) {
+                        Ok(job) => batch.push(job),
+                        Err(_) => break,
+                    }
+                }
+
+                // content which has been purged since it was queued is dropped
+                let mut dropped = 0;
+                {
+                    let Some(writer) = writer.upgrade() else {
+                        break;
+                    };
+                    let mut writer = writer.lock().unwrap();
+                    for job in &batch {
+                        match job {
+                            IndexJob::Content(hash) => match blobs.read(hash) {
+                                Some(content) => {
+                                    writer
+                                        .add_document(fields.content_doc(hash, &content))
+                                        .unwrap();
+                                }
+                                None => dropped += 1,
+                            },
+                            IndexJob::Tags { id, hash, tags } => {
+                                fields.add_tags(&writer, id, hash, tags)
+                            }
+                            IndexJob::Delete(hash) => {
+                                writer.delete_term(fields.hash_term(hash));
+                            }
+                        }
+                    }
+                    writer.commit().unwrap();
+                }
+                reader.reload().unwrap();
+
+                let mut status = progress.0.lock().unwrap();
+                status.indexed += batch.len() as u64 - dropped;
+                status.dropped += dropped;
+                status.last_commit = Some(
+                    std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .unwrap()
+                        .as_millis() as u64,
+                );
+                progress.1.notify_all();
+            }
+        });
+    }
+
+    // changes are only counted once they're on their way to the indexer, so they're sure to be
+    // counted as indexed or dropped too
+    fn queue(&self, job: IndexJob) {
+        let mut status = self.progress.0.lock().unwrap();
+        if self.jobs.send(job).is_ok() {
+            status.queued += 1;
+        }
+    }
+
+    /// Indexes the text content `hash`.
+    fn add(&self, hash: ssri::Integrity) {
+        self.queue(IndexJob::Content(hash));
+    }
+
+    /// How far the indexer has got, and how many documents are in the index.
+    pub fn status(&self) -> IndexStatus {
+        let mut status = self.progress.0.lock().unwrap().clone();
+        status.lag = status
+            .queued
+            .saturating_sub(status.indexed + status.dropped);
+        status.docs = self.reader.searcher().num_docs();
+        status
+    }
+
+    /// Waits for the indexer to commit the changes queued so far, so they can be searched for.
+    pub fn flush(&self) {
+        let (status, indexed) = &*self.progress;
+        let status = status.lock().unwrap();
+        let _ = indexed
+            .wait_timeout_while(status, std::time::Duration::from_secs(30), |status| {
+                status.indexed + status.dropped < status.queued
+            })
+            .unwrap();
+    }
+
+    /// Replaces the tags indexed for the item `id`, whose content is `hash`.
+    pub fn write_tags(&self, id: &Scru128Id, hash: &ssri::Integrity, tags: &[String]) {
+        self.queue(IndexJob::Tags {
+            id: *id,
+            hash: hash.clone(),
+            tags: tags.to_vec(),
+        });
+    }
+
+    /// Removes the content `hash` from the index, along with the tags of items holding it.
+    fn delete(&self, hash: &ssri::Integrity) {
+        self.queue(IndexJob::Delete(hash.clone()));
     }
 
     #[tracing::instrument(skip_all)]
-    fn clear(&mut self) {
-        self.writer.delete_all_documents().unwrap();
-        self.writer.commit().unwrap();
-        self.reader.reload().unwrap();
+    fn clear(&self) {
+        let writer = self.writer();
+        writer.delete_all_documents().unwrap();
+        self.commit(writer);
     }
 
+    /// Indexes everything in `sources`, once the index has been cleared. Content is read
+    /// straight from the CAS, so this can run without holding on to the store. Returns the
+    /// number of items in the CAS, and how many of them were indexed.
     #[tracing::instrument(skip_all)]
-    fn write_batch(&mut self, items: &[(ssri::Integrity, Vec<u8>)]) {
-        for (hash, content) in items {
-            let content_str = String::from_utf8_lossy(content);
-            let mut doc = tantivy::TantivyDocument::new();
-            doc.add_text(self.content_field, &content_str);
-            let bytes = bincode::serialize(hash).unwrap();
-            doc.add_bytes(self.hash_field, bytes);
-            self.writer.add_document(doc).unwrap();
-        }
-        self.writer.commit().unwrap();
-        self.reader.reload().unwrap();
+    pub fn rebuild(&self, sources: &IndexSources) -> tantivy::Result<(usize, usize)> {
+        // read the content before taking the writer, so writes from elsewhere aren't held up
+        let docs: Vec<tantivy::TantivyDocument> = sources
+            .content
+            .iter()
+            .filter_map(|hash| {
+                let content = sources.blobs.read(hash)?;
+                Some(self.fields.content_doc(hash, &content))
+            })
+            .collect();
+        let indexed_count = docs.len();
+
+        let mut writer = self.writer();
+        for doc in docs {
+            writer.add_document(doc)?;
+        }
+        for (id, hash, tags) in &sources.tags {
+            self.fields.add_tags(&writer, id, hash, tags);
+        }
+        writer.commit()?;
+        drop(writer);
+        self.reader.reload()?;
+
+        Ok((sources.total, indexed_count))
     }
 
     pub fn query(
@@ -261,64 +822,296 @@ impl Index {
         q: &str,
         limit: Option<usize>,
     ) -> Result<Vec<(ssri::Integrity, f32)>, Box<dyn std::error::Error>> {
-        // Build a QueryParser that targets the `content` field
-        let parser = tantivy::query::QueryParser::for_index(&self.index, vec![self.content_field]);
+        // Build a QueryParser that targets the `content` and `tags` fields
+        let parser = tantivy::query::QueryParser::for_index(
+            &self.index,
+            vec![self.fields.content, self.fields.tags],
+        );
         let query = parser.parse_query(q)?;
 
         let searcher = self.reader.searcher();
         let max = limit.unwrap_or(10_000);
         let top_docs = searcher.search(&query, &tantivy::collector::TopDocs::with_limit(max))?;
 
+        // content can match both on its own and through the tags of items holding it: keep the
+        // best scoring match
+        let mut seen = HashSet::new();
         let results = top_docs
             .into_iter()
             .map(|(score, doc_address)| {
                 use tantivy::schema::Value;
                 let doc: tantivy::TantivyDocument = searcher.doc(doc_address).unwrap();
-                let bytes = doc.get_first(self.hash_field).unwrap().as_bytes().unwrap();
+                let bytes = doc.get_first(self.fields.hash).unwrap().as_bytes().unwrap();
                 let hash: ssri::Integrity = bincode::deserialize(bytes).unwrap();
                 (hash, score)
             })
+            .filter(|(hash, _)| seen.insert(hash.clone()))
             .collect();
 
         Ok(results)
     }
 }
 
+impl IndexFields {
+    fn content_doc(&self, hash: &ssri::Integrity, content: &[u8]) -> tantivy::TantivyDocument {
+        let content = String::from_utf8_lossy(content);
+        let mut doc = tantivy::TantivyDocument::new();
+        doc.add_text(self.content, &content);
+        doc.add_bytes(self.hash, bincode::serialize(&hash).unwrap());
+        doc
+    }
+
+    fn hash_term(&self, hash: &ssri::Integrity) -> tantivy::Term {
+        tantivy::Term::from_field_bytes(self.hash, &bincode::serialize(&hash).unwrap())
+    }
+
+    fn add_tags(
+        &self,
+        writer: &tantivy::IndexWriter,
+        id: &Scru128Id,
+        hash: &ssri::Integrity,
+        tags: &[String],
+    ) {
+        let item = id.to_bytes();
+        writer.delete_term(tantivy::Term::from_field_bytes(self.item, &item));
+        if tags.is_empty() {
+            return;
+        }
+        let mut doc = tantivy::TantivyDocument::new();
+        doc.add_text(self.tags, tags.join(" "));
+        doc.add_bytes(self.hash, bincode::serialize(&hash).unwrap());
+        doc.add_bytes(self.item, item.to_vec());
+        writer.add_document(doc).unwrap();
+    }
+}
+
 #[derive(Serialize, Deserialize, Default, Debug, Clone)]
 pub struct Settings {
     pub openai_access_token: String,
     pub openai_selected_model: String,
     pub cross_stream_access_token: Option<String>,
+    // where items added to the cross-stream stack are published, see cross_stream::start
+    pub cross_stream_endpoint: Option<Endpoint>,
     pub activation_shortcut: Option<spotlight::Shortcut>,
+    pub shell: Option<ShellSettings>,
+    pub capture_rules: Option<Vec<CaptureRule>>,
+    pub rollover: Option<Rollover>,
+    // strftime format for the names of new stacks, see rollover::stack_name
+    pub stack_name_template: Option<String>,
+    // whether `GET /sql` answers read-only queries, for stores kept in SQLite
+    pub sql_endpoint: Option<bool>,
+    // peers to sync with, see sync::SyncSettings
+    pub sync: Option<crate::sync::SyncSettings>,
+}
+
+/// What to do with clips copied from a given application. Rules are checked in order and the
+/// first whose `source` matches the application the clip was copied from applies.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct CaptureRule {
+    // matched case-insensitively against the application's name, e.g. "1Password" or "Terminal"
+    pub source: String,
+    // don't capture the clip at all, e.g. for password managers
+    #[serde(default)]
+    pub ignore: bool,
+    // the name of the stack to capture into, rather than the current stack
+    pub stack: Option<String>,
+    pub content_type: Option<String>,
+}
+
+impl CaptureRule {
+    pub fn matches(&self, source: &str) -> bool {
+        !self.source.is_empty() && source.to_lowercase().contains(&self.source.to_lowercase())
+    }
+}
+
+/// How pipe commands are run. Every field is optional, falling back to `$SHELL -c`, with the
+/// shell's conventional rc file sourced when it exists.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct ShellSettings {
+    pub shell: Option<String>,
+    pub args: Option<Vec<String>>,
+    pub rc_file: Option<String>,
+    pub cwd: Option<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    pub path: Option<String>,
+}
+
+// room for bursts of content, before listeners on the bus fall behind
+const CONTENT_BUS_CAPACITY: usize = 1024;
+
+// bumped whenever the view's layout changes, so older snapshots are replayed from scratch
+const VIEW_SNAPSHOT_VERSION: u32 = 2;
+
+// set once the Delete packets written before they were tracked have been found
+const DELETES_KEY: &str = "deletes_tracked";
+
+// set once content types are kept in the content meta, rather than only in packets
+const CONTENT_TYPES_KEY: &str = "content_types_in_meta";
+
+// file references keep a snapshot of files up to this size
+const MAX_SNAPSHOT_SIZE: u64 = 10 * 1024 * 1024;
+
+/// What [`Store::fsck`] found wrong with the store, and whether it was repaired.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct FsckReport {
+    // how many blobs in the CAS, and packets, were checked
+    pub blobs: usize,
+    pub packets: usize,
+    // blobs whose content doesn't match their hash
+    pub corrupt_blobs: Vec<Integrity>,
+    // blobs with no content meta, which nothing refers to
+    pub orphan_blobs: Vec<Integrity>,
+    // content meta for content which no packet refers to
+    pub orphan_meta: Vec<Integrity>,
+    // content meta whose blob is missing from the CAS
+    pub missing_blobs: Vec<Integrity>,
+    // intact blobs which packets refer to, but which have lost their content meta
+    pub unlinked_blobs: Vec<Integrity>,
+    // packets whose content is missing, or corrupt
+    pub missing_content: Vec<Scru128Id>,
+    // packets which refer to an item, or a stack, which doesn't exist
+    pub missing_items: Vec<Scru128Id>,
+    // entries which couldn't be deserialized
+    pub unreadable_meta: usize,
+    pub unreadable_packets: usize,
+    pub repaired: bool,
+}
+
+impl FsckReport {
+    pub fn is_clean(&self) -> bool {
+        self.corrupt_blobs.is_empty()
+            && self.orphan_blobs.is_empty()
+            && self.orphan_meta.is_empty()
+            && self.missing_blobs.is_empty()
+            && self.unlinked_blobs.is_empty()
+            && self.missing_content.is_empty()
+            && self.missing_items.is_empty()
+            && self.unreadable_meta == 0
+            && self.unreadable_packets == 0
+    }
+}
+
+/// What [`Store::compact`] did to the packet log, or would do on a dry run.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct CompactReport {
+    // packets in the log before, and after, compaction
+    pub before: usize,
+    pub after: usize,
+    // recent packets which were kept as they are
+    pub kept: usize,
+    // packets which couldn't be read, or whose content is missing, which were left as they are
+    pub skipped: usize,
+    pub dry_run: bool,
+}
+
+/// A handle on the packet log, see [`Store::log`]. Packets whose content was gone as of when the
+/// handle was taken are skipped.
+pub struct Log {
+    packets: Arc<dyn Table>,
+    view_snapshots: Arc<dyn Table>,
+    content: HashSet<Integrity>,
+}
+
+impl Log {
+    pub fn scan_after(&self, after: Option<Scru128Id>) -> impl Iterator<Item = Packet> + use<'_> {
+        scan_after(self.packets.as_ref(), after, |hash| {
+            self.content.contains(hash)
+        })
+    }
+
+    pub fn load_view_snapshot(&self) -> Option<(Scru128Id, crate::view::View)> {
+        load_view_snapshot(self.view_snapshots.as_ref())
+    }
+}
+
+fn scan_after<'a>(
+    packets: &'a dyn Table,
+    after: Option<Scru128Id>,
+    cas_exists: impl Fn(&Integrity) -> bool + 'a,
+) -> impl Iterator<Item = Packet> + 'a {
+    let start = match after {
+        Some(id) => std::ops::Bound::Excluded(id.to_bytes().to_vec()),
+        None => std::ops::Bound::Unbounded,
+    };
+    packets
+        .range(start)
+        .filter_map(|(_, value)| deserialize_packet(&value))
+        .filter(move |packet| {
+            // Skip packets with dangling CAS hashes
+            if let Some(hash) = &packet.hash {
+                if !cas_exists(hash) {
+                    tracing::warn!("Skipping packet with missing CAS content: {}", hash);
+                    return false;
+                }
+            }
+            true
+        })
+}
+
+fn load_view_snapshot(view_snapshots: &dyn Table) -> Option<(Scru128Id, crate::view::View)> {
+    let (key, value) = view_snapshots.last()?;
+    let id = Scru128Id::from_bytes(key.try_into().ok()?);
+    match bincode::deserialize::<(u32, crate::view::View)>(&value) {
+        Ok((VIEW_SNAPSHOT_VERSION, view)) => Some((id, view)),
+        Ok((version, _)) => {
+            tracing::info!("Ignoring view snapshot from version {}", version);
+            None
+        }
+        Err(e) => {
+            tracing::warn!("Could not deserialize view snapshot: {}", e);
+            None
+        }
+    }
 }
 
 pub struct Store {
-    packets: sled::Tree,
-    content_meta: sled::Tree,
+    packets: Arc<dyn Table>,
+    content_meta: Arc<dyn Table>,
     content_meta_cache: HashMap<ssri::Integrity, ContentMeta>,
     syntaxes: HashSet<String>,
     pub content_bus_tx: tokio::sync::broadcast::Sender<ContentMeta>,
-    pub meta: sled::Tree,
-    pub cache_path: String,
+    pub meta: Arc<dyn Table>,
+    schedules: Arc<dyn Table>,
+    view_snapshots: Arc<dyn Table>,
+    // every packet written to the log, or removed from it, in the order it happened, keyed by
+    // sequence number, for peers to sync from
+    changes: Arc<dyn Table>,
+    // the ids of packets which were removed, so peers can't send them back
+    tombstones: Arc<dyn Table>,
+    // how publishing each item added to the cross-stream stack is going, keyed by item id
+    deliveries: Arc<dyn Table>,
+    // the ids of Delete packets which haven't been garbage collected, so they're found on start
+    // without replaying the log
+    deletes: Arc<dyn Table>,
+    pub blobs: Arc<dyn Blobs>,
     pub index: Index,
+    // how many times content has been purged
+    purges: u64,
 }
 
 impl Store {
+    /// Opens the store at `path`, in whichever backend it's kept in.
     pub fn new(path: &str) -> Store {
         let path = std::path::Path::new(path);
-        let db = sled::open(path.join("sled")).unwrap();
-        let packets = db.open_tree("packets").unwrap();
-        let content_meta = db.open_tree("content_meta").unwrap();
-        let meta = db.open_tree("meta").unwrap();
-        let cache_path = path.join("cas").into_os_string().into_string().unwrap();
+        let storage = storage::open(storage::detect(path), path).unwrap();
+        Store::with_storage(storage.as_ref())
+    }
 
-        let (content_bus_tx, _rx) = tokio::sync::broadcast::channel(20);
+    /// A store which is kept in memory, and gone once it's dropped.
+    pub fn in_memory() -> Store {
+        Store::with_storage(&MemoryStorage::default())
+    }
+
+    pub fn with_storage(storage: &dyn Storage) -> Store {
+        let (content_bus_tx, _rx) = tokio::sync::broadcast::channel(CONTENT_BUS_CAPACITY);
 
-        let (index, needs_rebuild) = Index::new(path.join("index"));
+        let blobs = storage.blobs();
+        let (index, needs_rebuild) = Index::new(storage.index_path(), blobs.clone());
 
         let mut store = Store {
-            packets,
-            content_meta,
+            packets: storage.table("packets"),
+            content_meta: storage.table("content_meta"),
             content_meta_cache: HashMap::new(),
             // TODO: oh my
             syntaxes: syntect::parsing::SyntaxSet::load_defaults_nonewlines()
@@ -329,10 +1122,20 @@ impl Store {
                 .collect(),
 
             content_bus_tx,
-            meta,
-            cache_path,
+            meta: storage.table("meta"),
+            schedules: storage.table("schedules"),
+            view_snapshots: storage.table("view_snapshots"),
+            changes: storage.table("changes"),
+            tombstones: storage.table("tombstones"),
+            deliveries: storage.table("deliveries"),
+            deletes: storage.table("deletes"),
+            blobs,
             index,
+            purges: 0,
         };
+        store.migrate_encoding();
+        store.init_changes();
+        store.init_deletes();
         store.content_meta_cache = store.scan_content_meta();
 
         // Auto-rebuild index if schema migration occurred
@@ -348,18 +1151,159 @@ impl Store {
         store
     }
 
+    /// Re-encodes the packets and content meta written before the current encoding, once.
+    /// Entries which can't be read are left as they are, for `stacks fsck` to report.
+    fn migrate_encoding(&self) {
+        if self.meta.get(ENCODING_KEY.as_bytes()).as_deref() == Some(&[ENCODING_VERSION]) {
+            return;
+        }
+
+        let mut packets = Vec::new();
+        let mut migrated = 0;
+        for (key, value) in self.packets.iter() {
+            if decode::<Packet>(&value).is_none() {
+                if let Some(packet) = deserialize_legacy_packet(&value) {
+                    packets.push((key, encode(&packet)));
+                    migrated += 1;
+                }
+            }
+        }
+        let mut content_meta = Vec::new();
+        for (key, value) in self.content_meta.iter() {
+            if decode::<ContentMeta>(&value).is_none() {
+                if let Ok(meta) = deserialize_legacy_content_meta(&value) {
+                    content_meta.push((key, encode(&meta)));
+                    migrated += 1;
+                }
+            }
+        }
+        self.packets.apply(Vec::new(), packets);
+        self.content_meta.apply(Vec::new(), content_meta);
+        self.meta
+            .insert(ENCODING_KEY.as_bytes(), &[ENCODING_VERSION]);
+
+        if migrated > 0 {
+            tracing::info!("Re-encoded {} packets and content meta", migrated);
+        }
+    }
+
+    /// Logs the packets written before the change log was kept, once, so peers get them too.
+    fn init_changes(&self) {
+        if !self.changes.is_empty() {
+            return;
+        }
+        let insert = self
+            .packets
+            .iter()
+            .enumerate()
+            .map(|(i, (key, _))| {
+                let seq = i as u64 + 1;
+                let mut value = vec![CHANGE_PACKET];
+                value.extend(key);
+                (seq.to_be_bytes().to_vec(), value)
+            })
+            .collect();
+        self.changes.apply(Vec::new(), insert);
+    }
+
+    /// Tracks the Delete packets written before they were tracked, once.
+    fn init_deletes(&self) {
+        if self.meta.get(DELETES_KEY.as_bytes()).is_some() {
+            return;
+        }
+        let insert = self
+            .scan_packets()
+            .filter(|packet| packet.packet_type == PacketType::Delete)
+            .map(|packet| (packet.id.to_bytes().to_vec(), Vec::new()))
+            .collect();
+        self.deletes.apply(Vec::new(), insert);
+        self.meta.insert(DELETES_KEY.as_bytes(), &[]);
+    }
+
+    /// The Delete packets which haven't been garbage collected yet.
+    pub fn delete_packets(&self) -> Vec<Packet> {
+        self.deletes
+            .iter()
+            .filter_map(|(key, _)| self.get_packet(&Scru128Id::from_bytes(key.try_into().ok()?)))
+            .collect()
+    }
+
+    fn log_change(&self, kind: u8, id: &Scru128Id) {
+        let mut value = vec![kind];
+        value.extend(id.to_bytes());
+        self.changes
+            .insert(&(self.last_change() + 1).to_be_bytes(), &value);
+    }
+
+    /// The sequence number of the latest change to the packet log.
+    pub fn last_change(&self) -> u64 {
+        self.changes
+            .last()
+            .and_then(|(key, _)| key.try_into().ok())
+            .map_or(0, u64::from_be_bytes)
+    }
+
+    /// Up to `limit` changes to the packet log after the change `seq`, along with the sequence
+    /// number of the last of them. Packets which have since been removed are left out: their
+    /// removal follows.
+    pub fn changes_after(&self, seq: u64, limit: usize) -> (Vec<Change>, u64) {
+        let from = std::ops::Bound::Excluded(seq.to_be_bytes().to_vec());
+        let mut last = seq;
+        let mut changes = Vec::new();
+        for (key, value) in self.changes.range(from).take(limit) {
+            last = key.try_into().map_or(last, u64::from_be_bytes);
+            let Some(id) = value.get(1..).and_then(|id| id.try_into().ok()) else {
+                continue;
+            };
+            let id = Scru128Id::from_bytes(id);
+            match value[0] {
+                CHANGE_REMOVED => changes.push(Change::Removed(id)),
+                _ => changes.extend(
+                    self.get_packet(&id)
+                        .map(|packet| Change::Packet(Box::new(packet))),
+                ),
+            }
+        }
+        (changes, last)
+    }
+
+    /// Whether the packet `id` was removed from the log.
+    pub fn is_removed(&self, id: &Scru128Id) -> bool {
+        self.tombstones.get(&id.to_bytes()).is_some()
+    }
+
+    /// Matches content against `filter`. Words of the form `from:<app>` match the application
+    /// the content was copied from, e.g. `from:terminal`, and the rest is matched against the
+    /// content itself.
     pub fn query(&self, filter: &str, content_type: &str) -> HashSet<ssri::Integrity> {
         let filter = filter.to_lowercase();
         let content_type = content_type.to_lowercase();
 
+        let (sources, words): (Vec<&str>, Vec<&str>) = filter
+            .split(' ')
+            .partition(|word| word.starts_with("from:"));
+        let sources: Vec<&str> = sources
+            .iter()
+            .map(|word| word.trim_start_matches("from:"))
+            .filter(|source| !source.is_empty())
+            .collect();
+        let filter = words.join(" ");
+        let filter = filter.trim();
+
         self.content_meta_cache
             .iter()
             .filter_map(|(hash, meta)| {
                 let terse = meta.terse.to_lowercase();
                 let content_type_meta = meta.content_type.to_lowercase();
+                let source = meta
+                    .source
+                    .as_ref()
+                    .map(|source| source.to_lowercase())
+                    .unwrap_or_default();
 
                 // TODO: oh my
-                if (filter.is_empty() || terse.contains(&filter))
+                if (filter.is_empty() || terse.contains(filter))
+                    && sources.iter().all(|s| source.contains(s))
                     && (content_type.is_empty()
                         || content_type == "all"
                         || content_type_meta == content_type
@@ -377,14 +1321,14 @@ impl Store {
     pub fn scan_content_meta(&self) -> HashMap<ssri::Integrity, ContentMeta> {
         let mut content_meta_cache = HashMap::new();
 
-        for (key, value) in self.content_meta.iter().flatten() {
+        for (key, value) in self.content_meta.iter() {
             let hash = bincode::deserialize::<ssri::Integrity>(&key);
-            let meta = bincode::deserialize::<ContentMeta>(&value);
+            let meta = deserialize_content_meta(&value);
 
             match (hash, meta) {
                 (Ok(hash), Ok(meta)) => {
                     // Skip content metadata if CAS content no longer exists
-                    if self.cas_read(&hash).is_none() {
+                    if !self.blobs.exists(&hash) {
                         tracing::warn!("Skipping content metadata for missing CAS entry: {}", hash);
                         continue;
                     }
@@ -397,20 +1341,32 @@ impl Store {
                     }
                     content_meta_cache.insert(hash, meta);
                 }
-                (Err(e), _) | (_, Err(e)) => {
-                    panic!("Could not deserialize content: {e:?}");
+                (hash, meta) => {
+                    // `stacks fsck --repair` clears these out
+                    let e = hash.err().map(|e| e.to_string()).or(meta.err());
+                    tracing::error!("Skipping content metadata which can't be read: {e:?}");
                 }
             }
         }
 
-        self.scan()
-            .filter(|p| p.packet_type == PacketType::Update || p.packet_type == PacketType::Add)
-            .for_each(|p| {
-                let meta = p.hash.and_then(|hash| content_meta_cache.get_mut(&hash));
-                if let (Some(meta), Some(content_type)) = (meta, p.content_type) {
-                    meta.content_type = content_type;
-                }
-            });
+        // content types used to only be recorded in packets, so stores from before they were
+        // kept in the content meta are brought up to date, once
+        if self.meta.get(CONTENT_TYPES_KEY.as_bytes()).is_none() {
+            self.scan_packets()
+                .filter(|p| p.packet_type == PacketType::Update || p.packet_type == PacketType::Add)
+                .for_each(|p| {
+                    let meta = p.hash.and_then(|hash| content_meta_cache.get_mut(&hash));
+                    if let (Some(meta), Some(content_type)) = (meta, p.content_type) {
+                        meta.content_type = content_type;
+                    }
+                });
+            for (hash, meta) in &content_meta_cache {
+                let encoded = encode(meta);
+                let hash_bytes = bincode::serialize(hash).unwrap();
+                self.content_meta.insert(&hash_bytes, &encoded);
+            }
+            self.meta.insert(CONTENT_TYPES_KEY.as_bytes(), b"1");
+        }
 
         content_meta_cache
     }
@@ -431,78 +1387,520 @@ impl Store {
         mime_type: MimeType,
         content_type: String,
     ) -> Integrity {
-        let hash = cacache::write_hash_sync(&self.cache_path, content).unwrap();
-        if let Some(meta) = self.content_meta_cache.get_mut(&hash) {
-            meta.content_type = content_type;
+        let hash = self.blobs.write(content);
+        if self.content_meta_cache.contains_key(&hash) {
+            self.update_content_meta(hash.clone(), |meta| meta.content_type = content_type);
             return hash;
         }
 
-        let terse = match mime_type {
-            MimeType::TextPlain => {
-                let text_content = String::from_utf8_lossy(content).into_owned();
-                if text_content.len() > 100 {
-                    text_content.chars().take(100).collect()
-                } else {
-                    text_content
-                }
-            }
-            MimeType::ImagePng => "Image".to_string(),
-        };
+        let terse = match mime_type {
+            MimeType::TextPlain => {
+                let text_content = String::from_utf8_lossy(content).into_owned();
+                if text_content.len() > 100 {
+                    text_content.chars().take(100).collect()
+                } else {
+                    text_content
+                }
+            }
+            MimeType::ImagePng => "Image".to_string(),
+        };
+
+        let meta = ContentMeta {
+            hash: hash.clone(),
+            mime_type: mime_type.clone(),
+            content_type,
+            terse,
+            tiktokens: 0,
+            snapshot: None,
+            source: None,
+            alternates: Vec::new(),
+        };
+        let encoded = encode(&meta);
+        let bytes = bincode::serialize(&hash).unwrap();
+        self.content_meta.insert(&bytes, &encoded);
+
+        self.content_meta_cache.insert(hash.clone(), meta.clone());
+
+        // text content is indexed in the background
+        if mime_type == MimeType::TextPlain {
+            self.index.add(hash.clone());
+        }
+        let _ = self.content_bus_tx.send(meta);
+
+        hash
+    }
+
+    /// Adds content from elsewhere, such as a peer, along with its meta, which it must match.
+    pub fn import_content(&mut self, meta: ContentMeta, content: &[u8]) -> Result<(), String> {
+        meta.hash.check(content).map_err(|e| e.to_string())?;
+        if self.content_meta_cache.contains_key(&meta.hash) {
+            return Ok(());
+        }
+        self.blobs.write(content);
+        let bytes = bincode::serialize(&meta.hash).unwrap();
+        self.content_meta.insert(&bytes, &encode(&meta));
+        self.content_meta_cache
+            .insert(meta.hash.clone(), meta.clone());
+
+        if meta.mime_type == MimeType::TextPlain {
+            self.index.add(meta.hash.clone());
+        }
+        let _ = self.content_bus_tx.send(meta);
+        Ok(())
+    }
+
+    pub fn cas_read(&self, hash: &Integrity) -> Option<Vec<u8>> {
+        self.blobs.read(hash)
+    }
+
+    /// Whether the content `hash` is in the CAS. Content meta is only loaded for content which
+    /// exists, and is dropped when it's purged, so this doesn't need to touch the CAS itself.
+    pub fn cas_exists(&self, hash: &Integrity) -> bool {
+        self.content_meta_cache.contains_key(hash)
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub fn purge(&mut self, hash: &Integrity) -> Result<(), Box<dyn std::error::Error>> {
+        // Remove from search index if it's text content
+        if let Some(meta) = self.content_meta_cache.get(hash) {
+            if meta.mime_type == MimeType::TextPlain {
+                self.index.delete(hash);
+            }
+        }
+
+        // Remove from content metadata
+        let hash_bytes = bincode::serialize(hash)?;
+        self.content_meta.remove(&hash_bytes);
+
+        // Remove from in-memory cache
+        let meta = self.content_meta_cache.remove(hash);
+
+        // view snapshots could still have items with the content, which the view expects to
+        // have content meta
+        self.view_snapshots.clear();
+        self.purges += 1;
+
+        // items added with the content can't be published any more
+        for id in self.deliveries_of(hash) {
+            self.delivery_remove(&id);
+        }
+
+        // Remove from CAS storage, along with any alternate representations, unless other
+        // content still refers to them
+        for alternate in meta.iter().flat_map(|meta| &meta.alternates) {
+            if !self.blob_referenced(&alternate.hash) {
+                let _ = self.blobs.remove(&alternate.hash);
+            }
+        }
+        if !self.blob_referenced(hash) {
+            self.blobs.remove(hash)?;
+        }
+
+        Ok(())
+    }
+
+    // whether the blob `hash` is content, or an alternate representation of content
+    fn blob_referenced(&self, hash: &Integrity) -> bool {
+        self.content_meta_cache.contains_key(hash)
+            || self
+                .content_meta_cache
+                .values()
+                .any(|meta| meta.alternates.iter().any(|alt| &alt.hash == hash))
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub fn enumerate_cas(&self) -> Vec<ssri::Integrity> {
+        // Since we use cacache::write_hash_sync (no key), list_sync won't find entries.
+        // Instead, enumerate from our content metadata cache, which tracks all CAS hashes.
+        self.content_meta_cache.keys().cloned().collect()
+    }
+
+    /// Checks the store for damage: every blob in the CAS is verified against its hash, and the
+    /// content meta and packets are cross-checked against the CAS and each other. With `repair`,
+    /// whatever can't be recovered is purged, blobs which lost their content meta are linked up
+    /// again, and the search index is rebuilt. The view needs rebuilding after a repair.
+    pub fn fsck(&mut self, repair: bool) -> FsckReport {
+        let mut report = FsckReport::default();
+
+        // blobs whose content matches their hash
+        let mut blobs = HashSet::new();
+        let mut on_disk = HashSet::new();
+        // the content meta can't be trusted to know about every blob, so the CAS is listed
+        for hash in self.blobs.list() {
+            report.blobs += 1;
+            match self.blobs.read_unchecked(&hash) {
+                Some(content) if hash.check(&content).is_ok() => {
+                    blobs.insert(hash.clone());
+                }
+                _ => report.corrupt_blobs.push(hash.clone()),
+            }
+            on_disk.insert(hash);
+        }
+
+        let mut metas = HashMap::new();
+        let mut unreadable_meta = Vec::new();
+        for (key, value) in self.content_meta.iter() {
+            let hash = bincode::deserialize::<Integrity>(&key);
+            match (hash, deserialize_content_meta(&value)) {
+                (Ok(hash), Ok(meta)) => {
+                    metas.insert(hash, meta);
+                }
+                _ => unreadable_meta.push(key),
+            }
+        }
+        report.unreadable_meta = unreadable_meta.len();
+
+        let mut packets = Vec::new();
+        let mut unreadable_packets = Vec::new();
+        for (key, value) in self.packets.iter() {
+            match deserialize_packet(&value) {
+                Some(packet) => packets.push(packet),
+                None => unreadable_packets.push(key),
+            }
+        }
+        report.packets = packets.len();
+        report.unreadable_packets = unreadable_packets.len();
+
+        // content is referenced by packets, along with the snapshots of file references, and by
+        // the virtual stack of pinned clips for its name; alternate representations, and file
+        // snapshots from before they were kept with packets, are referenced by their content
+        let mut roots: HashSet<Integrity> = packets
+            .iter()
+            .flat_map(|p| [p.hash.clone(), p.snapshot.clone()])
+            .flatten()
+            .collect();
+        roots.insert(Integrity::from(crate::ui::PINNED_NAME));
+        let mut referenced = roots.clone();
+        for meta in roots.iter().filter_map(|hash| metas.get(hash)) {
+            referenced.extend(meta.snapshot.clone());
+            referenced.extend(meta.alternates.iter().map(|alt| alt.hash.clone()));
+        }
+
+        for hash in metas.keys() {
+            if !on_disk.contains(hash) {
+                report.missing_blobs.push(hash.clone());
+            } else if !referenced.contains(hash) {
+                report.orphan_meta.push(hash.clone());
+            }
+        }
+        for hash in &blobs {
+            if metas.contains_key(hash) {
+                continue;
+            }
+            if roots.contains(hash) {
+                report.unlinked_blobs.push(hash.clone());
+            } else if !referenced.contains(hash) {
+                report.orphan_blobs.push(hash.clone());
+            }
+        }
+
+        // packets only refer to items which were added, or forked, before them; packets which
+        // are dropped take the items they would have created with them
+        let mut items = HashSet::from([crate::ui::PINNED_ID]);
+        for packet in &packets {
+            if packet
+                .hash
+                .as_ref()
+                .is_some_and(|hash| !blobs.contains(hash))
+            {
+                report.missing_content.push(packet.id);
+                continue;
+            }
+            let refs = match packet.packet_type {
+                PacketType::Add => [None, packet.stack_id],
+                PacketType::Update if packet.cross_stream => [None, packet.stack_id],
+                PacketType::Update | PacketType::Fork => [packet.source_id, packet.stack_id],
+                PacketType::Delete => [packet.source_id, None],
+            };
+            if refs.iter().flatten().any(|id| !items.contains(id)) {
+                report.missing_items.push(packet.id);
+                continue;
+            }
+            if matches!(packet.packet_type, PacketType::Add | PacketType::Fork) {
+                items.insert(packet.id);
+            }
+        }
+
+        for list in [
+            &mut report.corrupt_blobs,
+            &mut report.orphan_blobs,
+            &mut report.orphan_meta,
+            &mut report.missing_blobs,
+            &mut report.unlinked_blobs,
+        ] {
+            list.sort_by_key(|hash| hash.to_string());
+        }
+
+        if repair && !report.is_clean() {
+            for key in unreadable_meta {
+                self.content_meta.remove(&key);
+            }
+            for key in unreadable_packets {
+                self.packets.remove(&key);
+            }
+            for hash in report.corrupt_blobs.iter().chain(&report.orphan_meta) {
+                let _ = self.blobs.remove(hash);
+                self.forget_content_meta(hash);
+            }
+            for hash in &report.missing_blobs {
+                self.forget_content_meta(hash);
+            }
+            for hash in &report.orphan_blobs {
+                let _ = self.blobs.remove(hash);
+            }
+            for hash in &report.unlinked_blobs {
+                self.relink(hash, &packets);
+            }
+            for id in report.missing_content.iter().chain(&report.missing_items) {
+                self.remove_packet(id);
+            }
+            if let Err(e) = self.rebuild_index() {
+                tracing::error!("Failed to rebuild index after repair: {}", e);
+            }
+            report.repaired = true;
+        }
+
+        report
+    }
+
+    /// Rewrites the packet log as the fewest packets which build the same view, leaving the
+    /// packets from `keep_after` on as they are. Deleted items which haven't been garbage
+    /// collected yet are left as they are too, along with the packets which delete them, for
+    /// garbage collection to drop. Packets which can't be read, or whose content is missing,
+    /// aren't part of the view, so they're left as they are and reported. The log is only
+    /// rewritten once replaying the compacted log is checked to build the view it had before.
+    pub fn compact(
+        &self,
+        keep_after: Option<Scru128Id>,
+        dry_run: bool,
+    ) -> Result<CompactReport, String> {
+        let packets: Vec<Packet> = self.scan().collect();
+        let mut before = crate::view::View::new();
+        packets.iter().for_each(|p| before.merge(p));
+
+        let uncollected: HashSet<Scru128Id> = packets
+            .iter()
+            .filter(|p| p.packet_type == PacketType::Delete)
+            .flat_map(|p| [Some(p.id), p.source_id])
+            .flatten()
+            .collect();
+        let (history, kept): (Vec<Packet>, Vec<Packet>) = packets
+            .into_iter()
+            .partition(|p| keep_after.is_none_or(|after| p.id < after));
+
+        let mut view = crate::view::View::new();
+        history.iter().for_each(|p| view.merge(p));
+        let mut next_id = history.last().map_or(0, |p| p.id.to_u128());
+        let mut compacted = crate::compact::packets(&view, || {
+            next_id += 1;
+            Scru128Id::from_u128(next_id)
+        });
+        if let (Some(last), Some(first)) = (compacted.last(), kept.first()) {
+            if last.id >= first.id {
+                return Err("No room for the compacted packets before the history kept".into());
+            }
+        }
+        // an item last touched by deleting one of its children is touched by the Delete
+        // packet, which is left as it is, rather than a packet which would touch its stack too
+        compacted.retain(|p| !uncollected.contains(&p.id));
+        compacted.extend(
+            history
+                .iter()
+                .filter(|p| uncollected.contains(&p.id))
+                .cloned(),
+        );
+        compacted.sort_by_key(|p| p.id);
+
+        let mut replayed = crate::view::View::new();
+        compacted
+            .iter()
+            .chain(&kept)
+            .for_each(|p| replayed.merge(p));
+        if let Some(id) = before.first_difference(&replayed) {
+            return Err(format!(
+                "Compacting would change item {id}, so the packet log was left as it is"
+            ));
+        }
 
-        let meta = ContentMeta {
-            hash: hash.clone(),
-            mime_type: mime_type.clone(),
-            content_type,
-            terse,
-            tiktokens: 0,
+        let skipped = self.packets.len() - history.len() - kept.len();
+        if skipped > 0 {
+            tracing::warn!(
+                "Leaving {} unreadable or incomplete packets as they are",
+                skipped
+            );
+        }
+        let report = CompactReport {
+            before: self.packets.len(),
+            after: compacted.len() + kept.len() + skipped,
+            kept: kept.len(),
+            skipped,
+            dry_run,
         };
-        let encoded: Vec<u8> = bincode::serialize(&meta).unwrap();
-        let bytes = bincode::serialize(&hash).unwrap();
-        self.content_meta.insert(bytes, encoded).unwrap();
-
-        self.content_meta_cache.insert(hash.clone(), meta.clone());
+        if dry_run {
+            return Ok(report);
+        }
 
-        match mime_type {
-            MimeType::TextPlain => self.index.write(&hash, content),
-            MimeType::ImagePng => (),
+        // only the packets which were read, and compacted, are replaced
+        let compacted_ids: HashSet<Scru128Id> = compacted.iter().map(|p| p.id).collect();
+        let removed: Vec<&Packet> = history
+            .iter()
+            .filter(|p| !compacted_ids.contains(&p.id))
+            .collect();
+        let remove: Vec<Vec<u8>> = removed.iter().map(|p| p.id.to_bytes().to_vec()).collect();
+        let insert = compacted
+            .iter()
+            .map(|packet| (packet.id.to_bytes().to_vec(), encode(packet)))
+            .collect();
+        self.packets.apply(remove.clone(), insert);
+        self.deletes.apply(remove, Vec::new());
+        self.view_snapshots.clear();
+
+        // peers drop the packets which were compacted away, and pick up those which replace them
+        for packet in removed {
+            self.tombstones.insert(&packet.id.to_bytes(), &[]);
+            self.log_change(CHANGE_REMOVED, &packet.id);
+        }
+        for packet in &compacted {
+            self.log_change(CHANGE_PACKET, &packet.id);
         }
 
-        let _ = self.content_bus_tx.send(meta);
+        Ok(report)
+    }
 
-        hash
+    /// Drops the content meta for `hash`, leaving its blob, if there is one, alone.
+    fn forget_content_meta(&mut self, hash: &Integrity) {
+        if self.content_meta_cache.remove(hash).is_some() {
+            self.index.delete(hash);
+        }
+        self.content_meta.remove(&bincode::serialize(hash).unwrap());
     }
 
-    pub fn cas_read(&self, hash: &Integrity) -> Option<Vec<u8>> {
-        cacache::read_hash_sync(&self.cache_path, hash).ok()
+    /// Writes content meta for a blob which lost it, taking its content type from the last
+    /// packet which set one.
+    fn relink(&mut self, hash: &Integrity, packets: &[Packet]) {
+        let Some(content) = self.cas_read(hash) else {
+            return;
+        };
+        let mime_type = if std::str::from_utf8(&content).is_ok() {
+            MimeType::TextPlain
+        } else {
+            MimeType::ImagePng
+        };
+        let (mime_type, default_content_type) = infer_mime_type(&content, mime_type);
+        let content_type = packets
+            .iter()
+            .rev()
+            .filter(|p| p.hash.as_ref() == Some(hash))
+            .find_map(|p| p.content_type.clone())
+            .unwrap_or(default_content_type);
+        self.cas_write(&content, mime_type, content_type);
     }
 
-    #[tracing::instrument(skip_all)]
-    pub fn purge(&mut self, hash: &Integrity) -> Result<(), Box<dyn std::error::Error>> {
-        // Remove from search index if it's text content
-        if let Some(meta) = self.content_meta_cache.get(hash) {
-            if meta.mime_type == MimeType::TextPlain {
-                self.index.delete(hash);
-            }
-        }
+    /// Adds a reference to a file: the clip's content is the file's path, along with a snapshot
+    /// of the file's content when it's small enough and of a type which can be previewed.
+    pub fn add_file_ref(&mut self, path: &std::path::Path, stack_id: Scru128Id) -> Packet {
+        let snapshot = std::fs::metadata(path)
+            .ok()
+            .filter(|metadata| metadata.is_file() && metadata.len() <= MAX_SNAPSHOT_SIZE)
+            .and_then(|_| std::fs::read(path).ok())
+            .and_then(|content| {
+                let (mime_type, content_type) =
+                    crate::content_type::detect_file(Some(path), &content)?;
+                let (mime_type, default_content_type) = infer_mime_type(&content, mime_type);
+                let content_type = content_type
+                    .map(|content_type| content_type.to_string())
+                    .unwrap_or(default_content_type);
+                Some(self.cas_write(&content, mime_type, content_type))
+            });
 
-        // Remove from CAS storage
-        cacache::remove_hash_sync(&self.cache_path, hash)?;
+        let content_type = "File".to_string();
+        let hash = self.cas_write(
+            path.to_string_lossy().as_bytes(),
+            MimeType::TextPlain,
+            content_type.clone(),
+        );
 
-        // Remove from content metadata
-        let hash_bytes = bincode::serialize(hash)?;
-        self.content_meta.remove(hash_bytes)?;
+        let packet = Packet {
+            id: scru128::new(),
+            packet_type: PacketType::Add,
+            source_id: None,
+            hash: Some(hash),
+            stack_id: Some(stack_id),
+            ephemeral: false,
+            content_type: Some(content_type),
+            movement: None,
+            lock_status: None,
+            sort_order: None,
+            cross_stream: false,
+            directory: None,
+            stack_meta: None,
+            tags: None,
+            pin_status: None,
+            snapshot,
+        };
+        self.insert_packet(&packet);
+        packet
+    }
 
-        // Remove from in-memory cache
-        self.content_meta_cache.remove(hash);
+    fn update_content_meta(&mut self, hash: ssri::Integrity, f: impl FnOnce(&mut ContentMeta)) {
+        if let Some(meta) = self.content_meta_cache.get(&hash) {
+            let mut meta = meta.clone();
+            f(&mut meta);
 
-        Ok(())
+            let encoded = encode(&meta);
+            let hash_bytes = bincode::serialize(&hash).unwrap();
+            self.content_meta.insert(&hash_bytes, &encoded);
+            self.content_meta_cache.insert(hash, meta);
+        }
     }
 
-    #[tracing::instrument(skip_all)]
-    pub fn enumerate_cas(&self) -> Vec<ssri::Integrity> {
-        // Since we use cacache::write_hash_sync (no key), list_sync won't find entries.
-        // Instead, enumerate from our content metadata cache, which tracks all CAS hashes.
-        self.content_meta_cache.keys().cloned().collect()
+    /// Adds a clip along with the application it was copied from and any alternate
+    /// representations, as `(pasteboard type, content)`, which were on the pasteboard with it.
+    pub fn add_clip(
+        &mut self,
+        content: &[u8],
+        mime_type: MimeType,
+        stack_id: Scru128Id,
+        source: Option<String>,
+        alternates: &[(String, Vec<u8>)],
+    ) -> Packet {
+        let alternates: Vec<Alternate> = alternates
+            .iter()
+            .map(|(pasteboard_type, content)| Alternate {
+                pasteboard_type: pasteboard_type.clone(),
+                hash: self.blobs.write(content),
+            })
+            .collect();
+
+        let packet = self.add(content, mime_type, stack_id);
+        let hash = packet.hash.clone().unwrap();
+        let mut replaced = Vec::new();
+        // copying the same content again keeps what earlier copies brought along with it: newer
+        // alternates replace those of the same type, and the rest are kept
+        self.update_content_meta(hash, |meta| {
+            if source.is_some() {
+                meta.source = source;
+            }
+            for alternate in alternates {
+                let existing = meta
+                    .alternates
+                    .iter_mut()
+                    .find(|alt| alt.pasteboard_type == alternate.pasteboard_type);
+                match existing {
+                    Some(existing) => {
+                        let old = std::mem::replace(existing, alternate);
+                        replaced.push(old.hash);
+                    }
+                    None => meta.alternates.push(alternate),
+                }
+            }
+        });
+        for hash in replaced {
+            if !self.blob_referenced(&hash) {
+                let _ = self.blobs.remove(&hash);
+            }
+        }
+        packet
     }
 
     pub fn update_tiktokens(&mut self, hash: ssri::Integrity, tiktokens: usize) {
@@ -510,34 +1908,79 @@ impl Store {
             let mut meta = meta.clone();
             meta.tiktokens = tiktokens;
 
-            let encoded: Vec<u8> = bincode::serialize(&meta).unwrap();
+            let encoded = encode(&meta);
             let hash_bytes = bincode::serialize(&hash).unwrap();
-            self.content_meta
-                .insert(hash_bytes, encoded.clone())
-                .unwrap();
+            self.content_meta.insert(&hash_bytes, &encoded);
             self.content_meta_cache.insert(hash, meta.clone());
         }
     }
 
     pub fn insert_packet(&self, packet: &Packet) {
-        let encoded: Vec<u8> = bincode::serialize(&packet).unwrap();
-        self.packets.insert(packet.id.to_bytes(), encoded).unwrap();
+        let encoded = encode(packet);
+        self.packets.insert(&packet.id.to_bytes(), &encoded);
+        self.tombstones.remove(&packet.id.to_bytes());
+        if packet.packet_type == PacketType::Delete {
+            self.deletes.insert(&packet.id.to_bytes(), &[]);
+        }
+        self.log_change(CHANGE_PACKET, &packet.id);
+        self.invalidate_view_snapshots(&packet.id);
     }
 
     pub fn scan(&self) -> impl Iterator<Item = Packet> + use<'_> {
+        self.scan_after(None)
+    }
+
+    /// The packets written after the packet `after`, or all of them. Packets with dangling CAS
+    /// hashes are skipped.
+    pub fn scan_after(&self, after: Option<Scru128Id>) -> impl Iterator<Item = Packet> + use<'_> {
+        scan_after(self.packets.as_ref(), after, |hash| self.cas_exists(hash))
+    }
+
+    /// A handle on the packet log as it is now, for replaying it without holding on to the
+    /// store.
+    pub fn log(&self) -> Log {
+        Log {
+            packets: self.packets.clone(),
+            view_snapshots: self.view_snapshots.clone(),
+            content: self.content_meta_cache.keys().cloned().collect(),
+        }
+    }
+
+    /// How many times content has been purged, for noticing it's been purged since a `log` was
+    /// taken.
+    pub fn purges(&self) -> u64 {
+        self.purges
+    }
+
+    fn scan_packets(&self) -> impl Iterator<Item = Packet> + use<'_> {
         self.packets
             .iter()
-            .filter_map(|item| item.ok().and_then(|(_, value)| deserialize_packet(&value)))
-            .filter(|packet| {
-                // Skip packets with dangling CAS hashes
-                if let Some(hash) = &packet.hash {
-                    if self.cas_read(hash).is_none() {
-                        tracing::warn!("Skipping packet with missing CAS content: {}", hash);
-                        return false;
-                    }
-                }
-                true
-            })
+            .filter_map(|(_, value)| deserialize_packet(&value))
+    }
+
+    /// Saves `view`, as of the packet `last_id`, so the packets up to it don't need replaying.
+    /// Only the latest snapshot is kept.
+    pub fn save_view_snapshot(&self, last_id: &Scru128Id, view: &crate::view::View) {
+        let mut view = view.clone();
+        view.undo = None;
+        let encoded = bincode::serialize(&(VIEW_SNAPSHOT_VERSION, &view)).unwrap();
+        self.view_snapshots.clear();
+        self.view_snapshots.insert(&last_id.to_bytes(), &encoded);
+    }
+
+    /// The latest snapshot of the view, along with the id of the last packet it includes.
+    pub fn load_view_snapshot(&self) -> Option<(Scru128Id, crate::view::View)> {
+        load_view_snapshot(self.view_snapshots.as_ref())
+    }
+
+    /// Drops the view snapshots which include the packet `id`, or come after it: they no longer
+    /// reflect the log once it's removed, once a packet is inserted before them, or once the
+    /// content it was skipped for want of arrives.
+    pub fn invalidate_view_snapshots(&self, id: &Scru128Id) {
+        let from = std::ops::Bound::Included(id.to_bytes().to_vec());
+        for (key, _) in self.view_snapshots.range(from) {
+            self.view_snapshots.remove(&key);
+        }
     }
 
     pub fn add(&mut self, content: &[u8], mime_type: MimeType, stack_id: Scru128Id) -> Packet {
@@ -555,25 +1998,46 @@ impl Store {
             lock_status: None,
             sort_order: None,
             cross_stream: false,
+            directory: None,
+            stack_meta: None,
+            tags: None,
+            pin_status: None,
+            snapshot: None,
         };
         self.insert_packet(&packet);
         packet
     }
 
     pub fn add_stack(&mut self, name: &[u8], lock_status: StackLockStatus) -> Packet {
+        self.add_stack_to(name, lock_status, None)
+    }
+
+    /// Adds a stack inside the stack `parent_id`, or at the top level when it's None. Stacks
+    /// always have a lock status, which is what tells nested stacks apart from clips.
+    pub fn add_stack_to(
+        &mut self,
+        name: &[u8],
+        lock_status: StackLockStatus,
+        parent_id: Option<Scru128Id>,
+    ) -> Packet {
         let hash = self.cas_write(name, MimeType::TextPlain, "Text".to_string());
         let packet = Packet {
             id: scru128::new(),
             packet_type: PacketType::Add,
             source_id: None,
             hash: Some(hash),
-            stack_id: None,
+            stack_id: parent_id,
             ephemeral: false,
             content_type: None,
             movement: None,
             lock_status: Some(lock_status),
             sort_order: None,
             cross_stream: false,
+            directory: None,
+            stack_meta: None,
+            tags: None,
+            pin_status: None,
+            snapshot: None,
         };
         self.insert_packet(&packet);
         packet
@@ -602,6 +2066,11 @@ impl Store {
             lock_status: None,
             sort_order: None,
             cross_stream: false,
+            directory: None,
+            stack_meta: None,
+            tags: None,
+            pin_status: None,
+            snapshot: None,
         };
         self.insert_packet(&packet);
         packet
@@ -620,13 +2089,17 @@ impl Store {
             lock_status: None,
             sort_order: None,
             cross_stream: false,
+            directory: None,
+            stack_meta: None,
+            tags: None,
+            pin_status: None,
+            snapshot: None,
         };
         self.insert_packet(&packet);
         packet
     }
 
     pub fn update_content_type(&mut self, hash: ssri::Integrity, content_type: String) -> Packet {
-        let mut meta = self.content_meta_cache.get(&hash).unwrap().clone();
         let packet = Packet {
             id: scru128::new(),
             packet_type: PacketType::Update,
@@ -639,10 +2112,14 @@ impl Store {
             lock_status: None,
             sort_order: None,
             cross_stream: false,
+            directory: None,
+            stack_meta: None,
+            tags: None,
+            pin_status: None,
+            snapshot: None,
         };
         self.insert_packet(&packet);
-        meta.content_type = content_type;
-        self.content_meta_cache.insert(hash, meta);
+        self.update_content_meta(hash, |meta| meta.content_type = content_type);
         packet
     }
 
@@ -659,6 +2136,11 @@ impl Store {
             lock_status: None,
             sort_order: None,
             cross_stream: false,
+            directory: None,
+            stack_meta: None,
+            tags: None,
+            pin_status: None,
+            snapshot: None,
         };
         self.insert_packet(&packet);
         packet
@@ -677,6 +2159,132 @@ impl Store {
             lock_status: None,
             sort_order: None,
             cross_stream: true,
+            directory: None,
+            stack_meta: None,
+            tags: None,
+            pin_status: None,
+            snapshot: None,
+        };
+        self.insert_packet(&packet);
+        packet
+    }
+
+    pub fn update_stack_directory(
+        &self,
+        source_id: Scru128Id,
+        directory: StackDirectory,
+    ) -> Packet {
+        let packet = Packet {
+            id: scru128::new(),
+            packet_type: PacketType::Update,
+            source_id: Some(source_id),
+            hash: None,
+            stack_id: None,
+            ephemeral: false,
+            content_type: None,
+            movement: None,
+            lock_status: None,
+            sort_order: None,
+            cross_stream: false,
+            directory: Some(directory),
+            stack_meta: None,
+            tags: None,
+            pin_status: None,
+            snapshot: None,
+        };
+        self.insert_packet(&packet);
+        packet
+    }
+
+    /// Renames a stack: the new name is written to the CAS, and the stack's hash updated to it.
+    pub fn rename_stack(&mut self, source_id: Scru128Id, name: &str) -> Packet {
+        let hash = self.cas_write(name.as_bytes(), MimeType::TextPlain, "Text".to_string());
+        let packet = Packet {
+            id: scru128::new(),
+            packet_type: PacketType::Update,
+            source_id: Some(source_id),
+            hash: Some(hash),
+            stack_id: None,
+            ephemeral: false,
+            content_type: None,
+            movement: None,
+            lock_status: None,
+            sort_order: None,
+            cross_stream: false,
+            directory: None,
+            stack_meta: None,
+            tags: None,
+            pin_status: None,
+            snapshot: None,
+        };
+        self.insert_packet(&packet);
+        packet
+    }
+
+    pub fn update_stack_meta(&self, source_id: Scru128Id, stack_meta: StackMeta) -> Packet {
+        let packet = Packet {
+            id: scru128::new(),
+            packet_type: PacketType::Update,
+            source_id: Some(source_id),
+            hash: None,
+            stack_id: None,
+            ephemeral: false,
+            content_type: None,
+            movement: None,
+            lock_status: None,
+            sort_order: None,
+            cross_stream: false,
+            directory: None,
+            stack_meta: Some(stack_meta),
+            tags: None,
+            pin_status: None,
+            snapshot: None,
+        };
+        self.insert_packet(&packet);
+        packet
+    }
+
+    pub fn update_tags(&self, source_id: Scru128Id, tags: Tags) -> Packet {
+        let packet = Packet {
+            id: scru128::new(),
+            packet_type: PacketType::Update,
+            source_id: Some(source_id),
+            hash: None,
+            stack_id: None,
+            ephemeral: false,
+            content_type: None,
+            movement: None,
+            lock_status: None,
+            sort_order: None,
+            cross_stream: false,
+            directory: None,
+            stack_meta: None,
+            tags: Some(tags),
+            pin_status: None,
+            snapshot: None,
+        };
+        self.insert_packet(&packet);
+        packet
+    }
+
+    pub fn update_pin_status(&self, source_id: Scru128Id, pin_status: PinStatus) -> Packet {
+        let packet = Packet {
+            id: scru128::new(),
+            packet_type: PacketType::Update,
+            source_id: Some(source_id),
+            hash: None,
+            stack_id: None,
+            ephemeral: false,
+            content_type: None,
+            movement: None,
+            lock_status: None,
+            sort_order: None,
+            cross_stream: false,
+            directory: None,
+            stack_meta: None,
+            tags: None,
+            pin_status: Some(pin_status),
+            snapshot: None,
         };
         self.insert_packet(&packet);
         packet
@@ -699,6 +2307,11 @@ impl Store {
             lock_status: Some(lock_status),
             sort_order: None,
             cross_stream: false,
+            directory: None,
+            stack_meta: None,
+            tags: None,
+            pin_status: None,
+            snapshot: None,
         };
         self.insert_packet(&packet);
         packet
@@ -721,6 +2334,11 @@ impl Store {
             lock_status: None,
             sort_order: Some(sort_order),
             cross_stream: false,
+            directory: None,
+            stack_meta: None,
+            tags: None,
+            pin_status: None,
+            snapshot: None,
         };
         self.insert_packet(&packet);
         packet
@@ -749,6 +2367,11 @@ impl Store {
             lock_status: None,
             sort_order: None,
             cross_stream: false,
+            directory: None,
+            stack_meta: None,
+            tags: None,
+            pin_status: None,
+            snapshot: None,
         };
         self.insert_packet(&packet);
         packet
@@ -767,63 +2390,158 @@ impl Store {
             lock_status: None,
             sort_order: None,
             cross_stream: false,
+            directory: None,
+            stack_meta: None,
+            tags: None,
+            pin_status: None,
+            snapshot: None,
         };
         self.insert_packet(&packet);
+        self.delivery_remove(&source_id);
         packet
     }
 
     pub fn get_packet(&self, id: &Scru128Id) -> Option<Packet> {
-        let value = self.packets.get(id.to_bytes()).unwrap();
+        let value = self.packets.get(&id.to_bytes());
         value.and_then(|value| deserialize_packet(&value))
     }
 
+    /// Removes the packet `id` from the log, for good: peers which send it back are ignored. A
+    /// packet which hasn't arrived yet is kept out once it does.
     pub fn remove_packet(&self, id: &Scru128Id) -> Option<Packet> {
-        let removed = self.packets.remove(id.to_bytes()).unwrap();
+        self.invalidate_view_snapshots(id);
+        let removed = self.packets.remove(&id.to_bytes());
+        self.deletes.remove(&id.to_bytes());
+        if removed.is_some() || !self.is_removed(id) {
+            self.tombstones.insert(&id.to_bytes(), &[]);
+            self.log_change(CHANGE_REMOVED, id);
+        }
         removed.and_then(|value| deserialize_packet(&value))
     }
 
     pub fn settings_save(&self, settings: Settings) {
         let settings_str = serde_json::to_string(&settings).unwrap();
-        self.meta
-            .insert("settings", settings_str.as_bytes())
-            .unwrap();
+        self.meta.insert(b"settings", settings_str.as_bytes());
     }
 
     pub fn settings_get(&self) -> Option<Settings> {
-        let res = self.meta.get("settings").unwrap();
+        let res = self.meta.get(b"settings");
         res.map(|bytes| {
             let str = std::str::from_utf8(bytes.as_ref()).unwrap();
             serde_json::from_str(str).unwrap()
         })
     }
 
+    pub fn schedule_save(&self, schedule: &Schedule) {
+        let value = serde_json::to_vec(schedule).unwrap();
+        self.schedules.insert(&schedule.id.to_bytes(), &value);
+    }
+
+    pub fn schedule_list(&self) -> Vec<Schedule> {
+        self.schedules
+            .iter()
+            .filter_map(|(_, value)| serde_json::from_slice(&value).ok())
+            .collect()
+    }
+
+    pub fn schedule_remove(&self, id: &Scru128Id) -> Option<Schedule> {
+        let removed = self.schedules.remove(&id.to_bytes());
+        removed.and_then(|value| serde_json::from_slice(&value).ok())
+    }
+
+    /// Queues the item `id` to be published to the cross-stream endpoint, unless it already
+    /// has been.
+    pub fn delivery_queue(&self, id: &Scru128Id) {
+        if self.delivery_get(id).is_none() {
+            self.delivery_save(id, &Delivery::new(crate::cross_stream::now()));
+        }
+    }
+
+    pub fn delivery_get(&self, id: &Scru128Id) -> Option<Delivery> {
+        let value = self.deliveries.get(&id.to_bytes())?;
+        serde_json::from_slice(&value).ok()
+    }
+
+    pub fn delivery_save(&self, id: &Scru128Id, delivery: &Delivery) {
+        let value = serde_json::to_vec(delivery).unwrap();
+        self.deliveries.insert(&id.to_bytes(), &value);
+    }
+
+    pub fn delivery_remove(&self, id: &Scru128Id) {
+        self.deliveries.remove(&id.to_bytes());
+    }
+
+    // the items with deliveries which were added with the content `hash`
+    fn deliveries_of(&self, hash: &Integrity) -> Vec<Scru128Id> {
+        self.deliveries
+            .iter()
+            .filter_map(|(key, _)| Some(Scru128Id::from_bytes(key.try_into().ok()?)))
+            .filter(|id| {
+                self.get_packet(id)
+                    .is_some_and(|packet| packet.hash.as_ref() == Some(hash))
+            })
+            .collect()
+    }
+
+    /// The deliveries which are due to be attempted at `now`, oldest item first.
+    pub fn deliveries_due(&self, now: u64) -> Vec<(Scru128Id, Delivery)> {
+        self.deliveries
+            .iter()
+            .filter_map(|(key, value)| {
+                let id = Scru128Id::from_bytes(key.try_into().ok()?);
+                let delivery: Delivery = serde_json::from_slice(&value).ok()?;
+                delivery.is_due(now).then_some((id, delivery))
+            })
+            .collect()
+    }
+
     #[tracing::instrument(skip_all)]
     pub fn rebuild_index(&mut self) -> Result<(usize, usize), Box<dyn std::error::Error>> {
-        // Clear existing index
+        // tags live on items, rather than content, so replay the packets to find them
+        let mut view = crate::view::View::new();
+        self.scan().for_each(|packet| view.merge(&packet));
+        let sources = self.clear_index(&view);
+        Ok(self.index.rebuild(&sources)?)
+    }
+
+    /// Clears the search index, and returns what's needed to build it again with
+    /// [`Index::rebuild`]. Content written after this is indexed as usual.
+    pub fn clear_index(&self, view: &crate::view::View) -> IndexSources {
         self.index.clear();
 
-        // Collect all text content for batch indexing
         let all_hashes = self.enumerate_cas();
-        let mut items_to_index = Vec::new();
-
-        for hash in &all_hashes {
-            if let Some(meta) = self.content_meta_cache.get(hash) {
-                if meta.mime_type == MimeType::TextPlain {
-                    if let Some(content) = self.cas_read(hash) {
-                        items_to_index.push((hash.clone(), content));
-                    }
-                }
-            }
-        }
-
-        let indexed_count = items_to_index.len();
+        let content = all_hashes
+            .iter()
+            .filter(|hash| {
+                self.content_meta_cache
+                    .get(hash)
+                    .is_some_and(|meta| meta.mime_type == MimeType::TextPlain)
+            })
+            .cloned()
+            .collect();
+        let tags = view
+            .items
+            .values()
+            .filter(|item| !item.tags.is_empty())
+            .map(|item| {
+                let tags: Vec<String> = item.tags.iter().cloned().collect();
+                (item.id, item.hash.clone(), tags)
+            })
+            .collect();
 
-        // Batch write all items (single commit)
-        if !items_to_index.is_empty() {
-            self.index.write_batch(&items_to_index);
+        IndexSources {
+            blobs: self.blobs.clone(),
+            total: all_hashes.len(),
+            content,
+            tags,
         }
+    }
+}
 
-        Ok((all_hashes.len(), indexed_count))
+// content on its way to the index is committed before the index can be opened again
+impl Drop for Store {
+    fn drop(&mut self) {
+        self.index.flush();
     }
 }
 
@@ -4,10 +4,7 @@ use tempfile::tempdir;
 
 #[test]
 fn test_add() {
-    let dir = tempdir().unwrap();
-    let path = dir.path().to_str().unwrap();
-
-    let mut store = Store::new(path);
+    let mut store = Store::in_memory();
 
     let content = b"Hello, world!";
     let packet = store.add_stack(content, StackLockStatus::Unlocked);
@@ -26,10 +23,7 @@ fn test_add() {
 
 #[test]
 fn test_update() {
-    let dir = tempdir().unwrap();
-    let path = dir.path().to_str().unwrap();
-
-    let mut store = Store::new(path);
+    let mut store = Store::in_memory();
 
     let content = b"Hello, world!";
     let packet = store.add_stack(content, StackLockStatus::Unlocked);
@@ -55,10 +49,7 @@ fn test_update() {
 
 #[test]
 fn test_fork() {
-    let dir = tempdir().unwrap();
-    let path = dir.path().to_str().unwrap();
-
-    let mut store = Store::new(path);
+    let mut store = Store::in_memory();
 
     let content = b"Hello, world!";
     let packet = store.add_stack(content, StackLockStatus::Unlocked);
@@ -84,9 +75,7 @@ fn test_fork() {
 
 #[test]
 fn test_delete() {
-    let dir = tempdir().unwrap();
-    let path = dir.path().to_str().unwrap();
-    let mut store = Store::new(path);
+    let mut store = Store::in_memory();
     let content = b"Hello, world!";
     let packet = store.add_stack(content, StackLockStatus::Unlocked);
     let delete_packet = store.delete(packet.id);
@@ -96,10 +85,7 @@ fn test_delete() {
 
 #[test]
 fn test_query() {
-    let dir = tempdir().unwrap();
-    let path = dir.path().to_str().unwrap();
-
-    let mut store = Store::new(path);
+    let mut store = Store::in_memory();
 
     let content1 = b"Hello, world!";
     let content2 = b"Hello, fuzzy world!";
@@ -109,6 +95,8 @@ fn test_query() {
     store.add_stack(content2, StackLockStatus::Unlocked);
     store.add_stack(content3, StackLockStatus::Unlocked);
 
+    // text content is indexed in the background
+    store.index.flush();
     let results = store.index.query("fuzzy", None).unwrap();
     let results: Vec<_> = results
         .into_iter()
@@ -125,8 +113,7 @@ fn test_is_valid_https_url() {
 
 #[test]
 fn test_purge() {
-    let temp_dir = tempfile::tempdir().unwrap();
-    let mut store = Store::new(temp_dir.path().to_str().unwrap());
+    let mut store = Store::in_memory();
 
     let content = b"SECRET_KEY=super_secret_value";
     let stack_id = scru128::new();
@@ -159,8 +146,7 @@ fn test_purge() {
 
 #[test]
 fn test_enumerate_cas() {
-    let temp_dir = tempfile::tempdir().unwrap();
-    let mut store = Store::new(temp_dir.path().to_str().unwrap());
+    let mut store = Store::in_memory();
 
     let stack_id = scru128::new();
 
@@ -193,9 +179,7 @@ fn test_enumerate_cas() {
 
 #[test]
 fn test_index_deletion() {
-    let dir = tempdir().unwrap();
-    let path = dir.path().to_str().unwrap();
-    let mut store = Store::new(path);
+    let mut store = Store::in_memory();
 
     // Add test content that will be indexed
     let test_content = b"unique_test_content_for_deletion_123";
@@ -203,6 +187,9 @@ fn test_index_deletion() {
 
     println!("Added content with hash: {hash}");
 
+    // text content is indexed in the background
+    store.index.flush();
+
     // Verify content is searchable before deletion
     let search_results_before = store.index.query("unique_test_content", Some(10)).unwrap();
     println!(
@@ -220,6 +207,7 @@ fn test_index_deletion() {
     // Now purge the content (this should remove it from the index)
     println!("Purging content with hash: {hash}");
     store.purge(&hash).expect("Purge should succeed");
+    store.index.flush();
 
     // Verify content is no longer searchable after deletion
     let search_results_after = store.index.query("unique_test_content", Some(10)).unwrap();
@@ -245,9 +233,7 @@ fn test_index_deletion() {
 
 #[test]
 fn test_rebuild_index() {
-    let dir = tempdir().unwrap();
-    let path = dir.path().to_str().unwrap();
-    let mut store = Store::new(path);
+    let mut store = Store::in_memory();
 
     // Add multiple test items with different content types
     let text_content1 = b"first searchable content item";
@@ -260,6 +246,9 @@ fn test_rebuild_index() {
 
     println!("Added 3 text items to store and index");
 
+    // text content is indexed in the background
+    store.index.flush();
+
     // Verify all content is searchable before rebuild
     let search_results_before = store.index.query("searchable", Some(10)).unwrap();
     assert_eq!(
@@ -316,9 +305,7 @@ fn test_rebuild_index() {
 
 #[test]
 fn test_rebuild_index_with_mixed_content() {
-    let dir = tempdir().unwrap();
-    let path = dir.path().to_str().unwrap();
-    let mut store = Store::new(path);
+    let mut store = Store::in_memory();
 
     // Add text content that gets indexed
     let text_content = b"searchable text content";
@@ -328,6 +315,9 @@ fn test_rebuild_index_with_mixed_content() {
     let image_content = b"fake image bytes";
     let image_hash = store.cas_write(image_content, MimeType::ImagePng, "Image".to_string());
 
+    // text content is indexed in the background
+    store.index.flush();
+
     // Verify only text content is searchable
     let search_before = store.index.query("searchable", Some(10)).unwrap();
     assert_eq!(search_before.len(), 1, "Should find 1 text item");
@@ -359,3 +349,1016 @@ fn test_rebuild_index_with_mixed_content() {
         "Image content should still be readable"
     );
 }
+
+#[test]
+fn test_schedules() {
+    use crate::schedule::{Schedule, Trigger};
+
+    let dir = tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    let schedule = Schedule {
+        id: scru128::new(),
+        stack_id: scru128::new(),
+        command: "git log -5".to_string(),
+        trigger: Trigger::every("1h").unwrap(),
+        last_output: None,
+    };
+
+    {
+        let store = Store::new(path);
+        assert!(store.schedule_list().is_empty());
+        store.schedule_save(&schedule);
+    }
+
+    // schedules persist across restarts
+    let store = Store::new(path);
+    assert_eq!(store.schedule_list(), vec![schedule.clone()]);
+    assert_eq!(store.schedule_remove(&schedule.id), Some(schedule));
+    assert!(store.schedule_list().is_empty());
+}
+
+#[test]
+fn test_legacy_packet_v4() {
+    use crate::store::PacketV4;
+
+    let dir = tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    // a packet written before stacks could be bound to a directory
+    let legacy = PacketV4 {
+        id: scru128::new(),
+        packet_type: PacketType::Update,
+        source_id: Some(scru128::new()),
+        hash: None,
+        stack_id: None,
+        ephemeral: false,
+        content_type: None,
+        movement: None,
+        lock_status: Some(StackLockStatus::Locked),
+        sort_order: None,
+        cross_stream: false,
+    };
+    {
+        let db = sled::open(dir.path().join("sled")).unwrap();
+        let packets = db.open_tree("packets").unwrap();
+        packets
+            .insert(legacy.id.to_bytes(), bincode::serialize(&legacy).unwrap())
+            .unwrap();
+    }
+
+    let store = Store::new(path);
+    let packet = store.scan().next().unwrap();
+    assert_eq!(packet.id, legacy.id);
+    assert_eq!(packet.lock_status, Some(StackLockStatus::Locked));
+    assert_eq!(packet.directory, None);
+    assert_eq!(packet.stack_meta, None);
+}
+
+#[test]
+fn test_legacy_packet_v5() {
+    use crate::store::{PacketV5, StackDirectory};
+
+    let dir = tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    // a packet written before stacks had a description, color or icon
+    let legacy = PacketV5 {
+        id: scru128::new(),
+        packet_type: PacketType::Update,
+        source_id: Some(scru128::new()),
+        hash: None,
+        stack_id: None,
+        ephemeral: false,
+        content_type: None,
+        movement: None,
+        lock_status: None,
+        sort_order: None,
+        cross_stream: false,
+        directory: Some(StackDirectory {
+            path: "/src/project".to_string(),
+            watch: false,
+        }),
+    };
+    {
+        let db = sled::open(dir.path().join("sled")).unwrap();
+        let packets = db.open_tree("packets").unwrap();
+        packets
+            .insert(legacy.id.to_bytes(), bincode::serialize(&legacy).unwrap())
+            .unwrap();
+    }
+
+    let store = Store::new(path);
+    let packet = store.scan().next().unwrap();
+    assert_eq!(packet.id, legacy.id);
+    assert_eq!(packet.directory, legacy.directory);
+    assert_eq!(packet.stack_meta, None);
+}
+
+#[test]
+fn test_legacy_packet_v6() {
+    use crate::store::{PacketV6, StackMeta};
+
+    let dir = tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    // a packet written before items had tags
+    let legacy = PacketV6 {
+        id: scru128::new(),
+        packet_type: PacketType::Update,
+        source_id: Some(scru128::new()),
+        hash: None,
+        stack_id: None,
+        ephemeral: false,
+        content_type: None,
+        movement: None,
+        lock_status: None,
+        sort_order: None,
+        cross_stream: false,
+        directory: None,
+        stack_meta: Some(StackMeta {
+            description: Some("Notes".to_string()),
+            ..Default::default()
+        }),
+    };
+    {
+        let db = sled::open(dir.path().join("sled")).unwrap();
+        let packets = db.open_tree("packets").unwrap();
+        packets
+            .insert(legacy.id.to_bytes(), bincode::serialize(&legacy).unwrap())
+            .unwrap();
+    }
+
+    let store = Store::new(path);
+    let packet = store.scan().next().unwrap();
+    assert_eq!(packet.id, legacy.id);
+    assert_eq!(packet.stack_meta, legacy.stack_meta);
+    assert_eq!(packet.tags, None);
+}
+
+#[test]
+fn test_legacy_packet_v7() {
+    use crate::store::{PacketV7, Tags};
+
+    let dir = tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    // a packet written before items could be pinned
+    let legacy = PacketV7 {
+        id: scru128::new(),
+        packet_type: PacketType::Update,
+        source_id: Some(scru128::new()),
+        hash: None,
+        stack_id: None,
+        ephemeral: false,
+        content_type: None,
+        movement: None,
+        lock_status: None,
+        sort_order: None,
+        cross_stream: false,
+        directory: None,
+        stack_meta: None,
+        tags: Some(Tags {
+            add: vec!["todo".to_string()],
+            remove: Vec::new(),
+        }),
+    };
+    {
+        let db = sled::open(dir.path().join("sled")).unwrap();
+        let packets = db.open_tree("packets").unwrap();
+        packets
+            .insert(legacy.id.to_bytes(), bincode::serialize(&legacy).unwrap())
+            .unwrap();
+    }
+
+    let store = Store::new(path);
+    let packet = store.scan().next().unwrap();
+    assert_eq!(packet.id, legacy.id);
+    assert_eq!(packet.tags, legacy.tags);
+    assert_eq!(packet.pin_status, None);
+}
+
+#[test]
+fn test_add_file_ref() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    let mut store = Store::new(path);
+    let stack = store.add_stack(b"Files", StackLockStatus::Unlocked);
+
+    let file = dir.path().join("notes.md");
+    std::fs::write(&file, b"# Notes").unwrap();
+
+    let packet = store.add_file_ref(&file, stack.id);
+    let hash = packet.hash.unwrap();
+    assert_eq!(
+        store.cas_read(&hash).unwrap(),
+        file.to_string_lossy().as_bytes()
+    );
+
+    let meta = store.get_content_meta(&hash).unwrap();
+    assert_eq!(meta.content_type, "File");
+
+    // the snapshot keeps the file's content, typed by its extension
+    let snapshot = packet.snapshot.unwrap();
+    assert_eq!(store.cas_read(&snapshot).unwrap(), b"# Notes");
+    let snapshot_meta = store.get_content_meta(&snapshot).unwrap();
+    assert_eq!(snapshot_meta.content_type, "Markdown");
+
+    // the snapshot goes with the clip, not the path: adding the file again once it's changed
+    // leaves the earlier clip's snapshot be
+    std::fs::write(&file, b"# Notes, revised").unwrap();
+    let other = store.add_stack(b"Other", StackLockStatus::Unlocked);
+    let revised = store.add_file_ref(&file, other.id);
+    assert_eq!(revised.hash, Some(hash.clone()));
+    let revised_snapshot = revised.snapshot.clone().unwrap();
+    assert_ne!(revised_snapshot, snapshot);
+    assert_eq!(store.get_content_meta(&hash).unwrap().snapshot, None);
+
+    let mut view = crate::view::View::new();
+    store.scan().for_each(|p| view.merge(&p));
+    assert_eq!(view.items[&packet.id].snapshot, Some(snapshot.clone()));
+    assert_eq!(view.items[&revised.id].snapshot, Some(revised_snapshot));
+
+    // references to missing files are still added, without a snapshot
+    let packet = store.add_file_ref(&dir.path().join("missing.txt"), stack.id);
+    assert_eq!(packet.snapshot, None);
+
+    // snapshots are persisted
+    let id = revised.id;
+    drop(store);
+    let store = Store::new(path);
+    let stored = store.get_packet(&id).unwrap();
+    assert_eq!(stored.snapshot, revised.snapshot);
+}
+
+#[test]
+fn test_legacy_content_meta_v1() {
+    use crate::store::ContentMetaV1;
+
+    let dir = tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    let hash = {
+        let mut store = Store::new(path);
+        let stack = store.add_stack(b"Stack", StackLockStatus::Unlocked);
+        store
+            .add(b"legacy", MimeType::TextPlain, stack.id)
+            .hash
+            .unwrap()
+    };
+
+    // rewrite the metadata as it was stored before snapshots were tracked
+    {
+        let db = sled::open(dir.path().join("sled")).unwrap();
+        let content_meta = db.open_tree("content_meta").unwrap();
+        let legacy = ContentMetaV1 {
+            hash: hash.clone(),
+            mime_type: MimeType::TextPlain,
+            content_type: "Text".to_string(),
+            terse: "legacy".to_string(),
+            tiktokens: 1,
+        };
+        content_meta
+            .insert(
+                bincode::serialize(&hash).unwrap(),
+                bincode::serialize(&legacy).unwrap(),
+            )
+            .unwrap();
+    }
+
+    let store = Store::new(path);
+    let meta = store.get_content_meta(&hash).unwrap();
+    assert_eq!(meta.terse, "legacy");
+    assert_eq!(meta.tiktokens, 1);
+    assert_eq!(meta.snapshot, None);
+}
+
+#[test]
+fn test_add_clip() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    let mut store = Store::new(path);
+    let stack = store.add_stack(b"Stack", StackLockStatus::Unlocked);
+
+    let html = b"<b>bold</b>".to_vec();
+    let packet = store.add_clip(
+        b"bold",
+        MimeType::TextPlain,
+        stack.id,
+        Some("Safari".to_string()),
+        &[("public.html".to_string(), html.clone())],
+    );
+    store.add_clip(
+        b"ls -la",
+        MimeType::TextPlain,
+        stack.id,
+        Some("Terminal".to_string()),
+        &[],
+    );
+
+    let hash = packet.hash.unwrap();
+    let meta = store.get_content_meta(&hash).unwrap();
+    assert_eq!(meta.source, Some("Safari".to_string()));
+    assert_eq!(meta.alternates.len(), 1);
+    assert_eq!(meta.alternates[0].pasteboard_type, "public.html");
+    assert_eq!(store.cas_read(&meta.alternates[0].hash), Some(html.clone()));
+
+    // metadata survives a restart
+    drop(store);
+    let mut store = Store::new(path);
+    assert_eq!(store.get_content_meta(&hash).unwrap(), meta);
+
+    let terse = |store: &Store, filter: &str| {
+        let mut terse: Vec<String> = store
+            .query(filter, "")
+            .iter()
+            .map(|hash| store.get_content_meta(hash).unwrap().terse)
+            .collect();
+        terse.sort();
+        terse
+    };
+    assert_eq!(terse(&store, "from:terminal"), vec!["ls -la"]);
+    assert_eq!(terse(&store, "from:safari bold"), vec!["bold"]);
+    assert!(terse(&store, "from:safari ls").is_empty());
+
+    // copying the same content again adds to what earlier copies brought along with it
+    let rtf = b"{\\rtf1 \\b bold}".to_vec();
+    store.add_clip(b"bold", MimeType::TextPlain, stack.id, None, &[]);
+    store.add_clip(
+        b"bold",
+        MimeType::TextPlain,
+        stack.id,
+        None,
+        &[("public.rtf".to_string(), rtf.clone())],
+    );
+    let merged = store.get_content_meta(&hash).unwrap();
+    assert_eq!(merged.source, Some("Safari".to_string()));
+    let types: Vec<&str> = merged
+        .alternates
+        .iter()
+        .map(|alt| alt.pasteboard_type.as_str())
+        .collect();
+    assert_eq!(types, vec!["public.html", "public.rtf"]);
+    assert_eq!(store.cas_read(&merged.alternates[1].hash), Some(rtf));
+
+    // alternates are removed along with the clip's content, unless other content shares them
+    let other = store.add_clip(
+        b"BOLD",
+        MimeType::TextPlain,
+        stack.id,
+        None,
+        &[("public.html".to_string(), html.clone())],
+    );
+    store.purge(&hash).unwrap();
+    assert_eq!(store.cas_read(&merged.alternates[0].hash), Some(html));
+    assert_eq!(store.cas_read(&merged.alternates[1].hash), None);
+    store.purge(&other.hash.unwrap()).unwrap();
+    assert_eq!(store.cas_read(&merged.alternates[0].hash), None);
+}
+
+#[test]
+fn test_view_snapshot() {
+    use crate::view::View;
+
+    let dir = tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    let mut store = Store::new(path);
+    let stack = store.add_stack(b"Stack", StackLockStatus::Unlocked);
+    let item_1 = store.add(b"Item 1", MimeType::TextPlain, stack.id);
+    let item_2 = store.add(b"Item 2", MimeType::TextPlain, stack.id);
+
+    let mut view = View::new();
+    store.scan().for_each(|p| view.merge(&p));
+    store.save_view_snapshot(&item_2.id, &view);
+
+    // only the packets after the snapshot need replaying
+    let item_3 = store.add(b"Item 3", MimeType::TextPlain, stack.id);
+    store.update_content_type(item_3.hash.clone().unwrap(), "Markdown".to_string());
+    drop(store);
+    let mut store = Store::new(path);
+
+    let (id, mut snapshot) = store.load_view_snapshot().unwrap();
+    assert_eq!(id, item_2.id);
+    assert_eq!(snapshot.items, view.items);
+    let tail: Vec<Packet> = store.scan_after(Some(id)).collect();
+    assert_eq!(tail.len(), 2);
+    tail.iter().for_each(|p| snapshot.merge(p));
+
+    let mut view = View::new();
+    store.scan().for_each(|p| view.merge(&p));
+    assert_eq!(snapshot.items, view.items);
+
+    // content types are kept in the content meta, rather than replayed from packets
+    let meta = store.get_content_meta(&item_3.hash.unwrap()).unwrap();
+    assert_eq!(meta.content_type, "Markdown");
+
+    // removing packets after the snapshot leaves it be, removing those in it drops it
+    store.remove_packet(&item_3.id);
+    assert!(store.load_view_snapshot().is_some());
+    store.remove_packet(&item_1.id);
+    assert!(store.load_view_snapshot().is_none());
+
+    // as does purging content, which items in the snapshot could have
+    store.scan().for_each(|p| view.merge(&p));
+    store.save_view_snapshot(&item_3.id, &view);
+    store.purge(&item_2.hash.unwrap()).unwrap();
+    assert!(store.load_view_snapshot().is_none());
+}
+
+#[test]
+fn test_index_status() {
+    let mut store = Store::in_memory();
+
+    let status = store.index.status();
+    assert_eq!((status.queued, status.lag), (0, 0));
+    assert_eq!(status.last_commit, None);
+
+    // images aren't indexed, and text content is committed in a batch
+    let first = store.cas_write(
+        b"first batched item",
+        MimeType::TextPlain,
+        "Text".to_string(),
+    );
+    store.cas_write(
+        b"second batched item",
+        MimeType::TextPlain,
+        "Text".to_string(),
+    );
+    store.cas_write(b"\x89PNG", MimeType::ImagePng, "Image".to_string());
+    assert_eq!(store.index.status().queued, 2);
+
+    store.index.flush();
+    let status = store.index.status();
+    assert_eq!((status.queued, status.indexed, status.dropped), (2, 2, 0));
+    assert_eq!((status.lag, status.docs), (0, 2));
+    assert!(status.last_commit.is_some());
+    assert_eq!(store.index.query("batched", None).unwrap().len(), 2);
+
+    // purges and tags go through the same queue, so a purge can't be overtaken by the content
+    // it purges
+    let hash = store.cas_write(
+        b"purged right away",
+        MimeType::TextPlain,
+        "Text".to_string(),
+    );
+    store.purge(&hash).unwrap();
+    let id = scru128::new();
+    store.index.write_tags(&id, &first, &["queued".to_string()]);
+    assert_eq!(store.index.status().queued, 5);
+    store.index.flush();
+    let status = store.index.status();
+    // the purged content is dropped, unless the indexer got to it first
+    assert_eq!((status.queued, status.indexed + status.dropped), (5, 5));
+    assert_eq!(status.lag, 0);
+    assert!(store.index.query("purged", None).unwrap().is_empty());
+    assert_eq!(store.index.query("tags:queued", None).unwrap().len(), 1);
+}
+
+#[test]
+fn test_fsck() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+    let mut store = Store::new(path);
+
+    let stack = store.add_stack(b"Stack", StackLockStatus::Unlocked);
+    let item_1 = store.add(b"Item 1", MimeType::TextPlain, stack.id);
+    let item_2 = store.add(b"Item 2", MimeType::TextPlain, stack.id);
+    let item_3 = store.add(b"Item 3", MimeType::TextPlain, stack.id);
+    store.cas_write(
+        crate::ui::PINNED_NAME.as_bytes(),
+        MimeType::TextPlain,
+        "Text".to_string(),
+    );
+    assert!(store.fsck(false).is_clean());
+
+    // item 2's content is corrupted, and item 3's lost
+    let corrupt = item_2.hash.clone().unwrap();
+    let hex = corrupt.to_hex().1;
+    let blob = dir
+        .path()
+        .join("cas/content-v2/sha256")
+        .join(&hex[0..2])
+        .join(&hex[2..4])
+        .join(&hex[4..]);
+    std::fs::write(&blob, b"Item 2, corrupted").unwrap();
+    let missing = item_3.hash.clone().unwrap();
+    store.blobs.remove(&missing).unwrap();
+
+    // content nothing refers to
+    let orphan_meta = store.cas_write(b"orphan meta", MimeType::TextPlain, "Text".to_string());
+    let orphan_blob = store.blobs.write(b"orphan blob");
+
+    // a packet referring to content with no meta, which can be linked up again
+    let unlinked = store.blobs.write(b"unlinked");
+    let mut relinked = item_1.clone();
+    relinked.id = scru128::new();
+    relinked.hash = Some(unlinked.clone());
+    store.insert_packet(&relinked);
+
+    // a clip in a stack which doesn't exist, and a packet which refers to it in turn
+    let mut dangling = item_1.clone();
+    dangling.id = scru128::new();
+    dangling.stack_id = Some(scru128::new());
+    store.insert_packet(&dangling);
+    let delete = store.delete(dangling.id);
+
+    let report = store.fsck(false);
+    assert_eq!(report.blobs, 7);
+    assert_eq!(report.packets, 7);
+    assert_eq!(report.corrupt_blobs, vec![corrupt]);
+    assert_eq!(report.orphan_blobs, vec![orphan_blob]);
+    assert_eq!(report.orphan_meta, vec![orphan_meta.clone()]);
+    assert_eq!(report.missing_blobs, vec![missing]);
+    assert_eq!(report.unlinked_blobs, vec![unlinked.clone()]);
+    assert_eq!(report.missing_content, vec![item_2.id, item_3.id]);
+    assert_eq!(report.missing_items, vec![dangling.id, delete.id]);
+    assert!(!report.repaired);
+
+    let report = store.fsck(true);
+    assert!(report.repaired);
+    assert!(store.fsck(false).is_clean());
+    assert!(store.get_content_meta(&orphan_meta).is_none());
+    assert_eq!(
+        store.get_content_meta(&unlinked).unwrap().content_type,
+        "Text"
+    );
+
+    let ids: Vec<_> = store.scan().map(|p| p.id).collect();
+    assert_eq!(ids, vec![stack.id, item_1.id, relinked.id]);
+}
+
+#[test]
+fn test_compact() {
+    use crate::store::{Movement, PinStatus, StackDirectory, StackMeta, Tags};
+    use crate::view::View;
+
+    let mut store = Store::in_memory();
+    let replay = |store: &Store| {
+        let mut view = View::new();
+        store.scan().for_each(|p| view.merge(&p));
+        view
+    };
+
+    let stack = store.add_stack(b"Stack", StackLockStatus::Unlocked);
+    let nested = store.add_stack_to(b"Nested", StackLockStatus::Unlocked, Some(stack.id));
+    let item_1 = store.add(b"Item 1", MimeType::TextPlain, stack.id);
+    let item_2 = store.add(b"Item 2", MimeType::TextPlain, nested.id);
+    let item_3 = store.add(b"Item 3", MimeType::TextPlain, stack.id);
+
+    // re-copying an item touches it
+    for _ in 0..5 {
+        store.update_touch(item_1.id);
+    }
+    store.update_content_type(item_1.hash.clone().unwrap(), "Markdown".to_string());
+    store.update(
+        item_2.id,
+        Some(b"Item 2, edited"),
+        MimeType::TextPlain,
+        None,
+    );
+
+    // an item moved into a stack created after it, and a duplicate in the same stack
+    let later = store.add_stack(b"Later", StackLockStatus::Unlocked);
+    store.update(item_3.id, None, MimeType::TextPlain, Some(later.id));
+    store.fork(item_1.id, None, MimeType::TextPlain, Some(stack.id));
+
+    store.update_tags(
+        item_1.id,
+        Tags {
+            add: vec!["todo".to_string()],
+            remove: Vec::new(),
+        },
+    );
+    store.update_pin_status(item_2.id, PinStatus::Pinned);
+    store.update_stack_meta(
+        nested.id,
+        StackMeta {
+            description: Some("Notes".to_string()),
+            color: None,
+            icon: None,
+        },
+    );
+    store.update_stack_directory(
+        later.id,
+        StackDirectory {
+            path: "/tmp".to_string(),
+            watch: false,
+        },
+    );
+    store.update_stack_lock_status(later.id, StackLockStatus::Locked);
+    store.mark_as_cross_stream(stack.id);
+    store.update_move(item_1.id, Movement::Bottom);
+
+    // deleted items are left for garbage collection, and copies forked from them are kept
+    let deleted = store.add(b"Deleted", MimeType::TextPlain, nested.id);
+    let copy = store.fork(
+        deleted.id,
+        Some(b"Copy"),
+        MimeType::TextPlain,
+        Some(stack.id),
+    );
+    store.update_touch(deleted.id);
+    let delete = store.delete(deleted.id);
+    let before = replay(&store);
+    let packets = store.scan().count();
+
+    let report = store.compact(None, true).unwrap();
+    assert!(report.dry_run);
+    assert_eq!(report.before, packets);
+    assert_eq!(store.scan().count(), packets);
+
+    let report = store.compact(None, false).unwrap();
+    assert!(report.after < report.before);
+    assert_eq!(store.scan().count(), report.after);
+    assert!(store.get_packet(&deleted.id).is_some());
+    assert!(store.get_packet(&delete.id).is_some());
+    assert!(before.items.contains_key(&copy.id));
+
+    let after = replay(&store);
+    assert_eq!(before.first_difference(&after), None);
+    assert_eq!(after.items[&item_1.id].touched.len(), 2);
+    assert_eq!(
+        store
+            .get_content_meta(&item_1.hash.unwrap())
+            .unwrap()
+            .content_type,
+        "Markdown"
+    );
+
+    // there's nothing left to collapse
+    let report = store.compact(None, false).unwrap();
+    assert_eq!(report.before, report.after);
+    assert_eq!(before.first_difference(&replay(&store)), None);
+}
+
+#[test]
+fn test_compact_keep_history() {
+    let mut store = Store::in_memory();
+
+    let stack = store.add_stack(b"Stack", StackLockStatus::Unlocked);
+    let item = store.add(b"Item", MimeType::TextPlain, stack.id);
+    for _ in 0..3 {
+        store.update_touch(item.id);
+    }
+    let recent = store.update_touch(item.id);
+    store.update_touch(item.id);
+
+    // the history from `recent` on is kept as it is
+    let report = store.compact(Some(recent.id), false).unwrap();
+    assert_eq!((report.before, report.after, report.kept), (7, 5, 2));
+    let ids: Vec<_> = store.scan().map(|p| p.id).collect();
+    assert_eq!(ids[..2], [stack.id, item.id]);
+    assert_eq!(ids[3..], [recent.id, *ids.last().unwrap()]);
+}
+
+#[test]
+fn test_compact_incomplete() {
+    let mut store = Store::in_memory();
+
+    let stack = store.add_stack(b"Stack", StackLockStatus::Unlocked);
+    let item = store.add(b"Item", MimeType::TextPlain, stack.id);
+    store.update_touch(item.id);
+
+    // a packet synced before its content arrived isn't part of the view yet, so it's left
+    let incomplete = Packet {
+        id: scru128::new(),
+        hash: Some(ssri::Integrity::from(b"Not here yet")),
+        ..item.clone()
+    };
+    store.insert_packet(&incomplete);
+    store.update_touch(item.id);
+
+    let report = store.compact(None, false).unwrap();
+    assert_eq!(report.skipped, 1);
+    assert_eq!(report.after, store.scan().count() + 1);
+    assert_eq!(store.get_packet(&incomplete.id), Some(incomplete));
+}
+
+#[test]
+fn test_encoding_migration() {
+    use crate::store::{
+        Alternate, ContentMeta, ContentMetaV1, ContentMetaV2, Movement, PacketV3, PacketV4,
+        PacketV5, PacketV6, PacketV7, PinStatus, StackDirectory, StackMeta, StackSortOrder, Tags,
+    };
+
+    let dir = tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    // a packet for each historical layout, as it's read today
+    let packet = |packet_type| Packet {
+        id: scru128::new(),
+        packet_type,
+        source_id: Some(scru128::new()),
+        hash: None,
+        stack_id: Some(scru128::new()),
+        ephemeral: false,
+        content_type: None,
+        movement: None,
+        lock_status: None,
+        sort_order: None,
+        cross_stream: false,
+        directory: None,
+        stack_meta: None,
+        tags: None,
+        pin_status: None,
+        snapshot: None,
+    };
+    let v3 = packet(PacketType::Fork);
+    let v4 = Packet {
+        content_type: Some("Markdown".to_string()),
+        movement: Some(Movement::Up),
+        lock_status: Some(StackLockStatus::Locked),
+        sort_order: Some(StackSortOrder::Manual),
+        ..packet(PacketType::Update)
+    };
+    let v5 = Packet {
+        directory: Some(StackDirectory {
+            path: "/tmp".to_string(),
+            watch: true,
+        }),
+        ..packet(PacketType::Update)
+    };
+    let v6 = Packet {
+        stack_meta: Some(StackMeta {
+            description: Some("Notes".to_string()),
+            color: None,
+            icon: Some("star".to_string()),
+        }),
+        ..packet(PacketType::Update)
+    };
+    let v7 = Packet {
+        tags: Some(Tags {
+            add: vec!["todo".to_string()],
+            remove: vec!["done".to_string()],
+        }),
+        ..packet(PacketType::Update)
+    };
+    let v8 = Packet {
+        pin_status: Some(PinStatus::Pinned),
+        ..packet(PacketType::Update)
+    };
+
+    let legacy_packets = [
+        bincode::serialize(&PacketV3 {
+            id: v3.id,
+            packet_type: v3.packet_type.clone(),
+            source_id: v3.source_id,
+            hash: None,
+            stack_id: v3.stack_id,
+            ephemeral: false,
+        }),
+        bincode::serialize(&PacketV4 {
+            id: v4.id,
+            packet_type: v4.packet_type.clone(),
+            source_id: v4.source_id,
+            hash: None,
+            stack_id: v4.stack_id,
+            ephemeral: false,
+            content_type: v4.content_type.clone(),
+            movement: v4.movement.clone(),
+            lock_status: v4.lock_status.clone(),
+            sort_order: v4.sort_order.clone(),
+            cross_stream: false,
+        }),
+        bincode::serialize(&PacketV5 {
+            id: v5.id,
+            packet_type: v5.packet_type.clone(),
+            source_id: v5.source_id,
+            hash: None,
+            stack_id: v5.stack_id,
+            ephemeral: false,
+            content_type: None,
+            movement: None,
+            lock_status: None,
+            sort_order: None,
+            cross_stream: false,
+            directory: v5.directory.clone(),
+        }),
+        bincode::serialize(&PacketV6 {
+            id: v6.id,
+            packet_type: v6.packet_type.clone(),
+            source_id: v6.source_id,
+            hash: None,
+            stack_id: v6.stack_id,
+            ephemeral: false,
+            content_type: None,
+            movement: None,
+            lock_status: None,
+            sort_order: None,
+            cross_stream: false,
+            directory: None,
+            stack_meta: v6.stack_meta.clone(),
+        }),
+        bincode::serialize(&PacketV7 {
+            id: v7.id,
+            packet_type: v7.packet_type.clone(),
+            source_id: v7.source_id,
+            hash: None,
+            stack_id: v7.stack_id,
+            ephemeral: false,
+            content_type: None,
+            movement: None,
+            lock_status: None,
+            sort_order: None,
+            cross_stream: false,
+            directory: None,
+            stack_meta: None,
+            tags: v7.tags.clone(),
+        }),
+        bincode::serialize(&v8),
+    ];
+    let packets = vec![v3, v4, v5, v6, v7, v8];
+
+    // and content meta for each of its layouts
+    let cas = dir.path().join("cas");
+    let meta = |content: &[u8]| ContentMeta {
+        hash: cacache::write_hash_sync(&cas, content).unwrap(),
+        mime_type: MimeType::TextPlain,
+        content_type: "Text".to_string(),
+        terse: String::from_utf8_lossy(content).into_owned(),
+        tiktokens: 2,
+        snapshot: None,
+        source: None,
+        alternates: Vec::new(),
+    };
+    let meta_v1 = meta(b"meta v1");
+    let meta_v2 = ContentMeta {
+        snapshot: Some(ssri::Integrity::from("snapshot")),
+        ..meta(b"meta v2")
+    };
+    let meta_v3 = ContentMeta {
+        source: Some("Terminal".to_string()),
+        alternates: vec![Alternate {
+            pasteboard_type: "public.html".to_string(),
+            hash: ssri::Integrity::from("<b>meta v3</b>"),
+        }],
+        ..meta(b"meta v3")
+    };
+    let legacy_meta = [
+        bincode::serialize(&ContentMetaV1 {
+            hash: meta_v1.hash.clone(),
+            mime_type: meta_v1.mime_type.clone(),
+            content_type: meta_v1.content_type.clone(),
+            terse: meta_v1.terse.clone(),
+            tiktokens: meta_v1.tiktokens,
+        }),
+        bincode::serialize(&ContentMetaV2 {
+            hash: meta_v2.hash.clone(),
+            mime_type: meta_v2.mime_type.clone(),
+            content_type: meta_v2.content_type.clone(),
+            terse: meta_v2.terse.clone(),
+            tiktokens: meta_v2.tiktokens,
+            snapshot: meta_v2.snapshot.clone(),
+        }),
+        bincode::serialize(&meta_v3),
+    ];
+    let metas = vec![meta_v1, meta_v2, meta_v3];
+
+    {
+        let db = sled::open(dir.path().join("sled")).unwrap();
+        let tree = db.open_tree("packets").unwrap();
+        for (packet, legacy) in packets.iter().zip(legacy_packets) {
+            tree.insert(packet.id.to_bytes(), legacy.unwrap()).unwrap();
+        }
+        let tree = db.open_tree("content_meta").unwrap();
+        for (meta, legacy) in metas.iter().zip(legacy_meta) {
+            let key = bincode::serialize(&meta.hash).unwrap();
+            tree.insert(key, legacy.unwrap()).unwrap();
+        }
+    }
+
+    // the legacy entries are read, and re-encoded, on the first open, and read back as they
+    // were on the next
+    for _ in 0..2 {
+        let store = Store::new(path);
+        assert_eq!(store.scan().collect::<Vec<_>>(), packets);
+        for meta in &metas {
+            assert_eq!(store.get_content_meta(&meta.hash).as_ref(), Some(meta));
+        }
+    }
+
+    let db = sled::open(dir.path().join("sled")).unwrap();
+    for tree in ["packets", "content_meta"] {
+        for value in db.open_tree(tree).unwrap().iter().values() {
+            assert_eq!(value.unwrap()[..2], [0xFF, 1]);
+        }
+    }
+}
+
+#[test]
+fn test_migrate() {
+    use crate::storage::{copy, MemoryStorage, SledStorage, Storage};
+
+    let dir = tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    let (packets, image) = {
+        let mut store = Store::new(path);
+        let stack = store.add_stack(b"Stack", StackLockStatus::Unlocked);
+        store.add(b"Item 1", MimeType::TextPlain, stack.id);
+        let image = store.add(b"\x89PNG\r\n\x1a\n", MimeType::ImagePng, stack.id);
+        store.settings_save(crate::store::Settings {
+            openai_access_token: "token".to_string(),
+            openai_selected_model: "model".to_string(),
+            ..Default::default()
+        });
+        (store.scan().collect::<Vec<_>>(), image.hash.unwrap())
+    };
+
+    // a sled store copied into memory, and back to sled, reads back as it was written
+    let sled = SledStorage::open(dir.path()).unwrap();
+    let memory = MemoryStorage::default();
+    let (entries, blobs) = copy(&sled, &memory);
+    assert_eq!(blobs, 3);
+    assert!(entries > packets.len());
+    drop(sled);
+
+    let copied = tempdir().unwrap();
+    copy(&memory, &SledStorage::open(copied.path()).unwrap());
+
+    for storage in [
+        Box::new(memory) as Box<dyn Storage>,
+        Box::new(SledStorage::open(copied.path()).unwrap()),
+    ] {
+        let store = Store::with_storage(storage.as_ref());
+        assert_eq!(store.scan().collect::<Vec<_>>(), packets);
+        assert_eq!(store.get_content(&image).unwrap(), b"\x89PNG\r\n\x1a\n");
+        assert_eq!(store.get_content_meta(&image).unwrap().terse, "Image");
+        assert_eq!(store.settings_get().unwrap().openai_access_token, "token");
+    }
+}
+
+#[test]
+fn test_sqlite() {
+    use crate::sqlite::{query, SqliteStorage};
+
+    for inline_blobs in [true, false] {
+        let dir = tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+
+        let (stack, item, packets) = {
+            let mut store =
+                Store::with_storage(&SqliteStorage::open(dir.path(), inline_blobs).unwrap());
+            let stack = store.add_stack(b"Stack", StackLockStatus::Unlocked);
+            let item = store.add(b"Item 1", MimeType::TextPlain, stack.id);
+            store.update_content_type(item.hash.clone().unwrap(), "Markdown".to_string());
+            store.add(b"Item 2", MimeType::TextPlain, stack.id);
+            (stack, item, store.scan().collect::<Vec<_>>())
+        };
+        assert_eq!(dir.path().join("cas").is_dir(), !inline_blobs);
+
+        // the store is opened in the backend it's kept in
+        let mut store = Store::new(path);
+        assert_eq!(store.scan().collect::<Vec<_>>(), packets);
+        let hash = item.hash.clone().unwrap();
+        assert_eq!(store.get_content(&hash).unwrap(), b"Item 1");
+        assert!(store.fsck(false).is_clean());
+
+        // packets and content meta can be queried by their fields
+        let rows = query(
+            dir.path(),
+            &format!(
+                "SELECT p.id, m.terse, m.content_type FROM packets p \
+                 JOIN content_meta m ON m.hash = p.hash \
+                 WHERE p.stack_id = '{}' AND p.packet_type = 'Add' ORDER BY p.id",
+                stack.id
+            ),
+        )
+        .unwrap();
+        assert!(!rows.truncated);
+        let rows = rows.rows;
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["id"], item.id.to_string());
+        assert_eq!(rows[0]["terse"], "Item 1");
+        assert_eq!(rows[0]["content_type"], "Markdown");
+
+        // queries can't change the store
+        assert!(query(dir.path(), "DELETE FROM packets").is_err());
+
+        // nor return everything, or run forever
+        let rows = query(
+            dir.path(),
+            "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n) SELECT i FROM n",
+        )
+        .unwrap();
+        assert!(rows.truncated);
+        assert_eq!(rows.rows.len(), crate::sqlite::MAX_ROWS);
+        let error = crate::sqlite::query_with_timeout(
+            dir.path(),
+            "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n) \
+             SELECT count(*) FROM n",
+            std::time::Duration::from_millis(100),
+        )
+        .unwrap_err();
+        assert!(error.contains("stopped"), "{}", error);
+        assert_eq!(store.scan().count(), packets.len());
+    }
+
+    // stores kept in sled can't be queried
+    let dir = tempdir().unwrap();
+    Store::new(dir.path().to_str().unwrap());
+    assert!(query(dir.path(), "SELECT 1").is_err());
+}
@@ -0,0 +1,924 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use base64::{engine::general_purpose, Engine as _};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use scru128::Scru128Id;
+use serde::{Deserialize, Serialize};
+use ssri::Integrity;
+use subtle::ConstantTimeEq;
+
+use tauri::Manager;
+
+use crate::state::{SharedState, State};
+use crate::store::{ContentMeta, Packet, Store};
+
+// changes are exchanged in batches of at most this many
+const BATCH_SIZE: usize = 100;
+// seconds between syncs with peers, unless the settings say otherwise
+const DEFAULT_INTERVAL: u64 = 60;
+
+// the id this store goes by in shared directories
+const DEVICE_KEY: &str = "sync_device";
+// content a peer didn't have when it was pulled, along with the first packet which needs it
+const WANTED_KEY: &str = "sync_wanted";
+
+/// A change to the packet log, as it's exchanged with peers.
+///
+/// Packets are ordered by their ids, which are Scru128, so every device which has the same
+/// packets builds the same view, whichever order they arrived in: concurrent edits to an item
+/// resolve to the latest. Removing a packet is for good, so a removal wins over a peer which
+/// still has the packet.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Change {
+    Packet(Box<Packet>),
+    Removed(Scru128Id),
+}
+
+/// Content, as it's exchanged with peers. Alternate representations of content have no meta
+/// of their own.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Blob {
+    pub hash: Integrity,
+    pub meta: Option<ContentMeta>,
+    #[serde(with = "base64_content")]
+    pub content: Vec<u8>,
+}
+
+mod base64_content {
+    use super::*;
+
+    pub fn serialize<S: serde::Serializer>(content: &[u8], s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&general_purpose::STANDARD.encode(content))
+    }
+
+    pub fn deserialize<'de, D: serde::Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(d)?;
+        general_purpose::STANDARD
+            .decode(encoded)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Which peers to sync with, and how this store is served to peers over HTTP.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SyncSettings {
+    #[serde(default)]
+    pub peers: Vec<Peer>,
+    // seconds between syncs
+    pub interval: Option<u64>,
+    // the address to serve this store to HTTP peers on, e.g. "0.0.0.0:7575", and the bearer
+    // token they need: it isn't served without one
+    pub listen: Option<String>,
+    pub token: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Peer {
+    pub name: String,
+    #[serde(flatten)]
+    pub transport: TransportSettings,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "transport", rename_all = "snake_case")]
+pub enum TransportSettings {
+    // a directory shared between devices, by Syncthing, Dropbox or the like
+    Directory { path: PathBuf },
+    // another instance of Stacks, serving its store with `listen`
+    Http { url: String, token: Option<String> },
+}
+
+impl Peer {
+    fn transport(&self, device: &str) -> Box<dyn Transport> {
+        match &self.transport {
+            TransportSettings::Directory { path } => Box::new(Directory::new(path, device)),
+            TransportSettings::Http { url, token } => Box::new(Http::new(url, token.clone())),
+        }
+    }
+}
+
+/// How changes, and the content they need, get to and from a peer.
+pub trait Transport {
+    /// The peer's changes after `cursor`, and the cursor to pick up from next time. Cursors
+    /// are the transport's own, and start out empty.
+    fn pull(&self, cursor: &str) -> Result<(Vec<Change>, String), String>;
+    /// Whichever of the content `hashes` the peer has.
+    fn fetch(&self, hashes: &[Integrity]) -> Result<Vec<Blob>, String>;
+    /// Hands `changes` to the peer, along with the content they need.
+    fn push(&self, changes: &[Change], blobs: &[Blob]) -> Result<(), String>;
+}
+
+/// A store which syncs. Transports are only called on without the store, so a slow peer
+/// doesn't hold it up.
+pub trait Replica {
+    fn with_store<T>(&self, f: impl FnOnce(&mut Store) -> T) -> T;
+    /// Called once a sync has changed the packet log, with the packets it added.
+    fn changed(&self, added: &[Packet]);
+}
+
+/// What a sync with a peer did.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SyncReport {
+    pub peer: String,
+    // changes sent to the peer, and changes from it which were new here
+    pub pushed: usize,
+    pub pulled: usize,
+    // content fetched from the peer
+    pub blobs: usize,
+    pub error: Option<String>,
+}
+
+fn cursor(store: &Store, key: &str) -> String {
+    let cursor = store.meta.get(key.as_bytes()).unwrap_or_default();
+    String::from_utf8(cursor).unwrap_or_default()
+}
+
+fn save_cursor(store: &Store, key: &str, cursor: &str) {
+    store.meta.insert(key.as_bytes(), cursor.as_bytes());
+}
+
+/// The id this store goes by in shared directories, made up the first time it's needed.
+pub fn device(store: &Store) -> String {
+    let device = cursor(store, DEVICE_KEY);
+    if !device.is_empty() {
+        return device;
+    }
+    let device = scru128::new().to_string();
+    save_cursor(store, DEVICE_KEY, &device);
+    device
+}
+
+// the content packets refer to, along with the snapshots of file references
+fn hashes(changes: &[Change]) -> Vec<Integrity> {
+    needed(changes).into_iter().map(|(hash, _)| hash).collect()
+}
+
+// the content packets refer to, along with the first packet which refers to each
+fn needed(changes: &[Change]) -> Vec<(Integrity, Scru128Id)> {
+    let mut needed: Vec<(Integrity, Scru128Id)> = Vec::new();
+    let mut seen: HashMap<Integrity, usize> = HashMap::new();
+    let refs = changes.iter().flat_map(|change| match change {
+        Change::Packet(packet) => vec![
+            (packet.hash.clone(), packet.id),
+            (packet.snapshot.clone(), packet.id),
+        ],
+        Change::Removed(_) => Vec::new(),
+    });
+    for (hash, id) in refs.filter_map(|(hash, id)| Some((hash?, id))) {
+        match seen.get(&hash) {
+            Some(&i) => needed[i].1 = needed[i].1.min(id),
+            None => {
+                seen.insert(hash.clone(), needed.len());
+                needed.push((hash, id));
+            }
+        }
+    }
+    needed
+}
+
+fn wanted(store: &Store) -> Vec<(Integrity, Scru128Id)> {
+    let wanted = store.meta.get(WANTED_KEY.as_bytes()).unwrap_or_default();
+    serde_json::from_slice(&wanted).unwrap_or_default()
+}
+
+/// Adds `more` to the content which is wanted from peers, and drops whatever has arrived since.
+/// Returns whether anything arrived: packets which were skipped, as their content was missing,
+/// are picked up once the view is rebuilt.
+fn want(store: &Store, more: Vec<(Integrity, Scru128Id)>) -> bool {
+    let mut arrived = false;
+    let mut still_wanted: Vec<(Integrity, Scru128Id)> = Vec::new();
+    for (hash, id) in wanted(store).into_iter().chain(more) {
+        if store.cas_exists(&hash) {
+            // view snapshots taken since the packet was pulled don't include it
+            store.invalidate_view_snapshots(&id);
+            arrived = true;
+        } else if let Some(entry) = still_wanted.iter_mut().find(|(h, _)| h == &hash) {
+            entry.1 = entry.1.min(id);
+        } else {
+            still_wanted.push((hash, id));
+        }
+    }
+    store.meta.insert(
+        WANTED_KEY.as_bytes(),
+        &serde_json::to_vec(&still_wanted).unwrap(),
+    );
+    arrived
+}
+
+/// The content `hashes`, along with the snapshots and alternate representations which go with
+/// them.
+pub fn blobs(store: &Store, hashes: &[Integrity]) -> Vec<Blob> {
+    let mut wanted: Vec<(Integrity, bool)> = hashes.iter().map(|h| (h.clone(), true)).collect();
+    let mut seen = HashSet::new();
+    let mut blobs = Vec::new();
+    while let Some((hash, with_meta)) = wanted.pop() {
+        if !seen.insert(hash.clone()) {
+            continue;
+        }
+        let Some(content) = store.blobs.read(&hash) else {
+            continue;
+        };
+        let meta = with_meta.then(|| store.get_content_meta(&hash)).flatten();
+        if let Some(meta) = &meta {
+            wanted.extend(meta.snapshot.iter().map(|h| (h.clone(), true)));
+            wanted.extend(meta.alternates.iter().map(|a| (a.hash.clone(), false)));
+        }
+        blobs.push(Blob {
+            hash,
+            meta,
+            content,
+        });
+    }
+    blobs
+}
+
+/// Adds content from a peer. Returns how much of it was new: content which doesn't match its
+/// hash is left out.
+pub fn import(store: &mut Store, blobs: Vec<Blob>) -> usize {
+    let mut imported = 0;
+    for blob in blobs {
+        let result = match blob.meta {
+            _ if store.cas_exists(&blob.hash) => continue,
+            None if store.blobs.exists(&blob.hash) => continue,
+            Some(meta) if meta.hash == blob.hash => store.import_content(meta, &blob.content),
+            Some(_) => Err("content meta is for other content".to_string()),
+            None => match blob.hash.check(&blob.content) {
+                Ok(_) => {
+                    store.blobs.write(&blob.content);
+                    Ok(())
+                }
+                Err(e) => Err(e.to_string()),
+            },
+        };
+        match result {
+            Ok(()) => imported += 1,
+            Err(e) => tracing::warn!(hash = %blob.hash, "Skipping content from peer: {}", e),
+        }
+    }
+    imported
+}
+
+/// Applies `changes` from a peer, skipping those which are already here. Returns the packets
+/// which were added, and whether anything changed.
+pub fn apply(store: &Store, changes: &[Change]) -> (Vec<Packet>, bool) {
+    let mut added = Vec::new();
+    let mut changed = false;
+    for change in changes {
+        match change {
+            Change::Packet(packet) => {
+                if store.is_removed(&packet.id)
+                    || store.get_packet(&packet.id).as_ref() == Some(&**packet)
+                {
+                    continue;
+                }
+                store.insert_packet(packet);
+                added.push((**packet).clone());
+                changed = true;
+            }
+            Change::Removed(id) => {
+                if !store.is_removed(id) {
+                    store.remove_packet(id);
+                    changed = true;
+                }
+            }
+        }
+    }
+    (added, changed)
+}
+
+/// Syncs with the peer `name`: the changes made here since the last sync are pushed to it,
+/// then its changes are pulled, along with any content which is missing here.
+pub fn sync(
+    replica: &impl Replica,
+    name: &str,
+    transport: &dyn Transport,
+) -> Result<SyncReport, String> {
+    let mut report = SyncReport {
+        peer: name.to_string(),
+        ..Default::default()
+    };
+    let (push_key, pull_key) = (format!("sync_push:{name}"), format!("sync_pull:{name}"));
+
+    loop {
+        let (changes, blobs, pushed, last) = replica.with_store(|store| {
+            let pushed = cursor(store, &push_key).parse().unwrap_or(0);
+            let (changes, last) = store.changes_after(pushed, BATCH_SIZE);
+            let blobs = blobs(store, &hashes(&changes));
+            (changes, blobs, pushed, last)
+        });
+        if last == pushed {
+            break;
+        }
+        if !changes.is_empty() {
+            transport.push(&changes, &blobs)?;
+        }
+        report.pushed += changes.len();
+        replica.with_store(|store| save_cursor(store, &push_key, &last.to_string()));
+    }
+
+    let mut added = Vec::new();
+    let mut changed = false;
+
+    // content which earlier syncs couldn't get is asked for again, of every peer
+    let retry: Vec<Integrity> = replica
+        .with_store(|store| wanted(store))
+        .into_iter()
+        .map(|(hash, _)| hash)
+        .collect();
+    if !retry.is_empty() {
+        let fetched = transport.fetch(&retry)?;
+        changed |= replica.with_store(|store| {
+            report.blobs += import(store, fetched);
+            want(store, Vec::new())
+        });
+    }
+
+    loop {
+        let pulled = replica.with_store(|store| cursor(store, &pull_key));
+        let (changes, next) = transport.pull(&pulled)?;
+        let missing: Vec<(Integrity, Scru128Id)> = replica.with_store(|store| {
+            let needed = needed(&changes);
+            needed
+                .into_iter()
+                .filter(|(hash, _)| !store.cas_exists(hash))
+                .collect()
+        });
+        let fetched = match missing.is_empty() {
+            true => Vec::new(),
+            false => {
+                let hashes: Vec<Integrity> = missing.iter().map(|(hash, _)| hash.clone()).collect();
+                transport.fetch(&hashes)?
+            }
+        };
+
+        let (batch_added, batch_changed) = replica.with_store(|store| {
+            report.blobs += import(store, fetched);
+            // the packets are applied all the same, and the content the peer didn't send is
+            // asked for again on later syncs
+            let missing = missing
+                .into_iter()
+                .filter(|(hash, _)| !store.cas_exists(hash))
+                .collect();
+            want(store, missing);
+            // changes from the peer aren't pushed back to it, unless there are changes made
+            // here in between which it doesn't have yet
+            let caught_up = cursor(store, &push_key).parse() == Ok(store.last_change());
+            let applied = apply(store, &changes);
+            if caught_up {
+                save_cursor(store, &push_key, &store.last_change().to_string());
+            }
+            save_cursor(store, &pull_key, &next);
+            applied
+        });
+        report.pulled += batch_added.len();
+        added.extend(batch_added);
+        changed |= batch_changed;
+        if next == pulled {
+            break;
+        }
+    }
+
+    if changed {
+        replica.changed(&added);
+    }
+    Ok(report)
+}
+
+// syncs with one peer at a time, so syncs which overlap don't race on cursors
+static SYNCING: Mutex<()> = Mutex::new(());
+
+/// Syncs with each of `peers` in turn. A peer which can't be reached doesn't stop the rest.
+pub fn sync_all(replica: &impl Replica, peers: &[Peer]) -> Vec<SyncReport> {
+    let _syncing = SYNCING.lock().unwrap_or_else(|e| e.into_inner());
+    let device = replica.with_store(|store| device(store));
+    peers
+        .iter()
+        .map(|peer| {
+            let transport = peer.transport(&device);
+            sync(replica, &peer.name, transport.as_ref()).unwrap_or_else(|error| {
+                tracing::warn!(peer = peer.name, "Sync failed: {}", error);
+                SyncReport {
+                    peer: peer.name.clone(),
+                    error: Some(error),
+                    ..Default::default()
+                }
+            })
+        })
+        .collect()
+}
+
+/// Syncs through a directory shared between devices. Each device appends batches of its
+/// changes to a directory of its own, `devices/<device>`, and content is shared in `blobs`,
+/// named by its hash. Files are written under a temporary name, then renamed, so other devices
+/// don't see them half written.
+pub struct Directory {
+    path: PathBuf,
+    device: String,
+}
+
+impl Directory {
+    pub fn new(path: &Path, device: &str) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            device: device.to_string(),
+        }
+    }
+
+    fn blob_path(&self, hash: &Integrity) -> PathBuf {
+        let (algorithm, hex) = hash.to_hex();
+        self.path.join("blobs").join(format!("{algorithm}-{hex}"))
+    }
+
+    fn write(path: &Path, content: &[u8]) -> Result<(), String> {
+        let name = path.file_name().unwrap().to_string_lossy();
+        let tmp = path.with_file_name(format!(".{name}.tmp"));
+        std::fs::create_dir_all(path.parent().unwrap()).map_err(|e| e.to_string())?;
+        std::fs::write(&tmp, content).map_err(|e| e.to_string())?;
+        std::fs::rename(&tmp, path).map_err(|e| e.to_string())
+    }
+}
+
+impl Transport for Directory {
+    // the cursor is the last batch read from each of the other devices
+    fn pull(&self, cursor: &str) -> Result<(Vec<Change>, String), String> {
+        let mut cursors: BTreeMap<String, String> =
+            serde_json::from_str(cursor).unwrap_or_default();
+        let mut changes = Vec::new();
+
+        let devices = std::fs::read_dir(self.path.join("devices"))
+            .into_iter()
+            .flatten();
+        for device in devices.flatten() {
+            let name = device.file_name().to_string_lossy().into_owned();
+            if name == self.device {
+                continue;
+            }
+            let mut batches: Vec<String> = std::fs::read_dir(device.path())
+                .map_err(|e| e.to_string())?
+                .flatten()
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .filter(|batch| batch.ends_with(".json") && !batch.starts_with('.'))
+                .filter(|batch| cursors.get(&name).is_none_or(|last| batch > last))
+                .collect();
+            batches.sort();
+
+            for batch in batches {
+                let content = std::fs::read(device.path().join(&batch));
+                // a batch which can't be read yet is picked up on the next sync
+                let Ok(batch_changes) = content.map_err(|e| e.to_string()).and_then(|c| {
+                    serde_json::from_slice::<Vec<Change>>(&c).map_err(|e| e.to_string())
+                }) else {
+                    break;
+                };
+                changes.extend(batch_changes);
+                cursors.insert(name.clone(), batch);
+            }
+        }
+
+        Ok((changes, serde_json::to_string(&cursors).unwrap()))
+    }
+
+    fn fetch(&self, hashes: &[Integrity]) -> Result<Vec<Blob>, String> {
+        let mut blobs = Vec::new();
+        for hash in hashes {
+            let path = self.blob_path(hash);
+            let Ok(content) = std::fs::read(&path) else {
+                continue;
+            };
+            let meta = std::fs::read(path.with_extension("meta"))
+                .ok()
+                .and_then(|meta| serde_json::from_slice(&meta).ok());
+            blobs.push(Blob {
+                hash: hash.clone(),
+                meta,
+                content,
+            });
+        }
+        Ok(blobs)
+    }
+
+    fn push(&self, changes: &[Change], blobs: &[Blob]) -> Result<(), String> {
+        for blob in blobs {
+            let path = self.blob_path(&blob.hash);
+            if !path.exists() {
+                Self::write(&path, &blob.content)?;
+            }
+            if let Some(meta) = &blob.meta {
+                Self::write(
+                    &path.with_extension("meta"),
+                    &serde_json::to_vec(meta).unwrap(),
+                )?;
+            }
+        }
+        // batches are named by Scru128 ids, so they sort in the order they were written
+        let batch = self
+            .path
+            .join("devices")
+            .join(&self.device)
+            .join(format!("{}.json", scru128::new()));
+        Self::write(&batch, &serde_json::to_vec(changes).unwrap())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Changes {
+    changes: Vec<Change>,
+    cursor: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Push {
+    changes: Vec<Change>,
+    blobs: Vec<Blob>,
+}
+
+/// Syncs with another instance of Stacks, which serves its store with [`serve`].
+pub struct Http {
+    url: String,
+    token: Option<String>,
+    client: reqwest::blocking::Client,
+}
+
+impl Http {
+    pub fn new(url: &str, token: Option<String>) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            token,
+            client: reqwest::blocking::Client::new(),
+        }
+    }
+
+    fn send(
+        &self,
+        request: reqwest::blocking::RequestBuilder,
+    ) -> Result<reqwest::blocking::Response, String> {
+        let request = match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        };
+        let response = request.send().map_err(|e| e.to_string())?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().unwrap_or_default();
+            return Err(format!("{} replied {status}: {body}", self.url));
+        }
+        Ok(response)
+    }
+}
+
+impl Transport for Http {
+    // the cursor is the sequence number of the last change pulled from the peer
+    fn pull(&self, cursor: &str) -> Result<(Vec<Change>, String), String> {
+        let after: u64 = cursor.parse().unwrap_or(0);
+        let url = format!("{}/sync/changes?after={after}", self.url);
+        let response = self.send(self.client.get(url))?;
+        let changes: Changes = response.json().map_err(|e| e.to_string())?;
+        Ok((changes.changes, changes.cursor.to_string()))
+    }
+
+    fn fetch(&self, hashes: &[Integrity]) -> Result<Vec<Blob>, String> {
+        let url = format!("{}/sync/blobs", self.url);
+        let response = self.send(self.client.post(url).json(hashes))?;
+        response.json().map_err(|e| e.to_string())
+    }
+
+    fn push(&self, changes: &[Change], blobs: &[Blob]) -> Result<(), String> {
+        let url = format!("{}/sync/changes", self.url);
+        let push = Push {
+            changes: changes.to_vec(),
+            blobs: blobs.to_vec(),
+        };
+        self.send(self.client.post(url).json(&push))?;
+        Ok(())
+    }
+}
+
+type SyncResult = Result<Response<Full<Bytes>>, Box<dyn std::error::Error + Send + Sync>>;
+
+fn respond(status: StatusCode, content_type: &str, body: impl Into<Bytes>) -> SyncResult {
+    Ok(Response::builder()
+        .status(status)
+        .header("Content-Type", content_type)
+        .body(Full::new(body.into()))?)
+}
+
+async fn handle<R: Replica>(
+    replica: R,
+    token: Arc<str>,
+    req: Request<hyper::body::Incoming>,
+) -> SyncResult {
+    let authorized = req
+        .headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        // compared in constant time, so the token can't be guessed a byte at a time
+        .is_some_and(|presented| presented.as_bytes().ct_eq(token.as_bytes()).into());
+    if !authorized {
+        return respond(StatusCode::UNAUTHORIZED, "text/plain", "Unauthorized");
+    }
+
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, "/sync/changes") => {
+            let after: u64 = req
+                .uri()
+                .query()
+                .and_then(|query| {
+                    url::form_urlencoded::parse(query.as_bytes())
+                        .find(|(key, _)| key == "after")
+                        .and_then(|(_, value)| value.parse().ok())
+                })
+                .unwrap_or(0);
+            let (changes, cursor) =
+                replica.with_store(|store| store.changes_after(after, BATCH_SIZE));
+            let body = serde_json::to_vec(&Changes { changes, cursor })?;
+            respond(StatusCode::OK, "application/json", body)
+        }
+        (&Method::POST, "/sync/changes") => {
+            let body = req.into_body().collect().await?.to_bytes();
+            let push: Push = serde_json::from_slice(&body)?;
+            let (added, changed) = replica.with_store(|store| {
+                import(store, push.blobs);
+                apply(store, &push.changes)
+            });
+            if changed {
+                replica.changed(&added);
+            }
+            respond(StatusCode::OK, "text/plain", "")
+        }
+        (&Method::POST, "/sync/blobs") => {
+            let body = req.into_body().collect().await?.to_bytes();
+            let hashes: Vec<Integrity> = serde_json::from_slice(&body)?;
+            let blobs = replica.with_store(|store| blobs(store, &hashes));
+            respond(
+                StatusCode::OK,
+                "application/json",
+                serde_json::to_vec(&blobs)?,
+            )
+        }
+        _ => respond(StatusCode::NOT_FOUND, "text/plain", "Not found"),
+    }
+}
+
+/// Serves the store to HTTP peers on `listener`, to those which present `token`.
+pub async fn serve<R>(listener: tokio::net::TcpListener, replica: R, token: String)
+where
+    R: Replica + Clone + Send + Sync + 'static,
+{
+    let token: Arc<str> = token.into();
+    loop {
+        let Ok((stream, _)) = listener.accept().await else {
+            continue;
+        };
+        let io = TokioIo::new(stream);
+        let (replica, token) = (replica.clone(), token.clone());
+        tokio::spawn(async move {
+            let service = service_fn(move |req| handle(replica.clone(), token.clone(), req));
+            if let Err(err) = http1::Builder::new().serve_connection(io, service).await {
+                tracing::warn!("Error serving sync connection: {:?}", err);
+            }
+        });
+    }
+}
+
+/// The running app, as a replica: a sync which changes the packet log rebuilds the view.
+#[derive(Clone)]
+pub struct App {
+    pub app: tauri::AppHandle,
+    pub state: SharedState,
+}
+
+impl Replica for App {
+    fn with_store<T>(&self, f: impl FnOnce(&mut Store) -> T) -> T {
+        self.state.with_lock(|state| f(&mut state.store))
+    }
+
+    fn changed(&self, added: &[Packet]) {
+        State::synced(&self.state, added);
+        self.app.emit_all("refresh-items", true).unwrap();
+    }
+}
+
+/// Serves the store to HTTP peers, if the settings say to, and syncs with the peers in the
+/// settings every so often.
+pub fn start(app: tauri::AppHandle, state: SharedState) {
+    let replica = App { app, state };
+    let settings = replica
+        .state
+        .with_lock(|state| state.store.settings_get())
+        .and_then(|settings| settings.sync)
+        .unwrap_or_default();
+
+    match (settings.listen, settings.token) {
+        (Some(listen), Some(token)) => {
+            let replica = replica.clone();
+            tauri::async_runtime::spawn(async move {
+                match tokio::net::TcpListener::bind(&listen).await {
+                    Ok(listener) => serve(listener, replica, token).await,
+                    Err(e) => tracing::error!(listen, "Could not serve sync: {}", e),
+                }
+            });
+        }
+        (Some(_), None) => tracing::warn!("Not serving sync: there's no token for peers to use"),
+        _ => (),
+    }
+
+    tauri::async_runtime::spawn(async move {
+        tracing::info!(name = "sync", "booting");
+        loop {
+            // the settings are read every time, so peers can be changed without a restart
+            let settings = replica
+                .state
+                .with_lock(|state| state.store.settings_get())
+                .and_then(|settings| settings.sync)
+                .unwrap_or_default();
+            if !settings.peers.is_empty() {
+                let replica = replica.clone();
+                let peers = settings.peers.clone();
+                let _ = tokio::task::spawn_blocking(move || sync_all(&replica, &peers)).await;
+            }
+            let interval = settings.interval.unwrap_or(DEFAULT_INTERVAL).max(1);
+            tokio::time::sleep(std::time::Duration::from_secs(interval)).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::store::{MimeType, StackLockStatus, Tags};
+
+    type Shared = Arc<Mutex<Store>>;
+
+    impl Replica for Shared {
+        fn with_store<T>(&self, f: impl FnOnce(&mut Store) -> T) -> T {
+            f(&mut self.lock().unwrap())
+        }
+
+        fn changed(&self, _added: &[Packet]) {}
+    }
+
+    fn replica() -> Shared {
+        Arc::new(Mutex::new(Store::in_memory()))
+    }
+
+    fn packets(replica: &Shared) -> Vec<Packet> {
+        replica.with_store(|store| store.scan().collect())
+    }
+
+    fn tag(replica: &Shared, id: Scru128Id, tag: &str) {
+        replica.with_store(|store| {
+            store.update_tags(
+                id,
+                Tags {
+                    add: vec![tag.to_string()],
+                    remove: vec![],
+                },
+            )
+        });
+    }
+
+    #[test]
+    fn test_sync_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (replica(), replica());
+        let directory = |replica: &Shared| {
+            let device = replica.with_store(|store| device(store));
+            Directory::new(dir.path(), &device)
+        };
+
+        // items added on either device end up on both, with their content
+        let (stack, one) = a.with_store(|store| {
+            let stack = store.add_stack(b"Stack", StackLockStatus::Unlocked);
+            let one = store.add(b"one", MimeType::TextPlain, stack.id);
+            (stack, one)
+        });
+        let report = sync(&a, "shared", &directory(&a)).unwrap();
+        assert_eq!((report.pushed, report.pulled), (2, 0));
+
+        let two = b.with_store(|store| store.add(b"two", MimeType::TextPlain, stack.id));
+        let report = sync(&b, "shared", &directory(&b)).unwrap();
+        assert_eq!((report.pushed, report.pulled, report.blobs), (1, 2, 2));
+        sync(&a, "shared", &directory(&a)).unwrap();
+
+        assert_eq!(packets(&a), packets(&b));
+        assert_eq!(packets(&a).len(), 3);
+        for replica in [&a, &b] {
+            let content = replica.with_store(|store| store.get_content(two.hash.as_ref().unwrap()));
+            assert_eq!(content.unwrap(), b"two");
+        }
+
+        // concurrent edits to the same item merge, in the order they were made
+        tag(&a, one.id, "from-a");
+        tag(&b, one.id, "from-b");
+        for replica in [&a, &b, &a] {
+            sync(replica, "shared", &directory(replica)).unwrap();
+        }
+        assert_eq!(packets(&a), packets(&b));
+        assert_eq!(packets(&a).len(), 5);
+
+        // syncing again changes nothing
+        let report = sync(&b, "shared", &directory(&b)).unwrap();
+        assert_eq!((report.pushed, report.pulled), (0, 0));
+
+        // a packet removed on one device is removed on the other, and isn't brought back by a
+        // device which still had it
+        a.with_store(|store| store.remove_packet(&two.id)).unwrap();
+        sync(&a, "shared", &directory(&a)).unwrap();
+        let missing = |replica: &Shared| {
+            replica
+                .with_store(|store| store.get_packet(&two.id))
+                .is_none()
+        };
+        assert!(!missing(&b));
+        sync(&b, "shared", &directory(&b)).unwrap();
+        sync(&a, "shared", &directory(&a)).unwrap();
+        assert!(missing(&a));
+        assert!(missing(&b));
+        assert_eq!(packets(&a), packets(&b));
+    }
+
+    #[test]
+    fn test_sync_missing_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (replica(), replica());
+        let directory = |replica: &Shared| {
+            let device = replica.with_store(|store| device(store));
+            Directory::new(dir.path(), &device)
+        };
+
+        let (stack, one) = a.with_store(|store| {
+            let stack = store.add_stack(b"Stack", StackLockStatus::Unlocked);
+            let one = store.add(b"one", MimeType::TextPlain, stack.id);
+            (stack, one)
+        });
+        sync(&a, "shared", &directory(&a)).unwrap();
+
+        // the shared directory doesn't have the content yet, so the item isn't shown until it
+        // does, and it's asked for again meanwhile
+        let path = directory(&a).blob_path(one.hash.as_ref().unwrap());
+        let content = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let report = sync(&b, "shared", &directory(&b)).unwrap();
+        assert_eq!((report.pulled, report.blobs), (2, 1));
+        let ids = |replica: &Shared| packets(replica).iter().map(|p| p.id).collect::<Vec<_>>();
+        assert_eq!(ids(&b), vec![stack.id]);
+        assert_eq!(
+            b.with_store(|store| wanted(store)),
+            vec![(one.hash.clone().unwrap(), one.id)]
+        );
+
+        let report = sync(&b, "shared", &directory(&b)).unwrap();
+        assert_eq!((report.pulled, report.blobs), (0, 0));
+
+        // once it arrives, view snapshots which skipped the item are dropped
+        b.with_store(|store| store.save_view_snapshot(&one.id, &crate::view::View::new()));
+        std::fs::write(&path, content).unwrap();
+        let report = sync(&b, "shared", &directory(&b)).unwrap();
+        assert_eq!((report.pulled, report.blobs), (0, 1));
+        assert_eq!(packets(&a), packets(&b));
+        assert!(b.with_store(|store| store.load_view_snapshot()).is_none());
+        assert!(b.with_store(|store| wanted(store)).is_empty());
+    }
+
+    #[test]
+    fn test_sync_http() {
+        let (a, b) = (replica(), replica());
+        let stack = b.with_store(|store| store.add_stack(b"Stack", StackLockStatus::Unlocked));
+        b.with_store(|store| store.add(b"on b", MimeType::TextPlain, stack.id));
+        a.with_store(|store| store.add(b"on a", MimeType::TextPlain, stack.id));
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let listener = runtime
+            .block_on(tokio::net::TcpListener::bind("127.0.0.1:0"))
+            .unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = b.clone();
+        std::thread::spawn(move || runtime.block_on(serve(listener, server, "secret".into())));
+
+        let wrong = Http::new(&url, Some("wrong".into()));
+        let error = sync(&a, "b", &wrong).unwrap_err();
+        assert!(error.contains("401"), "{}", error);
+
+        let http = Http::new(&url, Some("secret".into()));
+        let report = sync(&a, "b", &http).unwrap();
+        assert_eq!((report.pushed, report.pulled, report.blobs), (1, 2, 2));
+        assert_eq!(packets(&a), packets(&b));
+        assert_eq!(packets(&a).len(), 3);
+
+        let report = sync(&a, "b", &http).unwrap();
+        assert_eq!((report.pushed, report.pulled), (0, 0));
+    }
+}
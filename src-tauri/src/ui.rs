@@ -1,4 +1,5 @@
 use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
 use scru128::Scru128Id;
 use ssri::Integrity;
@@ -9,6 +10,7 @@ use syntect::parsing::SyntaxSet;
 
 pub use crate::store::{MimeType, Store};
 
+use crate::ansi;
 use crate::util;
 use crate::view;
 
@@ -16,14 +18,27 @@ use crate::view;
 pub struct Item {
     pub id: Scru128Id,
     pub stack_id: Option<Scru128Id>,
+    pub is_stack: bool,
     pub name: String,
     pub last_touched: Scru128Id,
     pub touched: Vec<Scru128Id>,
     pub hash: Integrity,
+    pub snapshot: Option<Integrity>,
     pub ephemeral: bool,
     pub ordered: bool,
     pub locked: bool,
+    #[serde(default)]
+    pub pinned: bool,
     pub cross_stream: bool,
+    pub directory: Option<String>,
+    pub description: Option<String>,
+    pub color: Option<String>,
+    pub icon: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    // for items added to the cross-stream stack: how publishing them is going
+    #[serde(default)]
+    pub delivery: Option<crate::cross_stream::Delivery>,
 }
 
 #[derive(serde::Serialize, Debug, Clone)]
@@ -33,22 +48,42 @@ pub struct Layer {
     pub is_focus: bool,
 }
 
+/// What's shown of the stacks: `layers` is the breadcrumb path from the top level stacks down to
+/// the focus, and the contents of the focus when it's a stack. `root` and `sub` are the two
+/// adjacent layers which are displayed side by side, with the focus in one of them.
 #[derive(serde::Serialize, Debug, Clone)]
 pub struct Nav {
     pub root: Option<Layer>,
     pub sub: Option<Layer>,
+    pub layers: Vec<Layer>,
+    // the items which are selected for bulk operations, in the order they're displayed
+    pub selection: Vec<Scru128Id>,
     pub undo: Option<Item>,
 }
 
+/// A stack and everything nested inside it.
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct Tree {
+    #[serde(flatten)]
+    pub item: Item,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<Tree>,
+}
+
 #[derive(serde::Serialize, Debug, Clone)]
 pub struct UI {
     pub focused: Option<view::Focus>,
     pub last_selected: HashMap<Scru128Id, view::Focus>,
     pub matches: Option<HashSet<ssri::Integrity>>,
+    // the `#tag` words of the current filter
+    pub tags: Vec<String>,
+    // items selected for bulk operations, and where a range selection starts from
+    pub selection: HashSet<Scru128Id>,
+    pub selection_anchor: Option<Scru128Id>,
     pub view: view::View,
     pub theme_mode: String,
     pub is_visible: bool,
-    pub syntax_set: SyntaxSet,
+    pub syntax_set: Arc<SyntaxSet>,
 }
 
 impl UI {
@@ -61,10 +96,13 @@ impl UI {
             focused: None,
             last_selected: HashMap::new(),
             matches: None,
-            view: v.clone(),
+            tags: Vec::new(),
+            selection: HashSet::new(),
+            selection_anchor: None,
+            view: with_pinned(v.clone()),
             theme_mode: "light".to_string(),
             is_visible: false,
-            syntax_set,
+            syntax_set: Arc::new(syntax_set),
         }
     }
 
@@ -72,11 +110,26 @@ impl UI {
         self.focused = None;
         self.last_selected = HashMap::new();
         self.matches = None;
-        self.view = v;
+        self.tags = Vec::new();
+        self.clear_selection();
+        self.view = with_pinned(v);
     }
 
+    /// Filters the view to content matching `filter` and `content_type`. Words of the form `#tag`
+    /// match items with that tag, e.g. `#todo #work`.
     pub fn set_filter(&mut self, store: &Store, v: &view::View, filter: &str, content_type: &str) {
-        self.matches = if !filter.is_empty() || (content_type != "All" && !content_type.is_empty())
+        let (tags, words): (Vec<&str>, Vec<&str>) =
+            filter.split(' ').partition(|word| word.starts_with('#'));
+        self.tags = tags
+            .into_iter()
+            .filter_map(crate::store::normalize_tag)
+            .collect();
+        let filter = words.join(" ");
+        let filter = filter.trim();
+
+        self.matches = if !filter.is_empty()
+            || !self.tags.is_empty()
+            || (content_type != "All" && !content_type.is_empty())
         {
             let matches = store.query(filter, content_type);
             Some(matches)
@@ -87,7 +140,7 @@ impl UI {
             // if the current focus is a stack, navigate right, and then left to cause
             // last_selected to be set in order to preserve the current sub-focus
             if let Some(focused) = self.focused.as_ref() {
-                if focused.item.stack_id.is_none() {
+                if focused.item.is_stack {
                     self.select_right();
                     self.select_left();
                 }
@@ -99,11 +152,97 @@ impl UI {
     }
 
     pub fn refresh_view(&mut self, v: &view::View) {
-        self.view = if let Some(matches) = &self.matches {
-            v.filter(matches)
+        let view = if let Some(matches) = &self.matches {
+            v.filter(matches, &self.tags)
         } else {
             v.clone()
+        };
+        self.view = with_pinned(view);
+        // items which are deleted, or filtered out, drop out of the selection
+        let items = &self.view.items;
+        self.selection.retain(|id| items.contains_key(id));
+    }
+
+    /// Adds the item `id` to the selection, or removes it when it's already selected.
+    pub fn toggle_selection(&mut self, id: &Scru128Id) {
+        if !self.view.items.contains_key(id) {
+            return;
+        }
+        if !self.selection.remove(id) {
+            self.selection.insert(*id);
+        }
+        self.selection_anchor = Some(*id);
+    }
+
+    /// Selects the items from the last item toggled, or the focused item, through to `id`. Only
+    /// items in the same stack are selected.
+    pub fn select_range(&mut self, id: &Scru128Id) {
+        let Some(item) = self.view.items.get(id) else {
+            return;
+        };
+        let anchor = self
+            .selection_anchor
+            .or_else(|| self.focused.as_ref().map(|focus| focus.item.id))
+            .unwrap_or(*id);
+        let peers: Vec<Scru128Id> = self
+            .view
+            .get_peers(item)
+            .iter()
+            .map(|item| item.id)
+            .collect();
+        let end = peers.iter().position(|peer| peer == id).unwrap();
+        let start = peers.iter().position(|peer| *peer == anchor).unwrap_or(end);
+        self.selection
+            .extend(&peers[start.min(end)..=start.max(end)]);
+        self.selection_anchor = Some(anchor);
+    }
+
+    pub fn clear_selection(&mut self) {
+        self.selection.clear();
+        self.selection_anchor = None;
+    }
+
+    /// The items bulk operations act on: the selection, in the order the items are displayed,
+    /// or otherwise the focused item.
+    pub fn selected_items(&self) -> Vec<view::Item> {
+        if self.selection.is_empty() {
+            return self
+                .view
+                .get_best_focus(&self.focused)
+                .map(|focus| vec![focus.item])
+                .unwrap_or_default();
         }
+        let mut items: Vec<(Vec<usize>, view::Item)> = self
+            .selection
+            .iter()
+            .filter_map(|id| self.view.items.get(id))
+            .map(|item| (self.display_position(item), item.clone()))
+            .collect();
+        items.sort_by(|a, b| a.0.cmp(&b.0));
+        items.into_iter().map(|(_, item)| item).collect()
+    }
+
+    fn selected_ids(&self) -> Vec<Scru128Id> {
+        if self.selection.is_empty() {
+            return Vec::new();
+        }
+        self.selected_items().iter().map(|item| item.id).collect()
+    }
+
+    // the position of each of the item's ancestors among its peers, from the top level down
+    fn display_position(&self, item: &view::Item) -> Vec<usize> {
+        self.view
+            .ancestors(&item.id)
+            .iter()
+            .filter_map(|id| self.view.items.get(id))
+            .map(|item| {
+                self.view
+                    .get_peers(item)
+                    .iter()
+                    .position(|peer| peer.id == item.id)
+                    .unwrap_or_default()
+            })
+            .collect()
     }
 
     pub fn select(&mut self, focus: Option<view::Focus>) {
@@ -128,7 +267,7 @@ impl UI {
     pub fn select_down_stack(&mut self) {
         let focused = self.focused.clone().or(self.view.first());
         if let Some(focused) = focused {
-            if focused.item.stack_id.is_none() {
+            if focused.item.is_stack {
                 self.select(self.view.get_best_focus_next(&Some(focused)));
                 return;
             }
@@ -143,7 +282,7 @@ impl UI {
     pub fn select_up_stack(&mut self) {
         let focused = self.focused.clone().or(self.view.first());
         if let Some(focused) = focused {
-            if focused.item.stack_id.is_none() {
+            if focused.item.is_stack {
                 self.select(self.view.get_best_focus_prev(&Some(focused)));
                 return;
             }
@@ -188,89 +327,138 @@ impl UI {
 
     #[tracing::instrument(skip(self, store))]
     pub fn render(&self, store: &Store) -> Nav {
+        let undo = self.view.undo.as_ref().map(|item| with_meta(store, item));
         let focused = self.view.get_best_focus(&self.focused);
         if focused.is_none() {
             return Nav {
                 root: None,
                 sub: None,
-                undo: self.view.undo.as_ref().map(|item| with_meta(store, item)),
+                layers: Vec::new(),
+                selection: Vec::new(),
+                undo,
             };
         }
         let focused = focused.unwrap();
 
-        // the sub layer is focused
-        if let Some(stack_id) = focused.item.stack_id {
-            let items: Vec<_> = self
-                .view
-                .get_peers(&focused.item)
-                .iter()
-                .cloned()
-                .map(|item| with_meta(store, item))
-                .collect();
-            let selected = with_meta(store, &focused.item);
-
-            Nav {
-                root: Some(Layer {
-                    items: self
-                        .view
-                        .root()
-                        .iter()
-                        .map(|item| with_meta(store, item))
-                        .collect(),
-                    selected: with_meta(store, self.view.items.get(&stack_id).unwrap()),
-                    is_focus: false,
-                }),
-                sub: Some(Layer {
-                    items: items.clone(),
-                    selected,
-                    is_focus: true,
-                }),
-                undo: self.view.undo.as_ref().map(|item| with_meta(store, item)),
-            }
+        // a layer for each of the stacks the focus is nested in, and for the focus itself
+        let path = self.view.ancestors(&focused.item.id);
+        let mut layers: Vec<Layer> = path
+            .iter()
+            .filter_map(|id| self.view.items.get(id))
+            .map(|selected| Layer {
+                items: self
+                    .view
+                    .get_peers(selected)
+                    .iter()
+                    .map(|item| with_meta(store, item))
+                    .collect(),
+                selected: with_meta(store, selected),
+                is_focus: selected.id == focused.item.id,
+            })
+            .collect();
+        let focus_index = layers.len() - 1;
+
+        // followed by the contents of the focus, when it's a stack
+        let children: Vec<_> = self
+            .view
+            .children(&focused.item)
+            .iter()
+            .map(|id| self.view.items.get(id).unwrap().clone())
+            .collect();
+        if !children.is_empty() {
+            let possible = self.last_selected.get(&focused.item.id).cloned();
+            let possible = possible.or(self.view.get_focus_for_id(&children.first().unwrap().id));
+            let selected = self.view.get_best_focus(&possible).unwrap();
+            layers.push(Layer {
+                items: children.iter().map(|item| with_meta(store, item)).collect(),
+                selected: with_meta(store, &selected.item),
+                is_focus: false,
+            });
+        }
+
+        // show the focus along with its contents, or otherwise with the stack it's in
+        let (root, sub) = if layers.len() > focus_index + 1 {
+            (
+                Some(layers[focus_index].clone()),
+                Some(layers[focus_index + 1].clone()),
+            )
+        } else if focus_index > 0 {
+            (
+                Some(layers[focus_index - 1].clone()),
+                Some(layers[focus_index].clone()),
+            )
         } else {
-            // the root layer is focused
-            let children: Vec<_> = self
-                .view
-                .children(&focused.item)
-                .iter()
-                .map(|id| self.view.items.get(id).unwrap().clone())
-                .collect();
-
-            let sub = if !children.is_empty() {
-                let possible = self.last_selected.get(&focused.item.id).cloned();
-                let possible =
-                    possible.or(self.view.get_focus_for_id(&children.first().unwrap().id));
-                let selected = self.view.get_best_focus(&possible).unwrap();
-                let selected = with_meta(store, &selected.item);
-                let items: Vec<_> = children.iter().map(|item| with_meta(store, item)).collect();
-
-                Some(Layer {
-                    items: items.clone(),
-                    selected,
-                    is_focus: false,
-                })
-            } else {
-                None
-            };
+            (Some(layers[focus_index].clone()), None)
+        };
+
+        Nav {
+            root,
+            sub,
+            layers,
+            selection: self.selected_ids(),
+            undo,
+        }
+    }
 
-            Nav {
-                root: Some(Layer {
-                    items: self
-                        .view
-                        .root()
-                        .iter()
-                        .cloned()
-                        .map(|item| with_meta(store, item))
-                        .collect(),
-                    selected: with_meta(store, &focused.item),
-                    is_focus: true,
-                }),
-                sub,
-                undo: self.view.undo.as_ref().map(|item| with_meta(store, item)),
+    /// The stack `id` and everything nested inside it, or every top level stack when `id` is
+    /// None.
+    pub fn tree(&self, store: &Store, id: Option<&Scru128Id>) -> Vec<Tree> {
+        fn tree(store: &Store, view: &view::View, item: &view::Item) -> Tree {
+            Tree {
+                item: with_meta(store, item),
+                children: view
+                    .children(item)
+                    .iter()
+                    .filter_map(|id| view.items.get(id))
+                    .map(|child| tree(store, view, child))
+                    .collect(),
             }
         }
+
+        match id {
+            Some(id) => self
+                .view
+                .items
+                .get(id)
+                .map(|item| vec![tree(store, &self.view, item)])
+                .unwrap_or_default(),
+            None => self
+                .view
+                .root()
+                .into_iter()
+                .map(|item| tree(store, &self.view, item))
+                .collect(),
+        }
     }
 
+    pub fn generate_preview(
+        &self,
+        content: &Option<Vec<u8>>,
+        mime_type: &MimeType,
+        content_type: &String,
+        ephemeral: bool,
+    ) -> String {
+        self.previewer()
+            .generate_preview(content, mime_type, content_type, ephemeral)
+    }
+
+    pub fn previewer(&self) -> Previewer {
+        Previewer {
+            theme_mode: self.theme_mode.clone(),
+            syntax_set: self.syntax_set.clone(),
+        }
+    }
+}
+
+/// What's needed to render previews, so large content can be rendered without holding the
+/// state's lock.
+#[derive(Clone)]
+pub struct Previewer {
+    theme_mode: String,
+    syntax_set: Arc<SyntaxSet>,
+}
+
+impl Previewer {
     pub fn generate_preview(
         &self,
         content: &Option<Vec<u8>>,
@@ -289,19 +477,40 @@ impl UI {
     }
 }
 
+/// The id of the virtual stack which gathers the pinned clips from every stack. It's only ever
+/// displayed, and isn't in the store.
+pub const PINNED_ID: Scru128Id = Scru128Id::from_u128(0);
+pub const PINNED_NAME: &str = "Pinned";
+
+fn with_pinned(view: view::View) -> view::View {
+    view.with_pinned(PINNED_ID, Integrity::from(PINNED_NAME))
+}
+
 pub fn with_meta(store: &Store, item: &view::Item) -> Item {
     let content_meta = store.get_content_meta(&item.hash).unwrap();
     Item {
         id: item.id,
         stack_id: item.stack_id,
+        is_stack: item.is_stack,
         name: content_meta.terse.clone(),
         last_touched: item.last_touched,
         touched: item.touched.clone(),
         hash: item.hash.clone(),
+        snapshot: item.snapshot.clone(),
         ephemeral: item.ephemeral,
         ordered: item.ordered,
         locked: item.locked,
+        pinned: item.pinned,
         cross_stream: item.cross_stream,
+        directory: item
+            .directory
+            .as_ref()
+            .map(|directory| directory.path.clone()),
+        description: item.stack_meta.description.clone(),
+        color: item.stack_meta.color.clone(),
+        icon: item.stack_meta.icon.clone(),
+        tags: item.tags.iter().cloned().collect(),
+        delivery: store.delivery_get(&item.id),
     }
 }
 
@@ -430,6 +639,15 @@ pub fn generate_preview(
                     }
                 };
                 div.into_string()
+            } else if content_type == "ANSI" {
+                let html = ansi::to_html(&data[..data.len().min(8192)]);
+                let html = maud::PreEscaped(html);
+                let pre = html! {
+                    pre.("scroll-me")[ephemeral] style="margin: 0; white-space: pre-wrap; overflow-x: hidden" {
+                        (html)
+                    }
+                };
+                pre.into_string()
             } else {
                 let data = String::from_utf8(data.clone()).unwrap();
                 let pre = html! {
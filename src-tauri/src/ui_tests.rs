@@ -357,6 +357,355 @@ fn test_ui_render() {
     assert_nav_as_expected!(&state.ui.render(&state.store), (None, None));
 }
 
+#[test]
+fn test_ui_render_nested() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    let (sender, _receiver) = std::sync::mpsc::channel();
+    let mut state = State::new(path, sender);
+
+    let stack_id = state
+        .store
+        .add_stack(b"Stack A", StackLockStatus::Unlocked)
+        .id;
+    let _ = state.store.add(b"A::Item", MimeType::TextPlain, stack_id);
+    let nested_id = state
+        .store
+        .add_stack_to(b"Nested", StackLockStatus::Unlocked, Some(stack_id))
+        .id;
+    let item_id = state
+        .store
+        .add(b"N::Item 1", MimeType::TextPlain, nested_id)
+        .id;
+    let _ = state
+        .store
+        .add(b"N::Item 2", MimeType::TextPlain, nested_id);
+    state.rescan(None);
+
+    let names = |nav: &Nav| {
+        nav.layers
+            .iter()
+            .map(|layer| layer.selected.name.clone())
+            .collect::<Vec<_>>()
+    };
+
+    // a nested stack is shown along with its contents
+    state.ui.select(state.view.get_focus_for_id(&nested_id));
+    let nav = state.ui.render(&state.store);
+    assert_eq!(names(&nav), vec!["Stack A", "Nested", "N::Item 2"]);
+    assert_nav_as_expected!(
+        &nav,
+        (
+            Some(("Nested", vec!["Nested", "A::Item"], true)),
+            Some(("N::Item 2", vec!["N::Item 2", "N::Item 1"], false)),
+        ),
+    );
+
+    // a clip is shown with the stack it's in
+    state.ui.select(state.view.get_focus_for_id(&item_id));
+    let nav = state.ui.render(&state.store);
+    assert_eq!(names(&nav), vec!["Stack A", "Nested", "N::Item 1"]);
+    assert_nav_as_expected!(
+        &nav,
+        (
+            Some(("Nested", vec!["Nested", "A::Item"], false)),
+            Some(("N::Item 1", vec!["N::Item 2", "N::Item 1"], true)),
+        ),
+    );
+
+    // a stack can't be moved inside itself
+    let packet = state
+        .store
+        .update(stack_id, None, MimeType::TextPlain, Some(nested_id));
+    state.merge(&packet);
+    assert_eq!(state.view.items.get(&stack_id).unwrap().stack_id, None);
+
+    let tree = state.ui.tree(&state.store, Some(&stack_id));
+    assert_eq!(tree.len(), 1);
+    assert_eq!(
+        tree[0]
+            .children
+            .iter()
+            .map(|child| (child.item.name.as_str(), child.children.len()))
+            .collect::<Vec<_>>(),
+        vec![("Nested", 2), ("A::Item", 0)]
+    );
+
+    // filtering keeps the stacks a match is nested in
+    state.nav_set_filter("item 1", "");
+    let tree = state.ui.tree(&state.store, None);
+    assert_eq!(tree.len(), 1);
+    assert_eq!(tree[0].children.len(), 1);
+    assert_eq!(tree[0].children[0].item.name, "Nested");
+    assert_eq!(tree[0].children[0].children[0].item.name, "N::Item 1");
+}
+
+#[test]
+fn test_ui_filter_tags() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    let (sender, _receiver) = std::sync::mpsc::channel();
+    let mut state = State::new(path, sender);
+
+    let stack_id = state
+        .store
+        .add_stack(b"Stack 1", StackLockStatus::Unlocked)
+        .id;
+    let todo_id = state
+        .store
+        .add(b"buy milk", MimeType::TextPlain, stack_id)
+        .id;
+    let work_id = state
+        .store
+        .add(b"write report", MimeType::TextPlain, stack_id)
+        .id;
+    let _ = state.store.add(b"call home", MimeType::TextPlain, stack_id);
+    state.rescan(None);
+
+    let tags = |tags: &[&str]| tags.iter().map(|tag| tag.to_string()).collect::<Vec<_>>();
+    let item = state
+        .tag(todo_id, &tags(&["#Todo", "errand", " "]), &[])
+        .unwrap();
+    assert_eq!(
+        item.tags.into_iter().collect::<Vec<_>>(),
+        tags(&["errand", "todo"])
+    );
+    let item = state.tag(work_id, &tags(&["todo", "work"]), &[]).unwrap();
+    assert_eq!(item.tags.len(), 2);
+    let item = state.tag(todo_id, &[], &tags(&["errand"])).unwrap();
+    assert_eq!(item.tags.into_iter().collect::<Vec<_>>(), tags(&["todo"]));
+    assert!(state.tag(scru128::new(), &tags(&["todo"]), &[]).is_none());
+
+    // tags are matched alongside the rest of the filter
+    state.nav_set_filter("#todo", "");
+    assert_nav_as_expected!(
+        &state.ui.render(&state.store),
+        (
+            Some(("Stack 1", vec!["Stack 1"], false)),
+            Some(("write report", vec!["write report", "buy milk"], true)),
+        ),
+    );
+
+    state.nav_set_filter("#todo #WORK", "");
+    assert_nav_as_expected!(
+        &state.ui.render(&state.store),
+        (
+            Some(("Stack 1", vec!["Stack 1"], false)),
+            Some(("write report", vec!["write report"], true)),
+        ),
+    );
+
+    state.nav_set_filter("milk #todo", "");
+    assert_nav_as_expected!(
+        &state.ui.render(&state.store),
+        (
+            Some(("Stack 1", vec!["Stack 1"], false)),
+            Some(("buy milk", vec!["buy milk"], true)),
+        ),
+    );
+
+    state.nav_set_filter("#errand", "");
+    assert_nav_as_expected!(&state.ui.render(&state.store), (None, None));
+
+    // and indexed for search
+    let hash = |id| state.view.items.get(&id).unwrap().hash.clone();
+    state.store.index.flush();
+    let results = state.store.index.query("tags:work", None).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].0, hash(work_id));
+    let results = state.store.index.query("todo", None).unwrap();
+    assert_eq!(results.len(), 2);
+
+    // including after the index is rebuilt
+    state.store.rebuild_index().unwrap();
+    let results = state.store.index.query("tags:todo", None).unwrap();
+    assert_eq!(results.len(), 2);
+}
+
+#[test]
+fn test_ui_pinned() {
+    use crate::store::PinStatus;
+    use crate::ui::PINNED_ID;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    let (sender, _receiver) = std::sync::mpsc::channel();
+    let mut state = State::new(path, sender);
+
+    let stack_1 = state
+        .store
+        .add_stack(b"Stack 1", StackLockStatus::Unlocked)
+        .id;
+    let pinned_id = state
+        .store
+        .add(b"S1::Item 1", MimeType::TextPlain, stack_1)
+        .id;
+    let _ = state.store.add(b"S1::Item 2", MimeType::TextPlain, stack_1);
+    let stack_2 = state
+        .store
+        .add_stack(b"Stack 2", StackLockStatus::Unlocked)
+        .id;
+    let _ = state.store.add(b"S2::Item 1", MimeType::TextPlain, stack_2);
+    state.rescan(None);
+
+    // without pins, there's no pinned stack
+    assert!(!state.ui.view.items.contains_key(&PINNED_ID));
+
+    let packet = state.store.update_pin_status(pinned_id, PinStatus::Pinned);
+    state.merge(&packet);
+
+    // pinned items are kept at the top of their stack
+    state.ui.select(state.view.get_focus_for_id(&stack_1));
+    assert_nav_as_expected!(
+        &state.ui.render(&state.store),
+        (
+            Some(("Stack 1", vec!["Stack 2", "Stack 1", "Pinned"], true)),
+            Some(("S1::Item 1", vec!["S1::Item 1", "S1::Item 2"], false)),
+        ),
+    );
+
+    // and gathered in the pinned stack, which leads back to the pinned item's stack
+    state.ui.select(state.ui.view.get_focus_for_id(&PINNED_ID));
+    assert_nav_as_expected!(
+        &state.ui.render(&state.store),
+        (
+            Some(("Pinned", vec!["Stack 2", "Stack 1", "Pinned"], true)),
+            Some(("S1::Item 1", vec!["S1::Item 1"], false)),
+        ),
+    );
+    state.ui.select_right();
+    assert_eq!(state.ui.focused.as_ref().unwrap().item.id, pinned_id);
+    assert_eq!(
+        state.ui.focused.as_ref().unwrap().item.stack_id,
+        Some(stack_1)
+    );
+
+    // pinned items, and the stacks holding them, can't be deleted
+    assert!(state.can_delete(&pinned_id).is_err());
+    assert!(state.can_delete(&stack_1).is_err());
+    assert!(state.can_delete(&stack_2).is_ok());
+    assert!(state.can_delete(&PINNED_ID).is_err());
+
+    let packet = state
+        .store
+        .update_pin_status(pinned_id, PinStatus::Unpinned);
+    state.merge(&packet);
+    assert!(state.can_delete(&stack_1).is_ok());
+    assert!(!state.ui.view.items.contains_key(&PINNED_ID));
+}
+
+#[test]
+fn test_ui_selection() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    let (sender, _receiver) = std::sync::mpsc::channel();
+    let mut state = State::new(path, sender);
+
+    let stack_1 = state
+        .store
+        .add_stack(b"Stack 1", StackLockStatus::Unlocked)
+        .id;
+    let item_1 = state
+        .store
+        .add(b"S1::Item 1", MimeType::TextPlain, stack_1)
+        .id;
+    let item_2 = state
+        .store
+        .add(b"S1::Item 2", MimeType::TextPlain, stack_1)
+        .id;
+    let item_3 = state
+        .store
+        .add(b"S1::Item 3\n", MimeType::TextPlain, stack_1)
+        .id;
+    let stack_2 = state
+        .store
+        .add_stack(b"Stack 2", StackLockStatus::Unlocked)
+        .id;
+    state.rescan(None);
+
+    // without a selection, bulk operations act on the focused item
+    state.ui.select(state.view.get_focus_for_id(&item_2));
+    assert!(state.ui.render(&state.store).selection.is_empty());
+    assert_eq!(
+        state
+            .ui
+            .selected_items()
+            .iter()
+            .map(|item| item.id)
+            .collect::<Vec<_>>(),
+        vec![item_2]
+    );
+
+    // a range runs from the focused item, and the selection is kept in display order
+    state.ui.select_range(&item_1);
+    assert_eq!(
+        state.ui.render(&state.store).selection,
+        vec![item_2, item_1]
+    );
+    state.ui.toggle_selection(&item_2);
+    state.ui.toggle_selection(&item_3);
+    assert_eq!(
+        state.ui.render(&state.store).selection,
+        vec![item_3, item_1]
+    );
+
+    // text is joined with a blank line, or one per line for links
+    assert_eq!(state.selected_text().unwrap(), "S1::Item 3\n\nS1::Item 1");
+    state.set_selected_content_type("Link");
+    assert!(state.ui.selection.is_empty());
+    state.ui.toggle_selection(&item_3);
+    state.ui.toggle_selection(&item_1);
+    assert_eq!(state.selected_text().unwrap(), "S1::Item 3\nS1::Item 1");
+
+    // selected items are added to another stack in the order they're displayed
+    state.add_selected_to_stack(stack_2).unwrap();
+    state.ui.select(state.view.get_focus_for_id(&stack_2));
+    assert_nav_as_expected!(
+        &state.ui.render(&state.store),
+        (
+            Some(("Stack 2", vec!["Stack 2", "Stack 1"], true)),
+            Some(("S1::Item 3\n", vec!["S1::Item 3\n", "S1::Item 1"], false)),
+        ),
+    );
+
+    // deleting the selection is undone in one go
+    state.ui.select(state.view.get_focus_for_id(&stack_1));
+    state.ui.toggle_selection(&item_1);
+    state.ui.toggle_selection(&item_2);
+    state.delete_selected();
+    assert!(state.ui.selection.is_empty());
+    assert_nav_as_expected!(
+        &state.ui.render(&state.store),
+        (
+            Some(("Stack 1", vec!["Stack 1", "Stack 2"], true)),
+            Some(("S1::Item 3\n", vec!["S1::Item 3\n"], false)),
+        ),
+    );
+    state.undo();
+    state.ui.select(state.view.get_focus_for_id(&stack_1));
+    assert_nav_as_expected!(
+        &state.ui.render(&state.store),
+        (
+            Some(("Stack 1", vec!["Stack 2", "Stack 1"], true)),
+            Some((
+                "S1::Item 2",
+                vec!["S1::Item 3\n", "S1::Item 2", "S1::Item 1"],
+                false
+            )),
+        ),
+    );
+
+    // deleted items drop out of the selection
+    state.ui.toggle_selection(&item_1);
+    let packet = state.store.delete(item_1);
+    state.merge(&packet);
+    assert!(state.ui.render(&state.store).selection.is_empty());
+}
+
 #[test]
 fn test_ui_generate_preview() {
     let dir = tempfile::tempdir().unwrap();
@@ -1,22 +1,33 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 
 use scru128::Scru128Id;
 use ssri::Integrity;
 
-use crate::store::{Movement, Packet, PacketType, StackLockStatus, StackSortOrder};
+use crate::store::{
+    Movement, Packet, PacketType, PinStatus, StackDirectory, StackLockStatus, StackMeta,
+    StackSortOrder,
+};
 
-#[derive(serde::Serialize, Debug, Clone, PartialEq)]
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
 pub struct Item {
     pub id: Scru128Id,
     pub last_touched: Scru128Id,
     pub touched: Vec<Scru128Id>,
     pub hash: Integrity,
+    // for file references: a snapshot of the file's content when the reference was added
+    pub snapshot: Option<Integrity>,
     pub stack_id: Option<Scru128Id>,
+    // stacks can be nested inside other stacks, so aren't just the items without a stack
+    pub is_stack: bool,
     children: Vec<Scru128Id>,
     pub ephemeral: bool,
     pub ordered: bool,
     pub locked: bool,
+    pub pinned: bool,
     pub cross_stream: bool,
+    pub directory: Option<StackDirectory>,
+    pub stack_meta: StackMeta,
+    pub tags: BTreeSet<String>,
 }
 
 #[derive(serde::Serialize, Debug, Clone)]
@@ -25,7 +36,7 @@ pub struct Focus {
     pub index: usize,
 }
 
-#[derive(serde::Serialize, Debug, Clone)]
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 pub struct View {
     pub items: HashMap<Scru128Id, Item>,
     pub undo: Option<Item>,
@@ -53,16 +64,24 @@ impl View {
                     stack.children.retain(|id| id != &packet.id);
                 }
 
+                // stacks have a lock status, and top level items are always stacks
+                let is_stack = packet.stack_id.is_none() || packet.lock_status.is_some();
+
                 // If this packet isn't ephemeral, check if an item with the same hash already
                 // exists in the same stack, in order to avoid duplicates
-                if !packet.ephemeral {
+                if !packet.ephemeral && !is_stack {
                     if let Some(stack) = packet.stack_id.and_then(|id| self.items.get_mut(&id)) {
                         let children = stack.children.clone();
                         for child_id in children {
                             if let Some(child) = self.items.get_mut(&child_id) {
-                                if !child.ephemeral && &child.hash == packet.hash.as_ref().unwrap()
+                                if !child.ephemeral
+                                    && !child.is_stack
+                                    && &child.hash == packet.hash.as_ref().unwrap()
                                 {
                                     // If it exists, update it
+                                    if packet.snapshot.is_some() {
+                                        child.snapshot = packet.snapshot.clone();
+                                    }
                                     child.touched.push(packet.id);
                                     child.last_touched = packet.id;
                                     if let Some(stack) =
@@ -83,12 +102,18 @@ impl View {
                     last_touched: packet.id,
                     touched: vec![packet.id],
                     hash: packet.hash.clone().unwrap(),
+                    snapshot: packet.snapshot.clone(),
                     stack_id: packet.stack_id,
+                    is_stack,
                     children: Vec::new(),
                     ephemeral: packet.ephemeral,
                     ordered: false,
                     locked: matches!(packet.lock_status, Some(StackLockStatus::Locked)),
+                    pinned: false,
                     cross_stream: false,
+                    directory: None,
+                    stack_meta: StackMeta::default(),
+                    tags: BTreeSet::new(),
                 };
 
                 if let Some(stack) = packet.stack_id.and_then(|id| self.items.get_mut(&id)) {
@@ -146,13 +171,10 @@ impl View {
                     }
                     let stack = stack.unwrap().clone();
 
-                    let ordered_children = if !stack.ordered {
-                        // when switching from time modified desc to manual sort order, preserve
-                        // the current ordering so the initial movement feels right
-                        self.children(&stack)
-                    } else {
-                        stack.children
-                    };
+                    // moves are made in the order the stack is shown in: when switching from
+                    // time modified desc to manual sort order, this preserves the current
+                    // ordering so the initial movement feels right, and pinned items stay first
+                    let ordered_children = self.children(&stack);
 
                     // grab stack again from self.items, this time as mutable
                     let stack = self.items.get_mut(&stack_id).unwrap();
@@ -171,6 +193,19 @@ impl View {
                                     stack.children.swap(index, index + 1);
                                 }
                             }
+                            Movement::MoveTo { index: to } => {
+                                stack.children.remove(index);
+                                let to = (*to).min(stack.children.len());
+                                stack.children.insert(to, item_id);
+                            }
+                            Movement::Top => {
+                                stack.children.remove(index);
+                                stack.children.insert(0, item_id);
+                            }
+                            Movement::Bottom => {
+                                stack.children.remove(index);
+                                stack.children.push(item_id);
+                            }
                         }
                     }
 
@@ -189,6 +224,52 @@ impl View {
                     return;
                 }
 
+                if let Some(directory) = &packet.directory {
+                    if let Some(item) = self.items.get_mut(&source_id) {
+                        item.directory = if directory.path.is_empty() {
+                            None
+                        } else {
+                            Some(directory.clone())
+                        };
+                    }
+                    return;
+                }
+
+                if let Some(stack_meta) = &packet.stack_meta {
+                    if let Some(item) = self.items.get_mut(&source_id) {
+                        let fields = [
+                            (&mut item.stack_meta.description, &stack_meta.description),
+                            (&mut item.stack_meta.color, &stack_meta.color),
+                            (&mut item.stack_meta.icon, &stack_meta.icon),
+                        ];
+                        for (field, update) in fields {
+                            match update.as_deref() {
+                                Some("") => *field = None,
+                                Some(value) => *field = Some(value.to_string()),
+                                None => (),
+                            }
+                        }
+                    }
+                    return;
+                }
+
+                if let Some(tags) = &packet.tags {
+                    if let Some(item) = self.items.get_mut(&source_id) {
+                        item.tags.extend(tags.add.iter().cloned());
+                        for tag in &tags.remove {
+                            item.tags.remove(tag);
+                        }
+                    }
+                    return;
+                }
+
+                if let Some(pin_status) = &packet.pin_status {
+                    if let Some(item) = self.items.get_mut(&source_id) {
+                        item.pinned = *pin_status == PinStatus::Pinned;
+                    }
+                    return;
+                }
+
                 if let Some(lock_status) = &packet.lock_status {
                     if let Some(item) = self.items.get_mut(&source_id) {
                         match lock_status {
@@ -204,9 +285,14 @@ impl View {
 
                     if let Some(hash) = &packet.hash {
                         item.hash = hash.clone();
+                        item.snapshot = packet.snapshot.clone();
                     }
 
                     if let Some(new_stack_id) = packet.stack_id {
+                        // a stack can't be moved inside itself
+                        if self.ancestors(&new_stack_id).contains(&source_id) {
+                            return;
+                        }
                         if let Some(old_stack) =
                             item.stack_id.and_then(|id| self.items.get_mut(&id))
                         {
@@ -232,18 +318,18 @@ impl View {
                 let source_id = packet.source_id.unwrap();
 
                 if let Some(item) = self.items.get(&source_id) {
-                    assert!(
-                        item.stack_id.is_some(),
-                        "Forking Stacks is not supported yet"
-                    );
-
                     let mut new_item = item.clone();
                     new_item.id = packet.id;
 
+                    // a forked stack starts out empty: the copies of its items are forked into
+                    // it by the packets which follow
                     new_item.children = Vec::new();
+                    // only one stack is published to the cross stream
+                    new_item.cross_stream = false;
 
                     if let Some(hash) = &packet.hash {
                         new_item.hash = hash.clone();
+                        new_item.snapshot = packet.snapshot.clone();
                     }
 
                     if let Some(new_stack_id) = packet.stack_id {
@@ -292,19 +378,72 @@ impl View {
     #[tracing::instrument(skip_all)]
     pub fn children(&self, item: &Item) -> Vec<Scru128Id> {
         let mut children = item.children.clone();
+        let pinned = |child: &Scru128Id| self.items.get(child).is_some_and(|item| item.pinned);
         if item.ordered {
+            // pinned items first, each keeping their place in the order
+            children.sort_by_key(|child| !pinned(child));
             return children;
         }
+        // pinned items first, then the most recently touched
         children.sort_by_key(|child| {
             self.items
                 .get(child)
-                .map(|item| item.last_touched)
+                .map(|item| (item.pinned, item.last_touched))
                 .unwrap_or_default()
         });
         children.reverse();
         children
     }
 
+    /// Every pinned clip, from across all the stacks, most recently touched first.
+    pub fn pinned(&self) -> Vec<&Item> {
+        let mut pinned: Vec<_> = self
+            .items
+            .values()
+            .filter(|item| item.pinned && !item.is_stack)
+            .collect();
+        pinned.sort_by_key(|item| item.last_touched);
+        pinned.reverse();
+        pinned
+    }
+
+    /// Adds a virtual stack, `id`, named by `hash`, which gathers the pinned clips from every
+    /// stack. The clips stay in their own stacks: selecting one of them moves to its stack.
+    pub fn with_pinned(mut self, id: Scru128Id, hash: Integrity) -> Self {
+        let children: Vec<Scru128Id> = self.pinned().iter().map(|item| item.id).collect();
+        let Some(last_touched) = children
+            .iter()
+            .filter_map(|id| self.items.get(id))
+            .map(|item| item.last_touched)
+            .max()
+        else {
+            return self;
+        };
+        self.items.insert(
+            id,
+            Item {
+                id,
+                last_touched,
+                touched: vec![last_touched],
+                hash,
+                snapshot: None,
+                stack_id: None,
+                is_stack: true,
+                children,
+                ephemeral: false,
+                // the order is already set: most recently touched first
+                ordered: true,
+                locked: true,
+                pinned: false,
+                cross_stream: false,
+                directory: None,
+                stack_meta: StackMeta::default(),
+                tags: BTreeSet::new(),
+            },
+        );
+        self
+    }
+
     #[tracing::instrument(skip_all)]
     pub fn first(&self) -> Option<Focus> {
         let root = self.root();
@@ -400,31 +539,50 @@ impl View {
         self.get_best_focus_with_offset(focus, -1)
     }
 
+    /// Keeps the items whose content is in `matches`, and which have every one of `tags`.
     #[tracing::instrument(skip_all)]
-    pub fn filter(&self, matches: &HashSet<ssri::Integrity>) -> Self {
+    pub fn filter(&self, matches: &HashSet<ssri::Integrity>, tags: &[String]) -> Self {
+        // stacks are kept when they match, or when anything inside them is kept
+        fn keep(
+            view: &View,
+            item: &Item,
+            matches: &HashSet<ssri::Integrity>,
+            tags: &[String],
+            kept: &mut HashMap<Scru128Id, bool>,
+        ) -> bool {
+            if let Some(keep) = kept.get(&item.id) {
+                return *keep;
+            }
+            let mut keep_item =
+                matches.contains(&item.hash) && tags.iter().all(|tag| item.tags.contains(tag));
+            if item.is_stack {
+                for child in item.children.iter().filter_map(|id| view.items.get(id)) {
+                    keep_item |= keep(view, child, matches, tags, kept);
+                }
+            }
+            kept.insert(item.id, keep_item);
+            keep_item
+        }
+
+        let mut kept = HashMap::new();
+        for item in self.items.values() {
+            keep(self, item, matches, tags, &mut kept);
+        }
+
         let items: HashMap<Scru128Id, Item> = self
             .items
             .values()
-            .filter_map(|item| {
+            .filter(|item| kept[&item.id])
+            .map(|item| {
                 let mut item = item.clone();
-                if item.stack_id.is_none() {
+                if item.is_stack {
                     item.children = self
                         .children(&item)
                         .into_iter()
-                        .filter(|child_id| {
-                            if let Some(child) = self.items.get(child_id) {
-                                return matches.contains(&child.hash);
-                            }
-                            false
-                        })
+                        .filter(|child_id| kept.get(child_id) == Some(&true))
                         .collect();
-                    if item.children.is_empty() && !matches.contains(&item.hash) {
-                        return None;
-                    }
-                } else if !matches.contains(&item.hash) {
-                    return None;
                 }
-                Some((item.id, item))
+                (item.id, item)
             })
             .collect();
 
@@ -433,4 +591,40 @@ impl View {
             undo: self.undo.clone(),
         }
     }
+
+    /// The ids of the stacks `id` is nested inside, starting from the top level, followed by
+    /// `id` itself.
+    pub fn ancestors(&self, id: &Scru128Id) -> Vec<Scru128Id> {
+        let mut ancestors = vec![*id];
+        let mut curr = self.items.get(id);
+        while let Some(stack_id) = curr.and_then(|item| item.stack_id) {
+            // guard against a cycle, rather than loop forever
+            if ancestors.contains(&stack_id) {
+                break;
+            }
+            ancestors.push(stack_id);
+            curr = self.items.get(&stack_id);
+        }
+        ancestors.reverse();
+        ancestors
+    }
+
+    /// The first item which differs between this view and `other`. Which packets touched an
+    /// item, other than the last, isn't compared, and neither is the order children were added
+    /// to stacks which sort them by when they were touched.
+    pub fn first_difference(&self, other: &View) -> Option<Scru128Id> {
+        let normalize = |view: &View, item: &Item| Item {
+            touched: Vec::new(),
+            children: view.children(item),
+            ..item.clone()
+        };
+        let ids: BTreeSet<&Scru128Id> = self.items.keys().chain(other.items.keys()).collect();
+        ids.into_iter()
+            .find(|id| {
+                let a = self.items.get(id).map(|item| normalize(self, item));
+                let b = other.items.get(id).map(|item| normalize(other, item));
+                a != b
+            })
+            .copied()
+    }
 }
@@ -1,5 +1,5 @@
 pub use crate::state::State;
-pub use crate::store::{MimeType, StackLockStatus, Store};
+pub use crate::store::{MimeType, StackLockStatus, StackSortOrder, Store};
 pub use crate::view::View;
 
 macro_rules! assert_view_as_expected {
@@ -97,6 +97,51 @@ fn test_fork_item() {
     );
 }
 
+#[test]
+fn test_fork_stack() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    let mut store = Store::new(path);
+    let mut view = View::new();
+
+    let stack_id = store.add_stack(b"Stack 1", StackLockStatus::Unlocked).id;
+    let item_1 = store.add(b"Item 1", MimeType::TextPlain, stack_id).id;
+    let item_2 = store.add(b"Item 2", MimeType::TextPlain, stack_id).id;
+    store.update_stack_sort_order(stack_id, StackSortOrder::Manual);
+
+    // the fork of a stack is a new, empty stack, which its items are copied into
+    let fork_id = store
+        .fork(stack_id, Some(b"Stack 2"), MimeType::TextPlain, None)
+        .id;
+    store.fork(item_1, None, MimeType::TextPlain, Some(fork_id));
+    store.fork(item_2, None, MimeType::TextPlain, Some(fork_id));
+
+    store.scan().for_each(|p| view.merge(&p));
+    assert_view_as_expected!(
+        &store,
+        &view,
+        vec![
+            ("Stack 2", vec!["Item 1", "Item 2"]),
+            ("Stack 1", vec!["Item 1", "Item 2"]),
+        ],
+    );
+    let fork = view.items.get(&fork_id).unwrap();
+    assert!(fork.is_stack);
+    assert!(fork.ordered);
+
+    // forking a stack into another nests the copy there
+    let nested_id = store
+        .fork(fork_id, None, MimeType::TextPlain, Some(stack_id))
+        .id;
+    store
+        .scan()
+        .filter(|p| p.id == nested_id)
+        .for_each(|p| view.merge(&p));
+    assert_eq!(view.ancestors(&nested_id), vec![stack_id, nested_id]);
+    assert!(view.items.get(&nested_id).unwrap().is_stack);
+}
+
 #[test]
 fn test_move_item_to_new_stack() {
     let dir = tempfile::tempdir().unwrap();
@@ -173,3 +218,327 @@ fn test_no_duplicate_entry_on_same_hash() {
     assert_eq!(item.touched, vec![id1, id2]);
     assert_eq!(item.last_touched, id2);
 }
+
+#[test]
+fn test_stack_directory() {
+    use crate::store::StackDirectory;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    let (sender, _receiver) = std::sync::mpsc::channel();
+    let mut state = State::new(path, sender);
+
+    let stack_id = state
+        .store
+        .add_stack(b"Stack 1", StackLockStatus::Unlocked)
+        .id;
+    let directory = StackDirectory {
+        path: "/src/project".to_string(),
+        watch: true,
+    };
+    state
+        .store
+        .update_stack_directory(stack_id, directory.clone());
+    state.rescan(None);
+
+    let stack = state.view.items.get(&stack_id).unwrap();
+    assert_eq!(stack.directory, Some(directory));
+    assert_eq!(
+        state.shell_settings(Some(stack_id)).cwd,
+        Some("/src/project".to_string())
+    );
+
+    // an empty path unbinds the stack
+    state.store.update_stack_directory(
+        stack_id,
+        StackDirectory {
+            path: "".to_string(),
+            watch: false,
+        },
+    );
+    state.rescan(None);
+
+    let stack = state.view.items.get(&stack_id).unwrap();
+    assert_eq!(stack.directory, None);
+    assert_eq!(state.shell_settings(Some(stack_id)).cwd, None);
+}
+
+#[test]
+fn test_stack_rename_and_meta() {
+    use crate::store::StackMeta;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    let (sender, _receiver) = std::sync::mpsc::channel();
+    let mut state = State::new(path, sender);
+
+    let stack_id = state
+        .store
+        .add_stack(b"Stack 1", StackLockStatus::Unlocked)
+        .id;
+    state.store.add(b"Item 1", MimeType::TextPlain, stack_id);
+
+    state.store.rename_stack(stack_id, "Research");
+    state.store.update_stack_meta(
+        stack_id,
+        StackMeta {
+            description: Some("Reading for the talk".to_string()),
+            color: Some("teal".to_string()),
+            icon: None,
+        },
+    );
+    // fields which aren't given are left alone, and an empty string clears
+    state.store.update_stack_meta(
+        stack_id,
+        StackMeta {
+            description: None,
+            color: Some("".to_string()),
+            icon: Some("IconBook".to_string()),
+        },
+    );
+    state.rescan(None);
+
+    assert_view_as_expected!(
+        &state.store,
+        &state.view,
+        vec![("Research", vec!["Item 1"])]
+    );
+
+    let stack = crate::ui::with_meta(&state.store, state.view.items.get(&stack_id).unwrap());
+    assert_eq!(stack.name, "Research");
+    assert_eq!(stack.description, Some("Reading for the talk".to_string()));
+    assert_eq!(stack.color, None);
+    assert_eq!(stack.icon, Some("IconBook".to_string()));
+}
+
+#[test]
+fn test_stack_operations_undo() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    let (sender, _receiver) = std::sync::mpsc::channel();
+    let mut state = State::new(path, sender);
+
+    let stack_id_1 = state
+        .store
+        .add_stack(b"Stack 1", StackLockStatus::Unlocked)
+        .id;
+    state.store.add(b"Item 1", MimeType::TextPlain, stack_id_1);
+    let item_id_2 = state
+        .store
+        .add(b"Item 2", MimeType::TextPlain, stack_id_1)
+        .id;
+    state.store.add(b"Item 3", MimeType::TextPlain, stack_id_1);
+    let stack_id_2 = state
+        .store
+        .add_stack(b"Stack 2", StackLockStatus::Unlocked)
+        .id;
+    state.store.add(b"Item 2", MimeType::TextPlain, stack_id_2);
+    state.store.add(b"Item 4", MimeType::TextPlain, stack_id_2);
+    state.rescan(None);
+
+    let original = vec![
+        ("Stack 2", vec!["Item 4", "Item 2"]),
+        ("Stack 1", vec!["Item 3", "Item 2", "Item 1"]),
+    ];
+    assert_view_as_expected!(&state.store, &state.view, original.clone());
+
+    state.fork_stack(stack_id_1).unwrap();
+    assert_view_as_expected!(
+        &state.store,
+        &state.view,
+        vec![
+            ("Stack 1 (fork)", vec!["Item 3", "Item 2", "Item 1"]),
+            ("Stack 2", vec!["Item 4", "Item 2"]),
+            ("Stack 1", vec!["Item 3", "Item 2", "Item 1"]),
+        ],
+    );
+    state.undo();
+    assert_view_as_expected!(&state.store, &state.view, original.clone());
+
+    // items Stack 2 already has aren't duplicated
+    state.merge_stack(stack_id_1, stack_id_2).unwrap();
+    assert_view_as_expected!(
+        &state.store,
+        &state.view,
+        vec![("Stack 2", vec!["Item 3", "Item 1", "Item 4", "Item 2"])],
+    );
+    state.undo();
+    assert_view_as_expected!(&state.store, &state.view, original.clone());
+
+    state.split_stack(item_id_2, "Stack 3").unwrap();
+    assert_view_as_expected!(
+        &state.store,
+        &state.view,
+        vec![
+            ("Stack 3", vec!["Item 3", "Item 2"]),
+            ("Stack 2", vec!["Item 4", "Item 2"]),
+            ("Stack 1", vec!["Item 1"]),
+        ],
+    );
+    state.undo();
+    assert_view_as_expected!(&state.store, &state.view, original.clone());
+
+    // a deletion after an operation is undone first
+    state.fork_stack(stack_id_2).unwrap();
+    let packet = state.store.delete(stack_id_1);
+    state.merge(&packet);
+    state.undo();
+    assert_eq!(state.view.root().len(), 3);
+    state.undo();
+    assert_view_as_expected!(&state.store, &state.view, original);
+}
+
+#[test]
+fn test_move_items() {
+    use crate::store::Movement;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    let (sender, _receiver) = std::sync::mpsc::channel();
+    let mut state = State::new(path, sender);
+
+    let stack_id = state
+        .store
+        .add_stack(b"Stack 1", StackLockStatus::Unlocked)
+        .id;
+    let ids: Vec<_> = (1..=5)
+        .map(|i| {
+            state
+                .store
+                .add(
+                    format!("Item {i}").as_bytes(),
+                    MimeType::TextPlain,
+                    stack_id,
+                )
+                .id
+        })
+        .collect();
+    state.rescan(None);
+
+    let packet = state
+        .store
+        .update_move(ids[0], Movement::MoveTo { index: 1 });
+    state.merge(&packet);
+    assert_view_as_expected!(
+        &state.store,
+        &state.view,
+        vec![(
+            "Stack 1",
+            vec!["Item 5", "Item 1", "Item 4", "Item 3", "Item 2"]
+        )],
+    );
+
+    let packet = state.store.update_move(ids[2], Movement::Top);
+    state.merge(&packet);
+    let packet = state.store.update_move(ids[4], Movement::Bottom);
+    state.merge(&packet);
+    let packet = state
+        .store
+        .update_move(ids[3], Movement::MoveTo { index: 99 });
+    state.merge(&packet);
+    let order = vec!["Item 3", "Item 1", "Item 2", "Item 5", "Item 4"];
+    assert_view_as_expected!(&state.store, &state.view, vec![("Stack 1", order.clone())]);
+
+    // several items move together, keeping their order
+    state
+        .move_items(&[ids[4], ids[0]], Movement::Bottom)
+        .unwrap();
+    assert_view_as_expected!(
+        &state.store,
+        &state.view,
+        vec![(
+            "Stack 1",
+            vec!["Item 3", "Item 2", "Item 4", "Item 1", "Item 5"]
+        )],
+    );
+    state.move_items(&[ids[1], ids[4]], Movement::Top).unwrap();
+    assert_view_as_expected!(
+        &state.store,
+        &state.view,
+        vec![(
+            "Stack 1",
+            vec!["Item 2", "Item 5", "Item 3", "Item 4", "Item 1"]
+        )],
+    );
+    state.move_items(&[ids[1], ids[3]], Movement::Down).unwrap();
+    assert_view_as_expected!(
+        &state.store,
+        &state.view,
+        vec![(
+            "Stack 1",
+            vec!["Item 5", "Item 2", "Item 3", "Item 1", "Item 4"]
+        )],
+    );
+    state.move_items(&[ids[0], ids[3]], Movement::Up).unwrap();
+    assert_view_as_expected!(
+        &state.store,
+        &state.view,
+        vec![(
+            "Stack 1",
+            vec!["Item 5", "Item 2", "Item 1", "Item 4", "Item 3"]
+        )],
+    );
+    state
+        .move_items(&[ids[4], ids[2]], Movement::MoveTo { index: 2 })
+        .unwrap();
+    let order = vec!["Item 2", "Item 1", "Item 5", "Item 3", "Item 4"];
+    assert_view_as_expected!(&state.store, &state.view, vec![("Stack 1", order.clone())]);
+
+    // and the moves replay to the same order
+    state.rescan(None);
+    assert_view_as_expected!(&state.store, &state.view, vec![("Stack 1", order)]);
+}
+
+#[test]
+fn test_pinned_in_ordered_stack() {
+    use crate::store::{Movement, PinStatus};
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    let mut store = Store::new(path);
+    let mut view = View::new();
+
+    let stack_id = store.add_stack(b"Stack 1", StackLockStatus::Unlocked).id;
+    let ids: Vec<_> = (1..=4)
+        .map(|i| {
+            store
+                .add(
+                    format!("Item {i}").as_bytes(),
+                    MimeType::TextPlain,
+                    stack_id,
+                )
+                .id
+        })
+        .collect();
+    store.update_move(ids[0], Movement::Top);
+    store.update_pin_status(ids[2], PinStatus::Pinned);
+
+    // pinned items come first in manually ordered stacks too
+    store.scan().for_each(|p| view.merge(&p));
+    assert_view_as_expected!(
+        &store,
+        &view,
+        vec![("Stack 1", vec!["Item 3", "Item 1", "Item 4", "Item 2"])],
+    );
+
+    // moves are made in the order the stack is shown in
+    let packet = store.update_move(ids[3], Movement::MoveTo { index: 1 });
+    view.merge(&packet);
+    assert_view_as_expected!(
+        &store,
+        &view,
+        vec![("Stack 1", vec!["Item 3", "Item 4", "Item 1", "Item 2"])],
+    );
+    let packet = store.update_move(ids[3], Movement::Top);
+    view.merge(&packet);
+    assert_view_as_expected!(
+        &store,
+        &view,
+        vec![("Stack 1", vec!["Item 3", "Item 4", "Item 1", "Item 2"])],
+    );
+}
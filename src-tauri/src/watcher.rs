@@ -0,0 +1,318 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, TryRecvError};
+use std::time::{Duration, Instant};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use notify::{EventKind, RecursiveMode, Watcher};
+use scru128::Scru128Id;
+
+use tauri::Manager;
+
+use crate::content_type;
+use crate::state::SharedState;
+use crate::store::MimeType;
+use crate::view::View;
+
+// editors tend to write a file in several steps, so wait for changes to settle
+const SETTLE: Duration = Duration::from_millis(500);
+// skip large files, e.g. build artifacts
+const MAX_SIZE: u64 = 1024 * 1024;
+// a file which keeps changing is captured at most this often
+const COOLDOWN: Duration = Duration::from_secs(10);
+
+// directories of dependencies and build output, which are skipped even without an ignore file
+const SKIPPED_DIRS: [&str; 6] = [
+    "node_modules",
+    "target",
+    "build",
+    "dist",
+    "__pycache__",
+    "venv",
+];
+// the files which say what to ignore in the directory they're in, and below it
+const IGNORE_FILES: [&str; 2] = [".gitignore", ".ignore"];
+
+/// The directories to watch, for stacks bound to a directory with watching enabled.
+pub fn watched(view: &View) -> HashMap<Scru128Id, PathBuf> {
+    view.items
+        .values()
+        .filter(|item| item.is_stack)
+        .filter_map(|item| {
+            let directory = item.directory.as_ref()?;
+            directory
+                .watch
+                .then(|| (item.id, PathBuf::from(&directory.path)))
+        })
+        .collect()
+}
+
+/// The stack a changed file should be captured to, along with the directory it's bound to. The
+/// most specific directory wins when watched directories are nested. Hidden files, anything
+/// under a hidden directory such as `.git`, and anything under a dependency or build directory
+/// such as `node_modules` or `target`, are ignored.
+pub fn stack_for_path<'a>(
+    watched: &'a HashMap<Scru128Id, PathBuf>,
+    path: &Path,
+) -> Option<(Scru128Id, &'a Path)> {
+    watched
+        .iter()
+        .filter_map(|(id, dir)| Some((id, dir, path.strip_prefix(dir).ok()?)))
+        .filter(|(_, _, rel)| {
+            !rel.components().any(|c| match c {
+                Component::Normal(name) => {
+                    let name = name.to_string_lossy();
+                    name.starts_with('.') || SKIPPED_DIRS.contains(&name.as_ref())
+                }
+                _ => false,
+            })
+        })
+        .max_by_key(|(_, dir, _)| dir.components().count())
+        .map(|(id, dir, _)| (*id, dir.as_path()))
+}
+
+/// The ignore files under watched directories, read as they're needed.
+#[derive(Default)]
+pub struct Ignores {
+    dirs: HashMap<PathBuf, Gitignore>,
+}
+
+impl Ignores {
+    /// Whether `path`, under the watched directory `root`, is ignored by the ignore files in
+    /// `root` or the directories between. As with git, the ignore file nearest the path wins.
+    pub fn is_ignored(&mut self, root: &Path, path: &Path) -> bool {
+        let mut ignored = false;
+        let mut dirs: Vec<&Path> = path
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| dir.starts_with(root))
+            .collect();
+        dirs.reverse();
+        for dir in dirs {
+            let gitignore = self
+                .dirs
+                .entry(dir.to_path_buf())
+                .or_insert_with(|| Self::read(dir));
+            match gitignore.matched_path_or_any_parents(path, false) {
+                Match::Ignore(_) => ignored = true,
+                Match::Whitelist(_) => ignored = false,
+                Match::None => (),
+            }
+        }
+        ignored
+    }
+
+    /// Forgets what was read for the ignore file at `path`, if that's what it is.
+    pub fn changed(&mut self, path: &Path) {
+        let is_ignore_file = path
+            .file_name()
+            .is_some_and(|name| IGNORE_FILES.iter().any(|file| name == *file));
+        if let (true, Some(dir)) = (is_ignore_file, path.parent()) {
+            self.dirs.remove(dir);
+        }
+    }
+
+    fn read(dir: &Path) -> Gitignore {
+        let mut builder = GitignoreBuilder::new(dir);
+        for file in IGNORE_FILES {
+            let path = dir.join(file);
+            if path.exists() {
+                if let Some(e) = builder.add(&path) {
+                    tracing::warn!("Failed to read {}: {}", path.display(), e);
+                }
+            }
+        }
+        builder.build().unwrap_or_else(|_| Gitignore::empty())
+    }
+}
+
+// captures the file at `path`, unless it's what was captured from it last time. Returns the
+// hash of its content, if it was read
+fn capture(
+    app: &tauri::AppHandle,
+    state: &SharedState,
+    stack_id: Scru128Id,
+    path: &Path,
+    last: Option<&ssri::Integrity>,
+) -> Option<ssri::Integrity> {
+    // the file may have been removed again since the event
+    let metadata = std::fs::metadata(path).ok()?;
+    if !metadata.is_file() || metadata.len() == 0 || metadata.len() > MAX_SIZE {
+        return None;
+    }
+    let content = std::fs::read(path).ok()?;
+    let hash = ssri::Integrity::from(&content);
+    if last == Some(&hash) {
+        return Some(hash);
+    }
+
+    let Some((mime_type, content_type)) = content_type::detect_file(Some(path), &content) else {
+        // binary content which can't be previewed
+        return Some(hash);
+    };
+
+    tracing::info!(name = "watcher", path = %path.display(), "capture");
+    state.with_lock(|state| {
+        let packet = state.store.add(&content, mime_type.clone(), stack_id);
+        state.merge(&packet);
+
+        if mime_type == MimeType::TextPlain {
+            if let Some(content_type) = content_type {
+                let packet = state
+                    .store
+                    .update_content_type(packet.hash.unwrap(), content_type.to_string());
+                state.merge(&packet);
+            }
+        }
+    });
+    app.emit_all("refresh-items", true).unwrap();
+    Some(hash)
+}
+
+/// Watches the directories of stacks bound with `watch` set, capturing files which are created
+/// or modified under them as clips, unless they're ignored. The set of directories follows the
+/// views sent by `State`.
+pub fn start(app: tauri::AppHandle, state: SharedState, views: Receiver<View>) {
+    std::thread::spawn(move || {
+        let (event_tx, event_rx) = std::sync::mpsc::channel();
+        let mut watcher =
+            match notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+                if let Ok(event) = res {
+                    let _ = event_tx.send(event);
+                }
+            }) {
+                Ok(watcher) => watcher,
+                Err(e) => {
+                    tracing::error!("Failed to start directory watcher: {}", e);
+                    return;
+                }
+            };
+
+        let mut stacks: HashMap<Scru128Id, PathBuf> = HashMap::new();
+        let mut pending: HashMap<PathBuf, Instant> = HashMap::new();
+        // when each file was last captured, and what it held
+        let mut captured: HashMap<PathBuf, (Instant, ssri::Integrity)> = HashMap::new();
+        let mut ignores = Ignores::default();
+
+        loop {
+            // only the most recent view matters
+            let mut latest = None;
+            loop {
+                match views.try_recv() {
+                    Ok(view) => latest = Some(view),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            }
+            if let Some(view) = latest {
+                let next = watched(&view);
+                let curr_dirs: HashSet<_> = stacks.values().cloned().collect();
+                let next_dirs: HashSet<_> = next.values().cloned().collect();
+                for dir in curr_dirs.difference(&next_dirs) {
+                    let _ = watcher.unwatch(dir);
+                }
+                for dir in next_dirs.difference(&curr_dirs) {
+                    if let Err(e) = watcher.watch(dir, RecursiveMode::Recursive) {
+                        tracing::warn!("Failed to watch {}: {}", dir.display(), e);
+                    }
+                }
+                stacks = next;
+            }
+
+            match event_rx.recv_timeout(Duration::from_millis(100)) {
+                Ok(event) => {
+                    if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                        for path in event.paths {
+                            ignores.changed(&path);
+                            let Some((_, root)) = stack_for_path(&stacks, &path) else {
+                                continue;
+                            };
+                            if !ignores.is_ignored(root, &path) {
+                                pending.insert(path, Instant::now());
+                            }
+                        }
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+
+            let now = Instant::now();
+            // files wait until they've settled, and until they're out of their cooldown
+            let settled: Vec<PathBuf> = pending
+                .iter()
+                .filter(|(_, changed)| now.duration_since(**changed) >= SETTLE)
+                .filter(|(path, _)| {
+                    let last = captured.get(*path);
+                    last.is_none_or(|(at, _)| now.duration_since(*at) >= COOLDOWN)
+                })
+                .map(|(path, _)| path.clone())
+                .collect();
+            for path in settled {
+                pending.remove(&path);
+                let Some((stack_id, _)) = stack_for_path(&stacks, &path) else {
+                    continue;
+                };
+                let last = captured.get(&path).map(|(_, hash)| hash);
+                if let Some(hash) = capture(&app, &state, stack_id, &path, last) {
+                    captured.insert(path, (now, hash));
+                }
+            }
+            captured.retain(|_, (at, _)| now.duration_since(*at) < COOLDOWN * 6);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stack_for_path() {
+        let project = scru128::new();
+        let docs = scru128::new();
+        let watched = HashMap::from([
+            (project, PathBuf::from("/src/project")),
+            (docs, PathBuf::from("/src/project/docs")),
+        ]);
+
+        let stack = |path: &str| stack_for_path(&watched, Path::new(path)).map(|(id, _)| id);
+        assert_eq!(stack("/src/project/main.rs"), Some(project));
+        assert_eq!(stack("/src/project/docs/todo.md"), Some(docs));
+        assert_eq!(stack("/src/project/.git/index"), None);
+        assert_eq!(stack("/src/project/.main.rs.swp"), None);
+        assert_eq!(stack("/src/other/main.rs"), None);
+        assert_eq!(stack("/src/project/target/debug/build.log"), None);
+        assert_eq!(stack("/src/project/web/node_modules/x/index.js"), None);
+        assert_eq!(
+            stack_for_path(&watched, Path::new("/src/project/docs/todo.md")),
+            Some((docs, Path::new("/src/project/docs")))
+        );
+    }
+
+    #[test]
+    fn test_ignores() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::write(root.join(".gitignore"), "*.log\nout/\n").unwrap();
+        std::fs::create_dir_all(root.join("logs")).unwrap();
+        std::fs::write(root.join("logs").join(".ignore"), "!keep.log\n").unwrap();
+
+        let mut ignores = Ignores::default();
+        let mut ignored = |path: &str| ignores.is_ignored(root, &root.join(path));
+        assert!(!ignored("main.rs"));
+        assert!(ignored("debug.log"));
+        assert!(ignored("out/report.txt"));
+        assert!(ignored("logs/debug.log"));
+        // the nearest ignore file wins
+        assert!(!ignored("logs/keep.log"));
+
+        // changes to ignore files are picked up
+        std::fs::write(root.join(".gitignore"), "*.rs\n").unwrap();
+        ignores.changed(&root.join(".gitignore"));
+        let mut ignored = |path: &str| ignores.is_ignored(root, &root.join(path));
+        assert!(ignored("main.rs"));
+        assert!(!ignored("debug.log"));
+    }
+}
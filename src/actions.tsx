@@ -45,9 +45,24 @@ export const actions: Action[] = [
     },
   },
 
+  {
+    name: "Strip ANSI escapes",
+    canApply: (stack: Stack) => {
+      const item = stack.selected_item();
+      if (!item) return false;
+      return getContent(item).value?.content_type == "ANSI";
+    },
+    trigger: (stack: Stack) => {
+      const item = stack.selected_item();
+      if (item) {
+        invoke("store_strip_ansi", { sourceId: item.id });
+      }
+    },
+  },
+
   {
     name: "Set clip content type",
-    canApply: (stack: Stack) => stack.selected()?.stack_id != null,
+    canApply: (stack: Stack) => stack.selected()?.is_stack === false,
     keys: [<Icon name="IconCommandKey" />, <Icon name="IconShiftKey" />, "U"],
     matchKeyEvent: (event: KeyboardEvent) =>
       matchKeyEvent(event, { meta: true, shift: true, key: "u" }),
@@ -103,7 +118,7 @@ export const actions: Action[] = [
     keys: [<Icon name="IconCommandKey" />, "S"],
     matchKeyEvent: (event: KeyboardEvent) =>
       matchKeyEvent(event, { meta: true, code: "KeyS" }),
-    canApply: (stack: Stack) => stack.selected()?.stack_id != null,
+    canApply: (stack: Stack) => stack.selected()?.is_stack === false,
     trigger: (stack: Stack) => {
       modes.activate(stack, addToStackMode);
     },
@@ -131,13 +146,64 @@ export const actions: Action[] = [
     canApply: (stack: Stack) => !!stack.selected_item(),
   },
 
+  {
+    name: "Move clip to top",
+    canApply: (stack: Stack) => !!stack.selected_item(),
+    trigger: (stack: Stack) => {
+      const item = stack.selected_item();
+      if (item) {
+        invoke("store_move", { sourceIds: [item.id], movement: "top" });
+      }
+    },
+  },
+
+  {
+    name: "Move clip to bottom",
+    canApply: (stack: Stack) => !!stack.selected_item(),
+    trigger: (stack: Stack) => {
+      const item = stack.selected_item();
+      if (item) {
+        invoke("store_move", { sourceIds: [item.id], movement: "bottom" });
+      }
+    },
+  },
+
+  {
+    name: "Pin clip",
+    canApply: (stack: Stack) => stack.selected_item()?.pinned === false,
+    trigger: (stack: Stack) => {
+      const item = stack.selected_item();
+      if (item) {
+        invoke("store_set_pinned", { sourceId: item.id, pinned: true });
+      }
+    },
+  },
+
+  {
+    name: "Unpin clip",
+    canApply: (stack: Stack) => !!stack.selected_item()?.pinned,
+    trigger: (stack: Stack) => {
+      const item = stack.selected_item();
+      if (item) {
+        invoke("store_set_pinned", { sourceId: item.id, pinned: false });
+      }
+    },
+  },
+
   {
     name: "Delete clip",
     keys: [<Icon name="IconCommandKey" />, "DEL"],
     matchKeyEvent: (event: KeyboardEvent) =>
       matchKeyEvent(event, { meta: true, code: "Backspace" }),
-    canApply: (stack: Stack) => !!stack.selected_item(),
+    canApply: (stack: Stack) => {
+      const item = stack.selected_item();
+      return !!item && !item.pinned;
+    },
     trigger: (stack: Stack) => {
+      if (stack.nav.value.selection.length > 0) {
+        invoke("store_delete_selected", {});
+        return;
+      }
       const item = stack.selected_item();
       if (item) {
         invoke("store_delete", { id: item.id });
@@ -145,6 +211,12 @@ export const actions: Action[] = [
     },
   },
 
+  {
+    name: "Clear selection",
+    canApply: (stack: Stack) => stack.nav.value.selection.length > 0,
+    trigger: (stack: Stack) => stack.clearSelection(),
+  },
+
   {
     name: "Rename stack",
     keys: [
@@ -162,6 +234,39 @@ export const actions: Action[] = [
     trigger: (stack: Stack) => modes.activate(stack, renameStackMode),
   },
 
+  {
+    name: "Fork stack",
+    canApply: (stack: Stack) => !!stack.selected_stack(),
+    trigger: (stack: Stack) => {
+      const item = stack.selected_stack();
+      if (item) {
+        invoke("store_stack_fork", { sourceId: item.id });
+      }
+    },
+  },
+
+  {
+    name: "Merge stack",
+    canApply: (stack: Stack) => {
+      const item = stack.selected();
+      return !!item && item.is_stack;
+    },
+    trigger: (stack: Stack) => {
+      modes.activate(stack, addToStackMode);
+    },
+  },
+
+  {
+    name: "Split stack here",
+    canApply: (stack: Stack) => !!stack.selected_item(),
+    trigger: (stack: Stack) => {
+      const item = stack.selected_item();
+      if (item) {
+        invoke("store_stack_split", { sourceId: item.id, name: dn() });
+      }
+    },
+  },
+
   {
     name: "Pipe stack",
     keys: [<Icon name="IconAltKey" />, <Icon name="IconCommandKey" />, "P"],
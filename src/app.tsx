@@ -25,7 +25,7 @@ import { Stack } from "./types";
 import { default as theme } from "./theme";
 import { matchKeyEvent } from "./utils";
 
-const stack = new Stack({});
+const stack = new Stack({ layers: [], selection: [] });
 
 async function globalKeyHandler(event: KeyboardEvent) {
   if (!stack) return;
@@ -50,17 +50,27 @@ const state = (() => {
       console.log("Accept", selected.value, chosen);
 
       (async () => {
-        await invoke("store_add_to_stack", {
-          stackId: chosen.id,
-          sourceId: item.id,
-        });
+        if (stack.nav.value.selection.length > 0) {
+          await invoke("store_add_selected_to_stack", { stackId: chosen.id });
+        } else if (item.is_stack) {
+          // a stack is focused: merge it into the chosen stack
+          await invoke("store_stack_merge", {
+            sourceId: item.id,
+            targetId: chosen.id,
+          });
+        } else {
+          await invoke("store_add_to_stack", {
+            stackId: chosen.id,
+            sourceId: item.id,
+          });
+        }
         modes.deactivate();
       })();
     },
 
     accept_meta: (stack: Stack, modes: Modes) => {
       const item = stack.selected();
-      if (!item) return;
+      if (!item || item.is_stack) return;
 
       let name = currFilter.value;
       if (name === "") name = state.dn.value;
@@ -94,7 +104,10 @@ const state = (() => {
 })();
 
 export default {
-  name: () => "Stash clip to stack ...",
+  name: (stack: Stack) =>
+    stack.selected()?.is_stack
+      ? "Merge stack into ..."
+      : "Stash clip to stack ...",
 
   hotKeys: (stack: Stack, modes: Modes) => {
     const ret = [];
@@ -109,7 +122,7 @@ export default {
       });
     }
 
-    ret.push({
+    if (stack.selected()?.is_stack === false) ret.push({
       name: "Create new",
       keys: [
         <Icon name="IconCommandKey" />,
@@ -138,12 +151,15 @@ export default {
 
     state.currFilter.value = "";
 
+    // when merging a stack, it can't be merged into itself
+    const exclude = selected.is_stack ? selected.id : selected.stack_id;
+
     state.availOptions.value = stack.nav.value.root.items
-      .filter((item) => item.id != selected.stack_id);
+      .filter((item) => item.id != exclude);
 
     stack.getRoot().then((items) =>
       state.availOptions.value = items
-        .filter((item) => item.id != selected.stack_id)
+        .filter((item) => item.id != exclude)
     );
 
     state.selected.value = state.options.value[0]?.id || "";
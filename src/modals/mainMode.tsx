@@ -89,8 +89,9 @@ const Broadcast = ({ stack }: { stack: Stack }) => {
         {},
       );
       if (
-        settings && settings.cross_stream_access_token &&
-        settings.cross_stream_access_token.length === 64
+        settings && (settings.cross_stream_endpoint ||
+          settings.cross_stream_access_token &&
+            settings.cross_stream_access_token.length === 64)
       ) {
         tokenLooksGood.value = true;
       }
@@ -32,6 +32,9 @@ export default createModal(
           "N",
         ],
       },
+      {
+        name: "Nested Stack",
+      },
       {
         name: "Clip",
         keys: [
@@ -70,6 +73,18 @@ export default createModal(
         return;
       }
 
+      if (chosen == "Nested Stack") {
+        const parent = stack.selected_stack();
+        (async () => {
+          await invoke("store_new_stack", {
+            name: dn(),
+            parentId: parent?.id,
+          });
+          modes.deactivate();
+        })();
+        return;
+      }
+
       if (chosen == "Stack & Clip") {
         (async () => {
           await invoke("store_new_stack", {
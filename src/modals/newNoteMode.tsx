@@ -22,7 +22,7 @@ const state = (() => {
       }
 
       const args = {
-        stackId: selected.stack_id || selected.id,
+        stackId: selected.is_stack ? selected.id : selected.stack_id,
         content: curr.value,
         shouldFocus: true,
       };
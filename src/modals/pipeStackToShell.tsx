@@ -14,6 +14,7 @@ interface ExecStatus {
   out?: Cacheable;
   err?: Cacheable;
   code?: number;
+  error?: string;
 }
 
 const state = (() => {
@@ -43,15 +44,19 @@ const state = (() => {
     status,
     curr,
     stack_id,
-    accept_meta: async (_: Stack, __: Modes) => {
+    accept_meta: async (_: Stack, __: Modes, pty = false) => {
       exec_id += 1;
       status.value = undefined;
       const args = {
         execId: exec_id,
         stackId: stack_id.value,
         command: curr.value,
+        pty,
       };
-      invoke("store_pipe_stack_to_shell", args);
+      const id = exec_id;
+      invoke("store_pipe_stack_to_shell", args).catch((error: string) => {
+        if (id === exec_id) status.value = { exec_id: id, error };
+      });
     },
   };
 })();
@@ -59,7 +64,9 @@ const state = (() => {
 export default {
   name: () =>
     `Pipe stack to shell${
-      state.status.value?.code !== undefined
+      state.status.value?.error !== undefined
+        ? ` :: ${state.status.value.error}`
+        : state.status.value?.code !== undefined
         ? ` :: exit code: ${state.status.value.code}`
         : ""
     }`,
@@ -72,7 +79,18 @@ export default {
       ],
       onMouseDown: () => state.accept_meta(stack, modes),
       matchKeyEvent: (event: KeyboardEvent) =>
-        event.metaKey && event.key === "Enter",
+        event.metaKey && !event.shiftKey && event.key === "Enter",
+    },
+    {
+      name: "Execute in terminal",
+      keys: [
+        <Icon name="IconCommandKey" />,
+        <Icon name="IconShiftKey" />,
+        <Icon name="IconReturnKey" />,
+      ],
+      onMouseDown: () => state.accept_meta(stack, modes, true),
+      matchKeyEvent: (event: KeyboardEvent) =>
+        event.metaKey && event.shiftKey && event.key === "Enter",
     },
     {
       name: "Back",
@@ -14,6 +14,7 @@ interface ExecStatus {
   out?: Cacheable;
   err?: Cacheable;
   code?: number;
+  error?: string;
 }
 
 const state = (() => {
@@ -44,16 +45,20 @@ const state = (() => {
     status,
     curr,
     clip_id,
-    accept_meta: async (_: Stack, __: Modes) => {
+    accept_meta: async (_: Stack, __: Modes, pty = false) => {
       exec_id += 1;
       status.value = undefined;
       const args = {
         execId: exec_id,
         sourceId: clip_id.value,
         command: curr.value,
+        pty,
       };
       status.value = undefined;
-      invoke("store_pipe_to_command", args);
+      const id = exec_id;
+      invoke("store_pipe_to_command", args).catch((error: string) => {
+        if (id === exec_id) status.value = { exec_id: id, error };
+      });
     },
   };
 })();
@@ -61,7 +66,9 @@ const state = (() => {
 export default {
   name: () =>
     `Pipe clip to shell${
-      state.status.value?.code !== undefined
+      state.status.value?.error !== undefined
+        ? ` :: ${state.status.value.error}`
+        : state.status.value?.code !== undefined
         ? ` :: exit code: ${state.status.value.code}`
         : ""
     }`,
@@ -74,7 +81,18 @@ export default {
       ],
       onMouseDown: () => state.accept_meta(stack, modes),
       matchKeyEvent: (event: KeyboardEvent) =>
-        event.metaKey && event.key === "Enter",
+        event.metaKey && !event.shiftKey && event.key === "Enter",
+    },
+    {
+      name: "Execute in terminal",
+      keys: [
+        <Icon name="IconCommandKey" />,
+        <Icon name="IconShiftKey" />,
+        <Icon name="IconReturnKey" />,
+      ],
+      onMouseDown: () => state.accept_meta(stack, modes, true),
+      matchKeyEvent: (event: KeyboardEvent) =>
+        event.metaKey && event.shiftKey && event.key === "Enter",
     },
     {
       name: "Back",
@@ -27,10 +27,10 @@ const state = (() => {
 
       const args = {
         sourceId: focused_clip.value.id,
-        content: curr.value,
+        name: curr.value,
       };
 
-      await invoke("store_edit_note", args);
+      await invoke("store_stack_rename", args);
       modes.deactivate();
     },
   };
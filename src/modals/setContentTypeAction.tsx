@@ -12,6 +12,7 @@ const setContentType = createActionMode(
     availOptions.value = [
       "Plain Text",
       "Markdown",
+      "ANSI",
       "Nushell",
       "Shell",
       "C",
@@ -47,6 +48,11 @@ const setContentType = createActionMode(
   },
   (stack: Stack, modes: Modes, content_type: string) => {
     console.log(`Content type set to: ${content_type}`);
+    if (stack.nav.value.selection.length > 0) {
+      invoke("store_set_selected_content_type", { contentType: content_type });
+      modes.deactivate();
+      return;
+    }
     const item = stack.selected();
     if (!item) return;
     invoke("store_set_content_type", {
@@ -24,7 +24,7 @@ function getMeta(stack: Stack, item: Item, content: Content): MetaValue[] {
 
   let meta: MetaValue[] = [
     {
-      name: item.stack_id ? content.content_type : "Stack",
+      name: !item.is_stack ? content.content_type : "Stack",
       value: (
         <a
           onClick={(e) => {
@@ -59,7 +59,7 @@ function getMeta(stack: Stack, item: Item, content: Content): MetaValue[] {
 
   /*
   Todo: restore aggregate tiktoken count for entire stack
-  if (!item.stack_id) {
+  if (item.is_stack) {
     meta.push({
       name: "Tiktokens",
       value: (
@@ -74,7 +74,7 @@ function getMeta(stack: Stack, item: Item, content: Content): MetaValue[] {
   }
   */
 
-  if (item.stack_id && content.mime_type == "text/plain") {
+  if (!item.is_stack && content.mime_type == "text/plain") {
     const info = [
       { s: "word", n: content.words },
       { s: "char", n: content.chars },
@@ -93,6 +93,25 @@ function getMeta(stack: Stack, item: Item, content: Content): MetaValue[] {
     });
   }
 
+  if (item.is_stack && item.description) {
+    meta.push({ name: "Description", value: <span>{item.description}</span> });
+  }
+
+  if (item.pinned) {
+    meta.push({ name: "Pinned", value: <span>yes</span> });
+  }
+
+  if (item.tags.length > 0) {
+    meta.push({
+      name: "Tags",
+      value: <span>{item.tags.map((tag) => `#${tag}`).join(" ")}</span>,
+    });
+  }
+
+  if (content.source) {
+    meta.push({ name: "Copied From", value: <span>{content.source}</span> });
+  }
+
   if (content.content_type == "Link") {
     const url = content.terse;
     meta.push({
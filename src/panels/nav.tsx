@@ -6,11 +6,12 @@ import { borderRight, previewItem } from "../ui/app.css";
 import { Content, getContent, Item, Layer, Stack } from "../types";
 
 const TerseRow = (
-  { stack, item, isSelected, isFocused, content }: {
+  { stack, item, isSelected, isFocused, isMarked, content }: {
     stack: Stack;
     item: Item;
     isSelected: boolean;
     isFocused: boolean;
+    isMarked: boolean;
     content: Content | null;
   },
 ) => {
@@ -34,8 +35,12 @@ const TerseRow = (
     <div
       ref={theRef}
       className={"terserow" +
-        (isSelected ? (isFocused ? " highlight" : " selected") : "")}
-      onMouseDown={() => {
+        (isSelected ? (isFocused ? " highlight" : " selected") : "") +
+        (isMarked ? " marked" : "")}
+      onMouseDown={(e: MouseEvent) => {
+        // cmd-click toggles an item in the selection, shift-click selects a range
+        if (e.metaKey) return stack.toggleSelection(item.id);
+        if (e.shiftKey) return stack.selectRange(item.id);
         stack.select(item.id);
       }}
       title={content ? undefined : item.name}
@@ -109,6 +114,7 @@ const renderItems = (
               key={item.id}
               isSelected={item.id == layer.selected.id}
               isFocused={layer.is_focus}
+              isMarked={stack.nav.value.selection.includes(item.id)}
               content={content}
             />
           );
@@ -161,8 +167,31 @@ export function Nav({ stack }: { stack: Stack }) {
     };
   }, []);
 
+  // the stacks above the displayed layers, when the focus is nested
+  const crumbs = nav.layers
+    .slice(
+      0,
+      Math.max(0, nav.layers.findIndex((layer) =>
+        layer.selected.id == nav.root?.selected.id
+      )),
+    )
+    .map((layer) => layer.selected);
+
   return (
-    <div style="flex: 3; display: flex; height: 100%; overflow: hidden; gap: 0.5ch;">
+    <div style="flex: 3; display: flex; flex-direction: column; height: 100%; overflow: hidden; gap: 0.5ch;">
+      {crumbs.length > 0 && (
+        <div style="display: flex; gap: 0.5ch; opacity: 0.6; white-space: nowrap; overflow: hidden;">
+          {crumbs.map((item) => (
+            <span
+              style="cursor: pointer;"
+              onMouseDown={() => stack.select(item.id)}
+            >
+              {item.name} ›
+            </span>
+          ))}
+        </div>
+      )}
+      <div style="flex: 1; display: flex; overflow: hidden; gap: 0.5ch;">
       {nav.root
         ? (
           <>
@@ -206,6 +235,7 @@ export function Nav({ stack }: { stack: Stack }) {
           </>
         )
         : <i>no matches</i>}
+      </div>
     </div>
   );
 }
@@ -213,7 +243,7 @@ export function Nav({ stack }: { stack: Stack }) {
 const RowIcon = (
   { item, content }: { item: Item; content: Content | null },
 ) => {
-  if (!item.stack_id) return <Icon name="IconStack" />;
+  if (item.is_stack) return <Icon name="IconStack" />;
 
   if (!content) return <Icon name="IconClipboard" />;
 
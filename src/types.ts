@@ -18,11 +18,14 @@ export interface Content {
   words: number;
   chars: number;
   preview: string;
+  source?: string;
 }
 
 export interface Cacheable {
   id: Scru128Id;
   hash: SSRI;
+  // for file references: a snapshot of the file's content, which is previewed instead
+  snapshot?: SSRI;
   ephemeral: boolean;
 }
 
@@ -31,21 +34,29 @@ export function getContent(item: Cacheable): Signal<Content | null> {
     return ContentCache.byId(item.id);
   }
   ContentCache.clearId(item.id);
-  return ContentCache.byHash(item.hash);
+  return ContentCache.byHash(item.hash, item.snapshot);
 }
 
 export const ContentCache = (() => {
-  const hashCache: Map<SSRI, Signal<Content | null>> = new Map();
+  // keyed by hash, then by snapshot: clips of the same file can have different snapshots
+  const hashCache: Map<SSRI, Map<SSRI | undefined, Signal<Content | null>>> =
+    new Map();
   const idCache: Map<Scru128Id, Signal<Content | null>> = new Map();
 
-  function byHash(hash: SSRI): Signal<Content | null> {
-    let ret = hashCache.get(hash);
+  function byHash(hash: SSRI, snapshot?: SSRI): Signal<Content | null> {
+    let snapshots = hashCache.get(hash);
+    if (!snapshots) {
+      snapshots = new Map();
+      hashCache.set(hash, snapshots);
+    }
+    let ret = snapshots.get(snapshot);
     if (!ret) {
       ret = new Signal(null);
-      hashCache.set(hash, ret);
+      snapshots.set(snapshot, ret);
       (async () => {
         ret.value = await invoke("store_get_content", {
           hash: hash,
+          snapshot: snapshot,
         });
       })();
     }
@@ -81,10 +92,12 @@ export const ContentCache = (() => {
       (event: { payload: SSRI }) => {
         const hash = event.payload;
         console.log("content", hash);
-        let ret = hashCache.get(hash);
-        if (ret) {
+        for (const [snapshot, ret] of hashCache.get(hash) || []) {
           (async () => {
-            ret.value = await invoke("store_get_content", { hash: hash });
+            ret.value = await invoke("store_get_content", {
+              hash: hash,
+              snapshot: snapshot,
+            });
           })();
         }
       },
@@ -109,14 +122,30 @@ export const ContentCache = (() => {
 export interface Item {
   id: Scru128Id;
   stack_id?: Scru128Id;
+  is_stack: boolean;
   name: string;
   last_touched: Scru128Id;
   touched: Scru128Id[];
   hash: SSRI;
+  snapshot?: SSRI;
   ephemeral: boolean;
   locked: boolean;
+  pinned: boolean;
   ordered: boolean;
   cross_stream: boolean;
+  directory?: string;
+  description?: string;
+  color?: string;
+  icon?: string;
+  tags: string[];
+  delivery?: Delivery;
+}
+
+export interface Delivery {
+  status: "pending" | "delivered" | "failed";
+  attempts: number;
+  next_attempt: number;
+  error?: string;
 }
 
 export interface Layer {
@@ -128,6 +157,10 @@ export interface Layer {
 export interface Nav {
   root?: Layer;
   sub?: Layer;
+  // the breadcrumb path, from the top level stacks down to the focus
+  layers: Layer[];
+  // the items selected for bulk operations, in the order they're displayed
+  selection: Scru128Id[];
   undo?: Item;
 }
 
@@ -234,6 +267,11 @@ export class Stack {
   }
 
   async triggerCopy() {
+    if (this.nav.value.selection.length > 0) {
+      await invoke("store_copy_selected_to_clipboard", {});
+      await invoke("spotlight_hide");
+      return;
+    }
     const item = this.selected_item();
     if (!item) return;
     await invoke("store_copy_to_clipboard", {
@@ -242,6 +280,18 @@ export class Stack {
     await invoke("spotlight_hide");
   }
 
+  async toggleSelection(id: Scru128Id) {
+    this.nav.value = await invoke<Nav>("store_nav_toggle_selection", { id });
+  }
+
+  async selectRange(id: Scru128Id) {
+    this.nav.value = await invoke<Nav>("store_nav_select_range", { id });
+  }
+
+  async clearSelection() {
+    this.nav.value = await invoke<Nav>("store_nav_clear_selection", {});
+  }
+
   async select(id: string) {
     this.nav.value = await invoke<Nav>("store_nav_select", { focusedId: id });
   }
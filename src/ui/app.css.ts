@@ -147,6 +147,11 @@ globalStyle(".terserow.highlight", {
   color: vars.textColorReverse,
 });
 
+// items marked for bulk operations
+globalStyle(".terserow.marked", {
+  boxShadow: `inset 0.3ch 0 0 ${vars.backgroundColorHighlight}`,
+});
+
 export const previewItem = style({
   padding: "0.25lh 0",
   selectors: {